@@ -1,14 +1,64 @@
 //! An very lightweight ACPI implementation for extracting basic information
 //! about CPU topography and NUMA memory regions
 
-use core::intrinsics::size_of;
+use core::fmt;
+use core::mem::size_of;
 
+use crate::cpu;
 use crate::efi;
-use crate::mm::physmem::{PhysAddr, PhysSlice};
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysicalMemory};
+use crate::mm::rangeset;
+
+pub mod aml;
+pub mod bert;
+pub mod bgrt;
+pub mod catalog;
+pub mod cper;
+pub mod dmar;
+pub mod dsdt;
+pub mod erst;
+pub mod facs;
+pub mod fadt;
+pub mod hest;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+pub mod pptt;
+pub mod slit;
+pub mod spcr;
+pub mod srat;
+pub mod waet;
+#[cfg(test)]
+mod fixtures;
+#[cfg(test)]
+mod tests;
+
+use aml::{Namespace, ObjectKind};
+use bert::Bert;
+use bgrt::Bgrt;
+use catalog::AcpiTables;
+use dmar::Dmar;
+use dsdt::SleepType;
+use erst::Erst;
+use facs::Facs;
+use fadt::Fadt;
+use hest::Hest;
+use hpet::HpetTable;
+use madt::{CpuTopology, InterruptRouting, Madt};
+use mcfg::Mcfg;
+use pptt::{NodeKind, Pptt};
+use slit::NodeDistances;
+use spcr::SerialConsoleConfig;
+use srat::NumaTopology;
+use waet::Waet;
 
 /// A `Result` type that wraps and ACPI error
 type Result<T> = core::result::Result<T, Error>;
 
+/// The largest ACPI table we accept. The biggest DSDTs are a few hundred KiB,
+/// a longer table has a corrupt length.
+const MAX_TABLE_LENGTH: usize = 16 * 1024 * 1024;
+
 /// Different types of ACPI tables, mainly used for error information.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TableType {
@@ -19,18 +69,64 @@ pub enum TableType {
     /// The extended root system description pointer (ACPI 2.0).
     RsdpExtended,
 
+    /// Root System Description Table (ACPI 1.0).
+    Rsdt,
+
     /// Extended Systen Description Table.
     Xsdt,
 
+    /// Fixed ACPI Description Table.
+    Fadt,
+
+    /// Differentiated System Description Table.
+    Dsdt,
+
+    /// Secondary System Description Table.
+    Ssdt,
+
+    /// Firmware ACPI Control Structure.
+    Facs,
+
     /// Multiple APIC (Advanced Programmable Interrupt Controller) Description Table.
     Madt,
 
     /// System Resource Affinity Table.
     Srat,
 
+    /// System Locality Information Table.
+    Slit,
+
     /// Serial Port Console Redirection Table.
     Spcr,
 
+    /// High Precision Event Timer Table.
+    Hpet,
+
+    /// PCI Express Memory-mapped Configuration Space base address
+    /// description table.
+    Mcfg,
+
+    /// DMA Remapping Table.
+    Dmar,
+
+    /// Processor Properties Topology Table.
+    Pptt,
+
+    /// Boot Graphics Resource Table.
+    Bgrt,
+
+    /// Windows ACPI Emulated Devices Table.
+    Waet,
+
+    /// Boot Error Record Table.
+    Bert,
+
+    /// Hardware Error Source Table.
+    Hest,
+
+    /// Error Record Serialization Table.
+    Erst,
+
     /// Unknown table type
     Unknown([u8; 4]),
 }
@@ -39,20 +135,170 @@ impl From<[u8; 4]> for TableType {
     /// Convert from ACPI table string into an enum.
     fn from(val: [u8; 4]) -> Self {
         match &val {
+            b"RSDP" => Self::Rsdp,
+            b"RSDT" => Self::Rsdt,
             b"XSDT" => Self::Xsdt,
+            b"FACP" => Self::Fadt,
+            b"DSDT" => Self::Dsdt,
+            b"SSDT" => Self::Ssdt,
+            b"FACS" => Self::Facs,
             b"APIC" => Self::Madt,
             b"SRAT" => Self::Srat,
-            b"Spcr" => Self::Spcr,
+            b"SLIT" => Self::Slit,
+            b"SPCR" => Self::Spcr,
+            b"HPET" => Self::Hpet,
+            b"MCFG" => Self::Mcfg,
+            b"DMAR" => Self::Dmar,
+            b"PPTT" => Self::Pptt,
+            b"BGRT" => Self::Bgrt,
+            b"WAET" => Self::Waet,
+            b"BERT" => Self::Bert,
+            b"HEST" => Self::Hest,
+            b"ERST" => Self::Erst,
             _ => Self::Unknown(val),
         }
     }
 }
 
+impl TableType {
+    /// Returns `true` if we can't boot without this table, so it can't be
+    /// skipped by the lenient policy.
+    pub fn essential(&self) -> bool {
+        matches!(self, Self::Rsdp | Self::RsdpExtended | Self::Rsdt |
+            Self::Xsdt | Self::Madt)
+    }
+
+    /// The signature of the table.
+    pub fn signature(&self) -> [u8; 4] {
+        match self {
+            Self::Rsdp | Self::RsdpExtended => *b"RSDP",
+            Self::Rsdt => *b"RSDT",
+            Self::Xsdt => *b"XSDT",
+            Self::Fadt => *b"FACP",
+            Self::Dsdt => *b"DSDT",
+            Self::Ssdt => *b"SSDT",
+            Self::Facs => *b"FACS",
+            Self::Madt => *b"APIC",
+            Self::Srat => *b"SRAT",
+            Self::Slit => *b"SLIT",
+            Self::Spcr => *b"SPCR",
+            Self::Hpet => *b"HPET",
+            Self::Mcfg => *b"MCFG",
+            Self::Dmar => *b"DMAR",
+            Self::Pptt => *b"PPTT",
+            Self::Bgrt => *b"BGRT",
+            Self::Waet => *b"WAET",
+            Self::Bert => *b"BERT",
+            Self::Hest => *b"HEST",
+            Self::Erst => *b"ERST",
+            Self::Unknown(signature) => *signature,
+        }
+    }
+
+    /// The name of the table, `None` if we don't know it.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Rsdp => "Root System Description Pointer",
+            Self::RsdpExtended => "Extended Root System Description Pointer",
+            Self::Rsdt => "Root System Description Table",
+            Self::Xsdt => "Extended System Description Table",
+            Self::Fadt => "Fixed ACPI Description Table",
+            Self::Dsdt => "Differentiated System Description Table",
+            Self::Ssdt => "Secondary System Description Table",
+            Self::Facs => "Firmware ACPI Control Structure",
+            Self::Madt => "Multiple APIC Description Table",
+            Self::Srat => "System Resource Affinity Table",
+            Self::Slit => "System Locality Information Table",
+            Self::Spcr => "Serial Port Console Redirection Table",
+            Self::Hpet => "High Precision Event Timer Table",
+            Self::Mcfg => "PCI Express Memory-mapped Configuration Table",
+            Self::Dmar => "DMA Remapping Table",
+            Self::Pptt => "Processor Properties Topology Table",
+            Self::Bgrt => "Boot Graphics Resource Table",
+            Self::Waet => "Windows ACPI Emulated Devices Table",
+            Self::Bert => "Boot Error Record Table",
+            Self::Hest => "Hardware Error Source Table",
+            Self::Erst => "Error Record Serialization Table",
+            Self::Unknown(_) => return None,
+        })
+    }
+
+    /// The smallest valid length of the table with `revision`, including the
+    /// header. Tables we don't know only need their header.
+    fn min_length(&self, revision: u8) -> usize {
+        let header = size_of::<Table>();
+        match self {
+            // The ACPI 1.0 FADT, and the ACPI 2.0 one with 64-bit addresses
+            Self::Fadt if revision < 3 => 116,
+            Self::Fadt => 244,
+            Self::Facs => 64,
+            Self::Madt => header + 8,
+            Self::Srat => header + 12,
+            Self::Slit => header + 8,
+            Self::Spcr => 80,
+            Self::Hpet => 56,
+            Self::Mcfg => header + 8,
+            Self::Dmar => header + 12,
+            Self::Bgrt => 56,
+            Self::Waet => 40,
+            Self::Bert => 48,
+            Self::Hest => header + 4,
+            Self::Erst => 48,
+            _ => header,
+        }
+    }
+
+    /// The oldest valid revision of the table. Every table we know started
+    /// at revision 1, except the FACS which has its own version field.
+    fn min_revision(&self) -> u8 {
+        match self {
+            Self::Facs | Self::Unknown(_) => 0,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for TableType {
+    /// Format the table type as its signature, and its name with `{:#}`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &byte in self.signature().iter() {
+            if byte.is_ascii_graphic() {
+                write!(f, "{}", byte as char)?;
+            } else {
+                write!(f, "\\x{:02x}", byte)?;
+            }
+        }
+        match self.name() {
+            Some(name) if f.alternate() => write!(f, " ({})", name),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How to handle tables which fail validation or decoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Any bad table is an error.
+    Strict,
+
+    /// Only a bad RSDP, XSDT, RSDT or MADT is an error. Other bad tables are
+    /// skipped and their status is recorded in the catalog, so buggy
+    /// firmware doesn't stop us from booting.
+    Lenient,
+}
+
+/// The policy this image was built with.
+pub const POLICY: Policy = if cfg!(feature = "lenient-acpi") {
+    Policy::Lenient
+} else {
+    Policy::Strict
+};
+
 /// Errors from ACPI table parsing.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum Error {
     /// An EFI API returned sn error.
-    EfiError(efi::Error),
+    Efi(efi::Error),
 
     /// An ACPI table had an invalid checksum.
     ChecksumMismatch(TableType),
@@ -70,20 +316,167 @@ pub enum Error {
     // The XSDT table size was not evenly divisible by the array element size
     XsdtBadEntries,
 
+    // The RSDT table size was not evenly divisible by the array element size
+    RsdtBadEntries,
+
     // An integer overflow occurred
     IntegerOverflow,
+
+    /// A table we require was not present.
+    TableNotFound(TableType),
+
+    /// The MADT described more processors than we can track.
+    TooManyProcessors,
+
+    /// The MADT described more interrupt controller structures of a type
+    /// than we can track.
+    TooManyInterruptStructures,
+
+    /// The MCFG described more ECAM regions than we can track.
+    TooManyEcamSegments,
+
+    /// There were more ACPI tables than we can track.
+    TooManyTables,
+
+    /// The DMAR described more remapping structures or device scopes than
+    /// we can track.
+    TooManyDmarStructures,
+
+    /// A `RangeSet` operation failed while reserving the DMAR reserved
+    /// memory regions.
+    RmrrRangeSet(rangeset::Error),
+
+    /// A `RangeSet` operation failed while building the memory of a NUMA
+    /// node.
+    NumaRangeSet(rangeset::Error),
+
+    /// The PPTT described more processor hierarchy nodes, caches or private
+    /// resources than we can track.
+    TooManyTopologyNodes,
+
+    /// The HEST described more error sources than we can track.
+    TooManyErrorSources,
+
+    /// A structure in a table referred to another structure by an offset
+    /// which doesn't point at one.
+    InvalidReference(TableType),
+
+    /// A table, or a table pointer, at this physical address was outside of
+    /// the memory we can read.
+    Unreadable(u64),
+
+    /// A table had a revision older than the first revision of that table.
+    BadRevision(TableType, u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Efi(err) => write!(f, "EFI error {:?}", err),
+            Self::ChecksumMismatch(typ) =>
+                write!(f, "{} checksum mismatch", typ),
+            Self::SignatureMismatch(typ) =>
+                write!(f, "unexpected {} signature", typ),
+            Self::LengthMismatch(typ) => write!(f, "{} has a bad length", typ),
+            Self::RevisionTooOld =>
+                write!(f, "extended RSDP needs ACPI 2.0 or newer"),
+            Self::XsdtBadEntries => write!(f, "XSDT has a partial entry"),
+            Self::RsdtBadEntries => write!(f, "RSDT has a partial entry"),
+            Self::IntegerOverflow => write!(f, "integer overflow"),
+            Self::TableNotFound(typ) => write!(f, "no {} table", typ),
+            Self::TooManyProcessors => write!(f, "too many processors"),
+            Self::TooManyInterruptStructures =>
+                write!(f, "too many interrupt controller structures"),
+            Self::TooManyEcamSegments => write!(f, "too many ECAM regions"),
+            Self::TooManyTables => write!(f, "too many tables"),
+            Self::TooManyDmarStructures =>
+                write!(f, "too many DMAR structures"),
+            Self::RmrrRangeSet(err) =>
+                write!(f, "RMRR reservation failed: {:?}", err),
+            Self::NumaRangeSet(err) =>
+                write!(f, "NUMA node memory failed: {:?}", err),
+            Self::TooManyTopologyNodes => write!(f, "too many PPTT nodes"),
+            Self::TooManyErrorSources => write!(f, "too many HEST sources"),
+            Self::InvalidReference(typ) =>
+                write!(f, "{} has a bad structure reference", typ),
+            Self::Unreadable(addr) =>
+                write!(f, "unreadable memory at {:#x}", addr),
+            Self::BadRevision(typ, revision) =>
+                write!(f, "{} has bad revision {}", typ, revision),
+        }
+    }
+}
+
+/// Information gathered from the ACPI tables.
+pub struct Acpi {
+    /// Every table we found.
+    pub tables: AcpiTables,
+
+    /// The fixed hardware description from the FADT, `None` if there is no
+    /// FADT.
+    pub fadt: Option<Fadt>,
+
+    /// The `\_S5_` soft-off sleep type from the DSDT, `None` if there is no
+    /// DSDT or it doesn't declare one.
+    pub s5: Option<SleepType>,
+
+    /// The processors described by the MADT.
+    pub cpus: CpuTopology,
+
+    /// The interrupt routing described by the MADT.
+    pub interrupts: InterruptRouting,
+
+    /// The NUMA topology described by the SRAT, `None` if there is no SRAT.
+    pub numa: Option<NumaTopology>,
+
+    /// The serial console described by the SPCR, `None` if there is no SPCR.
+    pub serial_console: Option<SerialConsoleConfig>,
+
+    /// The HPET described by the HPET table, `None` if there is no HPET.
+    pub hpet: Option<HpetTable>,
+
+    /// The PCI Express ECAM regions from the MCFG, `None` if there is no
+    /// MCFG.
+    pub mcfg: Option<Mcfg>,
+
+    /// The IOMMUs and reserved memory regions from the DMAR, `None` if there
+    /// is no DMAR.
+    pub dmar: Option<Dmar>,
+
+    /// The processor and cache topology from the PPTT, `None` if there is no
+    /// PPTT.
+    pub pptt: Option<Pptt>,
+
+    /// The firmware control structure, `None` if the FADT doesn't point to
+    /// one.
+    pub facs: Option<Facs>,
+
+    /// The boot logo from the BGRT, `None` if there is no BGRT.
+    pub bgrt: Option<Bgrt>,
+
+    /// The emulated device hints from the WAET, `None` if there is no WAET.
+    pub waet: Option<Waet>,
+
+    /// The boot error region from the BERT, `None` if there is no BERT.
+    pub bert: Option<Bert>,
+
+    /// The hardware error sources from the HEST, `None` if there is no HEST.
+    pub hest: Option<Hest>,
+
+    /// The error record serialization interface from the ERST, `None` if
+    /// there is no ERST.
+    pub erst: Option<Erst>,
 }
 
-/// Compute an ACPI checksum on physical memory
-unsafe fn checksum(addr: PhysAddr, size: usize, typ: TableType) -> Result<()> {
+/// Compute an ACPI checksum on the memory of `mem`
+fn checksum<M: MemoryReader>(mem: &M, addr: PhysAddr, size: usize,
+        typ: TableType) -> Result<()> {
+    // Make sure the table doesn't wrap around the address space
+    addr.0.checked_add(size as u64).ok_or(Error::IntegerOverflow)?;
+
     // Compute and validate the checksum
-    let chk = (0..size as u64).try_fold(0u8, |acc, offset| {
-        Ok(acc.wrapping_add(
-            PhysAddr(addr.0.checked_add(offset)
-                .ok_or(Error::IntegerOverflow)?)
-                .read_unaligned::<u8>()
-        ))
-    })?;
+    let chk = mem.bytes(addr, size).map_err(|_| Error::Unreadable(addr.0))?
+        .iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
 
     if chk == 0 {
         Ok(())
@@ -119,13 +512,15 @@ struct Rsdp {
 }
 
 impl Rsdp {
-    /// Load an Rsdp structure from `addr`.
-    unsafe fn from_addr(addr: PhysAddr) -> Result<Self> {
+    /// Load an Rsdp structure from `addr`, read through `mem`.
+    unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr)
+            -> Result<Self> {
         // Validate the checksum
-        checksum(addr, size_of::<Self>(), TableType::Rsdp)?;
+        checksum(mem, addr, size_of::<Self>(), TableType::Rsdp)?;
 
         // Get the RSDP table
-        let rsdp = addr.read_unaligned::<Self>();
+        let rsdp = mem.read_unaligned::<Self>(addr)
+            .map_err(|_| Error::Unreadable(addr.0))?;
 
         // Check the signature.
         if &rsdp.signature != b"RSD PTR " {
@@ -160,10 +555,12 @@ struct RsdpExtended {
 }
 
 impl RsdpExtended {
-    unsafe fn from_addr(addr: PhysAddr) -> Result<Self> {
+    /// Load an extended RSDP structure from `addr`, read through `mem`.
+    unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr)
+            -> Result<Self> {
         // First, start by reading the RSDP. This is the ACPI 1.0 structure and
         // thus is a subset and backwards compatible with all future revisions.
-        let rsdp = Rsdp::from_addr(addr)?;
+        let rsdp = Rsdp::from_addr(mem, addr)?;
 
         // The extended RSDP required ACPI 2.0.
         if rsdp.revision < 2 {
@@ -171,10 +568,11 @@ impl RsdpExtended {
         }
 
         // Validate the checksum
-        checksum(addr, size_of::<Self>(), TableType::RsdpExtended)?;
+        checksum(mem, addr, size_of::<Self>(), TableType::RsdpExtended)?;
 
         // Get the extended RSDP table
-        let rsdp = addr.read_unaligned::<Self>();
+        let rsdp = mem.read_unaligned::<Self>(addr)
+            .map_err(|_| Error::Unreadable(addr.0))?;
 
         // Check the size
         if rsdp.length as usize != size_of::<Self>() {
@@ -186,6 +584,144 @@ impl RsdpExtended {
     }
 }
 
+/// The address space of a Generic Address Structure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressSpace {
+    /// System memory, MMIO.
+    SystemMemory,
+
+    /// System I/O, port I/O.
+    SystemIo,
+
+    /// PCI configuration space.
+    PciConfig,
+
+    /// Any other address space, which we don't support.
+    Other(u8),
+}
+
+impl From<u8> for AddressSpace {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::SystemMemory,
+            1 => Self::SystemIo,
+            2 => Self::PciConfig,
+            _ => Self::Other(val),
+        }
+    }
+}
+
+/// In-memory representation of an ACPI Generic Address Structure, which
+/// describes the location of a register.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct RawGenericAddress {
+    /// The address space the register is in.
+    address_space_id: u8,
+
+    /// The size in bits of the register, 0 for a data structure.
+    register_bit_width: u8,
+
+    /// The bit offset of the register at `address`.
+    register_bit_offset: u8,
+
+    /// The access size, 0 undefined, 1 byte, 2 word, 3 dword and 4 qword.
+    access_size: u8,
+
+    /// The address of the register in its address space.
+    address: u64,
+}
+
+/// The location of a register, decoded from an ACPI Generic Address
+/// Structure.
+#[derive(Clone, Copy, Debug)]
+pub struct GenericAddress {
+    /// The address space the register is in.
+    pub space: AddressSpace,
+
+    /// The size in bits of the register.
+    pub bit_width: u8,
+
+    /// The bit offset of the register at `address`.
+    pub bit_offset: u8,
+
+    /// The access size, 0 undefined, 1 byte, 2 word, 3 dword and 4 qword.
+    pub access_size: u8,
+
+    /// The address of the register in its address space.
+    pub address: u64,
+}
+
+impl From<RawGenericAddress> for GenericAddress {
+    fn from(val: RawGenericAddress) -> Self {
+        GenericAddress {
+            space: val.address_space_id.into(),
+            bit_width: val.register_bit_width,
+            bit_offset: val.register_bit_offset,
+            access_size: val.access_size,
+            address: val.address,
+        }
+    }
+}
+
+impl GenericAddress {
+    /// Returns `true` if the address is zero, which tables use to say the
+    /// register is not present.
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+
+    /// The width in bits of an access to this register. The access size
+    /// takes precedence, if it's undefined the register width is used.
+    fn access_width(&self) -> u8 {
+        match self.access_size {
+            1 => 8,
+            2 => 16,
+            3 => 32,
+            4 => 64,
+            _ => self.bit_width,
+        }
+    }
+
+    /// Read the register. Fails if the address space or access width is not
+    /// one we support.
+    pub unsafe fn read(&self) -> core::result::Result<u64, ()> {
+        let port = self.address as u16;
+        let addr = PhysAddr(self.address);
+
+        Ok(match (self.space, self.access_width()) {
+            (AddressSpace::SystemIo, 8) => cpu::in8(port) as u64,
+            (AddressSpace::SystemIo, 16) => cpu::in16(port) as u64,
+            (AddressSpace::SystemIo, 32) => cpu::in32(port) as u64,
+            (AddressSpace::SystemMemory, 8) => addr.read_volatile::<u8>() as u64,
+            (AddressSpace::SystemMemory, 16) => addr.read_volatile::<u16>() as u64,
+            (AddressSpace::SystemMemory, 32) => addr.read_volatile::<u32>() as u64,
+            (AddressSpace::SystemMemory, 64) => addr.read_volatile::<u64>(),
+            _ => return Err(()),
+        })
+    }
+
+    /// Write `val` to the register. Fails if the address space or access
+    /// width is not one we support.
+    pub unsafe fn write(&self, val: u64) -> core::result::Result<(), ()> {
+        let port = self.address as u16;
+        let addr = PhysAddr(self.address);
+
+        match (self.space, self.access_width()) {
+            (AddressSpace::SystemIo, 8) => cpu::out8(port, val as u8),
+            (AddressSpace::SystemIo, 16) => cpu::out16(port, val as u16),
+            (AddressSpace::SystemIo, 32) => cpu::out32(port, val as u32),
+            (AddressSpace::SystemMemory, 8) => addr.write_volatile(val as u8),
+            (AddressSpace::SystemMemory, 16) => addr.write_volatile(val as u16),
+            (AddressSpace::SystemMemory, 32) => addr.write_volatile(val as u32),
+            (AddressSpace::SystemMemory, 64) => addr.write_volatile(val),
+            _ => return Err(()),
+        }
+
+        Ok(())
+    }
+}
+
 /// In-memory representation of an ACPI table header.
 #[repr(C, packed)]
 struct Table {
@@ -230,19 +766,35 @@ struct Table {
 /// valid ACPI table. Returns (table header, table type, content address,
 /// payload_size).
 impl Table {
-    // From an Addr check the validity of the Table.
-    unsafe fn from_addr(addr: PhysAddr) -> Result<(Self, TableType, PhysAddr, usize)> {
+    // From an Addr check the validity of the Table, read through `mem`.
+    unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr)
+            -> Result<(Self, TableType, PhysAddr, usize)> {
         // Read the table.
-        let table = addr.read_unaligned::<Self>();
+        let table = mem.read_unaligned::<Self>(addr)
+            .map_err(|_| Error::Unreadable(addr.0))?;
 
         // Get the type of this table.
         let typ = TableType::from(table.signature);
 
-        // Validate the checksum.
-        checksum(addr, table.length as usize, typ)?;
-
-        // Make sure the table length is sane.
+        // Make sure the table length and revision are sane for its type
+        // before trusting the length for anything.
         let header_size = size_of::<Self>();
+        if (table.length as usize) < typ.min_length(table.revision) {
+            return Err(Error::LengthMismatch(typ));
+        }
+        if table.revision < typ.min_revision() {
+            return Err(Error::BadRevision(typ, table.revision));
+        }
+
+        // The table can't run past the memory region holding it, nor be
+        // larger than any firmware would make it.
+        let max_length = mem.extent(addr).min(MAX_TABLE_LENGTH as u64);
+        if table.length as u64 > max_length {
+            return Err(Error::LengthMismatch(typ));
+        }
+
+        // Validate the checksum.
+        checksum(mem, addr, table.length as usize, typ)?;
 
         let payload_addr = PhysAddr(
             addr.0
@@ -257,156 +809,415 @@ impl Table {
     }
 }
 
-/// The Multiple Apic Description Table
-struct Madt {}
-
-impl Madt {
-    /// Process the payload of an MADT based on a physical address and a size
-    unsafe fn from_addr(addr: PhysAddr, size: usize) -> Result<Self> {
-        /// The error type when the MADT is truncated
-        const E: Error = Error::LengthMismatch(TableType::Madt);
-        
-        // Create a slice to the physical memory
-        let mut slice = PhysSlice::new(addr, size);
-
-        // Read the local APIC physical address
-        let _local_apic_addr = slice.consume::<u32>().map_err(|_| E)?;
-
-        // Get the APIC flags
-        let _flags = slice.consume::<u32>().map_err(|_| E)?;
-
-        
-        // Handle interrup controller structures
-        while slice.len() > 0 {
-            // Read the interrupt controller header
-            let typ = slice.consume::<u8>().map_err(|_| E)?;
-            let len = slice.consume::<u8>().map_err(|_| E)?
-                .checked_sub(2).ok_or(E)?;
-            
-            match typ {
-                
-                0 => {
-                    #[repr(C, packed)]
-                    struct LocalApic {
-                        
-                        /// The OS associates this local apic structure with a
-                        /// processor object in the namespace when the _UID
-                        /// child object of the processor's device object (or 
-                        /// ProcessorId listed in the processor declaration 
-                        /// operator) evaluates to a numeric value that matches
-                        /// the numeric value in the field 
-                        acpi_processor_uid: u8,
-                        
-                        /// The processor's local APIC ID.
-                        apic_id: u8,
-                        
-                        /// Local APIC flags
-                        ///
-                        /// Bit 0: Enabled (set if ready for use)
-                        /// Bit 1: Online capable (RAZ is enabled, indicates if 
-                        /// the APIC can be enabled at runtime)
-                        flags: u32,
-                    }
-
-                    // Ensure the data is the correct size
-                    if len as usize != size_of::<LocalApic>() {
-                        return Err(E);
-                    }
-
-                    let _apic = slice.consume::<LocalApic>().map_err(|_| E);
-                }
-                
-                9 => {
-                    // Processor Local x2APIC structure
-                    #[repr(C, packed)]
-                    struct LocalX2apic{
-                        /// Reserved, must be zero
-                        reserved: u16,
-                        
-                        /// The processor's local x2APIC ID 
-                        x2apic_id: u32,
-                        
-                        /// Same as local APIC flags 
-                        flags: u32,
-                        
-                        /// OSPM associates the X2APIC Structure with a processor
-                        /// object declared in the namespace using the Device
-                        /// statement, when the _UID child object of the 
-                        /// processor device evaluates to a numeric value, by
-                        /// matching the numeric value with this field 
-                        acpi_processor_uid: u32,
-                    }
-
-                    // Ensure the data is the correct size
-                    if len as usize != size_of::<LocalX2apic>() {
-                        return Err(E);
-                    }
-
-                    let _x2_apic = slice.consume::<LocalX2apic>().map_err(|_| E);
+/// Find and validate every ACPI table: the RSDP, the XSDT or RSDT, every
+/// table they point to and the DSDT. Bad tables are handled per `policy`.
+pub unsafe fn tables(policy: Policy) -> Result<AcpiTables> {
+    // Get the ACPI table base from EFI.
+    let rsdp_addr = efi::get_acpi_table().map_err(Error::Efi)?;
 
-                }
-                _ => {
-                    // Unknown type, discard the data
-                    slice.discard(len as usize).map_err(|_| E)?;
-                }
-            }
-        }
-        Ok(Self{})
-    }
+    discover(&PhysicalMemory::new(), PhysAddr(rsdp_addr as u64), policy)
 }
 
-/// Initialize the ACPI subsystem.
-pub unsafe fn init() -> Result<()> {
-    // Get the ACPI table base from EFI.
-    let rsdp_addr = efi::get_acpi_table().map_err(|e|
-        Error::EfiError(e))?;
+/// Record the table at `addr`, which failed validation with `err`, as
+/// skipped in `tables`. Returns `err` if `policy` doesn't allow skipping it.
+unsafe fn skip<M: MemoryReader>(tables: &mut AcpiTables, mem: &M,
+        addr: PhysAddr, err: Error, policy: Policy) -> Result<()> {
+    // The header is all we have to go on, it may be garbage
+    let header = mem.read_unaligned::<Table>(addr).ok();
+    let essential = matches!(&header,
+        Some(x) if TableType::from(x.signature).essential());
+    if policy == Policy::Strict || essential {
+        return Err(err);
+    }
 
-    // Validate and get the RSDP.
-    let rsdp = RsdpExtended::from_addr(PhysAddr(rsdp_addr as u64))?;
+    tables.add_skipped(addr, header.as_ref(), err)
+}
 
-    // Get the XSDT
-    let (_, typ, xsdt, length) = 
-        Table::from_addr(PhysAddr(rsdp.xsdt_addr))?;
-    if typ != TableType::Xsdt {
+/// Find and validate every ACPI table starting from the RSDP at `rsdp_addr`,
+/// reading the tables through `mem`. `policy` decides whether a bad table
+/// is an error or is skipped.
+pub unsafe fn discover<M: MemoryReader>(mem: &M, rsdp_addr: PhysAddr,
+        policy: Policy) -> Result<AcpiTables> {
+    // Validate and get the RSDP.
+    let rsdp = Rsdp::from_addr(mem, rsdp_addr)?;
+
+    // Prefer the XSDT with 64-bit entries, which requires ACPI 2.0. Fall back
+    // to the 32-bit RSDT on ACPI 1.0 firmware, or if there is no XSDT.
+    let (root_addr, root_typ, entry_size) = if rsdp.revision >= 2 {
+        let rsdp = RsdpExtended::from_addr(mem, rsdp_addr)?;
+        if rsdp.xsdt_addr != 0 {
+            (rsdp.xsdt_addr, TableType::Xsdt, size_of::<u64>())
+        } else {
+            (rsdp.base.rsdt_addr as u64, TableType::Rsdt, size_of::<u32>())
+        }
+    } else {
+        (rsdp.rsdt_addr as u64, TableType::Rsdt, size_of::<u32>())
+    };
+
+    // Record the RSDP in the table catalog
+    let mut tables = AcpiTables::new();
+    tables.add_rsdp(rsdp_addr, rsdp.revision, rsdp.oem_id,
+        if rsdp.revision >= 2 {
+            size_of::<RsdpExtended>()
+        } else {
+            size_of::<Rsdp>()
+        } as u32)?;
+
+    // Get the XSDT or RSDT
+    let (header, typ, root, length) =
+        Table::from_addr(mem, PhysAddr(root_addr))?;
+    if typ != root_typ {
         return Err(Error::SignatureMismatch(typ));
     }
-
-    // Make sure the XSDT size is modulo a 64-bit address size
-    if length % size_of::<u64>() != 0 {
-        return Err(Error::XsdtBadEntries);
+    tables.add(PhysAddr(root_addr), &header)?;
+
+    // Make sure the table size is modulo the entry size
+    if length % entry_size != 0 {
+        return Err(if root_typ == TableType::Xsdt {
+            Error::XsdtBadEntries
+        } else {
+            Error::RsdtBadEntries
+        });
     }
-    // Get the number of entries in the XSDT
-    let entries = length / size_of::<u64>();
-
-    print!("XSDT entries {}\n", entries);
+    // Get the number of entries in the XSDT or RSDT
+    let entries = length / entry_size;
 
-    // Go through each table in the XSDT
+    // Go through each table in the XSDT or RSDT
     for idx in 0..entries {
-        // Get the physical address of the XSDT entry
+        // Get the physical address of the entry
         let entry_addr = idx
-            .checked_mul(size_of::<u64>())
-            .and_then(|x| x.checked_add(xsdt.0 as usize))
+            .checked_mul(entry_size)
+            .and_then(|x| x.checked_add(root.0 as usize))
             .ok_or(Error::IntegerOverflow)?;
 
-        // Get the table address by reading the XSDT entry.
+        // Get the table address by reading the entry.
         // It has been observed in OVMF that these addresses indeed can be unaligned.
-        let table_addr = PhysAddr(entry_addr as u64).read_unaligned::<u64>();
+        let entry_addr = PhysAddr(entry_addr as u64);
+        let table_addr = if entry_size == size_of::<u64>() {
+            mem.read_unaligned::<u64>(entry_addr)
+        } else {
+            mem.read_unaligned::<u32>(entry_addr).map(|x| x as u64)
+        }.map_err(|_| Error::Unreadable(entry_addr.0))?;
+
+        // Parse and validate the table header, and record it
+        let table_addr = PhysAddr(table_addr);
+        match Table::from_addr(mem, table_addr) {
+            Ok((header, _, _, _)) => tables.add(table_addr, &header)?,
+            Err(err) => skip(&mut tables, mem, table_addr, err, policy)?,
+        }
+    }
+
+    // The DSDT and FACS aren't in the XSDT, they come from the FADT
+    let mut dsdt = 0;
+    let mut facs = 0;
+    if let Some(idx) = tables.tables().iter()
+            .position(|x| &x.signature == b"FACP" && !x.skipped()) {
+        let table = tables.tables()[idx];
+        let (data, length) = table.payload();
+        match Fadt::from_addr(mem, data, length, table.revision) {
+            Ok(fadt) => {
+                dsdt = fadt.dsdt;
+                facs = fadt.facs;
+            }
+            Err(err) if policy == Policy::Strict => return Err(err),
+            Err(err) => tables.skip(idx, err),
+        }
+    }
+
+    if dsdt != 0 {
+        let dsdt = PhysAddr(dsdt);
+        let header = Table::from_addr(mem, dsdt)
+            .and_then(|(header, typ, _, _)| {
+                if typ != TableType::Dsdt {
+                    return Err(Error::SignatureMismatch(typ));
+                }
+                Ok(header)
+            });
+
+        match header {
+            Ok(header) => tables.add(dsdt, &header)?,
+            Err(err) => skip(&mut tables, mem, dsdt, err, policy)?,
+        }
+    }
+
+    // The FACS has no standard header, so it is validated on its own
+    if facs != 0 {
+        let facs = PhysAddr(facs);
+        match Facs::from_addr(mem, facs) {
+            Ok(header) => tables.add_facs(facs, &header)?,
+            Err(err) if policy == Policy::Strict => return Err(err),
+            Err(err) => tables.add_skipped(facs, None, err)?,
+        }
+    }
+
+    Ok(tables)
+}
+
+/// Initialize the ACPI subsystem.
+pub unsafe fn init() -> Result<Acpi> {
+    let tables = tables(POLICY)?;
+    decode(&PhysicalMemory::new(), tables, cpu::apic_id(), POLICY)
+}
 
-        // Parse and validate the table header
-        let (_, typ, data, length) = Table::from_addr(PhysAddr(table_addr))?;
+/// Decode the `tables` we understand, reading them through `mem`.
+/// `bsp_apic_id` is the APIC ID of the processor we're running on. `policy`
+/// decides whether a table which fails to decode is an error or is skipped.
+pub unsafe fn decode<M: MemoryReader>(mem: &M, tables: AcpiTables,
+        bsp_apic_id: u32, policy: Policy) -> Result<Acpi> {
+    print!("{}", tables);
+
+    // The MADT is essential, decode it first so everything else can be
+    // decoded straight into place rather than held on the stack twice
+    let madt = tables.find(*b"APIC")
+        .ok_or(Error::TableNotFound(TableType::Madt))?;
+    let (data, length) = madt.payload();
+    let Madt { cpus, interrupts } =
+        Madt::from_addr(mem, data, length, bsp_apic_id)?;
+
+    let mut acpi = Acpi {
+        tables,
+        fadt: None,
+        s5: None,
+        cpus,
+        interrupts,
+        numa: None,
+        serial_console: None,
+        hpet: None,
+        mcfg: None,
+        dmar: None,
+        pptt: None,
+        facs: None,
+        bgrt: None,
+        waet: None,
+        bert: None,
+        hest: None,
+        erst: None,
+    };
+    let mut distances = None;
+
+    // Decode the other tables we understand
+    for idx in 0..acpi.tables.tables().len() {
+        let table = acpi.tables.tables()[idx];
+        if table.skipped() {
+            continue;
+        }
+        let (data, length) = table.payload();
+
+        let decoded = match table.typ() {
+            TableType::Fadt => {
+                Fadt::from_addr(mem, data, length, table.revision)
+                    .map(|x| acpi.fadt = Some(x))
+            }
+
+            TableType::Srat => {
+                NumaTopology::from_addr(mem, data, length, table.revision)
+                    .map(|x| acpi.numa = Some(x))
+            }
+
+            TableType::Slit => {
+                NodeDistances::from_addr(mem, data, length)
+                    .map(|x| distances = Some(x))
+            }
 
-        match typ {
-            TableType::Madt => {
-                Madt::from_addr(data, length)?;
+            TableType::Hpet => {
+                HpetTable::from_addr(mem, data, length)
+                    .map(|x| acpi.hpet = Some(x))
+            }
+
+            TableType::Mcfg => {
+                Mcfg::from_addr(mem, data, length)
+                    .map(|x| acpi.mcfg = Some(x))
+            }
+
+            TableType::Dmar => {
+                Dmar::from_addr(mem, data, length).map(|table| {
+                    print!("DMAR: {} remapping units, {} reserved regions, \
+                        interrupt remapping {}\n", table.drhds().len(),
+                        table.rmrrs().len(), table.interrupt_remapping);
+                    acpi.dmar = Some(table);
+                })
+            }
+
+            TableType::Pptt => {
+                Pptt::from_addr(mem, data, length)
+                    .map(|x| acpi.pptt = Some(x))
+            }
+
+            // The FACS has no header, the whole structure is the table
+            TableType::Facs => {
+                Facs::from_addr(mem, PhysAddr(table.addr))
+                    .map(|x| acpi.facs = Some(x))
+            }
+
+            TableType::Bgrt => {
+                Bgrt::from_addr(mem, data, length)
+                    .map(|x| acpi.bgrt = Some(x))
+            }
+
+            TableType::Waet => {
+                Waet::from_addr(mem, data, length)
+                    .map(|x| acpi.waet = Some(x))
+            }
+
+            TableType::Bert => {
+                Bert::from_addr(mem, data, length)
+                    .map(|x| acpi.bert = Some(x))
+            }
+
+            TableType::Hest => {
+                Hest::from_addr(mem, data, length)
+                    .map(|x| acpi.hest = Some(x))
+            }
+
+            TableType::Erst => {
+                Erst::from_addr(mem, data, length)
+                    .map(|x| acpi.erst = Some(x))
             }
 
             TableType::Spcr => {
-                print!("We have an SPCR!\n");
+                SerialConsoleConfig::from_addr(mem, data, length,
+                        table.revision).map(|spcr| {
+                    print!("Serial console: {:?}\n", spcr);
+                    acpi.serial_console = Some(spcr);
+                })
             }
 
             // Unknown
-            _ => {}
+            _ => Ok(()),
+        };
+
+        // Only non-essential tables can be skipped
+        if let Err(err) = decoded {
+            if policy == Policy::Strict || table.typ().essential() {
+                return Err(err);
+            }
+            acpi.tables.skip(idx, err);
+        }
+    }
+
+    let Acpi { cpus, interrupts, .. } = &acpi;
+    print!("CPUs: {} enabled of {}, BSP APIC ID {:?}\n",
+        cpus.enabled().count(), cpus.processors().len(),
+        cpus.bsp().map(|bsp| bsp.apic_id));
+
+    print!("I/O APICs: {}, ISA IRQ overrides: {}, PC-AT compatible: {}\n",
+        interrupts.io_apics().len(), interrupts.overrides().len(),
+        interrupts.pcat_compat);
+
+    if let Some(numa) = &mut acpi.numa {
+        // The SLIT is only meaningful with the nodes from the SRAT
+        numa.distances = distances;
+
+        if numa.merged {
+            print!("WARNING: More than {} NUMA nodes, the rest are part of \
+                node {}\n", srat::MAX_NUMA_NODES, numa.nodes()[0].domain);
+        }
+
+        for node in numa.nodes() {
+            print!("NUMA node {}: {} CPUs, {:#x} bytes of memory \
+                ({:#x} hot-pluggable), nearest {:?}\n",
+                node.domain,
+                numa.cpus().iter().filter(|x| x.domain == node.domain).count(),
+                node.memory.sum().unwrap_or(0),
+                node.hotplug.sum().unwrap_or(0),
+                numa.nearest(node.domain));
         }
     }
-    Ok(())
+
+    if let Some(pptt) = &acpi.pptt {
+        print!("PPTT: {} packages, {} clusters, {} cores, {} threads, \
+            {} caches\n",
+            pptt.nodes_of(NodeKind::Package).count(),
+            pptt.nodes_of(NodeKind::Cluster).count(),
+            pptt.nodes_of(NodeKind::Core).count(),
+            pptt.nodes_of(NodeKind::Thread).count(),
+            pptt.caches().len());
+
+        // Show who the BSP shares its caches with
+        if let Some(bsp) = acpi.cpus.bsp() {
+            for level in 2..=3 {
+                print!("PPTT: BSP L{} shared with ACPI UIDs {:?}\n", level,
+                    pptt.sharing(bsp.acpi_uid, level));
+            }
+        }
+    }
+
+    // Report the errors the firmware recorded on the previous boot, a fatal
+    // one is why we're booting again
+    if let Some(bert) = &acpi.bert {
+        match bert.errors(mem) {
+            Ok(errors) => {
+                let mut count = 0;
+                for status in errors {
+                    print!("BERT: {}\n", status);
+                    count += 1;
+                }
+                if count == 0 {
+                    print!("BERT: no errors from the previous boot\n");
+                }
+            }
+            Err(err) => {
+                print!("BERT: {}\n", err);
+            }
+        }
+    }
+
+    // Machine check sources are where reset-causing hardware errors come
+    // from, so say which sources the platform has
+    if let Some(hest) = &acpi.hest {
+        print!("HEST: {} error sources, {} machine check sources\n",
+            hest.sources().len(), hest.machine_check_sources().count());
+        for source in hest.sources() {
+            print!("HEST: source {} {:?} enabled {} notification {:?}\n",
+                source.source_id, source.typ, source.enabled,
+                source.notification);
+        }
+    }
+
+    // Get the soft-off sleep type, scanning the DSDT for it if the walk
+    // didn't get that far
+    acpi.s5 = load_aml(mem, &acpi.tables);
+    if let Some(dsdt) = acpi.tables.find(*b"DSDT") {
+        let (data, length) = dsdt.payload();
+        acpi.s5 = acpi.s5.or_else(|| dsdt::find_s5(mem, data, length));
+        print!("DSDT: {} bytes of AML, \\_S5_ {:?}\n", length, acpi.s5);
+    }
+
+    if let Some(fadt) = &acpi.fadt {
+        print!("FADT: DSDT {:#x}, PM timer {:?}, reset {:?}, {:?}\n",
+            fadt.dsdt, fadt.pm_timer.map(|x| (x.register.address, x.width)),
+            fadt.reset.map(|x| (x.register.address, x.value)),
+            fadt.boot_arch);
+    }
+
+    // Report what we had to skip
+    if acpi.tables.skipped().next().is_some() {
+        print!("{}", acpi.tables.diagnostics());
+    }
+
+    Ok(acpi)
+}
+
+/// Build the namespace from the AML in the DSDT and SSDTs in `tables`, read
+/// through `mem`, and get the soft-off sleep type from it. The namespace is
+/// large, so it only lives for this call. AML we can't walk isn't fatal, we
+/// just know less about the devices.
+unsafe fn load_aml<M: MemoryReader>(mem: &M, tables: &AcpiTables)
+        -> Option<SleepType> {
+    let mut namespace = Namespace::new();
+    for table in tables.find_all(*b"DSDT").chain(tables.find_all(*b"SSDT")) {
+        let (data, length) = table.payload();
+        if let Ok(aml) = mem.bytes(data, length) {
+            if let Err(err) = namespace.load(aml) {
+                print!("AML: {} at {:#x}: {:?}\n",
+                    core::str::from_utf8(&table.signature).unwrap_or("????"),
+                    table.addr, err);
+            }
+        }
+    }
+    print!("AML: {} objects, {} devices\n", namespace.objects().len(),
+        namespace.objects().iter()
+            .filter(|x| x.kind == ObjectKind::Device).count());
+
+    dsdt::s5(&namespace)
 }
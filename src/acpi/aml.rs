@@ -0,0 +1,872 @@
+//! A minimal ACPI Machine Language (AML) namespace walker. We don't interpret
+//! AML, we walk the definition blocks of the DSDT and SSDTs to build a
+//! namespace of scopes, devices, names and methods, and evaluate the names
+//! and methods which just return a constant. That covers things like `\_S5_`
+//! and `_PRT` on most firmware without a full interpreter.
+
+use core::fmt;
+
+use crate::fixed_vec::FixedVec;
+
+#[cfg(test)]
+mod tests;
+
+/// The maximum number of definition blocks we can load.
+const MAX_TABLES: usize = 16;
+
+/// The maximum number of objects we can track in the namespace.
+const MAX_OBJECTS: usize = 512;
+
+/// The maximum nesting of scopes we walk into.
+const MAX_DEPTH: usize = 16;
+
+/// The maximum number of methods returning other objects we follow when
+/// evaluating.
+const MAX_EVAL_DEPTH: usize = 8;
+
+/// The parent stored for the root, which has none.
+const NO_PARENT: u16 = u16::MAX;
+
+/// AML namespace modifier and named object opcodes.
+const ALIAS_OP: u8 = 0x06;
+const NAME_OP: u8 = 0x08;
+const SCOPE_OP: u8 = 0x10;
+const METHOD_OP: u8 = 0x14;
+const EXTERNAL_OP: u8 = 0x15;
+
+/// AML data object opcodes and prefixes.
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ONES_OP: u8 = 0xff;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+const STRING_PREFIX: u8 = 0x0d;
+const QWORD_PREFIX: u8 = 0x0e;
+const BUFFER_OP: u8 = 0x11;
+const PACKAGE_OP: u8 = 0x12;
+const VAR_PACKAGE_OP: u8 = 0x13;
+
+/// AML statement opcodes.
+const IF_OP: u8 = 0xa0;
+const ELSE_OP: u8 = 0xa1;
+const WHILE_OP: u8 = 0xa2;
+const NOOP_OP: u8 = 0xa3;
+const RETURN_OP: u8 = 0xa4;
+
+/// AML extended opcodes, following `EXT_OP_PREFIX`.
+const EXT_OP_PREFIX: u8 = 0x5b;
+const MUTEX_OP: u8 = 0x01;
+const EVENT_OP: u8 = 0x02;
+const OP_REGION_OP: u8 = 0x80;
+const FIELD_OP: u8 = 0x81;
+const DEVICE_OP: u8 = 0x82;
+const PROCESSOR_OP: u8 = 0x83;
+const POWER_RES_OP: u8 = 0x84;
+const THERMAL_ZONE_OP: u8 = 0x85;
+const INDEX_FIELD_OP: u8 = 0x86;
+const BANK_FIELD_OP: u8 = 0x87;
+
+/// AML name string prefixes.
+const ROOT_CHAR: u8 = b'\\';
+const PARENT_PREFIX_CHAR: u8 = b'^';
+const NULL_NAME: u8 = 0x00;
+const DUAL_NAME_PREFIX: u8 = 0x2e;
+const MULTI_NAME_PREFIX: u8 = 0x2f;
+
+/// The scopes every namespace starts with.
+const PREDEFINED_SCOPES: [[u8; 4]; 5] =
+    [*b"_GPE", *b"_PR_", *b"_SB_", *b"_SI_", *b"_TZ_"];
+
+/// A `Result` type that wraps an AML error
+type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from walking or evaluating AML.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// More definition blocks were loaded than we can track.
+    TooManyTables,
+
+    /// The definition blocks declared more objects than we can track.
+    TooManyObjects,
+
+    /// Scopes were nested, or methods returned other objects, deeper than
+    /// we follow.
+    TooDeep,
+
+    /// A package length, name or data object ran past the end of the AML
+    /// containing it.
+    Truncated,
+
+    /// A name string was malformed.
+    BadName,
+
+    /// The object evaluated, or an object it refers to, isn't in the
+    /// namespace.
+    NotFound,
+
+    /// The AML does something we don't interpret, such as a method which
+    /// does more than return a constant.
+    Unsupported,
+}
+
+/// The kind of an object in the namespace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ObjectKind {
+    /// A scope, `Scope ()` or one of the predefined root scopes.
+    #[default]
+    Scope,
+
+    /// A device, `Device ()`.
+    Device,
+
+    /// A processor, `Processor ()`.
+    Processor,
+
+    /// A power resource, `PowerResource ()`.
+    PowerResource,
+
+    /// A thermal zone, `ThermalZone ()`.
+    ThermalZone,
+
+    /// A named data object, `Name ()`.
+    Name,
+
+    /// A control method, `Method ()`.
+    Method {
+        /// The number of arguments the method takes.
+        args: u8,
+
+        /// The method is serialized.
+        serialized: bool,
+    },
+
+    /// Anything else we record the name of but can't evaluate, such as an
+    /// operation region, mutex or event.
+    Other,
+}
+
+/// An object in the namespace. Firmware declares hundreds of these, so the
+/// indices and offsets are packed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Object {
+    /// The last segment of the object's path.
+    pub name: [u8; 4],
+
+    /// What the object is.
+    pub kind: ObjectKind,
+
+    /// The index of the definition block which declared the object.
+    table: u8,
+
+    /// The index of the object's parent, `NO_PARENT` for the root.
+    parent: u16,
+
+    /// The offset of the object's value (for names) or body (for methods)
+    /// in the definition block.
+    start: u32,
+
+    /// The end of the object's value or body.
+    end: u32,
+}
+
+impl Object {
+    /// Create the object `name` of `kind` in the scope `parent`, with its
+    /// value or body at `start..end` in definition block `table`.
+    fn new(name: [u8; 4], kind: ObjectKind, parent: usize, table: usize,
+            start: usize, end: usize) -> Self {
+        Object {
+            name,
+            kind,
+            table: table as u8,
+            parent: parent as u16,
+            start: start as u32,
+            end: end as u32,
+        }
+    }
+
+    /// The index of the object's parent, `None` for the root.
+    pub fn parent(&self) -> Option<usize> {
+        (self.parent != NO_PARENT).then_some(self.parent as usize)
+    }
+}
+
+/// A name string referring to an object, relative to a scope.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NameString<'a> {
+    /// The path starts at the root, `\`.
+    root: bool,
+
+    /// The number of parent prefixes, `^`.
+    parents: usize,
+
+    /// The 4 byte name segments.
+    segments: &'a [u8],
+}
+
+impl<'a> NameString<'a> {
+    /// Iterate through the name segments.
+    pub fn segments(&self) -> impl Iterator<Item = [u8; 4]> + 'a {
+        self.segments.chunks_exact(4).map(|x| [x[0], x[1], x[2], x[3]])
+    }
+}
+
+impl fmt::Display for NameString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.root {
+            write!(f, "\\")?;
+        }
+        for _ in 0..self.parents {
+            write!(f, "^")?;
+        }
+        for (ii, segment) in self.segments().enumerate() {
+            if ii != 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", core::str::from_utf8(&segment).unwrap_or("????"))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for NameString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// A value decoded from AML. Strings, buffers and packages refer to the AML
+/// they were declared in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// An integer.
+    Integer(u64),
+
+    /// A string, without its NUL terminator.
+    String(&'a [u8]),
+
+    /// The initializer of a buffer. The buffer may be declared larger, the
+    /// rest is zeros.
+    Buffer(&'a [u8]),
+
+    /// A package.
+    Package(Package<'a>),
+
+    /// A reference to another object, resolved relative to the scope of the
+    /// object it was found in.
+    Reference(NameString<'a>),
+}
+
+impl<'a> Value<'a> {
+    /// Get the value as an integer.
+    pub fn as_integer(&self) -> Option<u64> {
+        match *self {
+            Self::Integer(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a package.
+    pub fn as_package(&self) -> Option<Package<'a>> {
+        match *self {
+            Self::Package(package) => Some(package),
+            _ => None,
+        }
+    }
+}
+
+/// A package, decoded one element at a time as it is iterated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Package<'a> {
+    /// The AML of the initialized elements.
+    elements: &'a [u8],
+
+    /// The declared number of elements.
+    len: usize,
+}
+
+impl<'a> Package<'a> {
+    /// The declared number of elements. Elements past the initialized ones
+    /// are uninitialized, and aren't returned by `iter`.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the package has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate through the initialized elements. Iteration stops at an
+    /// element we can't decode.
+    pub fn iter(&self) -> PackageIter<'a> {
+        PackageIter {
+            cursor: Cursor::new(self.elements, 0, self.elements.len()),
+            remaining: self.len,
+        }
+    }
+
+    /// Get the element at `idx`.
+    pub fn get(&self, idx: usize) -> Option<Value<'a>> {
+        self.iter().nth(idx)
+    }
+}
+
+/// An iterator through the elements of a `Package`.
+pub struct PackageIter<'a> {
+    /// The AML of the remaining elements.
+    cursor: Cursor<'a>,
+
+    /// The number of declared elements remaining.
+    remaining: usize,
+}
+
+impl<'a> Iterator for PackageIter<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.cursor.pos >= self.cursor.end {
+            return None;
+        }
+
+        match self.cursor.value() {
+            Ok(value) => {
+                self.remaining -= 1;
+                Some(value)
+            }
+            Err(_) => {
+                self.remaining = 0;
+                None
+            }
+        }
+    }
+}
+
+/// A position in a block of AML, which can't go past `end`.
+#[derive(Clone, Copy)]
+struct Cursor<'a> {
+    /// The whole block of AML.
+    aml: &'a [u8],
+
+    /// The offset of the next byte.
+    pos: usize,
+
+    /// The offset we can't go past.
+    end: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Create a cursor over `aml[start..end]`.
+    fn new(aml: &'a [u8], start: usize, end: usize) -> Self {
+        Cursor { aml, pos: start, end: end.min(aml.len()) }
+    }
+
+    /// Get the next byte without consuming it.
+    fn peek(&self) -> Result<u8> {
+        if self.pos >= self.end {
+            return Err(Error::Truncated);
+        }
+        Ok(self.aml[self.pos])
+    }
+
+    /// Consume the next byte.
+    fn byte(&mut self) -> Result<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// Consume the next `len` bytes.
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.end - self.pos < len {
+            return Err(Error::Truncated);
+        }
+        self.pos += len;
+        Ok(&self.aml[self.pos - len..self.pos])
+    }
+
+    /// Consume a little endian integer of `bytes` bytes.
+    fn integer(&mut self, bytes: usize) -> Result<u64> {
+        Ok(self.take(bytes)?.iter().rev()
+            .fold(0, |acc, &x| (acc << 8) | x as u64))
+    }
+
+    /// Consume a PkgLength, returning the offset of the end of the package
+    /// it describes.
+    fn pkg_length(&mut self) -> Result<usize> {
+        let start = self.pos;
+
+        // Bits 7:6 of the lead byte are the number of bytes which follow it.
+        // With none, bits 5:0 are the length, otherwise only bits 3:0 are and
+        // the following bytes are the higher bits.
+        let lead = self.byte()?;
+        let follow = (lead >> 6) as usize;
+        let len = if follow == 0 {
+            (lead & 0x3f) as usize
+        } else {
+            (lead & 0x0f) as usize | (self.integer(follow)? as usize) << 4
+        };
+
+        // The length includes the PkgLength itself
+        if len < self.pos - start || len > self.end - start {
+            return Err(Error::Truncated);
+        }
+        Ok(start + len)
+    }
+
+    /// Consume a name string.
+    fn name(&mut self) -> Result<NameString<'a>> {
+        let mut root = false;
+        let mut parents = 0;
+        if self.peek()? == ROOT_CHAR {
+            root = true;
+            self.pos += 1;
+        } else {
+            while self.peek()? == PARENT_PREFIX_CHAR {
+                parents += 1;
+                self.pos += 1;
+            }
+        }
+
+        let count = match self.peek()? {
+            NULL_NAME => {
+                self.pos += 1;
+                0
+            }
+            DUAL_NAME_PREFIX => {
+                self.pos += 1;
+                2
+            }
+            MULTI_NAME_PREFIX => {
+                self.pos += 1;
+                self.byte()? as usize
+            }
+            b'A'..=b'Z' | b'_' => 1,
+            _ => return Err(Error::BadName),
+        };
+
+        Ok(NameString { root, parents, segments: self.take(count * 4)? })
+    }
+
+    /// Consume a data object or a reference to one.
+    fn value(&mut self) -> Result<Value<'a>> {
+        // A name where data is expected is a reference
+        if matches!(self.peek()?, ROOT_CHAR | PARENT_PREFIX_CHAR |
+                DUAL_NAME_PREFIX | MULTI_NAME_PREFIX | b'A'..=b'Z' | b'_') {
+            return Ok(Value::Reference(self.name()?));
+        }
+
+        Ok(match self.byte()? {
+            ZERO_OP => Value::Integer(0),
+            ONE_OP => Value::Integer(1),
+            ONES_OP => Value::Integer(!0),
+            BYTE_PREFIX => Value::Integer(self.integer(1)?),
+            WORD_PREFIX => Value::Integer(self.integer(2)?),
+            DWORD_PREFIX => Value::Integer(self.integer(4)?),
+            QWORD_PREFIX => Value::Integer(self.integer(8)?),
+            STRING_PREFIX => {
+                let len = self.aml[self.pos..self.end].iter()
+                    .position(|&x| x == 0).ok_or(Error::Truncated)?;
+                let string = self.take(len)?;
+                self.pos += 1;
+                Value::String(string)
+            }
+            BUFFER_OP => {
+                let end = self.pkg_length()?;
+                let size = self.value()?.as_integer()
+                    .ok_or(Error::Unsupported)?;
+                let init = self.take(end.checked_sub(self.pos)
+                    .ok_or(Error::Truncated)?)?;
+                Value::Buffer(&init[..init.len().min(size as usize)])
+            }
+            op @ PACKAGE_OP | op @ VAR_PACKAGE_OP => {
+                let end = self.pkg_length()?;
+                let len = if op == PACKAGE_OP {
+                    self.byte()? as usize
+                } else {
+                    self.value()?.as_integer()
+                        .ok_or(Error::Unsupported)? as usize
+                };
+                let elements = self.take(end.checked_sub(self.pos)
+                    .ok_or(Error::Truncated)?)?;
+                Value::Package(Package { elements, len })
+            }
+            _ => return Err(Error::Unsupported),
+        })
+    }
+}
+
+/// The ACPI namespace built from the definition blocks we loaded.
+pub struct Namespace<'a> {
+    /// The AML of each definition block we loaded.
+    tables: FixedVec<&'a [u8], MAX_TABLES>,
+
+    /// Every object, the root first.
+    objects: FixedVec<Object, MAX_OBJECTS>,
+}
+
+impl<'a> Namespace<'a> {
+    /// Create a namespace with the root and the predefined scopes.
+    pub fn new() -> Self {
+        let mut namespace = Namespace {
+            tables: FixedVec::new(),
+            objects: FixedVec::new(),
+        };
+
+        namespace.objects.push(Object {
+            name: *b"\\___",
+            parent: NO_PARENT,
+            ..Object::default()
+        }).unwrap();
+        for &name in PREDEFINED_SCOPES.iter() {
+            namespace.objects.push(Object::new(name, ObjectKind::Scope, 0, 0,
+                0, 0)).unwrap();
+        }
+
+        namespace
+    }
+
+    /// Load the AML of a definition block (the DSDT or an SSDT, everything
+    /// after the table header) into the namespace.
+    ///
+    /// Statements we can't walk, and everything after them in the same
+    /// scope, are ignored. Scopes end at a known length, so the walk carries
+    /// on after the scope.
+    pub fn load(&mut self, aml: &'a [u8]) -> Result<()> {
+        let table = self.tables.len();
+        self.tables.push(aml).map_err(|_| Error::TooManyTables)?;
+        self.term_list(table, 0, 0, aml.len(), 0)
+    }
+
+    /// Walk the statements in `start..end` of definition block `table`,
+    /// declaring objects in the scope `scope`.
+    fn term_list(&mut self, table: usize, scope: usize, start: usize,
+            end: usize, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+
+        let mut cursor = Cursor::new(self.tables.entries()[table], start, end);
+        while cursor.pos < cursor.end {
+            match self.term(table, scope, &mut cursor, depth) {
+                Err(Error::Unsupported) => break,
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walk the statement at `cursor`, declaring objects in the scope
+    /// `scope`.
+    fn term(&mut self, table: usize, scope: usize, cursor: &mut Cursor<'a>,
+            depth: usize) -> Result<()> {
+        match cursor.byte()? {
+            NAME_OP => {
+                let name = cursor.name()?;
+                let start = cursor.pos;
+                cursor.value()?;
+                self.declare(table, scope, &name, ObjectKind::Name, start,
+                    cursor.pos)?;
+            }
+
+            SCOPE_OP => {
+                self.container(table, scope, cursor, ObjectKind::Scope, 0,
+                    depth)?;
+            }
+
+            METHOD_OP => {
+                let end = cursor.pkg_length()?;
+                let name = cursor.name()?;
+                let flags = cursor.byte()?;
+                self.declare(table, scope, &name, ObjectKind::Method {
+                    args: flags & 7,
+                    serialized: flags & 8 != 0,
+                }, cursor.pos, end)?;
+                cursor.pos = end;
+            }
+
+            // We don't evaluate conditions, skip the whole statement
+            IF_OP | ELSE_OP | WHILE_OP => {
+                cursor.pos = cursor.pkg_length()?;
+            }
+
+            ALIAS_OP => {
+                cursor.name()?;
+                cursor.name()?;
+            }
+
+            // A declaration of an object in another definition block
+            EXTERNAL_OP => {
+                cursor.name()?;
+                cursor.take(2)?;
+            }
+
+            NOOP_OP => {}
+
+            EXT_OP_PREFIX => match cursor.byte()? {
+                DEVICE_OP => {
+                    self.container(table, scope, cursor, ObjectKind::Device,
+                        0, depth)?;
+                }
+
+                PROCESSOR_OP => {
+                    // Followed by the processor ID and the P_BLK address and
+                    // length
+                    self.container(table, scope, cursor,
+                        ObjectKind::Processor, 6, depth)?;
+                }
+
+                POWER_RES_OP => {
+                    // Followed by the system level and resource order
+                    self.container(table, scope, cursor,
+                        ObjectKind::PowerResource, 3, depth)?;
+                }
+
+                THERMAL_ZONE_OP => {
+                    self.container(table, scope, cursor,
+                        ObjectKind::ThermalZone, 0, depth)?;
+                }
+
+                MUTEX_OP => {
+                    let name = cursor.name()?;
+                    cursor.byte()?;
+                    self.declare(table, scope, &name, ObjectKind::Other, 0, 0)?;
+                }
+
+                EVENT_OP => {
+                    let name = cursor.name()?;
+                    self.declare(table, scope, &name, ObjectKind::Other, 0, 0)?;
+                }
+
+                OP_REGION_OP => {
+                    // The offset and length are usually constants
+                    let name = cursor.name()?;
+                    cursor.byte()?;
+                    cursor.value()?;
+                    cursor.value()?;
+                    self.declare(table, scope, &name, ObjectKind::Other, 0, 0)?;
+                }
+
+                // We don't track field units
+                FIELD_OP | INDEX_FIELD_OP | BANK_FIELD_OP => {
+                    cursor.pos = cursor.pkg_length()?;
+                }
+
+                _ => return Err(Error::Unsupported),
+            },
+
+            _ => return Err(Error::Unsupported),
+        }
+
+        Ok(())
+    }
+
+    /// Declare the object at `cursor` which contains other objects, and walk
+    /// its contents. `skip` is the number of bytes between its name and its
+    /// contents.
+    fn container(&mut self, table: usize, scope: usize,
+            cursor: &mut Cursor<'a>, kind: ObjectKind, skip: usize,
+            depth: usize) -> Result<()> {
+        let end = cursor.pkg_length()?;
+        let name = cursor.name()?;
+        cursor.take(skip)?;
+
+        // Opening a scope refers to an existing object, found like any
+        // other reference
+        let obj = match self.resolve(scope, &name) {
+            Some(obj) if kind == ObjectKind::Scope => obj,
+            _ => self.declare(table, scope, &name, kind, cursor.pos, end)?,
+        };
+        self.term_list(table, obj, cursor.pos, end, depth + 1)?;
+        cursor.pos = end;
+        Ok(())
+    }
+
+    /// Declare the object `name` in the scope `scope`, returning its index.
+    /// Missing scopes on its path are created, and declaring an existing
+    /// object (such as opening a scope on a device) reuses it.
+    fn declare(&mut self, table: usize, scope: usize, name: &NameString,
+            kind: ObjectKind, start: usize, end: usize) -> Result<usize> {
+        let mut obj = if name.root { 0 } else { scope };
+        for _ in 0..name.parents {
+            obj = self.objects()[obj].parent().ok_or(Error::BadName)?;
+        }
+
+        // A name with no segments is the scope itself
+        let count = name.segments().count();
+        if count == 0 {
+            return Ok(obj);
+        }
+
+        for (ii, segment) in name.segments().enumerate() {
+            let last = ii == count - 1;
+            obj = match self.child(obj, segment) {
+                Some(child) => {
+                    // Record what a scope turned out to be
+                    if last && kind != ObjectKind::Scope {
+                        self.objects.entries_mut()[child] =
+                            Object::new(segment, kind, obj, table, start, end);
+                    }
+                    child
+                }
+                None => {
+                    let object = if last {
+                        Object::new(segment, kind, obj, table, start, end)
+                    } else {
+                        Object::new(segment, ObjectKind::Scope, obj, table,
+                            0, 0)
+                    };
+                    self.objects.push(object)
+                        .map_err(|_| Error::TooManyObjects)?;
+                    self.objects.len() - 1
+                }
+            };
+        }
+
+        Ok(obj)
+    }
+
+    /// Get every object, the root first.
+    pub fn objects(&self) -> &[Object] {
+        self.objects.entries()
+    }
+
+    /// Get the index of the child `name` of the object `parent`.
+    pub fn child(&self, parent: usize, name: [u8; 4]) -> Option<usize> {
+        self.objects().iter()
+            .position(|x| x.parent() == Some(parent) && x.name == name)
+    }
+
+    /// Get the indices of the children of the object `parent`.
+    pub fn children(&self, parent: usize)
+            -> impl Iterator<Item = usize> + '_ {
+        self.objects().iter().enumerate()
+            .filter(move |(_, x)| x.parent() == Some(parent))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Resolve `name` relative to the object `scope`. A single name segment
+    /// is searched for in `scope` and then each of its parents.
+    pub fn resolve(&self, scope: usize, name: &NameString) -> Option<usize> {
+        let mut obj = if name.root { 0 } else { scope };
+        for _ in 0..name.parents {
+            obj = self.objects().get(obj)?.parent()?;
+        }
+
+        let mut segments = name.segments();
+        if !name.root && name.parents == 0 && name.segments.len() == 4 {
+            let segment = segments.next()?;
+            let mut scope = Some(obj);
+            while let Some(obj) = scope {
+                if let Some(found) = self.child(obj, segment) {
+                    return Some(found);
+                }
+                scope = self.objects()[obj].parent();
+            }
+            return None;
+        }
+
+        segments.try_fold(obj, |obj, segment| self.child(obj, segment))
+    }
+
+    /// Get the index of the object with the absolute `path`, such as
+    /// `\_SB.PCI0._PRT`. Segments shorter than 4 characters are padded with
+    /// `_`, like in ASL.
+    pub fn lookup(&self, path: &str) -> Option<usize> {
+        let path = path.strip_prefix('\\').unwrap_or(path);
+        if path.is_empty() {
+            return Some(0);
+        }
+
+        path.split('.').try_fold(0, |obj, segment| {
+            if segment.is_empty() || segment.len() > 4 {
+                return None;
+            }
+            let mut name = *b"____";
+            name[..segment.len()].copy_from_slice(segment.as_bytes());
+            self.child(obj, name)
+        })
+    }
+
+    /// Evaluate the object with the absolute `path`, such as
+    /// `\_SB.PCI0._PRT`.
+    pub fn evaluate(&self, path: &str) -> Result<Value<'a>> {
+        let obj = self.lookup(path).ok_or(Error::NotFound)?;
+        self.evaluate_object(obj)
+    }
+
+    /// Evaluate the object at index `obj`. Names evaluate to their value and
+    /// methods without arguments to the constant, or the value of the
+    /// object, they start by returning.
+    pub fn evaluate_object(&self, obj: usize) -> Result<Value<'a>> {
+        let mut obj = obj;
+        for _ in 0..MAX_EVAL_DEPTH {
+            let object = self.objects().get(obj).ok_or(Error::NotFound)?;
+            let mut cursor = Cursor::new(
+                self.tables.entries()[object.table as usize],
+                object.start as usize, object.end as usize);
+
+            let value = match object.kind {
+                ObjectKind::Name => return cursor.value(),
+                ObjectKind::Method { args: 0, .. } => {
+                    while cursor.peek()? == NOOP_OP {
+                        cursor.pos += 1;
+                    }
+                    if cursor.byte()? != RETURN_OP {
+                        return Err(Error::Unsupported);
+                    }
+                    cursor.value()?
+                }
+                _ => return Err(Error::Unsupported),
+            };
+
+            // A method returning a named object returns its value
+            obj = match value {
+                Value::Reference(name) => {
+                    self.resolve(obj, &name).ok_or(Error::NotFound)?
+                }
+                _ => return Ok(value),
+            };
+        }
+
+        Err(Error::TooDeep)
+    }
+
+    /// Get the absolute path of the object at index `obj`, for display.
+    pub fn path(&self, obj: usize) -> Path<'_, 'a> {
+        Path(self, obj)
+    }
+}
+
+impl Default for Namespace<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The absolute path of an object in a namespace, which implements
+/// `Display`.
+pub struct Path<'n, 'a>(&'n Namespace<'a>, usize);
+
+impl Path<'_, '_> {
+    /// Write the path of the object at index `obj`, its parents first.
+    fn write(&self, f: &mut fmt::Formatter, obj: usize) -> fmt::Result {
+        let object = self.0.objects().get(obj).ok_or(fmt::Error)?;
+        match object.parent() {
+            None => return write!(f, "\\"),
+            Some(0) => write!(f, "\\")?,
+            Some(parent) => {
+                self.write(f, parent)?;
+                write!(f, ".")?;
+            }
+        }
+        write!(f, "{}", core::str::from_utf8(&object.name).unwrap_or("????"))
+    }
+}
+
+impl fmt::Display for Path<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, self.1)
+    }
+}
@@ -0,0 +1,386 @@
+//! Tests for the AML namespace walker, against definition blocks assembled by
+//! hand from the ASL in `tests/data/aml`, and the DSDT of a real machine.
+
+use super::*;
+use std::path::Path;
+
+/// Where the ASL sources and captured tables live.
+const DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+
+/// A q35 style DSDT with PCI interrupt routing, `q35-dsdt.asl`.
+///
+/// ```text
+/// Scope (\_SB)
+/// {
+///     Device (PCI0)
+///     {
+///         Name (_HID, EisaId ("PNP0A08"))
+///         Name (_UID, Zero)
+///         OperationRegion (PCST, SystemIO, 0xAE00, 0x08)
+///         Field (PCST, DWordAcc, NoLock, WriteAsZeros)
+///         {
+///             PCIU, 32,
+///             PCID, 32
+///         }
+///         Name (PRTP, Package (0x02)
+///         {
+///             Package (0x04) { 0xFFFF, Zero, LNKE, Zero },
+///             Package (0x04) { 0x0001FFFF, One, \_SB.LNKF, Zero }
+///         })
+///         Method (_PRT, 0, NotSerialized)
+///         {
+///             Return (PRTP)
+///         }
+///         Method (_STA, 0, NotSerialized)
+///         {
+///             If (PCIU)
+///             {
+///                 Return (0x0F)
+///             }
+///             Return (Zero)
+///         }
+///     }
+///     Device (LNKE)
+///     {
+///         Name (_HID, EisaId ("PNP0C0F"))
+///         Name (_UID, 0x05)
+///         Method (_STA, 0, NotSerialized)
+///         {
+///             Return (0x0B)
+///         }
+///     }
+///     Device (LNKF)
+///     {
+///         Name (_HID, EisaId ("PNP0C0F"))
+///         Name (_UID, 0x06)
+///         Name (_STR, "Link F")
+///         Name (_PRS, ResourceTemplate ()
+///         {
+///             IRQ (Level, ActiveLow, Shared) {5,10,11}
+///         })
+///     }
+/// }
+/// Name (\_S5, Package (0x04) { 0x05, 0x05, Zero, Zero })
+/// ```
+const DSDT: &[u8] = &[
+    0x10, 0x49, 0x0d, 0x5c, 0x5f, 0x53, 0x42, 0x5f, 0x5b, 0x82, 0x4a, 0x07,
+    0x50, 0x43, 0x49, 0x30, 0x08, 0x5f, 0x48, 0x49, 0x44, 0x0c, 0x41, 0xd0,
+    0x0a, 0x08, 0x08, 0x5f, 0x55, 0x49, 0x44, 0x00, 0x5b, 0x80, 0x50, 0x43,
+    0x53, 0x54, 0x01, 0x0b, 0x00, 0xae, 0x0a, 0x08, 0x5b, 0x81, 0x10, 0x50,
+    0x43, 0x53, 0x54, 0x43, 0x50, 0x43, 0x49, 0x55, 0x20, 0x50, 0x43, 0x49,
+    0x44, 0x20, 0x08, 0x50, 0x52, 0x54, 0x50, 0x12, 0x22, 0x02, 0x12, 0x0b,
+    0x04, 0x0b, 0xff, 0xff, 0x00, 0x4c, 0x4e, 0x4b, 0x45, 0x00, 0x12, 0x13,
+    0x04, 0x0c, 0xff, 0xff, 0x01, 0x00, 0x01, 0x5c, 0x2e, 0x5f, 0x53, 0x42,
+    0x5f, 0x4c, 0x4e, 0x4b, 0x46, 0x00, 0x14, 0x0b, 0x5f, 0x50, 0x52, 0x54,
+    0x00, 0xa4, 0x50, 0x52, 0x54, 0x50, 0x14, 0x11, 0x5f, 0x53, 0x54, 0x41,
+    0x00, 0xa0, 0x08, 0x50, 0x43, 0x49, 0x55, 0xa4, 0x0a, 0x0f, 0xa4, 0x00,
+    0x5b, 0x82, 0x20, 0x4c, 0x4e, 0x4b, 0x45, 0x08, 0x5f, 0x48, 0x49, 0x44,
+    0x0c, 0x41, 0xd0, 0x0c, 0x0f, 0x08, 0x5f, 0x55, 0x49, 0x44, 0x0a, 0x05,
+    0x14, 0x09, 0x5f, 0x53, 0x54, 0x41, 0x00, 0xa4, 0x0a, 0x0b, 0x5b, 0x82,
+    0x32, 0x4c, 0x4e, 0x4b, 0x46, 0x08, 0x5f, 0x48, 0x49, 0x44, 0x0c, 0x41,
+    0xd0, 0x0c, 0x0f, 0x08, 0x5f, 0x55, 0x49, 0x44, 0x0a, 0x06, 0x08, 0x5f,
+    0x53, 0x54, 0x52, 0x0d, 0x4c, 0x69, 0x6e, 0x6b, 0x20, 0x46, 0x00, 0x08,
+    0x5f, 0x50, 0x52, 0x53, 0x11, 0x09, 0x0a, 0x06, 0x23, 0x20, 0x0c, 0x18,
+    0x79, 0x00, 0x08, 0x5c, 0x5f, 0x53, 0x35, 0x5f, 0x12, 0x08, 0x04, 0x0a,
+    0x05, 0x0a, 0x05, 0x00, 0x00,
+];
+
+/// An SSDT adding a slot to the PCI root bridge of `DSDT`, and a processor,
+/// `q35-ssdt.asl`.
+///
+/// ```text
+/// External (\_SB.PCI0, DeviceObj)
+/// Scope (\_SB.PCI0)
+/// {
+///     Device (S08)
+///     {
+///         Name (_ADR, 0x00010000)
+///         Name (_SUN, One)
+///     }
+/// }
+/// Scope (\_PR)
+/// {
+///     Processor (CPU0, 0x00, 0x00000000, 0x00)
+///     {
+///         Method (_STA, 0, NotSerialized)
+///         {
+///             Return (0x0F)
+///         }
+///     }
+/// }
+/// ```
+const SSDT: &[u8] = &[
+    0x15, 0x5c, 0x2e, 0x5f, 0x53, 0x42, 0x5f, 0x50, 0x43, 0x49, 0x30, 0x06,
+    0x00, 0x10, 0x22, 0x5c, 0x2e, 0x5f, 0x53, 0x42, 0x5f, 0x50, 0x43, 0x49,
+    0x30, 0x5b, 0x82, 0x15, 0x53, 0x30, 0x38, 0x5f, 0x08, 0x5f, 0x41, 0x44,
+    0x52, 0x0c, 0x00, 0x00, 0x01, 0x00, 0x08, 0x5f, 0x53, 0x55, 0x4e, 0x01,
+    0x10, 0x1d, 0x5c, 0x5f, 0x50, 0x52, 0x5f, 0x5b, 0x83, 0x15, 0x43, 0x50,
+    0x55, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x14, 0x09, 0x5f, 0x53,
+    0x54, 0x41, 0x00, 0xa4, 0x0a, 0x0f,
+];
+
+/// Load `DSDT` and `SSDT` into a namespace.
+fn namespace() -> Namespace<'static> {
+    let mut namespace = Namespace::new();
+    namespace.load(DSDT).unwrap();
+    namespace.load(SSDT).unwrap();
+    namespace
+}
+
+/// Read the AML of the definition block in `path`, without its header.
+fn definition_block(path: &Path) -> Vec<u8> {
+    let table = std::fs::read(path).unwrap();
+    assert!(table.len() >= 36, "{} is truncated", path.display());
+    table[36..].to_vec()
+}
+
+#[test]
+fn walk() {
+    let namespace = namespace();
+
+    let pci0 = namespace.lookup("\\_SB.PCI0").unwrap();
+    assert_eq!(namespace.objects()[pci0].kind, ObjectKind::Device);
+    assert_eq!(namespace.path(pci0).to_string(), "\\_SB_.PCI0");
+
+    // Field units aren't tracked, but the region is
+    assert_eq!(namespace.objects()[namespace.lookup("\\_SB.PCI0.PCST")
+        .unwrap()].kind, ObjectKind::Other);
+    assert!(namespace.lookup("\\_SB.PCI0.PCIU").is_none());
+
+    // The SSDT added to the DSDT's device, and to a predefined scope
+    let children: Vec<String> = namespace.children(pci0)
+        .map(|x| namespace.path(x).to_string()).collect();
+    assert_eq!(children, ["\\_SB_.PCI0._HID", "\\_SB_.PCI0._UID",
+        "\\_SB_.PCI0.PCST", "\\_SB_.PCI0.PRTP", "\\_SB_.PCI0._PRT",
+        "\\_SB_.PCI0._STA", "\\_SB_.PCI0.S08_"]);
+    let cpu0 = namespace.lookup("\\_PR.CPU0").unwrap();
+    assert_eq!(namespace.objects()[cpu0].kind, ObjectKind::Processor);
+    assert_eq!(namespace.lookup("\\_PR_.CPU0"), Some(cpu0));
+    assert_eq!(namespace.lookup("\\"), Some(0));
+    assert!(namespace.lookup("\\_SB.PCI00").is_none());
+}
+
+#[test]
+fn evaluate_names() {
+    let namespace = namespace();
+
+    assert_eq!(namespace.evaluate("\\_SB.PCI0._HID"),
+        Ok(Value::Integer(0x080a_d041)));
+    assert_eq!(namespace.evaluate("\\_SB.PCI0._UID"), Ok(Value::Integer(0)));
+    assert_eq!(namespace.evaluate("\\_SB.LNKF._STR"),
+        Ok(Value::String(b"Link F")));
+    assert_eq!(namespace.evaluate("\\_SB.LNKF._PRS"),
+        Ok(Value::Buffer(&[0x23, 0x20, 0x0c, 0x18, 0x79, 0x00])));
+    assert_eq!(namespace.evaluate("\\_SB.PCI0.S08._ADR"),
+        Ok(Value::Integer(0x1_0000)));
+
+    let s5 = namespace.evaluate("\\_S5").unwrap().as_package().unwrap();
+    assert_eq!(s5.len(), 4);
+    assert_eq!(s5.iter().map(|x| x.as_integer().unwrap())
+        .collect::<Vec<_>>(), [5, 5, 0, 0]);
+}
+
+#[test]
+fn evaluate_methods() {
+    let namespace = namespace();
+
+    assert_eq!(namespace.evaluate("\\_SB.LNKE._STA"),
+        Ok(Value::Integer(0xb)));
+    assert_eq!(namespace.evaluate("\\_PR.CPU0._STA"),
+        Ok(Value::Integer(0xf)));
+
+    // We don't evaluate conditions
+    assert_eq!(namespace.evaluate("\\_SB.PCI0._STA"),
+        Err(Error::Unsupported));
+    assert_eq!(namespace.evaluate("\\_SB.PCI0"), Err(Error::Unsupported));
+    assert_eq!(namespace.evaluate("\\_SB.PCI0._CRS"), Err(Error::NotFound));
+}
+
+#[test]
+fn evaluate_prt() {
+    let namespace = namespace();
+    let pci0 = namespace.lookup("\\_SB.PCI0").unwrap();
+
+    // The method returns the package named PRTP
+    let prt = namespace.evaluate("\\_SB.PCI0._PRT").unwrap()
+        .as_package().unwrap();
+    assert_eq!(prt.len(), 2);
+
+    let mut routes = Vec::new();
+    for entry in prt.iter() {
+        let entry = entry.as_package().unwrap();
+        let address = entry.get(0).unwrap().as_integer().unwrap();
+        let pin = entry.get(1).unwrap().as_integer().unwrap();
+
+        // The source is a link device, relative to the PCI root bridge
+        let source = match entry.get(2).unwrap() {
+            Value::Reference(name) => namespace.resolve(pci0, &name).unwrap(),
+            value => panic!("unexpected source {:?}", value),
+        };
+        let uid = namespace.evaluate_object(namespace.child(source, *b"_UID")
+            .unwrap()).unwrap().as_integer().unwrap();
+        routes.push((address, pin, namespace.path(source).to_string(), uid));
+    }
+
+    assert_eq!(routes, [
+        (0xffff, 0, "\\_SB_.LNKE".to_string(), 5),
+        (0x1_ffff, 1, "\\_SB_.LNKF".to_string(), 6),
+    ]);
+}
+
+#[test]
+fn resolve() {
+    let namespace = namespace();
+    let lnke = namespace.lookup("\\_SB.LNKE").unwrap();
+    let prtp = namespace.lookup("\\_SB.PCI0.PRTP").unwrap();
+
+    // Single segments are searched for up the tree, paths are not
+    let name = NameString { root: false, parents: 0, segments: b"LNKF" };
+    assert_eq!(namespace.resolve(lnke, &name),
+        namespace.lookup("\\_SB.LNKF"));
+    let name = NameString { root: false, parents: 0, segments: b"PRTP" };
+    assert_eq!(namespace.resolve(lnke, &name), None);
+    let name = NameString { root: false, parents: 1, segments: b"PCI0PRTP" };
+    assert_eq!(namespace.resolve(lnke, &name), Some(prtp));
+    assert_eq!(name.to_string(), "^PCI0.PRTP");
+    let name = NameString { root: true, parents: 0, segments: b"" };
+    assert_eq!(namespace.resolve(prtp, &name), Some(0));
+}
+
+#[test]
+fn unsupported_statements() {
+    // Scope (\_SB)
+    // {
+    //     Device (DEV0)
+    //     {
+    //         Store (One, Local0)
+    //         Name (HIDN, One)
+    //     }
+    //     Name (SEEN, One)
+    // }
+    let aml = [
+        0x10, 0x1c, 0x5c, 0x5f, 0x53, 0x42, 0x5f, 0x5b, 0x82, 0x0e, 0x44, 0x45,
+        0x56, 0x30, 0x70, 0x01, 0x60, 0x08, 0x48, 0x49, 0x44, 0x4e, 0x01, 0x08,
+        0x53, 0x45, 0x45, 0x4e, 0x01,
+    ];
+    let mut namespace = Namespace::new();
+    namespace.load(&aml).unwrap();
+
+    // The walk stops at the statement, and carries on after the device
+    assert!(namespace.lookup("\\_SB.DEV0").is_some());
+    assert!(namespace.lookup("\\_SB.DEV0.HIDN").is_none());
+    assert_eq!(namespace.evaluate("\\_SB.SEEN"), Ok(Value::Integer(1)));
+}
+
+#[test]
+fn truncated() {
+    // A scope longer than the definition block
+    let mut namespace = Namespace::new();
+    assert_eq!(namespace.load(&DSDT[..100]), Err(Error::Truncated));
+
+    // A package whose elements are cut off stops iterating
+    //
+    // Name (PKG, Package (0x03) { One, 0x0A... })
+    let aml = [0x08, b'P', b'K', b'G', b'_', 0x12, 0x04, 0x03, 0x01, 0x0a];
+    let mut namespace = Namespace::new();
+    namespace.load(&aml).unwrap();
+    let package = namespace.evaluate("\\PKG").unwrap().as_package().unwrap();
+    assert_eq!(package.len(), 3);
+    assert_eq!(package.iter().collect::<Vec<_>>(), [Value::Integer(1)]);
+}
+
+#[test]
+fn too_many_objects() {
+    let mut aml = Vec::new();
+    for ii in 0..MAX_OBJECTS {
+        aml.extend_from_slice(format!("\x08N{:03}\x00", ii).as_bytes());
+    }
+    let mut namespace = Namespace::new();
+    assert_eq!(namespace.load(&aml), Err(Error::TooManyObjects));
+}
+
+#[test]
+fn too_deep() {
+    // Scopes nested deeper than we walk
+    let mut aml = Vec::new();
+    for depth in 0..=MAX_DEPTH {
+        let len = (MAX_DEPTH - depth) * 7 + 6;
+        aml.extend_from_slice(&[0x10, 0x40 | (len & 0xf) as u8,
+            (len >> 4) as u8, b'S', b'C', b'P', b'_']);
+    }
+    let mut namespace = Namespace::new();
+    assert_eq!(namespace.load(&aml), Err(Error::TooDeep));
+
+    // Methods returning each other
+    //
+    // Method (LOOP, 0, NotSerialized) { Return (POOL) }
+    // Method (POOL, 0, NotSerialized) { Return (LOOP) }
+    let aml = [
+        0x14, 0x0b, 0x4c, 0x4f, 0x4f, 0x50, 0x00, 0xa4, 0x50, 0x4f, 0x4f, 0x4c,
+        0x14, 0x0b, 0x50, 0x4f, 0x4f, 0x4c, 0x00, 0xa4, 0x4c, 0x4f, 0x4f, 0x50,
+    ];
+    let mut namespace = Namespace::new();
+    namespace.load(&aml).unwrap();
+    assert_eq!(namespace.evaluate("\\LOOP"), Err(Error::TooDeep));
+}
+
+#[test]
+fn too_many_tables() {
+    let mut namespace = Namespace::new();
+    for _ in 0..MAX_TABLES {
+        namespace.load(&[]).unwrap();
+    }
+    assert_eq!(namespace.load(&[]), Err(Error::TooManyTables));
+}
+
+
+#[test]
+fn firecracker() {
+    // The DSDT of a Firecracker guest, as compiled by its iasl
+    let aml = definition_block(&Path::new(DATA)
+        .join("firecracker/dsdt.dat"));
+    let mut namespace = Namespace::new();
+    namespace.load(&aml).unwrap();
+
+    let sb = namespace.lookup("\\_SB").unwrap();
+    let devices: Vec<String> = namespace.children(sb)
+        .filter(|&x| namespace.objects()[x].kind == ObjectKind::Device)
+        .map(|x| namespace.path(x).to_string()).collect();
+    assert_eq!(devices, ["\\_SB_.VGEN", "\\_SB_.VCLK", "\\_SB_.GED_",
+        "\\_SB_.PC00", "\\_SB_.COM1", "\\_SB_.PS2_"]);
+
+    // The PCI root bridge, with a hotplug slot per device
+    assert_eq!(namespace.evaluate("\\_SB.PC00._HID"),
+        Ok(Value::Integer(0x080a_d041)));
+    assert_eq!(namespace.evaluate("\\_SB.PC00._SEG"), Ok(Value::Integer(0)));
+    let pc00 = namespace.lookup("\\_SB.PC00").unwrap();
+    let slots = namespace.children(pc00)
+        .filter(|&x| namespace.objects()[x].kind == ObjectKind::Device)
+        .count();
+    assert_eq!(slots, 32);
+    assert_eq!(namespace.evaluate("\\_SB.PC00.S001._ADR"),
+        Ok(Value::Integer(0x1_0000)));
+
+    // One route per slot
+    let prt = namespace.evaluate("\\_SB.PC00._PRT").unwrap()
+        .as_package().unwrap();
+    assert_eq!(prt.len(), 32);
+    for (slot, entry) in prt.iter().enumerate() {
+        let entry = entry.as_package().unwrap();
+        assert_eq!(entry.get(0).unwrap().as_integer(),
+            Some((slot as u64) << 16 | 0xffff));
+        assert_eq!(entry.get(1).unwrap().as_integer(), Some(0));
+    }
+
+    // The serial port and a method we can run
+    assert_eq!(namespace.evaluate("\\_SB.COM1._HID"),
+        Ok(Value::Integer(0x0105_d041)));
+    assert_eq!(namespace.evaluate("\\_SB.COM1._DDN"),
+        Ok(Value::String(b"COM1")));
+    assert_eq!(namespace.evaluate("\\_SB.VCLK._STA"), Ok(Value::Integer(0xf)));
+
+    // The GED's event handler does real work
+    assert_eq!(namespace.evaluate("\\_SB.GED._EVT"),
+        Err(Error::Unsupported));
+    assert!(namespace.lookup("\\_S5").is_none());
+}
@@ -0,0 +1,77 @@
+//! Boot Error Record Table (BERT) parsing, which points at the errors the
+//! firmware recorded on the previous boot. A fatal error there, such as an
+//! uncorrected machine check, is what reset the machine, rather than
+//! anything we did.
+
+use super::cper::ErrorStatus;
+use super::{Error, Result, TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The boot error region described by the BERT.
+#[derive(Clone, Copy, Debug)]
+pub struct Bert {
+    /// Physical address of the boot error region.
+    pub region: u64,
+
+    /// Length of the boot error region in bytes.
+    pub region_length: u32,
+}
+
+/// In-memory representation of the BERT payload.
+#[repr(C, packed)]
+struct RawBert {
+    /// Length of the boot error region
+    region_length: u32,
+
+    /// Physical address of the boot error region
+    region: u64,
+}
+
+impl Bert {
+    /// Process the payload of the BERT (everything after the table header) at
+    /// `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the BERT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Bert);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let bert = slice.consume::<RawBert>().map_err(|_| E)?;
+        if slice.len() != 0 {
+            return Err(E);
+        }
+
+        Ok(Bert {
+            region: bert.region,
+            region_length: bert.region_length,
+        })
+    }
+
+    /// The error status blocks in the boot error region, read through `mem`.
+    /// Fails if the region can't be read.
+    pub fn errors<'a, M: MemoryReader>(&self, mem: &'a M)
+            -> Result<BootErrors<'a>> {
+        let region = mem.bytes(PhysAddr(self.region),
+            self.region_length as usize)
+            .map_err(|_| Error::Unreadable(self.region))?;
+        Ok(BootErrors(region))
+    }
+}
+
+/// An iterator over the error status blocks of a boot error region.
+/// Iteration stops at the first block which doesn't report an error or
+/// doesn't fit in the region.
+pub struct BootErrors<'a>(&'a [u8]);
+
+impl<'a> Iterator for BootErrors<'a> {
+    type Item = ErrorStatus<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let status = ErrorStatus::parse(self.0)
+            .filter(|status| status.is_active())?;
+        self.0 = &self.0[status.length()..];
+        Some(status)
+    }
+}
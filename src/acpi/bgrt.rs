@@ -0,0 +1,84 @@
+//! Boot Graphics Resource Table (BGRT) parsing, the logo the firmware drew
+//! while booting.
+
+use super::{Error, Result, TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// BGRT status: the image is on the screen.
+const DISPLAYED: u8 = 1 << 0;
+
+/// The boot logo described by the BGRT.
+#[derive(Clone, Copy, Debug)]
+pub struct Bgrt {
+    /// The version of the BGRT, 1.
+    pub version: u16,
+
+    /// The image is still on the screen.
+    pub displayed: bool,
+
+    /// The clockwise rotation of the image relative to the display, in
+    /// degrees.
+    pub orientation: u16,
+
+    /// The image type, 0 is a bitmap.
+    pub image_type: u8,
+
+    /// Physical address of the image.
+    pub image_address: u64,
+
+    /// X offset of the image's upper left corner on the screen.
+    pub offset_x: u32,
+
+    /// Y offset of the image's upper left corner on the screen.
+    pub offset_y: u32,
+}
+
+/// In-memory representation of the BGRT payload.
+#[repr(C, packed)]
+struct RawBgrt {
+    /// Version, 1
+    version: u16,
+
+    /// Bit 0 is displayed, bits 2:1 are the orientation offset
+    status: u8,
+
+    /// Image type, 0 is a bitmap
+    image_type: u8,
+
+    /// Physical address of the image
+    image_address: u64,
+
+    /// X offset of the image
+    offset_x: u32,
+
+    /// Y offset of the image
+    offset_y: u32,
+}
+
+impl Bgrt {
+    /// Process the payload of the BGRT (everything after the table header) at
+    /// `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the BGRT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Bgrt);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let bgrt = slice.consume::<RawBgrt>().map_err(|_| E)?;
+        if slice.len() != 0 {
+            return Err(E);
+        }
+
+        Ok(Bgrt {
+            version: bgrt.version,
+            displayed: bgrt.status & DISPLAYED != 0,
+            orientation: ((bgrt.status >> 1) & 3) as u16 * 90,
+            image_type: bgrt.image_type,
+            image_address: bgrt.image_address,
+            offset_x: bgrt.offset_x,
+            offset_y: bgrt.offset_y,
+        })
+    }
+}
@@ -0,0 +1,349 @@
+//! A catalog of every ACPI table the firmware gave us, so tables can be
+//! looked up by signature after the XSDT has been walked.
+
+use core::fmt;
+use core::mem::size_of;
+
+use super::{Error, Result, Table, TableType};
+use super::facs::Facs;
+use crate::fixed_vec::FixedVec;
+use crate::mm::physmem::{MemoryReader, PhysAddr};
+
+/// The maximum number of tables we can track.
+pub const MAX_TABLES: usize = 64;
+
+/// The most bytes of a skipped table we hex dump. Its length comes from a
+/// header which may not have validated, and could be anything up to 4 GiB.
+const MAX_SKIPPED_DUMP: u32 = 64 * 1024;
+
+/// Whether a table could be used.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum TableStatus {
+    /// The table validated and, if we understand it, decoded.
+    #[default]
+    Ok,
+
+    /// The table failed validation or decoding and was skipped, which the
+    /// lenient policy allows for non-essential tables.
+    Skipped(Error),
+}
+
+/// An ACPI table we found.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TableInfo {
+    /// The table signature.
+    pub signature: [u8; 4],
+
+    /// Physical address of the table, including the header.
+    pub addr: u64,
+
+    /// Length of the table, including the header.
+    pub length: u32,
+
+    /// The table revision.
+    pub revision: u8,
+
+    /// OEM ID.
+    pub oem_id: [u8; 6],
+
+    /// OEM table ID.
+    pub oem_table_id: [u8; 8],
+
+    /// OEM revision.
+    pub oem_revision: u32,
+
+    /// Vendor ID of the utility which created the table.
+    pub creator_id: [u8; 4],
+
+    /// Revision of the utility which created the table.
+    pub creator_revision: u32,
+
+    /// Whether the table could be used.
+    pub status: TableStatus,
+}
+
+impl TableInfo {
+    /// Create the catalog entry for the validated table `header` at `addr`.
+    fn new(addr: PhysAddr, header: &Table) -> Self {
+        TableInfo {
+            signature: header.signature,
+            addr: addr.0,
+            length: header.length,
+            revision: header.revision,
+            oem_id: header.oemid,
+            oem_table_id: header.oem_table_id.to_le_bytes(),
+            oem_revision: header.oem_revision,
+            creator_id: header.creator_id.to_le_bytes(),
+            creator_revision: header.creator_revision,
+            status: TableStatus::Ok,
+        }
+    }
+
+    /// The type of the table.
+    pub fn typ(&self) -> TableType {
+        TableType::from(self.signature)
+    }
+
+    /// Returns `true` if the table was skipped.
+    pub fn skipped(&self) -> bool {
+        matches!(self.status, TableStatus::Skipped(_))
+    }
+
+    /// Get a hex dump of the table in the `acpidump` text format, which
+    /// `acpixtract` turns back into binary tables. The table is read through
+    /// `mem`.
+    pub fn hex_dump<'a, M: MemoryReader>(&self, mem: &'a M) -> HexDump<'a, M> {
+        HexDump(*self, mem)
+    }
+
+    /// The address and size of the table payload, everything after the
+    /// header.
+    pub fn payload(&self) -> (PhysAddr, usize) {
+        (PhysAddr(self.addr + size_of::<Table>() as u64),
+            (self.length as usize).saturating_sub(size_of::<Table>()))
+    }
+}
+
+/// Write `bytes` as ASCII, replacing anything unprintable, padded to `width`.
+fn ascii(f: &mut fmt::Formatter, bytes: &[u8], width: usize) -> fmt::Result {
+    for &byte in bytes {
+        let chr = if byte == b' ' || byte.is_ascii_graphic() {
+            byte as char
+        } else if byte == 0 {
+            ' '
+        } else {
+            '?'
+        };
+        write!(f, "{}", chr)?;
+    }
+    for _ in bytes.len()..width {
+        write!(f, " ")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for TableInfo {
+    /// Format the table like a line of `acpidump -s`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "  ")?;
+        ascii(f, &self.signature, 4)?;
+        write!(f, " 0x{:016X} {:06X}", self.addr, self.length)?;
+
+        // The FACS has no header beyond its signature and length
+        if &self.signature == b"FACS" {
+            return Ok(());
+        }
+
+        write!(f, " (v{:02} ", self.revision)?;
+        ascii(f, &self.oem_id, 6)?;
+
+        // The RSDP only has an OEM ID
+        if &self.signature != b"RSDP" {
+            write!(f, " ")?;
+            ascii(f, &self.oem_table_id, 8)?;
+            write!(f, " {:08X} ", self.oem_revision)?;
+            ascii(f, &self.creator_id, 4)?;
+            write!(f, " {:08X}", self.creator_revision)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A table formatted as an `acpidump` hex dump, see `TableInfo::hex_dump`.
+pub struct HexDump<'a, M: MemoryReader>(TableInfo, &'a M);
+
+impl<'a, M: MemoryReader> fmt::Display for HexDump<'a, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let table = &self.0;
+
+        writeln!(f, "{} @ 0x{:016X}",
+            core::str::from_utf8(&table.signature).unwrap_or("????"),
+            table.addr)?;
+
+        // Don't trust the length of a skipped table
+        let length = if table.skipped() {
+            core::cmp::min(table.length, MAX_SKIPPED_DUMP)
+        } else {
+            table.length
+        };
+
+        for offset in (0..length).step_by(16) {
+            // Read this line of the table, a skipped table may run off the
+            // end of memory so stop there
+            let len = core::cmp::min(16, length - offset) as usize;
+            let line = match self.1.bytes(
+                    PhysAddr(table.addr + offset as u64), len) {
+                Ok(line) => line,
+                Err(_) if table.skipped() => break,
+                Err(_) => return Err(fmt::Error),
+            };
+
+            write!(f, "    {:04X}:", offset)?;
+            for byte in line {
+                write!(f, " {:02X}", byte)?;
+            }
+            for _ in len..16 {
+                write!(f, "   ")?;
+            }
+
+            write!(f, "  ")?;
+            for &byte in line {
+                let chr = if byte == b' ' || byte.is_ascii_graphic() {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(f, "{}", chr)?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f)
+    }
+}
+
+/// Every ACPI table we found, in the order we found them.
+#[derive(Clone, Copy, Debug)]
+pub struct AcpiTables {
+    /// The tables.
+    tables: FixedVec<TableInfo, MAX_TABLES>,
+}
+
+impl AcpiTables {
+    /// Create an empty catalog.
+    pub fn new() -> Self {
+        AcpiTables {
+            tables: FixedVec::new(),
+        }
+    }
+
+    /// Add the RSDP at `addr`, with the ACPI `revision`, `oem_id` and
+    /// structure `length`.
+    pub(super) fn add_rsdp(&mut self, addr: PhysAddr, revision: u8,
+            oem_id: [u8; 6], length: u32) -> Result<()> {
+        self.tables.push(TableInfo {
+            signature: *b"RSDP",
+            addr: addr.0,
+            length,
+            revision,
+            oem_id,
+            ..TableInfo::default()
+        }).map_err(|_| Error::TooManyTables)
+    }
+
+    /// Add the validated FACS at `addr`. It has no standard header, so its
+    /// version stands in for the revision.
+    pub(super) fn add_facs(&mut self, addr: PhysAddr, facs: &Facs)
+            -> Result<()> {
+        self.tables.push(TableInfo {
+            signature: *b"FACS",
+            addr: addr.0,
+            length: facs.length,
+            revision: facs.version,
+            ..TableInfo::default()
+        }).map_err(|_| Error::TooManyTables)
+    }
+
+    /// Add the validated table `header` at `addr`.
+    pub(super) fn add(&mut self, addr: PhysAddr, header: &Table) -> Result<()> {
+        self.tables.push(TableInfo::new(addr, header))
+            .map_err(|_| Error::TooManyTables)
+    }
+
+    /// Add the table at `addr` which failed validation with `err`. `header`
+    /// is its unvalidated header, `None` if even that couldn't be read.
+    pub(super) fn add_skipped(&mut self, addr: PhysAddr, header: Option<&Table>,
+            err: Error) -> Result<()> {
+        let info = match header {
+            Some(header) => TableInfo::new(addr, header),
+            None => TableInfo {
+                signature: *b"????",
+                addr: addr.0,
+                ..TableInfo::default()
+            },
+        };
+
+        self.tables.push(TableInfo {
+            status: TableStatus::Skipped(err),
+            ..info
+        }).map_err(|_| Error::TooManyTables)
+    }
+
+    /// Mark the table at index `idx` as skipped because it failed decoding
+    /// with `err`.
+    pub(super) fn skip(&mut self, idx: usize, err: Error) {
+        if let Some(table) = self.tables.entries_mut().get_mut(idx) {
+            table.status = TableStatus::Skipped(err);
+        }
+    }
+
+    /// Get every table.
+    pub fn tables(&self) -> &[TableInfo] {
+        self.tables.entries()
+    }
+
+    /// Find the first usable table with `signature`.
+    pub fn find(&self, signature: [u8; 4]) -> Option<&TableInfo> {
+        self.find_all(signature).next()
+    }
+
+    /// Find every usable table with `signature`, for tables like the SSDT
+    /// which can occur more than once.
+    pub fn find_all(&self, signature: [u8; 4])
+            -> impl Iterator<Item = &TableInfo> {
+        self.tables().iter()
+            .filter(move |x| x.signature == signature && !x.skipped())
+    }
+
+    /// Get the tables which were skipped.
+    pub fn skipped(&self) -> impl Iterator<Item = &TableInfo> {
+        self.tables().iter().filter(|x| x.skipped())
+    }
+
+    /// Get a report of the status of every table.
+    pub fn diagnostics(&self) -> Diagnostics<'_> {
+        Diagnostics(self)
+    }
+}
+
+impl AcpiTables {
+    /// Print every table as a hex dump in the `acpidump` text format, reading
+    /// the tables through `mem`.
+    pub fn dump<M: MemoryReader>(&self, mem: &M) {
+        for table in self.tables() {
+            print!("{}", table.hex_dump(mem));
+        }
+    }
+}
+
+/// The status of every table, see `AcpiTables::diagnostics`.
+pub struct Diagnostics<'a>(&'a AcpiTables);
+
+impl<'a> fmt::Display for Diagnostics<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tables = self.0;
+        writeln!(f, "ACPI: {} tables, {} skipped", tables.tables().len(),
+            tables.skipped().count())?;
+
+        for table in tables.tables() {
+            write!(f, "  ")?;
+            ascii(f, &table.signature, 4)?;
+            write!(f, " 0x{:016X} ", table.addr)?;
+            match &table.status {
+                TableStatus::Ok => writeln!(f, "ok")?,
+                TableStatus::Skipped(err) => writeln!(f, "skipped: {:?}", err)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AcpiTables {
+    /// Format the catalog like `acpidump -s`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for table in self.tables() {
+            writeln!(f, "{}", table)?;
+        }
+        Ok(())
+    }
+}
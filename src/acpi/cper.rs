@@ -0,0 +1,454 @@
+//! Common Platform Error Record (CPER) decoding, from the UEFI
+//! specification. The boot error region of the BERT and the error sources of
+//! the HEST report errors as generic error status blocks, each holding CPER
+//! sections which say what failed.
+
+use core::convert::TryInto;
+use core::fmt;
+
+/// Block status bit: an uncorrectable error is valid.
+const BLOCK_UNCORRECTABLE: u32 = 1 << 0;
+
+/// Block status bit: a correctable error is valid.
+const BLOCK_CORRECTABLE: u32 = 1 << 1;
+
+/// Block status bit: more than one uncorrectable error is valid.
+const BLOCK_MULTIPLE_UNCORRECTABLE: u32 = 1 << 2;
+
+/// Block status bit: more than one correctable error is valid.
+const BLOCK_MULTIPLE_CORRECTABLE: u32 = 1 << 3;
+
+/// Size of a generic error status block header.
+const STATUS_HEADER_SIZE: usize = 20;
+
+/// Size of a generic error data entry header before revision 3.
+const DATA_HEADER_SIZE: usize = 64;
+
+/// Size of a generic error data entry header from revision 3, which adds a
+/// timestamp.
+const DATA_HEADER_SIZE_V3: usize = 72;
+
+/// Section validation bit: the FRU ID is valid.
+const SECTION_FRU_ID: u8 = 1 << 0;
+
+/// Section validation bit: the FRU text is valid.
+const SECTION_FRU_TEXT: u8 = 1 << 1;
+
+/// Section validation bit: the timestamp is valid.
+const SECTION_TIMESTAMP: u8 = 1 << 2;
+
+/// Read a little endian `u16` at `offset` in `bytes`.
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+/// Read a little endian `u32` at `offset` in `bytes`.
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Read a little endian `u64` at `offset` in `bytes`.
+fn u64_at(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// A GUID in the mixed endian layout UEFI uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guid(pub u32, pub u16, pub u16, pub [u8; 8]);
+
+impl Guid {
+    /// Read a GUID at `offset` in `bytes`.
+    fn at(bytes: &[u8], offset: usize) -> Option<Self> {
+        Some(Guid(u32_at(bytes, offset)?, u16_at(bytes, offset + 4)?,
+            u16_at(bytes, offset + 6)?,
+            bytes.get(offset + 8..offset + 16)?.try_into().ok()?))
+    }
+
+    /// Returns `true` if this is the nil GUID.
+    pub fn is_nil(&self) -> bool {
+        *self == Guid(0, 0, 0, [0; 8])
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Guid(a, b, c, d) = self;
+        write!(f, "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-", a, b, c, d[0], d[1])?;
+        for byte in &d[2..] {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The severity of an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// A recoverable, uncorrected error.
+    Recoverable,
+
+    /// A fatal error, the reason for a machine check induced reset.
+    Fatal,
+
+    /// An error the hardware corrected.
+    Corrected,
+
+    /// Informational, not an error.
+    Informational,
+
+    /// A reserved severity value.
+    Reserved(u32),
+}
+
+impl From<u32> for Severity {
+    fn from(val: u32) -> Self {
+        match val {
+            0 => Self::Recoverable,
+            1 => Self::Fatal,
+            2 => Self::Corrected,
+            3 => Self::Informational,
+            _ => Self::Reserved(val),
+        }
+    }
+}
+
+/// The kind of error a CPER section describes, from its section type GUID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionType {
+    /// A processor error not specific to an architecture.
+    ProcessorGeneric,
+
+    /// An IA-32 or x64 processor error, usually a machine check.
+    Ia32X64,
+
+    /// An ARM processor error.
+    Arm,
+
+    /// A platform memory error.
+    PlatformMemory,
+
+    /// A platform memory error with wider fields.
+    PlatformMemory2,
+
+    /// A PCI Express error.
+    Pcie,
+
+    /// A PCI or PCI-X bus error.
+    PciBus,
+
+    /// A PCI or PCI-X component error.
+    PciComponent,
+
+    /// A reference to an error record the firmware keeps.
+    FirmwareErrorRecord,
+
+    /// A DMA remapping error.
+    DmarGeneric,
+
+    /// A section type we don't know.
+    Unknown(Guid),
+}
+
+impl From<Guid> for SectionType {
+    fn from(guid: Guid) -> Self {
+        match guid {
+            Guid(0x9876ccad, 0x47b4, 0x4bdb,
+                [0xb6, 0x5e, 0x16, 0xf1, 0x93, 0xc4, 0xf3, 0xdb]) =>
+                Self::ProcessorGeneric,
+            Guid(0xdc3ea0b0, 0xa144, 0x4797,
+                [0xb9, 0x5b, 0x53, 0xfa, 0x24, 0x2b, 0x6e, 0x1d]) =>
+                Self::Ia32X64,
+            Guid(0xe19e3d16, 0xbc11, 0x11e4,
+                [0x9c, 0xaa, 0xc2, 0x05, 0x1d, 0x5d, 0x46, 0xb0]) =>
+                Self::Arm,
+            Guid(0xa5bc1114, 0x6f64, 0x4ede,
+                [0xb8, 0x63, 0x3e, 0x83, 0xed, 0x7c, 0x83, 0xb1]) =>
+                Self::PlatformMemory,
+            Guid(0x61ec04fc, 0x48e6, 0xd813,
+                [0x25, 0xc9, 0x8d, 0xaa, 0x44, 0x75, 0x0b, 0x12]) =>
+                Self::PlatformMemory2,
+            Guid(0xd995e954, 0xbbc1, 0x430f,
+                [0xad, 0x91, 0xb4, 0x4d, 0xcb, 0x3c, 0x6f, 0x35]) =>
+                Self::Pcie,
+            Guid(0xc5753963, 0x3b84, 0x4095,
+                [0xbf, 0x78, 0xed, 0xda, 0xd3, 0xf9, 0xc9, 0xdd]) =>
+                Self::PciBus,
+            Guid(0xeb5e4685, 0xca66, 0x4769,
+                [0xb6, 0xa2, 0x26, 0x06, 0x8b, 0x00, 0x13, 0x26]) =>
+                Self::PciComponent,
+            Guid(0x81212a96, 0x09ed, 0x4996,
+                [0x94, 0x71, 0x8d, 0x72, 0x9c, 0x8e, 0x69, 0xed]) =>
+                Self::FirmwareErrorRecord,
+            Guid(0x5b51fef7, 0xc79d, 0x4434,
+                [0x8f, 0x1b, 0xaa, 0x62, 0xde, 0x3e, 0x2c, 0x64]) =>
+                Self::DmarGeneric,
+            _ => Self::Unknown(guid),
+        }
+    }
+}
+
+impl fmt::Display for SectionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ProcessorGeneric => write!(f, "processor error"),
+            Self::Ia32X64 => write!(f, "IA32/X64 processor error"),
+            Self::Arm => write!(f, "ARM processor error"),
+            Self::PlatformMemory | Self::PlatformMemory2 =>
+                write!(f, "memory error"),
+            Self::Pcie => write!(f, "PCIe error"),
+            Self::PciBus => write!(f, "PCI bus error"),
+            Self::PciComponent => write!(f, "PCI component error"),
+            Self::FirmwareErrorRecord => write!(f, "firmware error record"),
+            Self::DmarGeneric => write!(f, "DMA remapping error"),
+            Self::Unknown(guid) => write!(f, "section {}", guid),
+        }
+    }
+}
+
+/// A CPER section, one generic error data entry in an error status block.
+#[derive(Clone, Copy, Debug)]
+pub struct Section<'a> {
+    /// What kind of error the section describes.
+    pub typ: SectionType,
+
+    /// The severity of this error.
+    pub severity: Severity,
+
+    /// The revision of the data entry header.
+    pub revision: u16,
+
+    /// The field replaceable unit the error is in, `None` if not given.
+    pub fru_id: Option<Guid>,
+
+    /// A name for the field replaceable unit, `None` if not given.
+    pub fru_text: Option<&'a [u8]>,
+
+    /// When the error was recorded, in the UEFI CPER timestamp format.
+    /// `None` if not given or the header is too old to have one.
+    pub timestamp: Option<u64>,
+
+    /// The section data, its layout depends on `typ`.
+    pub data: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    /// Parse a generic error data entry at the start of `bytes`, giving the
+    /// section and its length including the header.
+    fn parse(bytes: &'a [u8]) -> Option<(Self, usize)> {
+        let typ = Guid::at(bytes, 0)?.into();
+        let severity = u32_at(bytes, 16)?.into();
+        let revision = u16_at(bytes, 20)?;
+        let validation = *bytes.get(22)?;
+        let length = u32_at(bytes, 24)? as usize;
+
+        // Revision 3 added a timestamp to the header
+        let header = if revision >= 0x300 {
+            DATA_HEADER_SIZE_V3
+        } else {
+            DATA_HEADER_SIZE
+        };
+        let data = bytes.get(header..header.checked_add(length)?)?;
+
+        // The FRU text is NUL padded
+        let fru_text = bytes.get(44..64)?;
+        let fru_text = &fru_text[..fru_text.iter().position(|&x| x == 0)
+            .unwrap_or(fru_text.len())];
+
+        let section = Section {
+            typ,
+            severity,
+            revision,
+            fru_id: (validation & SECTION_FRU_ID != 0)
+                .then_some(Guid::at(bytes, 28)?),
+            fru_text: (validation & SECTION_FRU_TEXT != 0)
+                .then_some(fru_text),
+            timestamp: if header == DATA_HEADER_SIZE_V3 &&
+                    validation & SECTION_TIMESTAMP != 0 {
+                Some(u64_at(bytes, 64)?)
+            } else {
+                None
+            },
+            data,
+        };
+        Some((section, header + length))
+    }
+
+    /// The physical address of a memory error, `None` if this isn't a
+    /// memory error or the firmware didn't give the address.
+    pub fn physical_address(&self) -> Option<u64> {
+        /// Validation bit: the physical address is valid.
+        const PHYSICAL_ADDRESS: u64 = 1 << 1;
+
+        match self.typ {
+            SectionType::PlatformMemory | SectionType::PlatformMemory2 => {
+                let validation = u64_at(self.data, 0)?;
+                (validation & PHYSICAL_ADDRESS != 0)
+                    .then_some(u64_at(self.data, 16)?)
+            }
+            _ => None,
+        }
+    }
+
+    /// The local APIC ID of the processor with an IA32/X64 processor error,
+    /// `None` if this isn't one or the firmware didn't give the APIC ID.
+    pub fn apic_id(&self) -> Option<u64> {
+        /// Validation bit: the local APIC ID is valid.
+        const APIC_ID: u64 = 1 << 0;
+
+        if self.typ != SectionType::Ia32X64 {
+            return None;
+        }
+        let validation = u64_at(self.data, 0)?;
+        (validation & APIC_ID != 0).then_some(u64_at(self.data, 8)?)
+    }
+
+    /// The segment, bus, device and function of the device with a PCIe
+    /// error, `None` if this isn't one or the firmware didn't give the
+    /// device.
+    pub fn pcie_device(&self) -> Option<(u16, u8, u8, u8)> {
+        /// Validation bit: the device ID is valid.
+        const DEVICE_ID: u64 = 1 << 3;
+
+        if self.typ != SectionType::Pcie {
+            return None;
+        }
+        let validation = u64_at(self.data, 0)?;
+        if validation & DEVICE_ID == 0 {
+            return None;
+        }
+        Some((u16_at(self.data, 33)?, *self.data.get(35)?,
+            *self.data.get(32)?, *self.data.get(31)?))
+    }
+}
+
+impl fmt::Display for Section<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?}, {} bytes)", self.typ, self.severity,
+            self.data.len())?;
+        if let Some(text) = self.fru_text.filter(|x| !x.is_empty()) {
+            write!(f, " FRU \"{}\"",
+                core::str::from_utf8(text).unwrap_or("?"))?;
+        }
+        if let Some(addr) = self.physical_address() {
+            write!(f, " at {:#x}", addr)?;
+        }
+        if let Some(apic_id) = self.apic_id() {
+            write!(f, " on APIC ID {}", apic_id)?;
+        }
+        if let Some((seg, bus, dev, func)) = self.pcie_device() {
+            write!(f, " at {:04x}:{:02x}:{:02x}.{}", seg, bus, dev, func)?;
+        }
+        Ok(())
+    }
+}
+
+/// A generic error status block, the errors a source reported with the CPER
+/// sections describing them.
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorStatus<'a> {
+    /// An uncorrectable error was reported.
+    pub uncorrectable: bool,
+
+    /// A correctable error was reported.
+    pub correctable: bool,
+
+    /// More than one uncorrectable error was reported.
+    pub multiple_uncorrectable: bool,
+
+    /// More than one correctable error was reported.
+    pub multiple_correctable: bool,
+
+    /// The number of CPER sections the block claims to have.
+    pub entries: u16,
+
+    /// The severity of the most severe section.
+    pub severity: Severity,
+
+    /// Raw error data in a format specific to the error source.
+    pub raw_data: &'a [u8],
+
+    /// The generic error data entries.
+    data: &'a [u8],
+
+    /// The length of the block including its raw data.
+    length: usize,
+}
+
+impl<'a> ErrorStatus<'a> {
+    /// Parse a generic error status block at the start of `bytes`. Returns
+    /// `None` if the block runs past the end of `bytes`.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let status = u32_at(bytes, 0)?;
+        let raw_offset = u32_at(bytes, 4)? as usize;
+        let raw_length = u32_at(bytes, 8)? as usize;
+        let data_length = u32_at(bytes, 12)? as usize;
+        let severity = u32_at(bytes, 16)?.into();
+
+        let data = bytes.get(STATUS_HEADER_SIZE..
+            STATUS_HEADER_SIZE.checked_add(data_length)?)?;
+
+        // The raw data is optional, and follows the data entries
+        let (raw_data, length) = if raw_offset != 0 {
+            let end = raw_offset.checked_add(raw_length)?;
+            (bytes.get(raw_offset..end)?,
+                end.max(STATUS_HEADER_SIZE + data_length))
+        } else {
+            (&[][..], STATUS_HEADER_SIZE + data_length)
+        };
+
+        Some(ErrorStatus {
+            uncorrectable: status & BLOCK_UNCORRECTABLE != 0,
+            correctable: status & BLOCK_CORRECTABLE != 0,
+            multiple_uncorrectable: status & BLOCK_MULTIPLE_UNCORRECTABLE != 0,
+            multiple_correctable: status & BLOCK_MULTIPLE_CORRECTABLE != 0,
+            entries: ((status >> 4) & 0x3ff) as u16,
+            severity,
+            raw_data,
+            data,
+            length,
+        })
+    }
+
+    /// Returns `true` if the block reports an error. Firmware leaves the
+    /// block status zero when there is nothing to report.
+    pub fn is_active(&self) -> bool {
+        self.uncorrectable || self.correctable || self.entries != 0
+    }
+
+    /// The length of the block in bytes, including its raw data.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// The CPER sections in the block. Iteration stops at the first section
+    /// which doesn't fit in the block.
+    pub fn sections(&self) -> Sections<'a> {
+        Sections(self.data)
+    }
+}
+
+impl fmt::Display for ErrorStatus<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} error, {} sections", self.severity, self.entries)?;
+        if self.multiple_uncorrectable || self.multiple_correctable {
+            write!(f, ", multiple errors")?;
+        }
+        for (idx, section) in self.sections().enumerate() {
+            write!(f, "\n  [{}] {}", idx, section)?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the CPER sections of an error status block.
+pub struct Sections<'a>(&'a [u8]);
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Section<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (section, length) = Section::parse(self.0)?;
+        self.0 = &self.0[length..];
+        Some(section)
+    }
+}
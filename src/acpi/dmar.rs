@@ -0,0 +1,399 @@
+//! DMA Remapping (DMAR) table parsing, describing the Intel VT-d IOMMUs and
+//! the memory devices may DMA to behind the OS's back.
+
+use core::mem::size_of;
+
+use super::{Error, Result, TableType};
+use crate::fixed_vec::FixedVec;
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+use crate::mm::rangeset::{Range, RangeSet};
+
+/// The maximum number of remapping units we can track.
+const MAX_DRHDS: usize = 8;
+
+/// The maximum number of reserved memory regions we can track.
+const MAX_RMRRS: usize = 16;
+
+/// The maximum number of root port ATS structures we can track.
+const MAX_ATSRS: usize = 8;
+
+/// The maximum number of device scopes we can track per structure.
+const MAX_SCOPES: usize = 16;
+
+/// The maximum depth of a device scope path.
+const MAX_PATH: usize = 4;
+
+/// DMAR flag: interrupt remapping is supported.
+const INTR_REMAP: u8 = 1 << 0;
+
+/// DMAR flag: the firmware asks the OS not to enable x2APIC mode.
+const X2APIC_OPT_OUT: u8 = 1 << 1;
+
+/// DMAR flag: the firmware set up DMA protection and the OS should keep it.
+const DMA_CTRL_PLATFORM_OPT_IN: u8 = 1 << 2;
+
+/// DRHD flag: the unit covers every PCI device in its segment not covered
+/// by another unit.
+const INCLUDE_PCI_ALL: u8 = 1 << 0;
+
+/// ATSR flag: every root port in the segment supports ATS.
+const ALL_PORTS: u8 = 1 << 0;
+
+/// The kind of device a device scope refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceScopeType {
+    /// A PCI endpoint device.
+    PciEndpoint,
+
+    /// A PCI bridge and every device below it.
+    PciSubHierarchy,
+
+    /// An I/O APIC.
+    IoApic,
+
+    /// An HPET.
+    Hpet,
+
+    /// An ACPI namespace device.
+    AcpiNamespaceDevice,
+
+    /// A reserved type.
+    Reserved(u8),
+}
+
+impl From<u8> for DeviceScopeType {
+    fn from(val: u8) -> Self {
+        match val {
+            1 => Self::PciEndpoint,
+            2 => Self::PciSubHierarchy,
+            3 => Self::IoApic,
+            4 => Self::Hpet,
+            5 => Self::AcpiNamespaceDevice,
+            _ => Self::Reserved(val),
+        }
+    }
+}
+
+impl Default for DeviceScopeType {
+    fn default() -> Self {
+        Self::Reserved(0)
+    }
+}
+
+/// One hop in a device scope path, a device and function on the bus below
+/// the previous hop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PciPathEntry {
+    /// PCI device number.
+    pub device: u8,
+
+    /// PCI function number.
+    pub function: u8,
+}
+
+/// A device a DMAR structure applies to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceScope {
+    /// The kind of device.
+    pub typ: DeviceScopeType,
+
+    /// The I/O APIC ID, HPET number or ACPI device number, depending on
+    /// `typ`.
+    pub enumeration_id: u8,
+
+    /// The bus the path starts on.
+    pub start_bus: u8,
+
+    /// The path from `start_bus` to the device through bridges.
+    pub path: FixedVec<PciPathEntry, MAX_PATH>,
+}
+
+/// A DMA remapping hardware unit (DRHD), one IOMMU.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Drhd {
+    /// The unit covers every device in the segment not covered by another
+    /// unit.
+    pub include_pci_all: bool,
+
+    /// The PCI segment the unit is in.
+    pub segment: u16,
+
+    /// Physical address of the unit's registers.
+    pub register_base: u64,
+
+    /// Size of the register set as a power of 2 number of 4 KiB pages.
+    pub size: u8,
+
+    /// The devices the unit covers.
+    pub scopes: FixedVec<DeviceScope, MAX_SCOPES>,
+}
+
+/// A reserved memory region reporting structure (RMRR), memory the firmware
+/// lets devices DMA to, such as USB controllers for legacy keyboard
+/// emulation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rmrr {
+    /// The PCI segment of the devices.
+    pub segment: u16,
+
+    /// The reserved memory region.
+    pub range: Range,
+
+    /// The devices which use the region.
+    pub scopes: FixedVec<DeviceScope, MAX_SCOPES>,
+}
+
+/// A root port ATS capability reporting structure (ATSR), the root ports
+/// which support Address Translation Services.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Atsr {
+    /// Every root port in the segment supports ATS.
+    pub all_ports: bool,
+
+    /// The PCI segment of the root ports.
+    pub segment: u16,
+
+    /// The root ports which support ATS.
+    pub scopes: FixedVec<DeviceScope, MAX_SCOPES>,
+}
+
+/// The decoded DMAR table.
+#[derive(Clone, Copy, Debug)]
+pub struct Dmar {
+    /// The maximum DMA physical address width in bits.
+    pub host_address_width: u8,
+
+    /// Interrupt remapping is supported.
+    pub interrupt_remapping: bool,
+
+    /// The firmware asks the OS not to enable x2APIC mode.
+    pub x2apic_opt_out: bool,
+
+    /// The firmware set up DMA protection and the OS should keep it.
+    pub dma_ctrl_platform_opt_in: bool,
+
+    /// The remapping hardware units.
+    drhds: FixedVec<Drhd, MAX_DRHDS>,
+
+    /// The reserved memory regions.
+    rmrrs: FixedVec<Rmrr, MAX_RMRRS>,
+
+    /// The root port ATS structures.
+    atsrs: FixedVec<Atsr, MAX_ATSRS>,
+}
+
+/// Parse the device scopes in the rest of `slice`.
+unsafe fn device_scopes<M: MemoryReader>(mut slice: PhysSlice<M>)
+        -> Result<FixedVec<DeviceScope, MAX_SCOPES>> {
+    /// The error type when the DMAR is truncated
+    const E: Error = Error::LengthMismatch(TableType::Dmar);
+
+    /// In-memory representation of a device scope header.
+    #[repr(C, packed)]
+    struct DeviceScopeHeader {
+        /// Device scope type
+        typ: u8,
+
+        /// Length of the structure, including the path
+        length: u8,
+
+        /// Flags
+        flags: u8,
+
+        /// Reserved
+        reserved: u8,
+
+        /// I/O APIC ID, HPET number or ACPI device number
+        enumeration_id: u8,
+
+        /// The bus the path starts on
+        start_bus: u8,
+    }
+
+    let mut scopes = FixedVec::new();
+    while slice.len() > 0 {
+        let header = slice.consume::<DeviceScopeHeader>().map_err(|_| E)?;
+        let path_len = (header.length as usize)
+            .checked_sub(size_of::<DeviceScopeHeader>())
+            .ok_or(E)?;
+        if path_len % size_of::<u16>() != 0 {
+            return Err(E);
+        }
+
+        let mut path = FixedVec::new();
+        for _ in 0..path_len / size_of::<u16>() {
+            path.push(PciPathEntry {
+                device: slice.consume::<u8>().map_err(|_| E)?,
+                function: slice.consume::<u8>().map_err(|_| E)?,
+            }).map_err(|_| Error::TooManyDmarStructures)?;
+        }
+
+        scopes.push(DeviceScope {
+            typ: header.typ.into(),
+            enumeration_id: header.enumeration_id,
+            start_bus: header.start_bus,
+            path,
+        }).map_err(|_| Error::TooManyDmarStructures)?;
+    }
+
+    Ok(scopes)
+}
+
+impl Dmar {
+    /// Process the payload of the DMAR (everything after the table header)
+    /// at `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the DMAR is truncated
+        const E: Error = Error::LengthMismatch(TableType::Dmar);
+
+        /// The error when there are more structures than we can track
+        const FULL: Error = Error::TooManyDmarStructures;
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let host_address_width = slice.consume::<u8>().map_err(|_| E)?;
+        let flags = slice.consume::<u8>().map_err(|_| E)?;
+        slice.discard(10).map_err(|_| E)?;
+
+        let mut dmar = Dmar {
+            host_address_width: host_address_width.wrapping_add(1),
+            interrupt_remapping: flags & INTR_REMAP != 0,
+            x2apic_opt_out: flags & X2APIC_OPT_OUT != 0,
+            dma_ctrl_platform_opt_in: flags & DMA_CTRL_PLATFORM_OPT_IN != 0,
+            drhds: FixedVec::new(),
+            rmrrs: FixedVec::new(),
+            atsrs: FixedVec::new(),
+        };
+
+        // Handle remapping structures
+        while slice.len() > 0 {
+            // Read the remapping structure header
+            let typ = slice.consume::<u16>().map_err(|_| E)?;
+            let len = (slice.consume::<u16>().map_err(|_| E)? as usize)
+                .checked_sub(2 * size_of::<u16>()).ok_or(E)?;
+
+            // Split off this structure, so the device scopes are the rest of
+            // it
+            if slice.len() < len {
+                return Err(E);
+            }
+            let mut body = PhysSlice::new(mem, slice.addr(), len);
+            slice.discard(len).map_err(|_| E)?;
+
+            match typ {
+                0 => {
+                    // DMA Remapping Hardware Unit Definition structure
+                    #[repr(C, packed)]
+                    struct DrhdHeader {
+                        /// Flags, bit 0 is INCLUDE_PCI_ALL
+                        flags: u8,
+
+                        /// Size of the register set, 2^N 4 KiB pages
+                        size: u8,
+
+                        /// The PCI segment of the unit
+                        segment: u16,
+
+                        /// Base address of the remapping hardware register set
+                        register_base: u64,
+                    }
+
+                    let drhd = body.consume::<DrhdHeader>().map_err(|_| E)?;
+                    dmar.drhds.push(Drhd {
+                        include_pci_all: drhd.flags & INCLUDE_PCI_ALL != 0,
+                        segment: drhd.segment,
+                        register_base: drhd.register_base,
+                        size: drhd.size,
+                        scopes: device_scopes(body)?,
+                    }).map_err(|_| FULL)?;
+                }
+
+                1 => {
+                    // Reserved Memory Region Reporting structure
+                    #[repr(C, packed)]
+                    struct RmrrHeader {
+                        /// Reserved
+                        reserved: u16,
+
+                        /// The PCI segment of the devices
+                        segment: u16,
+
+                        /// Base address of the 4 KiB aligned region
+                        base: u64,
+
+                        /// Last address of the region, inclusive
+                        limit: u64,
+                    }
+
+                    let rmrr = body.consume::<RmrrHeader>().map_err(|_| E)?;
+                    if rmrr.limit < rmrr.base {
+                        return Err(E);
+                    }
+
+                    dmar.rmrrs.push(Rmrr {
+                        segment: rmrr.segment,
+                        range: Range {
+                            start: rmrr.base,
+                            end: rmrr.limit,
+                        },
+                        scopes: device_scopes(body)?,
+                    }).map_err(|_| FULL)?;
+                }
+
+                2 => {
+                    // Root Port ATS Capability Reporting structure
+                    #[repr(C, packed)]
+                    struct AtsrHeader {
+                        /// Flags, bit 0 is ALL_PORTS
+                        flags: u8,
+
+                        /// Reserved
+                        reserved: u8,
+
+                        /// The PCI segment of the root ports
+                        segment: u16,
+                    }
+
+                    let atsr = body.consume::<AtsrHeader>().map_err(|_| E)?;
+                    dmar.atsrs.push(Atsr {
+                        all_ports: atsr.flags & ALL_PORTS != 0,
+                        segment: atsr.segment,
+                        scopes: device_scopes(body)?,
+                    }).map_err(|_| FULL)?;
+                }
+
+                // Other structures, such as RHSA and ANDD, are not needed
+                _ => {}
+            }
+        }
+
+        Ok(dmar)
+    }
+
+    /// Get the remapping hardware units.
+    pub fn drhds(&self) -> &[Drhd] {
+        self.drhds.entries()
+    }
+
+    /// Get the reserved memory regions.
+    pub fn rmrrs(&self) -> &[Rmrr] {
+        self.rmrrs.entries()
+    }
+
+    /// Get the root port ATS structures.
+    pub fn atsrs(&self) -> &[Atsr] {
+        self.atsrs.entries()
+    }
+
+    /// Remove every reserved memory region from `memory`, so devices which
+    /// DMA to them can't corrupt anything we allocate.
+    pub fn reserve<const N: usize>(&self, memory: &mut RangeSet<N>)
+            -> Result<()> {
+        for rmrr in self.rmrrs() {
+            memory.remove(rmrr.range).map_err(Error::RmrrRangeSet)?;
+        }
+        Ok(())
+    }
+}
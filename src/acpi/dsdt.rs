@@ -0,0 +1,136 @@
+//! The `\_S5_` package from the Differentiated System Description Table
+//! (DSDT), which holds the values to write to PM1 control to power off. We
+//! evaluate it in the AML namespace, and fall back to a minimal scanner for
+//! AML the namespace walker gives up on.
+
+use super::aml::Namespace;
+use crate::mm::physmem::{MemoryReader, PhysAddr};
+
+/// AML NameOp, `Name(NameString, DataRefObject)`.
+const NAME_OP: u8 = 0x08;
+
+/// AML root prefix, `\`.
+const ROOT_PREFIX: u8 = b'\\';
+
+/// AML PackageOp.
+const PACKAGE_OP: u8 = 0x12;
+
+/// AML integer constants and prefixes.
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ONES_OP: u8 = 0xff;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+const QWORD_PREFIX: u8 = 0x0e;
+
+/// The SLP_TYPa and SLP_TYPb values for a sleep state, to be written to the
+/// PM1a and PM1b control registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SleepType {
+    /// Value for the SLP_TYP field of PM1a control.
+    pub slp_typ_a: u8,
+
+    /// Value for the SLP_TYP field of PM1b control.
+    pub slp_typ_b: u8,
+}
+
+/// Evaluate `\_S5_` in `namespace` for the soft-off sleep type.
+pub fn s5(namespace: &Namespace) -> Option<SleepType> {
+    let package = namespace.evaluate("\\_S5_").ok()?.as_package()?;
+    let mut values = package.iter().map(|x| x.as_integer());
+    let slp_typ_a = values.next()??;
+    let slp_typ_b = values.next()??;
+
+    // SLP_TYP is a 3-bit field
+    Some(SleepType {
+        slp_typ_a: (slp_typ_a & 7) as u8,
+        slp_typ_b: (slp_typ_b & 7) as u8,
+    })
+}
+
+/// Scan the AML of the DSDT (everything after the table header) at `addr`
+/// for `size` bytes, read through `mem`, for the `\_S5_` soft-off sleep type.
+pub fn find_s5<M: MemoryReader>(mem: &M, addr: PhysAddr, size: usize)
+        -> Option<SleepType> {
+    find_s5_in(mem.bytes(addr, size).ok()?)
+}
+
+/// Scan `aml` for the `\_S5_` soft-off sleep type.
+pub fn find_s5_in(aml: &[u8]) -> Option<SleepType> {
+    let mut start = 0;
+    while let Some(pos) = aml.get(start..)?.windows(4)
+            .position(|x| x == b"_S5_") {
+        let pos = start + pos;
+        start = pos + 1;
+
+        // The name must be declared with a NameOp, optionally with a root
+        // prefix. Anything else is a reference to it or just data which
+        // happens to match.
+        let declared = match pos.checked_sub(1).map(|x| aml[x]) {
+            Some(NAME_OP) => true,
+            Some(ROOT_PREFIX) => {
+                pos.checked_sub(2).map(|x| aml[x]) == Some(NAME_OP)
+            }
+            _ => false,
+        };
+        if !declared {
+            continue;
+        }
+
+        if let Some(sleep_type) = parse_package(&aml[pos + 4..]) {
+            return Some(sleep_type);
+        }
+    }
+
+    None
+}
+
+/// Parse the sleep type package, `Package () { SLP_TYPa, SLP_TYPb, ... }`,
+/// at the start of `aml`.
+fn parse_package(aml: &[u8]) -> Option<SleepType> {
+    let mut aml = aml.iter().copied();
+
+    if aml.next()? != PACKAGE_OP {
+        return None;
+    }
+
+    // Skip the PkgLength, bits 7:6 of the lead byte are the number of bytes
+    // which follow it
+    let lead = aml.next()?;
+    for _ in 0..(lead >> 6) {
+        aml.next()?;
+    }
+
+    // NumElements
+    if aml.next()? < 2 {
+        return None;
+    }
+
+    let slp_typ_a = parse_integer(&mut aml)?;
+    let slp_typ_b = parse_integer(&mut aml)?;
+
+    // SLP_TYP is a 3-bit field
+    Some(SleepType {
+        slp_typ_a: (slp_typ_a & 7) as u8,
+        slp_typ_b: (slp_typ_b & 7) as u8,
+    })
+}
+
+/// Parse an AML integer constant from `aml`.
+fn parse_integer(aml: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let bytes = match aml.next()? {
+        ZERO_OP => return Some(0),
+        ONE_OP => return Some(1),
+        ONES_OP => return Some(!0),
+        BYTE_PREFIX => 1,
+        WORD_PREFIX => 2,
+        DWORD_PREFIX => 4,
+        QWORD_PREFIX => 8,
+        _ => return None,
+    };
+
+    (0..bytes).try_fold(0u64, |acc, ii| {
+        Some(acc | ((aml.next()? as u64) << (ii * 8)))
+    })
+}
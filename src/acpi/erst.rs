@@ -0,0 +1,61 @@
+//! Error Record Serialization Table (ERST) parsing, the firmware interface
+//! for saving error records to persistent storage.
+
+use core::mem::size_of;
+
+use super::{Error, Result, Table, TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The size of a serialization instruction entry.
+const INSTRUCTION_SIZE: usize = 32;
+
+/// The serialization interface described by the ERST.
+#[derive(Clone, Copy, Debug)]
+pub struct Erst {
+    /// The number of serialization instructions.
+    pub instructions: u32,
+}
+
+/// In-memory representation of the ERST serialization header.
+#[repr(C, packed)]
+struct SerializationHeader {
+    /// Length of the serialization header, 12. Some firmware includes the
+    /// table header and says 48.
+    header_length: u32,
+
+    /// Reserved
+    reserved: u32,
+
+    /// Number of instruction entries following
+    instruction_entries: u32,
+}
+
+impl Erst {
+    /// Process the payload of the ERST (everything after the table header) at
+    /// `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the ERST is truncated
+        const E: Error = Error::LengthMismatch(TableType::Erst);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let header = slice.consume::<SerializationHeader>().map_err(|_| E)?;
+
+        // The instructions are the rest of the table, exactly
+        let instructions = header.instruction_entries;
+        if slice.len() != (instructions as usize)
+                .checked_mul(INSTRUCTION_SIZE).ok_or(E)? {
+            return Err(E);
+        }
+        let header_length = header.header_length as usize;
+        if header_length != size_of::<SerializationHeader>() &&
+                header_length != size_of::<Table>() +
+                size_of::<SerializationHeader>() {
+            return Err(E);
+        }
+
+        Ok(Erst { instructions })
+    }
+}
@@ -0,0 +1,107 @@
+//! Firmware ACPI Control Structure (FACS) parsing. The FACS is pointed to by
+//! the FADT and, unlike the other tables, has no standard header or checksum.
+
+use core::mem::size_of;
+
+use super::{Error, Result, TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr};
+
+/// FACS flag: the platform supports S4BIOS_REQ.
+const S4BIOS: u32 = 1 << 0;
+
+/// FACS flag: the platform can resume through a 64-bit waking vector.
+const WAKE_64BIT_SUPPORTED: u32 = 1 << 1;
+
+/// The firmware control structure described by the FACS.
+#[derive(Clone, Copy, Debug)]
+pub struct Facs {
+    /// The length of the FACS, at least 64 bytes.
+    pub length: u32,
+
+    /// The hardware configuration at boot, which the OS compares on resume
+    /// from S4 to detect a changed machine.
+    pub hardware_signature: u32,
+
+    /// The address the firmware jumps to on wake, in real mode for the
+    /// 32-bit vector.
+    pub waking_vector: u32,
+
+    /// The 64-bit waking vector, `None` if not set or before version 1.
+    pub x_waking_vector: Option<u64>,
+
+    /// The platform supports S4BIOS_REQ.
+    pub s4bios: bool,
+
+    /// The platform can resume through a 64-bit waking vector.
+    pub wake_64bit_supported: bool,
+
+    /// The version of the FACS.
+    pub version: u8,
+}
+
+/// In-memory representation of the FACS.
+#[repr(C, packed)]
+struct RawFacs {
+    /// "FACS"
+    signature: [u8; 4],
+
+    /// Length of the FACS, at least 64
+    length: u32,
+
+    /// Hardware signature
+    hardware_signature: u32,
+
+    /// 32-bit firmware waking vector
+    firmware_waking_vector: u32,
+
+    /// The global lock
+    global_lock: u32,
+
+    /// Firmware control structure feature flags
+    flags: u32,
+
+    /// 64-bit firmware waking vector (version 1)
+    x_firmware_waking_vector: u64,
+
+    /// Version of the FACS
+    version: u8,
+
+    /// Reserved
+    reserved: [u8; 3],
+
+    /// OSPM enabled feature flags (version 2)
+    ospm_flags: u32,
+
+    /// Reserved
+    reserved2: [u8; 24],
+}
+
+impl Facs {
+    /// Process the FACS at `addr`, read through `mem`. Unlike the other
+    /// tables this is the whole structure, as there is no standard header.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr)
+            -> Result<Self> {
+        let facs = mem.read_unaligned::<RawFacs>(addr)
+            .map_err(|_| Error::Unreadable(addr.0))?;
+
+        let typ = TableType::from(facs.signature);
+        if typ != TableType::Facs {
+            return Err(Error::SignatureMismatch(typ));
+        }
+        if (facs.length as usize) < size_of::<RawFacs>() {
+            return Err(Error::LengthMismatch(typ));
+        }
+
+        Ok(Facs {
+            length: facs.length,
+            hardware_signature: facs.hardware_signature,
+            waking_vector: facs.firmware_waking_vector,
+            x_waking_vector: (facs.version >= 1 &&
+                facs.x_firmware_waking_vector != 0)
+                .then_some(facs.x_firmware_waking_vector),
+            s4bios: facs.flags & S4BIOS != 0,
+            wake_64bit_supported: facs.flags & WAKE_64BIT_SUPPORTED != 0,
+            version: facs.version,
+        })
+    }
+}
@@ -0,0 +1,442 @@
+//! Fixed ACPI Description Table (FADT, signature "FACP") parsing. The FADT
+//! locates the DSDT and the fixed hardware registers: the PM timer, PM1
+//! control blocks and the reset register.
+
+use core::mem::size_of;
+
+use super::{AddressSpace, Error, GenericAddress, RawGenericAddress, Result,
+    TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr};
+
+/// FADT flag: the PM timer is 32 bits wide rather than 24.
+const TMR_VAL_EXT: u32 = 1 << 8;
+
+/// FADT flag: the reset register is supported.
+const RESET_REG_SUP: u32 = 1 << 10;
+
+/// FADT flag: the platform is hardware-reduced ACPI, without the fixed
+/// hardware registers.
+const HW_REDUCED_ACPI: u32 = 1 << 20;
+
+/// IA-PC boot architecture flag: there are legacy devices such as an RTC or
+/// a floppy controller.
+const LEGACY_DEVICES: u16 = 1 << 0;
+
+/// IA-PC boot architecture flag: there is an 8042 keyboard controller.
+const I8042: u16 = 1 << 1;
+
+/// IA-PC boot architecture flag: there is no VGA hardware.
+const VGA_NOT_PRESENT: u16 = 1 << 2;
+
+/// IA-PC boot architecture flag: MSIs must not be enabled.
+const MSI_NOT_SUPPORTED: u16 = 1 << 3;
+
+/// IA-PC boot architecture flag: the OS must not enable PCIe ASPM.
+const PCIE_ASPM_CONTROLS: u16 = 1 << 4;
+
+/// IA-PC boot architecture flag: there is no CMOS RTC.
+const CMOS_RTC_NOT_PRESENT: u16 = 1 << 5;
+
+/// The legacy devices the platform has, from the IA-PC boot architecture
+/// flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BootArch {
+    /// There are legacy devices such as an RTC or a floppy controller.
+    pub legacy_devices: bool,
+
+    /// There is an 8042 keyboard controller.
+    pub i8042: bool,
+
+    /// There is no VGA hardware.
+    pub vga_not_present: bool,
+
+    /// MSIs must not be enabled.
+    pub msi_not_supported: bool,
+
+    /// The OS must not enable PCIe ASPM.
+    pub pcie_aspm_controls: bool,
+
+    /// There is no CMOS RTC.
+    pub cmos_rtc_not_present: bool,
+}
+
+impl From<u16> for BootArch {
+    fn from(val: u16) -> Self {
+        BootArch {
+            legacy_devices: val & LEGACY_DEVICES != 0,
+            i8042: val & I8042 != 0,
+            vga_not_present: val & VGA_NOT_PRESENT != 0,
+            msi_not_supported: val & MSI_NOT_SUPPORTED != 0,
+            pcie_aspm_controls: val & PCIE_ASPM_CONTROLS != 0,
+            cmos_rtc_not_present: val & CMOS_RTC_NOT_PRESENT != 0,
+        }
+    }
+}
+
+/// The ACPI power management timer, a fixed 3.579545 MHz counter.
+#[derive(Clone, Copy, Debug)]
+pub struct PmTimer {
+    /// The timer's counter register.
+    pub register: GenericAddress,
+
+    /// Width of the counter in bits, 24 or 32.
+    pub width: u8,
+}
+
+impl PmTimer {
+    /// The frequency of the PM timer in Hz.
+    pub const FREQUENCY: u64 = 3_579_545;
+
+    /// The I/O port of the counter, `None` if it's not in I/O space.
+    pub fn port(&self) -> Option<u16> {
+        if self.register.space == AddressSpace::SystemIo &&
+                self.register.address <= u16::MAX as u64 {
+            Some(self.register.address as u16)
+        } else {
+            None
+        }
+    }
+}
+
+/// The reset register, writing `value` to it resets the system.
+#[derive(Clone, Copy, Debug)]
+pub struct ResetRegister {
+    /// The reset register.
+    pub register: GenericAddress,
+
+    /// The value to write to reset.
+    pub value: u8,
+}
+
+/// The decoded FADT. Where the table has both a 32-bit field and a 64-bit
+/// `X_` field, the `X_` field is used if it is present and non-zero.
+#[derive(Clone, Copy, Debug)]
+pub struct Fadt {
+    /// The table revision.
+    pub revision: u8,
+
+    /// Physical address of the FACS, 0 if there is none.
+    pub facs: u64,
+
+    /// Physical address of the DSDT.
+    pub dsdt: u64,
+
+    /// The interrupt the SCI is wired to in 8259 mode.
+    pub sci_interrupt: u16,
+
+    /// The I/O port to write `acpi_enable` and `acpi_disable` to, to
+    /// transfer ownership of the ACPI hardware. 0 if not supported.
+    pub smi_command: u32,
+
+    /// The value to write to `smi_command` to enable ACPI.
+    pub acpi_enable: u8,
+
+    /// The value to write to `smi_command` to disable ACPI.
+    pub acpi_disable: u8,
+
+    /// PM1a event register block.
+    pub pm1a_event: Option<GenericAddress>,
+
+    /// PM1b event register block.
+    pub pm1b_event: Option<GenericAddress>,
+
+    /// PM1a control register block.
+    pub pm1a_control: Option<GenericAddress>,
+
+    /// PM1b control register block.
+    pub pm1b_control: Option<GenericAddress>,
+
+    /// The ACPI PM timer.
+    pub pm_timer: Option<PmTimer>,
+
+    /// The reset register, if supported.
+    pub reset: Option<ResetRegister>,
+
+    /// The RTC CMOS index of the century, 0 if not supported.
+    pub century: u8,
+
+    /// The legacy devices the platform has.
+    pub boot_arch: BootArch,
+
+    /// Raw FADT feature flags.
+    pub flags: u32,
+}
+
+/// In-memory representation of the FADT payload (everything after the
+/// table header) up to the ACPI 6 fields. Older, shorter tables are zero
+/// extended.
+#[repr(C, packed)]
+struct RawFadt {
+    /// 32-bit physical address of the FACS
+    firmware_ctrl: u32,
+
+    /// 32-bit physical address of the DSDT
+    dsdt: u32,
+
+    /// ACPI 1.0 interrupt model, reserved since
+    reserved1: u8,
+
+    /// Preferred power management profile
+    preferred_pm_profile: u8,
+
+    /// System vector the SCI interrupt is wired to in 8259 mode
+    sci_int: u16,
+
+    /// System port address of the SMI command port
+    smi_cmd: u32,
+
+    /// Value to write to SMI_CMD to disable SMI ownership of the ACPI
+    /// hardware registers
+    acpi_enable: u8,
+
+    /// Value to write to SMI_CMD to re-enable SMI ownership of the ACPI
+    /// hardware registers
+    acpi_disable: u8,
+
+    /// Value to write to SMI_CMD to enter the S4BIOS state
+    s4bios_req: u8,
+
+    /// Value to write to SMI_CMD to assume processor performance state
+    /// control responsibility
+    pstate_cnt: u8,
+
+    /// System port address of the PM1a event register block
+    pm1a_evt_blk: u32,
+
+    /// System port address of the PM1b event register block
+    pm1b_evt_blk: u32,
+
+    /// System port address of the PM1a control register block
+    pm1a_cnt_blk: u32,
+
+    /// System port address of the PM1b control register block
+    pm1b_cnt_blk: u32,
+
+    /// System port address of the PM2 control register block
+    pm2_cnt_blk: u32,
+
+    /// System port address of the PM timer control register block
+    pm_tmr_blk: u32,
+
+    /// System port address of general-purpose event 0 register block
+    gpe0_blk: u32,
+
+    /// System port address of general-purpose event 1 register block
+    gpe1_blk: u32,
+
+    /// Number of bytes decoded by PM1a_EVT_BLK and PM1b_EVT_BLK
+    pm1_evt_len: u8,
+
+    /// Number of bytes decoded by PM1a_CNT_BLK and PM1b_CNT_BLK
+    pm1_cnt_len: u8,
+
+    /// Number of bytes decoded by PM2_CNT_BLK
+    pm2_cnt_len: u8,
+
+    /// Number of bytes decoded by PM_TMR_BLK, 4 if supported
+    pm_tmr_len: u8,
+
+    /// Length of GPE0_BLK
+    gpe0_blk_len: u8,
+
+    /// Length of GPE1_BLK
+    gpe1_blk_len: u8,
+
+    /// Offset where GPE1 based events start
+    gpe1_base: u8,
+
+    /// Value to write to SMI_CMD for _CST support
+    cst_cnt: u8,
+
+    /// Worst case latency to enter and exit C2
+    p_lvl2_lat: u16,
+
+    /// Worst case latency to enter and exit C3
+    p_lvl3_lat: u16,
+
+    /// Legacy cache flush size
+    flush_size: u16,
+
+    /// Legacy cache flush stride
+    flush_stride: u16,
+
+    /// Duty cycle setting offset in the P_CNT register
+    duty_offset: u8,
+
+    /// Duty cycle setting width in the P_CNT register
+    duty_width: u8,
+
+    /// RTC CMOS RAM index of the day-of-month alarm
+    day_alrm: u8,
+
+    /// RTC CMOS RAM index of the month of year alarm
+    mon_alrm: u8,
+
+    /// RTC CMOS RAM index of the century
+    century: u8,
+
+    /// IA-PC boot architecture flags, reserved in ACPI 1.0
+    iapc_boot_arch: u16,
+
+    /// Reserved
+    reserved2: u8,
+
+    /// Fixed feature flags
+    flags: u32,
+
+    /// The reset register
+    reset_reg: RawGenericAddress,
+
+    /// Value to write to RESET_REG to reset the system
+    reset_value: u8,
+
+    /// ARM boot architecture flags
+    arm_boot_arch: u16,
+
+    /// FADT minor version
+    fadt_minor_version: u8,
+
+    /// 64-bit physical address of the FACS
+    x_firmware_ctrl: u64,
+
+    /// 64-bit physical address of the DSDT
+    x_dsdt: u64,
+
+    /// Extended address of the PM1a event register block
+    x_pm1a_evt_blk: RawGenericAddress,
+
+    /// Extended address of the PM1b event register block
+    x_pm1b_evt_blk: RawGenericAddress,
+
+    /// Extended address of the PM1a control register block
+    x_pm1a_cnt_blk: RawGenericAddress,
+
+    /// Extended address of the PM1b control register block
+    x_pm1b_cnt_blk: RawGenericAddress,
+
+    /// Extended address of the PM2 control register block
+    x_pm2_cnt_blk: RawGenericAddress,
+
+    /// Extended address of the PM timer control register block
+    x_pm_tmr_blk: RawGenericAddress,
+
+    /// Extended address of the general-purpose event 0 register block
+    x_gpe0_blk: RawGenericAddress,
+
+    /// Extended address of the general-purpose event 1 register block
+    x_gpe1_blk: RawGenericAddress,
+
+    /// Sleep control register for hardware-reduced ACPI
+    sleep_control_reg: RawGenericAddress,
+
+    /// Sleep status register for hardware-reduced ACPI
+    sleep_status_reg: RawGenericAddress,
+
+    /// Hypervisor vendor identity
+    hypervisor_vendor_identity: u64,
+}
+
+/// Pick the register block for a field with both a 32-bit I/O port and an
+/// `X_` Generic Address Structure. `len` is the length in bytes of the
+/// 32-bit block.
+fn register_block(x_block: RawGenericAddress, block: u32, len: u8)
+        -> Option<GenericAddress> {
+    let x_block = GenericAddress::from(x_block);
+    if !x_block.is_null() {
+        Some(x_block)
+    } else if block != 0 {
+        Some(GenericAddress {
+            space: AddressSpace::SystemIo,
+            bit_width: len.wrapping_mul(8),
+            bit_offset: 0,
+            access_size: 0,
+            address: block as u64,
+        })
+    } else {
+        None
+    }
+}
+
+impl Fadt {
+    /// Process the payload of the FADT (everything after the table header)
+    /// at `addr` for `size` bytes, read through `mem`. `revision` is the table
+    /// revision from the header.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize, revision: u8) -> Result<Self> {
+        // The ACPI 1.0 FADT ends with the flags
+        if size < 116 - 36 {
+            return Err(Error::LengthMismatch(TableType::Fadt));
+        }
+
+        // Fields past the end of the table are treated as zero, not present
+        let mut bytes = [0u8; size_of::<RawFadt>()];
+        let len = core::cmp::min(size, bytes.len());
+        bytes[..len].copy_from_slice(mem.bytes(addr, len)
+            .map_err(|_| Error::Unreadable(addr.0))?);
+        let fadt = core::ptr::read_unaligned(bytes.as_ptr() as *const RawFadt);
+
+        let flags = fadt.flags;
+
+        // The PM timer block is always 32 bits, if it's anything else the
+        // timer is not supported
+        let pm_timer = register_block(fadt.x_pm_tmr_blk, fadt.pm_tmr_blk,
+                fadt.pm_tmr_len)
+            .filter(|x| x.bit_width == 32)
+            .map(|register| PmTimer {
+                register,
+                width: if flags & TMR_VAL_EXT != 0 { 32 } else { 24 },
+            });
+
+        let reset = Some(GenericAddress::from(fadt.reset_reg))
+            .filter(|x| flags & RESET_REG_SUP != 0 && !x.is_null())
+            .map(|register| ResetRegister {
+                register,
+                value: fadt.reset_value,
+            });
+
+        // The boot architecture flags were reserved in ACPI 1.0, where every
+        // PC had its legacy devices
+        let boot_arch = if revision >= 2 {
+            fadt.iapc_boot_arch.into()
+        } else {
+            (LEGACY_DEVICES | I8042).into()
+        };
+
+        Ok(Fadt {
+            revision,
+            facs: if fadt.x_firmware_ctrl != 0 {
+                fadt.x_firmware_ctrl
+            } else {
+                fadt.firmware_ctrl as u64
+            },
+            dsdt: if fadt.x_dsdt != 0 {
+                fadt.x_dsdt
+            } else {
+                fadt.dsdt as u64
+            },
+            sci_interrupt: fadt.sci_int,
+            smi_command: fadt.smi_cmd,
+            acpi_enable: fadt.acpi_enable,
+            acpi_disable: fadt.acpi_disable,
+            pm1a_event: register_block(fadt.x_pm1a_evt_blk, fadt.pm1a_evt_blk,
+                fadt.pm1_evt_len),
+            pm1b_event: register_block(fadt.x_pm1b_evt_blk, fadt.pm1b_evt_blk,
+                fadt.pm1_evt_len),
+            pm1a_control: register_block(fadt.x_pm1a_cnt_blk,
+                fadt.pm1a_cnt_blk, fadt.pm1_cnt_len),
+            pm1b_control: register_block(fadt.x_pm1b_cnt_blk,
+                fadt.pm1b_cnt_blk, fadt.pm1_cnt_len),
+            pm_timer,
+            reset,
+            century: fadt.century,
+            boot_arch,
+            flags,
+        })
+    }
+
+    /// Returns `true` if this is a hardware-reduced ACPI platform, without
+    /// the fixed hardware registers.
+    pub fn hardware_reduced(&self) -> bool {
+        self.flags & HW_REDUCED_ACPI != 0
+    }
+}
@@ -0,0 +1,854 @@
+//! ACPI tables for the host tests, laid out in a byte buffer which stands in
+//! for physical memory. Tables captured from real firmware live in
+//! `tests/data`, and synthesized tables mirror machines we have no capture
+//! of and cover malformed cases.
+
+use std::path::Path;
+use std::vec::Vec;
+
+use super::cper::Guid;
+use super::{discover, decode, Acpi, AcpiTables, Policy, Result};
+use crate::efi::mock::MockFirmware;
+use crate::mm::physmem::{BufferMemory, PhysAddr};
+
+/// Physical address fixture images are placed at by default.
+pub const BASE: u64 = 0x7ffd_0000;
+
+/// Offset of the checksum in a table header.
+const CHECKSUM: usize = 9;
+
+/// Offsets of the DSDT and X_DSDT pointers in the FADT.
+const FADT_DSDT: usize = 40;
+const FADT_X_DSDT: usize = 140;
+
+/// Offsets of the FACS pointers in the FADT.
+const FADT_FACS: usize = 36;
+const FADT_X_FACS: usize = 132;
+
+/// Size of the extended RSDP.
+const RSDP_SIZE: usize = 36;
+
+/// Directory of the captured tables, a directory per machine.
+const CAPTURED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+
+/// Set the checksum byte at `offset` so `bytes` sums to zero.
+pub fn fix_checksum(bytes: &mut [u8], offset: usize) {
+    bytes[offset] = 0;
+    let sum = bytes.iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
+    bytes[offset] = sum.wrapping_neg();
+}
+
+/// Build a table with `signature` and `revision` around `payload`, with the
+/// header QEMU fills in and a valid checksum.
+pub fn table(signature: &[u8; 4], revision: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(signature);
+    bytes.extend_from_slice(&(36 + payload.len() as u32).to_le_bytes());
+    bytes.push(revision);
+    bytes.push(0);
+    bytes.extend_from_slice(b"BOCHS ");
+    bytes.extend_from_slice(b"BXPC");
+    bytes.extend_from_slice(signature);
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(b"BXPC");
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(payload);
+    fix_checksum(&mut bytes, CHECKSUM);
+    bytes
+}
+
+/// A raw Generic Address Structure.
+pub fn gas(space: u8, bit_width: u8, access_size: u8, address: u64)
+        -> [u8; 12] {
+    let mut bytes = [0u8; 12];
+    bytes[0] = space;
+    bytes[1] = bit_width;
+    bytes[3] = access_size;
+    bytes[4..].copy_from_slice(&address.to_le_bytes());
+    bytes
+}
+
+/// An MADT, built one interrupt controller structure at a time.
+pub struct MadtBuilder(Vec<u8>);
+
+impl MadtBuilder {
+    /// Start an MADT with the local APIC at `local_apic_addr`.
+    pub fn new(local_apic_addr: u32, pcat_compat: bool) -> Self {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&local_apic_addr.to_le_bytes());
+        bytes.extend_from_slice(&(pcat_compat as u32).to_le_bytes());
+        MadtBuilder(bytes)
+    }
+
+    /// Add a structure of `typ` with `body`, the length is filled in.
+    pub fn raw(mut self, typ: u8, body: &[u8]) -> Self {
+        self.0.push(typ);
+        self.0.push(2 + body.len() as u8);
+        self.0.extend_from_slice(body);
+        self
+    }
+
+    /// Add a Processor Local APIC structure.
+    pub fn local_apic(self, uid: u8, apic_id: u8, flags: u32) -> Self {
+        let mut body = vec![uid, apic_id];
+        body.extend_from_slice(&flags.to_le_bytes());
+        self.raw(0, &body)
+    }
+
+    /// Add an I/O APIC structure.
+    pub fn io_apic(self, id: u8, address: u32, gsi_base: u32) -> Self {
+        let mut body = vec![id, 0];
+        body.extend_from_slice(&address.to_le_bytes());
+        body.extend_from_slice(&gsi_base.to_le_bytes());
+        self.raw(1, &body)
+    }
+
+    /// Add an ISA Interrupt Source Override structure.
+    pub fn interrupt_override(self, source: u8, gsi: u32, flags: u16) -> Self {
+        let mut body = vec![0, source];
+        body.extend_from_slice(&gsi.to_le_bytes());
+        body.extend_from_slice(&flags.to_le_bytes());
+        self.raw(2, &body)
+    }
+
+    /// Add a Local APIC NMI structure.
+    pub fn local_apic_nmi(self, uid: u8, flags: u16, lint: u8) -> Self {
+        let mut body = vec![uid];
+        body.extend_from_slice(&flags.to_le_bytes());
+        body.push(lint);
+        self.raw(4, &body)
+    }
+
+    /// Add a Processor Local x2APIC structure.
+    pub fn x2apic(self, uid: u32, x2apic_id: u32, flags: u32) -> Self {
+        let mut body = vec![0, 0];
+        body.extend_from_slice(&x2apic_id.to_le_bytes());
+        body.extend_from_slice(&flags.to_le_bytes());
+        body.extend_from_slice(&uid.to_le_bytes());
+        self.raw(9, &body)
+    }
+
+    /// Add a Local x2APIC NMI structure.
+    pub fn x2apic_nmi(self, uid: u32, flags: u16, lint: u8) -> Self {
+        let mut body = Vec::new();
+        body.extend_from_slice(&flags.to_le_bytes());
+        body.extend_from_slice(&uid.to_le_bytes());
+        body.extend_from_slice(&[lint, 0, 0, 0]);
+        self.raw(0xa, &body)
+    }
+
+    /// Build the table.
+    pub fn build(self) -> Vec<u8> {
+        table(b"APIC", 3, &self.0)
+    }
+}
+
+/// An SRAT, built one affinity structure at a time.
+pub struct SratBuilder(Vec<u8>);
+
+impl SratBuilder {
+    /// Start an SRAT.
+    pub fn new() -> Self {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        SratBuilder(bytes)
+    }
+
+    /// Add an enabled Processor Local APIC Affinity structure.
+    pub fn cpu(mut self, apic_id: u8, domain: u32) -> Self {
+        let domain = domain.to_le_bytes();
+        self.0.extend_from_slice(&[0, 16, domain[0], apic_id]);
+        self.0.extend_from_slice(&1u32.to_le_bytes());
+        self.0.extend_from_slice(&[0, domain[1], domain[2], domain[3]]);
+        self.0.extend_from_slice(&0u32.to_le_bytes());
+        self
+    }
+
+    /// Add an enabled Memory Affinity structure.
+    pub fn memory(self, domain: u32, base: u64, length: u64) -> Self {
+        self.memory_affinity(domain, base, length, 1)
+    }
+
+    /// Add an enabled, hot-pluggable Memory Affinity structure.
+    pub fn hotplug_memory(self, domain: u32, base: u64, length: u64)
+            -> Self {
+        self.memory_affinity(domain, base, length, 3)
+    }
+
+    /// Add a Memory Affinity structure with `flags`.
+    fn memory_affinity(mut self, domain: u32, base: u64, length: u64,
+            flags: u32) -> Self {
+        self.0.extend_from_slice(&[1, 40]);
+        self.0.extend_from_slice(&domain.to_le_bytes());
+        self.0.extend_from_slice(&[0, 0]);
+        self.0.extend_from_slice(&base.to_le_bytes());
+        self.0.extend_from_slice(&length.to_le_bytes());
+        self.0.extend_from_slice(&0u32.to_le_bytes());
+        self.0.extend_from_slice(&flags.to_le_bytes());
+        self.0.extend_from_slice(&0u64.to_le_bytes());
+        self
+    }
+
+    /// Add an enabled Processor Local x2APIC Affinity structure.
+    pub fn x2apic_cpu(mut self, x2apic_id: u32, domain: u32) -> Self {
+        self.0.extend_from_slice(&[2, 24, 0, 0]);
+        self.0.extend_from_slice(&domain.to_le_bytes());
+        self.0.extend_from_slice(&x2apic_id.to_le_bytes());
+        self.0.extend_from_slice(&1u32.to_le_bytes());
+        self.0.extend_from_slice(&0u32.to_le_bytes());
+        self.0.extend_from_slice(&0u32.to_le_bytes());
+        self
+    }
+
+    /// Build the table.
+    pub fn build(self) -> Vec<u8> {
+        table(b"SRAT", 3, &self.0)
+    }
+}
+
+/// A PPTT, built one structure at a time. Each method returns the offset
+/// other structures refer to the new one by.
+pub struct PpttBuilder(Vec<u8>);
+
+impl PpttBuilder {
+    /// Start a PPTT.
+    pub fn new() -> Self {
+        PpttBuilder(Vec::new())
+    }
+
+    /// The offset of the next structure from the start of the table.
+    fn offset(&self) -> u32 {
+        (36 + self.0.len()) as u32
+    }
+
+    /// Add a revision 3 cache of `size` bytes and cache type `typ` (0 data,
+    /// 1 instruction, 2 unified) with 64 byte lines. Its cache ID is its
+    /// offset.
+    pub fn cache(&mut self, size: u32, typ: u8, next_level: u32) -> u32 {
+        let offset = self.offset();
+        self.0.extend_from_slice(&[1, 28, 0, 0]);
+        self.0.extend_from_slice(&0xd1u32.to_le_bytes());
+        self.0.extend_from_slice(&next_level.to_le_bytes());
+        self.0.extend_from_slice(&size.to_le_bytes());
+        self.0.extend_from_slice(&0u32.to_le_bytes());
+        self.0.extend_from_slice(&[0, typ << 2]);
+        self.0.extend_from_slice(&64u16.to_le_bytes());
+        self.0.extend_from_slice(&offset.to_le_bytes());
+        offset
+    }
+
+    /// Add a processor hierarchy node.
+    pub fn node(&mut self, flags: u32, parent: u32, acpi_uid: u32,
+            resources: &[u32]) -> u32 {
+        let offset = self.offset();
+        self.0.extend_from_slice(&[0, 20 + 4 * resources.len() as u8, 0, 0]);
+        self.0.extend_from_slice(&flags.to_le_bytes());
+        self.0.extend_from_slice(&parent.to_le_bytes());
+        self.0.extend_from_slice(&acpi_uid.to_le_bytes());
+        self.0.extend_from_slice(&(resources.len() as u32).to_le_bytes());
+        for resource in resources {
+            self.0.extend_from_slice(&resource.to_le_bytes());
+        }
+        offset
+    }
+
+    /// Build the table.
+    pub fn build(self) -> Vec<u8> {
+        table(b"PPTT", 3, &self.0)
+    }
+}
+
+/// A SLIT with the distance matrix `distances`.
+pub fn slit(distances: &[&[u8]]) -> Vec<u8> {
+    let mut payload = (distances.len() as u64).to_le_bytes().to_vec();
+    for row in distances {
+        payload.extend_from_slice(row);
+    }
+    table(b"SLIT", 1, &payload)
+}
+
+/// An MCFG with an ECAM region for each `(base, segment, start, end)`.
+pub fn mcfg(regions: &[(u64, u16, u8, u8)]) -> Vec<u8> {
+    let mut payload = 0u64.to_le_bytes().to_vec();
+    for &(base, segment, start_bus, end_bus) in regions {
+        payload.extend_from_slice(&base.to_le_bytes());
+        payload.extend_from_slice(&segment.to_le_bytes());
+        payload.extend_from_slice(&[start_bus, end_bus, 0, 0, 0, 0]);
+    }
+    table(b"MCFG", 1, &payload)
+}
+
+/// QEMU's HPET table: 3 comparators, a 64-bit counter and legacy
+/// replacement, vendor 0x8086, at 0xfed00000.
+pub fn hpet() -> Vec<u8> {
+    let mut payload = 0x8086_a201u32.to_le_bytes().to_vec();
+    payload.extend_from_slice(&gas(0, 0, 0, 0xfed0_0000));
+    payload.push(0);
+    payload.extend_from_slice(&0u16.to_le_bytes());
+    payload.push(0);
+    table(b"HPET", 1, &payload)
+}
+
+/// A revision 3 FADT with the q35 ICH9 power management block at 0x600 and
+/// the reset register at 0xcf9. The DSDT pointers are filled in by
+/// `Firmware::build`.
+pub fn fadt() -> Vec<u8> {
+    let mut payload = vec![0u8; 244 - 36];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        payload[offset - 36..offset - 36 + bytes.len()]
+            .copy_from_slice(bytes);
+    };
+
+    // SCI, SMI command port, ACPI enable and disable
+    put(46, &9u16.to_le_bytes());
+    put(48, &0xb2u32.to_le_bytes());
+    put(52, &[0xf1, 0xf0]);
+
+    // PM1a event and control blocks and the PM timer
+    put(56, &0x600u32.to_le_bytes());
+    put(64, &0x604u32.to_le_bytes());
+    put(76, &0x608u32.to_le_bytes());
+    put(88, &[4, 2, 0, 4]);
+
+    // Century, IA-PC boot flags (8042) and flags (TMR_VAL_EXT, RESET_REG_SUP)
+    put(108, &[0x32]);
+    put(109, &2u16.to_le_bytes());
+    put(112, &((1u32 << 8) | (1 << 10)).to_le_bytes());
+
+    // Reset register
+    put(116, &gas(1, 8, 0, 0xcf9));
+    put(128, &[0x0f]);
+
+    table(b"FACP", 3, &payload)
+}
+
+/// A DMAR with one DRHD covering every device and an RMRR at `rmrr` for the
+/// USB controller at 00:1d.0.
+pub fn dmar(rmrr: (u64, u64)) -> Vec<u8> {
+    let mut payload = vec![38, 1];
+    payload.extend_from_slice(&[0; 10]);
+
+    // DRHD, INCLUDE_PCI_ALL
+    payload.extend_from_slice(&0u16.to_le_bytes());
+    payload.extend_from_slice(&16u16.to_le_bytes());
+    payload.extend_from_slice(&[1, 0, 0, 0]);
+    payload.extend_from_slice(&0xfed9_0000u64.to_le_bytes());
+
+    // RMRR with a PCI endpoint device scope
+    payload.extend_from_slice(&1u16.to_le_bytes());
+    payload.extend_from_slice(&32u16.to_le_bytes());
+    payload.extend_from_slice(&[0; 4]);
+    payload.extend_from_slice(&rmrr.0.to_le_bytes());
+    payload.extend_from_slice(&rmrr.1.to_le_bytes());
+    payload.extend_from_slice(&[1, 8, 0, 0, 0, 0, 0x1d, 0]);
+
+    table(b"DMAR", 1, &payload)
+}
+
+/// A HEST, built one error source structure at a time.
+pub struct HestBuilder(Vec<u8>, u32);
+
+impl HestBuilder {
+    /// Start a HEST.
+    pub fn new() -> Self {
+        HestBuilder(Vec::new(), 0)
+    }
+
+    /// Start an enabled error source with the common fields.
+    fn source(&mut self, typ: u16, source_id: u16, flags: u8) {
+        self.1 += 1;
+        self.0.extend_from_slice(&typ.to_le_bytes());
+        self.0.extend_from_slice(&source_id.to_le_bytes());
+        self.0.extend_from_slice(&[0, 0, flags, 1]);
+        self.0.extend_from_slice(&1u32.to_le_bytes());
+        self.0.extend_from_slice(&1u32.to_le_bytes());
+    }
+
+    /// Add a notification structure of type `typ`.
+    fn notification(&mut self, typ: u8) {
+        self.0.extend_from_slice(&[typ, 28]);
+        self.0.extend_from_slice(&[0; 26]);
+    }
+
+    /// Add an IA-32 machine check source with `banks` banks.
+    pub fn machine_check(mut self, source_id: u16, banks: u8) -> Self {
+        self.source(0, source_id, 0);
+        self.0.extend_from_slice(&[0; 16]);
+        self.0.extend_from_slice(&[banks, 0, 0, 0, 0, 0, 0, 0]);
+        self.0.extend_from_slice(&vec![0; banks as usize * 28]);
+        self
+    }
+
+    /// Add an IA-32 corrected machine check source polled every second,
+    /// with `banks` banks.
+    pub fn corrected_machine_check(mut self, source_id: u16, banks: u8)
+            -> Self {
+        self.source(1, source_id, 0);
+        self.notification(0);
+        self.0.extend_from_slice(&[banks, 0, 0, 0]);
+        self.0.extend_from_slice(&vec![0; banks as usize * 28]);
+        self
+    }
+
+    /// Add an IA-32 NMI source.
+    pub fn nmi(mut self, source_id: u16) -> Self {
+        self.1 += 1;
+        self.0.extend_from_slice(&2u16.to_le_bytes());
+        self.0.extend_from_slice(&source_id.to_le_bytes());
+        self.0.extend_from_slice(&[0; 16]);
+        self
+    }
+
+    /// Add a PCI Express root port AER source.
+    pub fn pcie_root_port(mut self, source_id: u16) -> Self {
+        self.source(6, source_id, 0x2);
+        self.0.extend_from_slice(&[0; 32]);
+        self
+    }
+
+    /// Add a firmware first version 2 generic hardware error source,
+    /// notified through an SCI, whose error status block address is in the
+    /// register at `status_address`.
+    pub fn generic_v2(mut self, source_id: u16, status_address: u64)
+            -> Self {
+        self.source(10, source_id, 0x1);
+        self.0.extend_from_slice(&4096u32.to_le_bytes());
+        self.0.extend_from_slice(&gas(0, 64, 4, status_address));
+        self.notification(3);
+        self.0.extend_from_slice(&4096u32.to_le_bytes());
+        self.0.extend_from_slice(&[0; 28]);
+        self
+    }
+
+    /// Build the table.
+    pub fn build(self) -> Vec<u8> {
+        let mut payload = self.1.to_le_bytes().to_vec();
+        payload.extend_from_slice(&self.0);
+        table(b"HEST", 1, &payload)
+    }
+}
+
+/// The bytes of a GUID in the mixed endian layout UEFI uses.
+pub fn guid(guid: Guid) -> Vec<u8> {
+    let Guid(a, b, c, d) = guid;
+    let mut bytes = a.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&b.to_le_bytes());
+    bytes.extend_from_slice(&c.to_le_bytes());
+    bytes.extend_from_slice(&d);
+    bytes
+}
+
+/// A generic error status block with `severity` holding a revision 3 CPER
+/// section for each `(section type, severity, FRU text, data)`.
+pub fn error_status(severity: u32, sections: &[(Guid, u32, &str, &[u8])])
+        -> Vec<u8> {
+    let mut data = Vec::new();
+    for &(typ, severity, fru_text, section) in sections {
+        data.extend_from_slice(&guid(typ));
+        data.extend_from_slice(&severity.to_le_bytes());
+        data.extend_from_slice(&0x300u16.to_le_bytes());
+
+        // FRU text and timestamp valid
+        data.extend_from_slice(&[0x6, 0]);
+        data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        let mut text = [0; 20];
+        text[..fru_text.len()].copy_from_slice(fru_text.as_bytes());
+        data.extend_from_slice(&text);
+        data.extend_from_slice(&0x2026_1018_0012_3456u64.to_le_bytes());
+        data.extend_from_slice(section);
+    }
+
+    // Uncorrectable, with the number of sections
+    let status = 1 | (sections.len() as u32) << 4;
+    let mut bytes = status.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&severity.to_le_bytes());
+    bytes.extend_from_slice(&data);
+    bytes
+}
+
+/// AML declaring `Name (_S5, Package (0x04) { Zero, Zero, Zero, Zero })` as
+/// in the q35 DSDT.
+pub const S5_AML: &[u8] =
+    &[0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00];
+
+/// The firmware tables of a machine, before they are laid out in memory.
+pub struct Firmware {
+    /// Use an ACPI 1.0 RSDP and an RSDT instead of an XSDT.
+    acpi1: bool,
+
+    /// The tables pointed to by the XSDT or RSDT.
+    tables: Vec<Vec<u8>>,
+
+    /// The AML of the DSDT, `None` for no DSDT.
+    dsdt: Option<Vec<u8>>,
+
+    /// The FACS, `None` for no FACS.
+    facs: Option<Vec<u8>>,
+}
+
+impl Firmware {
+    /// ACPI 2.0 firmware with an XSDT and no tables.
+    pub fn new() -> Self {
+        Firmware {
+            acpi1: false,
+            tables: Vec::new(),
+            dsdt: None,
+            facs: None,
+        }
+    }
+
+    /// Use an ACPI 1.0 RSDP and an RSDT.
+    pub fn acpi1(mut self) -> Self {
+        self.acpi1 = true;
+        self
+    }
+
+    /// Add `table` to the XSDT or RSDT.
+    pub fn table(mut self, table: Vec<u8>) -> Self {
+        self.tables.push(table);
+        self
+    }
+
+    /// Add a DSDT with `aml`, pointed to by the FADT.
+    pub fn dsdt(mut self, aml: &[u8]) -> Self {
+        self.dsdt = Some(table(b"DSDT", 2, aml));
+        self
+    }
+
+    /// Load the tables captured from `machine`, the `acpixtract` binaries
+    /// (one `<signature>.dat` per table) in its directory under `tests/data`.
+    /// The tables are used as captured, only the FADT pointers to the DSDT
+    /// and FACS are moved to where `build_at` places them.
+    pub fn captured(machine: &str) -> Self {
+        let dir = Path::new(CAPTURED).join(machine);
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension() == Some("dat".as_ref()))
+            .collect();
+        paths.sort();
+
+        let mut firmware = Firmware::new();
+        for path in paths {
+            let table = std::fs::read(&path)
+                .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            match &table[..4] {
+                b"DSDT" => firmware.dsdt = Some(table),
+                b"FACS" => firmware.facs = Some(table),
+                _ => firmware.tables.push(table),
+            }
+        }
+        firmware
+    }
+
+    /// Add a version 2 FACS, pointed to by the FADT.
+    pub fn facs(mut self) -> Self {
+        let mut facs = vec![0u8; 64];
+        facs[..4].copy_from_slice(b"FACS");
+        facs[4..8].copy_from_slice(&64u32.to_le_bytes());
+        facs[8..12].copy_from_slice(&0x1234u32.to_le_bytes());
+        facs[32] = 2;
+        self.facs = Some(facs);
+        self
+    }
+
+    /// Lay the tables out in memory at `BASE`.
+    pub fn build(self) -> Image {
+        self.build_at(BASE)
+    }
+
+    /// Lay the tables out in memory at `base`: the RSDP, the XSDT or RSDT,
+    /// the tables in order and the DSDT, each 16 byte aligned, and the FACS
+    /// 64 byte aligned.
+    pub fn build_at(mut self, base: u64) -> Image {
+        let align = |x: usize| (x + 15) & !15;
+        let entry_size = if self.acpi1 { 4 } else { 8 };
+
+        // Place everything
+        let root = align(RSDP_SIZE);
+        let mut offset = align(root + 36 + self.tables.len() * entry_size);
+        let mut offsets = Vec::new();
+        for table in &self.tables {
+            offsets.push(offset);
+            offset = align(offset + table.len());
+        }
+        let dsdt = offset;
+        if let Some(table) = &self.dsdt {
+            offset = align(offset + table.len());
+        }
+        let facs = (offset + 63) & !63;
+        if let Some(table) = &self.facs {
+            offset = facs + table.len();
+        }
+
+        // Point the FADT at the DSDT
+        if self.dsdt.is_some() {
+            let dsdt_addr = base + dsdt as u64;
+            for fadt in self.tables.iter_mut().filter(|x| &x[..4] == b"FACP") {
+                fadt[FADT_DSDT..FADT_DSDT + 4]
+                    .copy_from_slice(&(dsdt_addr as u32).to_le_bytes());
+                if fadt.len() >= FADT_X_DSDT + 8 {
+                    fadt[FADT_X_DSDT..FADT_X_DSDT + 8]
+                        .copy_from_slice(&dsdt_addr.to_le_bytes());
+                }
+                fix_checksum(fadt, CHECKSUM);
+            }
+        }
+
+        // Point the FADT at the FACS
+        if self.facs.is_some() {
+            let facs_addr = base + facs as u64;
+            for fadt in self.tables.iter_mut().filter(|x| &x[..4] == b"FACP") {
+                fadt[FADT_FACS..FADT_FACS + 4]
+                    .copy_from_slice(&(facs_addr as u32).to_le_bytes());
+                if fadt.len() >= FADT_X_FACS + 8 {
+                    fadt[FADT_X_FACS..FADT_X_FACS + 8]
+                        .copy_from_slice(&facs_addr.to_le_bytes());
+                }
+                fix_checksum(fadt, CHECKSUM);
+            }
+        }
+
+        let mut bytes = vec![0u8; offset];
+        let mut image_tables = Vec::new();
+
+        // The XSDT or RSDT
+        let mut entries = Vec::new();
+        for &offset in &offsets {
+            let addr = base + offset as u64;
+            if self.acpi1 {
+                entries.extend_from_slice(&(addr as u32).to_le_bytes());
+            } else {
+                entries.extend_from_slice(&addr.to_le_bytes());
+            }
+        }
+        let root_table = if self.acpi1 {
+            table(b"RSDT", 1, &entries)
+        } else {
+            table(b"XSDT", 1, &entries)
+        };
+        bytes[root..root + root_table.len()].copy_from_slice(&root_table);
+        image_tables.push((root, root_table.len()));
+
+        // The tables
+        for (table, &offset) in self.tables.iter().zip(&offsets) {
+            bytes[offset..offset + table.len()].copy_from_slice(table);
+            image_tables.push((offset, table.len()));
+        }
+        if let Some(table) = &self.dsdt {
+            bytes[dsdt..dsdt + table.len()].copy_from_slice(table);
+            image_tables.push((dsdt, table.len()));
+        }
+        if let Some(table) = &self.facs {
+            bytes[facs..facs + table.len()].copy_from_slice(table);
+            image_tables.push((facs, table.len()));
+        }
+
+        // The RSDP
+        let root_addr = base + root as u64;
+        bytes[..8].copy_from_slice(b"RSD PTR ");
+        bytes[9..15].copy_from_slice(b"BOCHS ");
+        if self.acpi1 {
+            bytes[16..20].copy_from_slice(&(root_addr as u32).to_le_bytes());
+        } else {
+            bytes[15] = 2;
+            bytes[20..24].copy_from_slice(&(RSDP_SIZE as u32).to_le_bytes());
+            bytes[24..32].copy_from_slice(&root_addr.to_le_bytes());
+        }
+
+        let mut image = Image {
+            base,
+            bytes,
+            tables: image_tables,
+            policy: Policy::Strict,
+        };
+        image.fix_rsdp();
+        image
+    }
+}
+
+/// Firmware tables laid out in a buffer standing in for physical memory.
+pub struct Image {
+    /// Physical address of the start of `bytes`, where the RSDP is.
+    pub base: u64,
+
+    /// The memory contents.
+    pub bytes: Vec<u8>,
+
+    /// The offset and length of every table but the RSDP.
+    tables: Vec<(usize, usize)>,
+
+    /// The policy to parse the tables with.
+    policy: Policy,
+}
+
+impl Image {
+    /// Parse the tables with the lenient policy.
+    pub fn lenient(mut self) -> Self {
+        self.policy = Policy::Lenient;
+        self
+    }
+
+    /// Physical address of the RSDP.
+    pub fn rsdp(&self) -> PhysAddr {
+        PhysAddr(self.base)
+    }
+
+    /// The buffer as memory.
+    pub fn memory(&self) -> BufferMemory<'_> {
+        BufferMemory::new(self.base, &self.bytes)
+    }
+
+    /// Get the bytes of the first table with `signature`, as laid out.
+    pub fn table_mut(&mut self, signature: &[u8; 4]) -> &mut [u8] {
+        let bytes = &self.bytes;
+        let &(offset, len) = self.tables.iter()
+            .find(|&&(offset, _)| &bytes[offset..offset + 4] == signature)
+            .expect("no such table");
+        &mut self.bytes[offset..offset + len]
+    }
+
+    /// Change the length of the first table with `signature` to `length`,
+    /// keeping the checksum valid. The table runs into whatever follows it.
+    pub fn set_length(&mut self, signature: &[u8; 4], length: u32) {
+        let bytes = &self.bytes;
+        let entry = self.tables.iter_mut()
+            .find(|(offset, _)| &bytes[*offset..*offset + 4] == signature)
+            .expect("no such table");
+        entry.1 = length as usize;
+        let table = self.table_mut(signature);
+        table[4..8].copy_from_slice(&length.to_le_bytes());
+        fix_checksum(table, CHECKSUM);
+    }
+
+    /// Fix the checksum of the first table with `signature` after tampering
+    /// with it.
+    pub fn fix_checksum(&mut self, signature: &[u8; 4]) {
+        fix_checksum(self.table_mut(signature), CHECKSUM);
+    }
+
+    /// Fix both RSDP checksums after tampering with it.
+    pub fn fix_rsdp(&mut self) {
+        fix_checksum(&mut self.bytes[..20], 8);
+        fix_checksum(&mut self.bytes[..RSDP_SIZE], 32);
+    }
+
+    /// Find every table, as `acpi::tables` would on this firmware.
+    pub fn discover(&self) -> Result<AcpiTables> {
+        unsafe { discover(&self.memory(), self.rsdp(), self.policy) }
+    }
+
+    /// Find and decode every table, as `acpi::init` would on this firmware
+    /// running on the processor with `bsp_apic_id`. The console output goes
+    /// to a mock firmware.
+    pub fn decode(&self, bsp_apic_id: u32) -> Result<Acpi> {
+        let (_fw, ret) = MockFirmware::new().install();
+        ret.expect("mock firmware failed to install");
+        unsafe {
+            decode(&self.memory(), self.discover()?, bsp_apic_id, self.policy)
+        }
+    }
+}
+
+/// QEMU q35 with 4 processors: FADT, DSDT, FACS, MADT, HPET, MCFG and WAET.
+pub fn q35() -> Firmware {
+    Firmware::new()
+        .table(fadt())
+        .table(MadtBuilder::new(0xfee0_0000, true)
+            .local_apic(0, 0, 1)
+            .local_apic(1, 1, 1)
+            .local_apic(2, 2, 1)
+            .local_apic(3, 3, 1)
+            .io_apic(0, 0xfec0_0000, 0)
+            .interrupt_override(0, 2, 0)
+            .interrupt_override(5, 5, 0xd)
+            .interrupt_override(9, 9, 0xd)
+            .interrupt_override(10, 10, 0xd)
+            .interrupt_override(11, 11, 0xd)
+            .local_apic_nmi(0xff, 0, 1)
+            .build())
+        .table(hpet())
+        .table(mcfg(&[(0xb000_0000, 0, 0, 255)]))
+        .table(table(b"WAET", 1, &2u32.to_le_bytes()))
+        .dsdt(S5_AML)
+        .facs()
+}
+
+/// A two socket server with 4 cores per socket, one I/O APIC and NUMA node
+/// per socket, and a VT-d IOMMU with an RMRR for USB. Each socket has an L3,
+/// each pair of cores an L2 and each core its own L1 caches.
+pub fn multi_socket() -> Firmware {
+    let mut madt = MadtBuilder::new(0xfee0_0000, true);
+    for socket in 0..2u8 {
+        for core in 0..4u8 {
+            madt = madt.local_apic(socket * 4 + core, socket * 16 + core, 1);
+        }
+    }
+
+    let mut pptt = PpttBuilder::new();
+    for socket in 0..2u32 {
+        let l3 = pptt.cache(32 << 20, 2, 0);
+        let package = pptt.node(0x3, 0, socket, &[l3]);
+        for pair in 0..2u32 {
+            let l2 = pptt.cache(1 << 20, 2, l3);
+            let cluster = pptt.node(0, package, 0, &[l2]);
+            for core in 0..2u32 {
+                let l1d = pptt.cache(48 << 10, 0, l2);
+                let l1i = pptt.cache(32 << 10, 1, l2);
+                pptt.node(0xa, cluster, socket * 4 + pair * 2 + core,
+                    &[l1d, l1i]);
+            }
+        }
+    }
+
+    Firmware::new()
+        .table(fadt())
+        .table(madt
+            .io_apic(8, 0xfec0_0000, 0)
+            .io_apic(9, 0xfec0_1000, 24)
+            .interrupt_override(0, 2, 0)
+            .interrupt_override(9, 9, 0xd)
+            .local_apic_nmi(0xff, 0x5, 1)
+            .build())
+        .table(SratBuilder::new()
+            .cpu(0, 0).cpu(1, 0).cpu(2, 0).cpu(3, 0)
+            .cpu(16, 1).cpu(17, 1).cpu(18, 1).cpu(19, 1)
+            .memory(0, 0, 0xa0000)
+            .memory(0, 0x10_0000, 0x7ff0_0000)
+            .memory(1, 0x1_0000_0000, 0x8000_0000)
+            .build())
+        .table(slit(&[&[10, 21], &[21, 10]]))
+        .table(mcfg(&[(0x8000_0000, 0, 0, 127), (0x9000_0000, 1, 128, 255)]))
+        .table(dmar((0x7b80_0000, 0x7b9f_ffff)))
+        .table(pptt.build())
+        .dsdt(S5_AML)
+}
+
+/// A guest with more than 255 processors' worth of APIC IDs, described only
+/// with x2APIC structures.
+pub fn x2apic_only() -> Firmware {
+    Firmware::new()
+        .table(fadt())
+        .table(MadtBuilder::new(0xfee0_0000, false)
+            .x2apic(0, 0x100, 1)
+            .x2apic(1, 0x101, 1)
+            .x2apic(2, 0x102, 1)
+            .x2apic(3, 0x103, 0)
+            .io_apic(0, 0xfec0_0000, 0)
+            .x2apic_nmi(0xffff_ffff, 0, 1)
+            .x2apic_nmi(2, 0x5, 0)
+            .build())
+        .table(SratBuilder::new()
+            .x2apic_cpu(0x100, 0)
+            .x2apic_cpu(0x101, 0)
+            .x2apic_cpu(0x102, 1)
+            .memory(0, 0, 0x8000_0000)
+            .memory(1, 0x1_0000_0000, 0x8000_0000)
+            .build())
+        .dsdt(S5_AML)
+}
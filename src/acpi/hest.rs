@@ -0,0 +1,398 @@
+//! Hardware Error Source Table (HEST) parsing, the sources of hardware
+//! errors the platform reports to the OS.
+
+use super::{Error, GenericAddress, RawGenericAddress, Result, TableType};
+use crate::fixed_vec::FixedVec;
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The maximum number of error sources we can track.
+pub const MAX_ERROR_SOURCES: usize = 64;
+
+/// Error source flag: the source is a firmware first source, the firmware
+/// handles its errors before the OS sees them.
+const FLAG_FIRMWARE_FIRST: u8 = 1 << 0;
+
+/// Error source flag: the source applies to every bank or device of its
+/// kind.
+const FLAG_GLOBAL: u8 = 1 << 1;
+
+/// Size of an IA-32 machine check bank structure.
+const BANK_SIZE: usize = 28;
+
+/// The kind of an error source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ErrorSourceType {
+    /// IA-32 machine check exceptions.
+    #[default]
+    MachineCheck,
+
+    /// IA-32 corrected machine checks.
+    CorrectedMachineCheck,
+
+    /// Errors reported through a non-maskable interrupt.
+    Nmi,
+
+    /// PCI Express root port advanced error reporting.
+    PcieRootPort,
+
+    /// PCI Express endpoint advanced error reporting.
+    PcieDevice,
+
+    /// PCI Express bridge advanced error reporting.
+    PcieBridge,
+
+    /// A generic hardware error source, errors reported as CPER through an
+    /// error status block.
+    Generic,
+
+    /// A generic hardware error source which also needs acknowledging.
+    GenericV2,
+
+    /// IA-32 deferred machine checks.
+    DeferredMachineCheck,
+}
+
+impl ErrorSourceType {
+    /// Returns `true` if the source reports processor machine checks.
+    pub fn is_machine_check(&self) -> bool {
+        matches!(self, Self::MachineCheck | Self::CorrectedMachineCheck |
+            Self::DeferredMachineCheck)
+    }
+}
+
+/// How an error source tells the OS about an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notification {
+    /// The OS has to poll the source.
+    Polled,
+
+    /// An external interrupt.
+    ExternalInterrupt,
+
+    /// A local interrupt.
+    LocalInterrupt,
+
+    /// A system control interrupt.
+    Sci,
+
+    /// A non-maskable interrupt.
+    Nmi,
+
+    /// A corrected machine check interrupt.
+    Cmci,
+
+    /// A machine check exception.
+    Mce,
+
+    /// A GPIO signal.
+    Gpio,
+
+    /// An ARM synchronous external abort.
+    Sea,
+
+    /// An ARM SError interrupt.
+    Sei,
+
+    /// A GSIV interrupt.
+    Gsiv,
+
+    /// A software delegated exception.
+    SoftwareDelegated,
+
+    /// A reserved notification type.
+    Reserved(u8),
+}
+
+impl From<u8> for Notification {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::Polled,
+            1 => Self::ExternalInterrupt,
+            2 => Self::LocalInterrupt,
+            3 => Self::Sci,
+            4 => Self::Nmi,
+            5 => Self::Cmci,
+            6 => Self::Mce,
+            7 => Self::Gpio,
+            8 => Self::Sea,
+            9 => Self::Sei,
+            10 => Self::Gsiv,
+            11 => Self::SoftwareDelegated,
+            _ => Self::Reserved(val),
+        }
+    }
+}
+
+/// An error source described by the HEST.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ErrorSource {
+    /// The kind of error source.
+    pub typ: ErrorSourceType,
+
+    /// The firmware's identifier for the source.
+    pub source_id: u16,
+
+    /// The source is enabled.
+    pub enabled: bool,
+
+    /// The firmware handles the source's errors first.
+    pub firmware_first: bool,
+
+    /// The source applies to every bank or device of its kind.
+    pub global: bool,
+
+    /// The number of machine check banks, 0 for other sources.
+    pub banks: u8,
+
+    /// How the source tells the OS about an error, `None` for sources
+    /// without a notification structure.
+    pub notification: Option<Notification>,
+
+    /// For generic sources, the register holding the physical address of the
+    /// source's error status block.
+    pub error_status_address: Option<GenericAddress>,
+}
+
+/// The error sources described by the HEST.
+#[derive(Clone, Copy, Debug)]
+pub struct Hest {
+    /// The error sources.
+    sources: FixedVec<ErrorSource, MAX_ERROR_SOURCES>,
+}
+
+/// In-memory representation of the start of the error source structures
+/// with flags, every type but the NMI.
+#[repr(C, packed)]
+struct RawErrorSource {
+    /// Error source type
+    typ: u16,
+
+    /// Error source ID
+    source_id: u16,
+
+    /// The related source for a generic source, reserved otherwise
+    related_source_id: u16,
+
+    /// Firmware first and global flags
+    flags: u8,
+
+    /// The source is enabled
+    enabled: u8,
+
+    /// Number of error records to pre-allocate
+    records_to_preallocate: u32,
+
+    /// Maximum number of sections per record
+    max_sections_per_record: u32,
+}
+
+/// In-memory representation of a hardware error notification structure.
+#[repr(C, packed)]
+struct RawNotification {
+    /// Notification type
+    typ: u8,
+
+    /// Length of this structure
+    length: u8,
+
+    /// Which fields the OS may write
+    configuration_write_enable: u16,
+
+    /// Polling interval in milliseconds
+    poll_interval: u32,
+
+    /// Interrupt vector
+    vector: u32,
+
+    /// Errors in the window before switching to polling
+    switch_to_polling_threshold_value: u32,
+
+    /// The window in milliseconds for switching to polling
+    switch_to_polling_threshold_window: u32,
+
+    /// Errors in the window before reporting
+    error_threshold_value: u32,
+
+    /// The window in milliseconds for reporting
+    error_threshold_window: u32,
+}
+
+impl Hest {
+    /// Process the payload of the HEST (everything after the table header) at
+    /// `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the HEST is truncated
+        const E: Error = Error::LengthMismatch(TableType::Hest);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let count = slice.consume::<u32>().map_err(|_| E)?;
+
+        let mut sources = FixedVec::new();
+        for _ in 0..count {
+            // The structures have no length, it follows from the type
+            let typ = mem.read_unaligned::<u16>(slice.addr()).map_err(|_| E)?;
+            let source = match typ {
+                // IA-32 machine check and deferred machine check
+                0 | 11 => {
+                    let raw = slice.consume::<RawErrorSource>()
+                        .map_err(|_| E)?;
+                    let notification = if typ == 11 {
+                        Some(slice.consume::<RawNotification>()
+                            .map_err(|_| E)?.typ.into())
+                    } else {
+                        // Global capability and control init data
+                        slice.discard(16).map_err(|_| E)?;
+                        None
+                    };
+                    let banks = slice.consume::<u8>().map_err(|_| E)?;
+                    slice.discard(if typ == 11 { 3 } else { 7 })
+                        .map_err(|_| E)?;
+                    slice.discard(banks as usize * BANK_SIZE)
+                        .map_err(|_| E)?;
+                    ErrorSource {
+                        typ: if typ == 11 {
+                            ErrorSourceType::DeferredMachineCheck
+                        } else {
+                            ErrorSourceType::MachineCheck
+                        },
+                        banks,
+                        notification,
+                        ..ErrorSource::from(raw)
+                    }
+                }
+
+                // IA-32 corrected machine check
+                1 => {
+                    let raw = slice.consume::<RawErrorSource>()
+                        .map_err(|_| E)?;
+                    let notification = slice.consume::<RawNotification>()
+                        .map_err(|_| E)?;
+                    let banks = slice.consume::<u8>().map_err(|_| E)?;
+                    slice.discard(3 + banks as usize * BANK_SIZE)
+                        .map_err(|_| E)?;
+                    ErrorSource {
+                        typ: ErrorSourceType::CorrectedMachineCheck,
+                        banks,
+                        notification: Some(notification.typ.into()),
+                        ..ErrorSource::from(raw)
+                    }
+                }
+
+                // IA-32 NMI, which has no flags
+                2 => {
+                    /// In-memory representation of an NMI error source.
+                    #[repr(C, packed)]
+                    struct Nmi {
+                        /// Error source type
+                        typ: u16,
+
+                        /// Error source ID
+                        source_id: u16,
+
+                        /// Reserved
+                        reserved: u32,
+
+                        /// Number of error records to pre-allocate
+                        records_to_preallocate: u32,
+
+                        /// Maximum number of sections per record
+                        max_sections_per_record: u32,
+
+                        /// Maximum size of the raw error data
+                        max_raw_data_length: u32,
+                    }
+
+                    let nmi = slice.consume::<Nmi>().map_err(|_| E)?;
+                    ErrorSource {
+                        typ: ErrorSourceType::Nmi,
+                        source_id: nmi.source_id,
+                        enabled: true,
+                        notification: Some(Notification::Nmi),
+                        ..Default::default()
+                    }
+                }
+
+                // PCI Express root port, endpoint and bridge AER, which all
+                // start with the same fields
+                6..=8 => {
+                    let raw = slice.consume::<RawErrorSource>()
+                        .map_err(|_| E)?;
+                    let (typ, rest) = match typ {
+                        6 => (ErrorSourceType::PcieRootPort, 32),
+                        7 => (ErrorSourceType::PcieDevice, 28),
+                        _ => (ErrorSourceType::PcieBridge, 40),
+                    };
+                    slice.discard(rest).map_err(|_| E)?;
+                    ErrorSource { typ, ..ErrorSource::from(raw) }
+                }
+
+                // Generic hardware error source, version 1 and 2
+                9 | 10 => {
+                    let raw = slice.consume::<RawErrorSource>()
+                        .map_err(|_| E)?;
+
+                    // Skip the maximum raw data length
+                    slice.discard(4).map_err(|_| E)?;
+                    let address = slice.consume::<RawGenericAddress>()
+                        .map_err(|_| E)?;
+                    let notification = slice.consume::<RawNotification>()
+                        .map_err(|_| E)?;
+
+                    // Skip the block length, and the read ack register and
+                    // masks of version 2
+                    slice.discard(if typ == 10 { 32 } else { 4 })
+                        .map_err(|_| E)?;
+                    ErrorSource {
+                        typ: if typ == 10 {
+                            ErrorSourceType::GenericV2
+                        } else {
+                            ErrorSourceType::Generic
+                        },
+                        notification: Some(notification.typ.into()),
+                        error_status_address: Some(address.into()),
+                        ..ErrorSource::from(raw)
+                    }
+                }
+
+                // Without a length we can't step over other types
+                _ => return Err(E),
+            };
+
+            sources.push(source).map_err(|_| Error::TooManyErrorSources)?;
+        }
+
+        // Every source must have been accounted for
+        if slice.len() != 0 {
+            return Err(E);
+        }
+
+        Ok(Hest { sources })
+    }
+
+    /// Get the error sources.
+    pub fn sources(&self) -> &[ErrorSource] {
+        self.sources.entries()
+    }
+
+    /// Get the enabled sources which report processor machine checks.
+    pub fn machine_check_sources(&self)
+            -> impl Iterator<Item = &ErrorSource> {
+        self.sources().iter()
+            .filter(|x| x.enabled && x.typ.is_machine_check())
+    }
+}
+
+impl From<RawErrorSource> for ErrorSource {
+    fn from(val: RawErrorSource) -> Self {
+        ErrorSource {
+            source_id: val.source_id,
+            enabled: val.enabled != 0,
+            firmware_first: val.flags & FLAG_FIRMWARE_FIRST != 0,
+            global: val.flags & FLAG_GLOBAL != 0,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! High Precision Event Timer (HPET) table parsing.
+
+use super::{Error, GenericAddress, RawGenericAddress, Result, TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The HPET described by the HPET table.
+#[derive(Clone, Copy, Debug)]
+pub struct HpetTable {
+    /// The base address of the HPET register block.
+    pub base: GenericAddress,
+
+    /// Hardware revision ID.
+    pub revision: u8,
+
+    /// The number of comparators in the first timer block.
+    pub comparators: u8,
+
+    /// The main counter is 64 bits wide.
+    pub counter_64bit: bool,
+
+    /// The HPET can replace the legacy PIT and RTC interrupts.
+    pub legacy_replacement: bool,
+
+    /// PCI vendor ID of the first timer block.
+    pub vendor_id: u16,
+
+    /// The HPET sequence number.
+    pub number: u8,
+
+    /// The minimum clock tick in periodic mode without lost interrupts.
+    pub minimum_tick: u16,
+
+    /// Page protection and OEM attributes.
+    pub page_protection: u8,
+}
+
+/// In-memory representation of the HPET payload.
+#[repr(C, packed)]
+struct Hpet {
+    /// Hardware ID of the event timer block, the same layout as the low 32
+    /// bits of the general capabilities register
+    event_timer_block_id: u32,
+
+    /// The base address of the event timer block
+    base_address: RawGenericAddress,
+
+    /// The HPET sequence number
+    hpet_number: u8,
+
+    /// The minimum clock tick in periodic mode without lost interrupts
+    minimum_tick: u16,
+
+    /// Page protection and OEM attributes
+    page_protection: u8,
+}
+
+impl HpetTable {
+    /// Process the payload of the HPET table (everything after the table
+    /// header) at `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the HPET table is truncated
+        const E: Error = Error::LengthMismatch(TableType::Hpet);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let hpet = slice.consume::<Hpet>().map_err(|_| E)?;
+        let id = hpet.event_timer_block_id;
+
+        Ok(HpetTable {
+            base: hpet.base_address.into(),
+            revision: id as u8,
+            comparators: (((id >> 8) & 0x1f) + 1) as u8,
+            counter_64bit: id & (1 << 13) != 0,
+            legacy_replacement: id & (1 << 15) != 0,
+            vendor_id: (id >> 16) as u16,
+            number: hpet.hpet_number,
+            minimum_tick: hpet.minimum_tick,
+            page_protection: hpet.page_protection,
+        })
+    }
+}
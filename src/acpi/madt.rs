@@ -0,0 +1,678 @@
+//! Multiple APIC Description Table (MADT) parsing, and the CPU topology built
+//! from its Local APIC and x2APIC structures.
+
+use core::mem::size_of;
+
+use super::{Error, Result, TableType};
+use crate::fixed_vec::FixedVec;
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The maximum number of processors we can track.
+pub const MAX_CPUS: usize = 256;
+
+/// The maximum number of I/O APICs we can track.
+const MAX_IO_APICS: usize = 16;
+
+/// The maximum number of interrupt source overrides we can track.
+const MAX_OVERRIDES: usize = 32;
+
+/// The maximum number of NMI sources we can track.
+const MAX_NMI_SOURCES: usize = 16;
+
+/// MADT flag: the system has a PC-AT compatible dual 8259 setup which must be
+/// masked when using the APICs.
+const PCAT_COMPAT: u32 = 1 << 0;
+
+/// Local APIC flag: the processor is ready for use.
+const LAPIC_ENABLED: u32 = 1 << 0;
+
+/// Local APIC flag: the processor can be enabled at runtime. Only meaningful
+/// if `LAPIC_ENABLED` is clear.
+const LAPIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// A processor described by the MADT.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Processor {
+    /// The processor's local APIC ID, or x2APIC ID for x2APIC structures.
+    pub apic_id: u32,
+
+    /// The ACPI processor UID, matching the processor object in the
+    /// namespace (and processor IDs in other tables such as the PPTT).
+    pub acpi_uid: u32,
+
+    /// The processor is ready for use.
+    pub enabled: bool,
+
+    /// The processor is not enabled, but can be enabled at runtime.
+    pub online_capable: bool,
+
+    /// This is the bootstrap processor, the one we are running on.
+    pub bsp: bool,
+
+    /// The logical core number. The BSP is always 0, followed by the enabled
+    /// processors in APIC ID order and then the online capable ones. `None`
+    /// for processors which can never be used.
+    pub logical_id: Option<u32>,
+}
+
+/// Every processor described by the MADT.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuTopology {
+    /// Processors ordered by logical ID, unusable processors last.
+    cpus: FixedVec<Processor, MAX_CPUS>,
+}
+
+impl CpuTopology {
+    /// Create a new topology with no processors.
+    fn new() -> Self {
+        CpuTopology {
+            cpus: FixedVec::new(),
+        }
+    }
+
+    /// Add a processor from a Local APIC or x2APIC structure. Firmware may
+    /// describe the same processor with both, so duplicate APIC IDs are
+    /// ignored.
+    fn add(&mut self, apic_id: u32, acpi_uid: u32, flags: u32) -> Result<()> {
+        if self.by_apic_id(apic_id).is_some() {
+            return Ok(());
+        }
+
+        self.cpus.push(Processor {
+            apic_id,
+            acpi_uid,
+            enabled: flags & LAPIC_ENABLED != 0,
+            online_capable: flags & LAPIC_ENABLED == 0
+                && flags & LAPIC_ONLINE_CAPABLE != 0,
+            bsp: false,
+            logical_id: None,
+        }).map_err(|_| Error::TooManyProcessors)
+    }
+
+    /// Mark the BSP and assign the logical core numbers once all processors
+    /// have been added.
+    fn finalize(&mut self, bsp_apic_id: u32) {
+        for cpu in self.cpus.entries_mut() {
+            cpu.bsp = cpu.apic_id == bsp_apic_id;
+        }
+
+        // Order by: BSP, enabled, online capable, unusable. Then by APIC ID,
+        // which makes the numbering independent of the MADT order.
+        self.cpus.entries_mut().sort_unstable_by_key(|cpu| {
+            let class = if cpu.bsp {
+                0
+            } else if cpu.enabled {
+                1
+            } else if cpu.online_capable {
+                2
+            } else {
+                3
+            };
+            (class, cpu.apic_id)
+        });
+
+        let mut logical_id = 0;
+        for cpu in self.cpus.entries_mut() {
+            if cpu.bsp || cpu.enabled || cpu.online_capable {
+                cpu.logical_id = Some(logical_id);
+                logical_id += 1;
+            }
+        }
+    }
+
+    /// Get all processors, ordered by logical ID with unusable ones last.
+    pub fn processors(&self) -> &[Processor] {
+        self.cpus.entries()
+    }
+
+    /// Get the processors which are enabled and can be brought up now.
+    pub fn enabled(&self) -> impl Iterator<Item = &Processor> {
+        self.processors().iter().filter(|cpu| cpu.enabled)
+    }
+
+    /// Get the bootstrap processor.
+    pub fn bsp(&self) -> Option<&Processor> {
+        self.processors().iter().find(|cpu| cpu.bsp)
+    }
+
+    /// Find a processor by its APIC ID.
+    pub fn by_apic_id(&self, apic_id: u32) -> Option<&Processor> {
+        self.processors().iter().find(|cpu| cpu.apic_id == apic_id)
+    }
+
+    /// Find a processor by its ACPI processor UID.
+    pub fn by_acpi_uid(&self, acpi_uid: u32) -> Option<&Processor> {
+        self.processors().iter().find(|cpu| cpu.acpi_uid == acpi_uid)
+    }
+
+    /// Find a processor by its logical core number.
+    pub fn by_logical_id(&self, logical_id: u32) -> Option<&Processor> {
+        self.processors().get(logical_id as usize)
+            .filter(|cpu| cpu.logical_id == Some(logical_id))
+    }
+}
+
+/// Polarity of an interrupt input, from the MPS INTI flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// Conforms to the specifications of the bus.
+    ConformsToBus,
+
+    /// Active high.
+    ActiveHigh,
+
+    /// Active low.
+    ActiveLow,
+
+    /// Reserved encoding.
+    Reserved,
+}
+
+/// Trigger mode of an interrupt input, from the MPS INTI flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Conforms to the specifications of the bus.
+    ConformsToBus,
+
+    /// Edge triggered.
+    Edge,
+
+    /// Level triggered.
+    Level,
+
+    /// Reserved encoding.
+    Reserved,
+}
+
+/// Decoded MPS INTI flags, describing the polarity and trigger mode of an
+/// interrupt input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntiFlags {
+    /// Polarity of the interrupt.
+    pub polarity: Polarity,
+
+    /// Trigger mode of the interrupt.
+    pub trigger: TriggerMode,
+}
+
+impl From<u16> for IntiFlags {
+    fn from(val: u16) -> Self {
+        IntiFlags {
+            polarity: match val & 3 {
+                0 => Polarity::ConformsToBus,
+                1 => Polarity::ActiveHigh,
+                3 => Polarity::ActiveLow,
+                _ => Polarity::Reserved,
+            },
+            trigger: match (val >> 2) & 3 {
+                0 => TriggerMode::ConformsToBus,
+                1 => TriggerMode::Edge,
+                3 => TriggerMode::Level,
+                _ => TriggerMode::Reserved,
+            },
+        }
+    }
+}
+
+impl Default for IntiFlags {
+    fn default() -> Self {
+        IntiFlags::from(0)
+    }
+}
+
+impl IntiFlags {
+    /// Resolve "conforms to bus" for an ISA interrupt, which is edge
+    /// triggered and active high.
+    pub fn isa(self) -> Self {
+        IntiFlags {
+            polarity: match self.polarity {
+                Polarity::ConformsToBus => Polarity::ActiveHigh,
+                x => x,
+            },
+            trigger: match self.trigger {
+                TriggerMode::ConformsToBus => TriggerMode::Edge,
+                x => x,
+            },
+        }
+    }
+}
+
+/// An I/O APIC.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IoApic {
+    /// The I/O APIC's ID.
+    pub id: u8,
+
+    /// The 32-bit physical address of the I/O APIC registers.
+    pub address: u32,
+
+    /// The global system interrupt number where this I/O APIC's inputs start.
+    pub gsi_base: u32,
+}
+
+/// A mapping of a bus relative interrupt source to a global system
+/// interrupt, which differs from the identity mapping.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InterruptOverride {
+    /// The bus, 0 for ISA.
+    pub bus: u8,
+
+    /// The bus relative interrupt source (IRQ).
+    pub source: u8,
+
+    /// The global system interrupt this source signals.
+    pub gsi: u32,
+
+    /// Polarity and trigger mode.
+    pub flags: IntiFlags,
+}
+
+/// A global system interrupt which should be configured as an NMI.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NmiSource {
+    /// The global system interrupt.
+    pub gsi: u32,
+
+    /// Polarity and trigger mode.
+    pub flags: IntiFlags,
+}
+
+/// A local APIC LINT input which is connected to NMI.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalApicNmi {
+    /// The ACPI processor UID this applies to, `None` for all processors.
+    pub acpi_uid: Option<u32>,
+
+    /// The local APIC interrupt input (LINTn) NMI is connected to.
+    pub lint: u8,
+
+    /// Polarity and trigger mode.
+    pub flags: IntiFlags,
+}
+
+/// The interrupt routing described by the MADT, what an interrupt
+/// controller driver needs to route legacy IRQs and NMIs.
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptRouting {
+    /// Physical address of the local APICs, with any 64-bit override from
+    /// the MADT applied.
+    pub local_apic_addr: u64,
+
+    /// The system has PC-AT compatible dual 8259s which must be masked.
+    pub pcat_compat: bool,
+
+    /// The I/O APICs.
+    io_apics: FixedVec<IoApic, MAX_IO_APICS>,
+
+    /// The interrupt source overrides.
+    overrides: FixedVec<InterruptOverride, MAX_OVERRIDES>,
+
+    /// The NMI sources.
+    nmi_sources: FixedVec<NmiSource, MAX_NMI_SOURCES>,
+
+    /// The local APIC NMI inputs.
+    local_apic_nmis: FixedVec<LocalApicNmi, MAX_CPUS>,
+}
+
+impl InterruptRouting {
+    /// Create new routing information with nothing in it.
+    fn new(local_apic_addr: u32, flags: u32) -> Self {
+        InterruptRouting {
+            local_apic_addr: local_apic_addr as u64,
+            pcat_compat: flags & PCAT_COMPAT != 0,
+            io_apics: FixedVec::new(),
+            overrides: FixedVec::new(),
+            nmi_sources: FixedVec::new(),
+            local_apic_nmis: FixedVec::new(),
+        }
+    }
+
+    /// Get the I/O APICs.
+    pub fn io_apics(&self) -> &[IoApic] {
+        self.io_apics.entries()
+    }
+
+    /// Get the interrupt source overrides.
+    pub fn overrides(&self) -> &[InterruptOverride] {
+        self.overrides.entries()
+    }
+
+    /// Get the NMI sources.
+    pub fn nmi_sources(&self) -> &[NmiSource] {
+        self.nmi_sources.entries()
+    }
+
+    /// Get the local APIC NMI inputs.
+    pub fn local_apic_nmis(&self) -> &[LocalApicNmi] {
+        self.local_apic_nmis.entries()
+    }
+
+    /// Get the local APIC NMI inputs which apply to the processor with
+    /// `acpi_uid`.
+    pub fn local_apic_nmis_for(&self, acpi_uid: u32)
+            -> impl Iterator<Item = &LocalApicNmi> {
+        self.local_apic_nmis().iter()
+            .filter(move |nmi| match nmi.acpi_uid {
+                Some(uid) => uid == acpi_uid,
+                None => true,
+            })
+    }
+
+    /// Resolve a legacy ISA `irq` (eg. 0 for the PIT, 4 for COM1) to the
+    /// global system interrupt it signals and its polarity and trigger mode.
+    /// Without an override ISA IRQs are identity mapped.
+    pub fn isa_irq(&self, irq: u8) -> (u32, IntiFlags) {
+        self.overrides().iter()
+            .find(|x| x.bus == 0 && x.source == irq)
+            .map(|x| (x.gsi, x.flags.isa()))
+            .unwrap_or((irq as u32, IntiFlags::default().isa()))
+    }
+
+    /// Find the I/O APIC which handles `gsi`, and the input of that I/O APIC
+    /// it's on. The MADT does not tell us how many inputs an I/O APIC has,
+    /// so this is the I/O APIC with the closest base below `gsi`.
+    pub fn io_apic_for_gsi(&self, gsi: u32) -> Option<(&IoApic, u32)> {
+        self.io_apics().iter()
+            .filter(|x| x.gsi_base <= gsi)
+            .max_by_key(|x| x.gsi_base)
+            .map(|x| (x, gsi - x.gsi_base))
+    }
+}
+
+/// The Multiple Apic Description Table
+pub struct Madt {
+    /// The processors described by the MADT.
+    pub cpus: CpuTopology,
+
+    /// The interrupt routing described by the MADT.
+    pub interrupts: InterruptRouting,
+}
+
+impl Madt {
+    /// Process the payload of an MADT based on a physical address and a
+    /// size, read through `mem`. `bsp_apic_id` is the APIC ID of the
+    /// processor we're running on.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize, bsp_apic_id: u32) -> Result<Self> {
+        /// The error type when the MADT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Madt);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        // Read the local APIC physical address
+        let local_apic_addr = slice.consume::<u32>().map_err(|_| E)?;
+
+        // Get the APIC flags
+        let flags = slice.consume::<u32>().map_err(|_| E)?;
+
+        let mut cpus = CpuTopology::new();
+        let mut interrupts = InterruptRouting::new(local_apic_addr, flags);
+
+        /// The error when there are more structures of a type than we can
+        /// track.
+        const FULL: Error = Error::TooManyInterruptStructures;
+
+        // Handle interrup controller structures
+        while slice.len() > 0 {
+            // Read the interrupt controller header
+            let typ = slice.consume::<u8>().map_err(|_| E)?;
+            let len = slice.consume::<u8>().map_err(|_| E)?
+                .checked_sub(2).ok_or(E)?;
+
+            match typ {
+
+                0 => {
+                    #[repr(C, packed)]
+                    struct LocalApic {
+
+                        /// The OS associates this local apic structure with a
+                        /// processor object in the namespace when the _UID
+                        /// child object of the processor's device object (or
+                        /// ProcessorId listed in the processor declaration
+                        /// operator) evaluates to a numeric value that matches
+                        /// the numeric value in the field
+                        acpi_processor_uid: u8,
+
+                        /// The processor's local APIC ID.
+                        apic_id: u8,
+
+                        /// Local APIC flags
+                        ///
+                        /// Bit 0: Enabled (set if ready for use)
+                        /// Bit 1: Online capable (RAZ is enabled, indicates if
+                        /// the APIC can be enabled at runtime)
+                        flags: u32,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<LocalApic>() {
+                        return Err(E);
+                    }
+
+                    let apic = slice.consume::<LocalApic>().map_err(|_| E)?;
+                    cpus.add(apic.apic_id as u32, apic.acpi_processor_uid as u32,
+                        apic.flags)?;
+                }
+
+                9 => {
+                    // Processor Local x2APIC structure
+                    #[repr(C, packed)]
+                    struct LocalX2apic{
+                        /// Reserved, must be zero
+                        reserved: u16,
+
+                        /// The processor's local x2APIC ID
+                        x2apic_id: u32,
+
+                        /// Same as local APIC flags
+                        flags: u32,
+
+                        /// OSPM associates the X2APIC Structure with a processor
+                        /// object declared in the namespace using the Device
+                        /// statement, when the _UID child object of the
+                        /// processor device evaluates to a numeric value, by
+                        /// matching the numeric value with this field
+                        acpi_processor_uid: u32,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<LocalX2apic>() {
+                        return Err(E);
+                    }
+
+                    let x2_apic = slice.consume::<LocalX2apic>().map_err(|_| E)?;
+                    cpus.add(x2_apic.x2apic_id, x2_apic.acpi_processor_uid,
+                        x2_apic.flags)?;
+                }
+
+                1 => {
+                    // I/O APIC structure
+                    #[repr(C, packed)]
+                    struct IoApicStruct {
+                        /// The I/O APIC's ID
+                        io_apic_id: u8,
+
+                        /// Reserved, must be zero
+                        reserved: u8,
+
+                        /// The 32-bit physical address to access this I/O APIC
+                        io_apic_addr: u32,
+
+                        /// The global system interrupt number where this I/O
+                        /// APIC's interrupt inputs start
+                        gsi_base: u32,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<IoApicStruct>() {
+                        return Err(E);
+                    }
+
+                    let io_apic = slice.consume::<IoApicStruct>().map_err(|_| E)?;
+                    interrupts.io_apics.push(IoApic {
+                        id: io_apic.io_apic_id,
+                        address: io_apic.io_apic_addr,
+                        gsi_base: io_apic.gsi_base,
+                    }).map_err(|_| FULL)?;
+                }
+
+                2 => {
+                    // Interrupt Source Override structure
+                    #[repr(C, packed)]
+                    struct InterruptSourceOverride {
+                        /// 0, constant, meaning ISA
+                        bus: u8,
+
+                        /// Bus-relative interrupt source (IRQ)
+                        source: u8,
+
+                        /// The global system interrupt that this bus-relative
+                        /// interrupt source will signal
+                        gsi: u32,
+
+                        /// MPS INTI flags
+                        flags: u16,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<InterruptSourceOverride>() {
+                        return Err(E);
+                    }
+
+                    let iso = slice.consume::<InterruptSourceOverride>()
+                        .map_err(|_| E)?;
+                    interrupts.overrides.push(InterruptOverride {
+                        bus: iso.bus,
+                        source: iso.source,
+                        gsi: iso.gsi,
+                        flags: iso.flags.into(),
+                    }).map_err(|_| FULL)?;
+                }
+
+                3 => {
+                    // Non-Maskable Interrupt (NMI) Source structure
+                    #[repr(C, packed)]
+                    struct NmiSourceStruct {
+                        /// MPS INTI flags
+                        flags: u16,
+
+                        /// The global system interrupt that this NMI will
+                        /// signal
+                        gsi: u32,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<NmiSourceStruct>() {
+                        return Err(E);
+                    }
+
+                    let nmi = slice.consume::<NmiSourceStruct>().map_err(|_| E)?;
+                    interrupts.nmi_sources.push(NmiSource {
+                        gsi: nmi.gsi,
+                        flags: nmi.flags.into(),
+                    }).map_err(|_| FULL)?;
+                }
+
+                4 => {
+                    // Local APIC NMI structure
+                    #[repr(C, packed)]
+                    struct LocalApicNmiStruct {
+                        /// Value corresponding to the _UID listed in the
+                        /// processor's device object. 0xff means all
+                        /// processors
+                        acpi_processor_uid: u8,
+
+                        /// MPS INTI flags
+                        flags: u16,
+
+                        /// Local APIC interrupt input LINTn to which NMI is
+                        /// connected
+                        lint: u8,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<LocalApicNmiStruct>() {
+                        return Err(E);
+                    }
+
+                    let nmi = slice.consume::<LocalApicNmiStruct>()
+                        .map_err(|_| E)?;
+                    interrupts.local_apic_nmis.push(LocalApicNmi {
+                        acpi_uid: Some(nmi.acpi_processor_uid as u32)
+                            .filter(|&x| x != 0xff),
+                        lint: nmi.lint,
+                        flags: nmi.flags.into(),
+                    }).map_err(|_| FULL)?;
+                }
+
+                5 => {
+                    // Local APIC Address Override structure
+                    #[repr(C, packed)]
+                    struct LocalApicAddressOverride {
+                        /// Reserved, must be zero
+                        reserved: u16,
+
+                        /// Physical address of the local APIC, overrides the
+                        /// 32-bit address in the MADT header
+                        local_apic_addr: u64,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<LocalApicAddressOverride>() {
+                        return Err(E);
+                    }
+
+                    let ovr = slice.consume::<LocalApicAddressOverride>()
+                        .map_err(|_| E)?;
+                    interrupts.local_apic_addr = ovr.local_apic_addr;
+                }
+
+                0xa => {
+                    // Local x2APIC NMI structure
+                    #[repr(C, packed)]
+                    struct LocalX2apicNmi {
+                        /// MPS INTI flags
+                        flags: u16,
+
+                        /// UID corresponding to the ID listed in the processor
+                        /// device object. 0xffffffff means all processors
+                        acpi_processor_uid: u32,
+
+                        /// Local x2APIC interrupt input LINTn to which NMI is
+                        /// connected
+                        lint: u8,
+
+                        /// Reserved, must be zero
+                        reserved: [u8; 3],
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<LocalX2apicNmi>() {
+                        return Err(E);
+                    }
+
+                    let nmi = slice.consume::<LocalX2apicNmi>().map_err(|_| E)?;
+                    interrupts.local_apic_nmis.push(LocalApicNmi {
+                        acpi_uid: Some(nmi.acpi_processor_uid)
+                            .filter(|&x| x != 0xffff_ffff),
+                        lint: nmi.lint,
+                        flags: nmi.flags.into(),
+                    }).map_err(|_| FULL)?;
+                }
+
+                _ => {
+                    // Unknown type, discard the data
+                    slice.discard(len as usize).map_err(|_| E)?;
+                }
+            }
+        }
+
+        cpus.finalize(bsp_apic_id);
+
+        Ok(Self {
+            cpus,
+            interrupts,
+        })
+    }
+}
@@ -0,0 +1,98 @@
+//! PCI Express memory mapped configuration (MCFG) table parsing, giving the
+//! ECAM regions for each PCI segment group.
+
+use super::{Error, Result, TableType};
+use crate::fixed_vec::FixedVec;
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The maximum number of ECAM regions we can track.
+pub const MAX_ECAM_SEGMENTS: usize = 16;
+
+/// An ECAM region, the memory mapped configuration space of a range of buses
+/// in a PCI segment group.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EcamSegment {
+    /// Physical address of the configuration space of bus 0, even if
+    /// `start_bus` is not 0.
+    pub base: u64,
+
+    /// The PCI segment group number.
+    pub segment: u16,
+
+    /// The first bus decoded by this region.
+    pub start_bus: u8,
+
+    /// The last bus decoded by this region.
+    pub end_bus: u8,
+}
+
+/// The ECAM regions described by the MCFG.
+#[derive(Clone, Copy, Debug)]
+pub struct Mcfg {
+    /// The ECAM regions.
+    segments: FixedVec<EcamSegment, MAX_ECAM_SEGMENTS>,
+}
+
+impl Mcfg {
+    /// Process the payload of the MCFG (everything after the table header)
+    /// at `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the MCFG is truncated
+        const E: Error = Error::LengthMismatch(TableType::Mcfg);
+
+        /// In-memory representation of an MCFG configuration space base
+        /// address allocation structure.
+        #[repr(C, packed)]
+        struct Allocation {
+            /// Base address of the enhanced configuration mechanism
+            base_address: u64,
+
+            /// PCI segment group number
+            segment: u16,
+
+            /// Start PCI bus number decoded by this host bridge
+            start_bus: u8,
+
+            /// End PCI bus number decoded by this host bridge
+            end_bus: u8,
+
+            /// Reserved
+            reserved: u32,
+        }
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        // Skip the reserved field
+        slice.discard(8).map_err(|_| E)?;
+
+        let mut segments = FixedVec::new();
+        while slice.len() > 0 {
+            let alloc = slice.consume::<Allocation>().map_err(|_| E)?;
+            segments.push(EcamSegment {
+                base: alloc.base_address,
+                segment: alloc.segment,
+                start_bus: alloc.start_bus,
+                end_bus: alloc.end_bus,
+            }).map_err(|_| Error::TooManyEcamSegments)?;
+        }
+
+        Ok(Mcfg {
+            segments,
+        })
+    }
+
+    /// Get the ECAM regions.
+    pub fn segments(&self) -> &[EcamSegment] {
+        self.segments.entries()
+    }
+
+    /// Find the ECAM region which decodes `bus` in PCI segment group
+    /// `segment`.
+    pub fn find(&self, segment: u16, bus: u8) -> Option<&EcamSegment> {
+        self.segments().iter().find(|x| {
+            x.segment == segment && x.start_bus <= bus && bus <= x.end_bus
+        })
+    }
+}
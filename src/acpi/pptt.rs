@@ -0,0 +1,624 @@
+//! Processor Properties Topology Table (PPTT) parsing, the tree of packages,
+//! clusters, cores and threads and the caches attached to them.
+
+use core::mem::size_of;
+
+use super::{Error, Result, Table, TableType};
+use super::madt::MAX_CPUS;
+use crate::fixed_vec::FixedVec;
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The maximum number of processor hierarchy nodes we can track, enough for
+/// two packages of 64 cores with 2 threads each (386 nodes).
+pub const MAX_NODES: usize = 512;
+
+/// The maximum number of cache structures we can track, enough for private
+/// L1 and L2 caches on each of those cores and an L3 per package.
+pub const MAX_CACHES: usize = 512;
+
+/// The maximum number of caches we can track per node.
+const MAX_PRIVATE_CACHES: usize = 4;
+
+/// The maximum number of cache levels we report for a processor.
+const MAX_CACHE_LEVELS: usize = 16;
+
+/// The index stored for a missing parent or next level of cache.
+const NONE: u16 = u16::MAX;
+
+/// Node flag: the node is a physical package.
+const PHYSICAL_PACKAGE: u32 = 1 << 0;
+
+/// Node flag: the ACPI processor ID matches a processor in the namespace
+/// and the MADT.
+const ACPI_ID_VALID: u32 = 1 << 1;
+
+/// Node flag: the node is a hardware thread of a core.
+const PROCESSOR_IS_THREAD: u32 = 1 << 2;
+
+/// Node flag: the node has no children.
+const NODE_IS_LEAF: u32 = 1 << 3;
+
+/// Node flag: every child of the node is implemented identically.
+const IDENTICAL_IMPLEMENTATION: u32 = 1 << 4;
+
+/// Cache flag: the size field is valid.
+const SIZE_VALID: u8 = 1 << 0;
+
+/// Cache flag: the number of sets field is valid.
+const SETS_VALID: u8 = 1 << 1;
+
+/// Cache flag: the associativity field is valid.
+const ASSOCIATIVITY_VALID: u8 = 1 << 2;
+
+/// Cache flag: the cache type in the attributes is valid.
+const CACHE_TYPE_VALID: u8 = 1 << 4;
+
+/// Cache flag: the write policy in the attributes is valid.
+const WRITE_POLICY_VALID: u8 = 1 << 5;
+
+/// Cache flag: the line size field is valid.
+const LINE_SIZE_VALID: u8 = 1 << 6;
+
+/// Cache flag: the cache ID field is valid (revision 3).
+const CACHE_ID_VALID: u8 = 1 << 7;
+
+/// Cache attribute: write-through rather than write-back.
+const WRITE_THROUGH: u8 = 1 << 4;
+
+/// The level of the processor hierarchy a node represents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NodeKind {
+    /// A physical package, a socket.
+    Package,
+
+    /// A group of cores inside a package, or any other level the firmware
+    /// chose to describe.
+    #[default]
+    Cluster,
+
+    /// A core, either a leaf or the parent of threads.
+    Core,
+
+    /// A hardware thread of a core.
+    Thread,
+}
+
+/// A processor hierarchy node. There can be hundreds of these, so they are
+/// stored as the firmware gave them with indices packed into `u16`s.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Node {
+    /// The offset of the node from the start of the PPTT, which is how other
+    /// structures refer to it.
+    pub offset: u32,
+
+    /// What the node represents.
+    pub kind: NodeKind,
+
+    /// The flags from the table, see `PHYSICAL_PACKAGE` and friends.
+    flags: u32,
+
+    /// The ACPI processor UID, valid with `ACPI_ID_VALID`.
+    acpi_uid: u32,
+
+    /// The index of the parent node, `NONE` for the roots.
+    parent: u16,
+
+    /// The number of entries used in `caches`.
+    cache_count: u8,
+
+    /// The indices of the caches private to this node.
+    caches: [u16; MAX_PRIVATE_CACHES],
+}
+
+impl Node {
+    /// The index of the parent node, `None` for the roots.
+    pub fn parent(&self) -> Option<usize> {
+        (self.parent != NONE).then_some(self.parent as usize)
+    }
+
+    /// The ACPI processor UID, matching `Processor::acpi_uid` in the MADT.
+    /// For a leaf this identifies the processor, for other nodes it is only
+    /// an identifier for the group. `None` if the firmware didn't mark it
+    /// valid.
+    pub fn acpi_uid(&self) -> Option<u32> {
+        (self.flags & ACPI_ID_VALID != 0).then_some(self.acpi_uid)
+    }
+
+    /// Returns `true` if the node has no children.
+    pub fn leaf(&self) -> bool {
+        self.flags & NODE_IS_LEAF != 0
+    }
+
+    /// Returns `true` if every child of the node is implemented identically.
+    pub fn identical(&self) -> bool {
+        self.flags & IDENTICAL_IMPLEMENTATION != 0
+    }
+
+    /// The indices of the caches private to this node, in the order the
+    /// firmware listed them.
+    pub fn caches(&self) -> impl Iterator<Item = usize> + '_ {
+        self.caches[..self.cache_count as usize].iter().map(|&x| x as usize)
+    }
+}
+
+/// What a cache holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheType {
+    /// Data only.
+    Data,
+
+    /// Instructions only.
+    Instruction,
+
+    /// Data and instructions.
+    Unified,
+}
+
+/// A cache type structure, stored as the firmware gave it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cache {
+    /// The offset of the cache from the start of the PPTT, which is how
+    /// other structures refer to it.
+    pub offset: u32,
+
+    /// The size of the cache in bytes.
+    size: u32,
+
+    /// The number of sets.
+    sets: u32,
+
+    /// A unique ID for the cache (revision 3).
+    id: u32,
+
+    /// The size of a cache line in bytes.
+    line_size: u16,
+
+    /// The index of the next level of cache, `NONE` for the last level.
+    next_level: u16,
+
+    /// The number of ways.
+    associativity: u8,
+
+    /// The attributes, the cache type and write policy.
+    attributes: u8,
+
+    /// Which of the fields are valid, see `SIZE_VALID` and friends. Every
+    /// flag the specification defines fits in a byte.
+    flags: u8,
+}
+
+impl Cache {
+    /// The value `val` of a field, if the firmware set `flag` to say it is
+    /// valid.
+    fn field<T>(&self, flag: u8, val: T) -> Option<T> {
+        (self.flags & flag != 0).then_some(val)
+    }
+
+    /// The size of the cache in bytes.
+    pub fn size(&self) -> Option<u32> {
+        self.field(SIZE_VALID, self.size)
+    }
+
+    /// The number of sets.
+    pub fn sets(&self) -> Option<u32> {
+        self.field(SETS_VALID, self.sets)
+    }
+
+    /// The number of ways.
+    pub fn associativity(&self) -> Option<u8> {
+        self.field(ASSOCIATIVITY_VALID, self.associativity)
+    }
+
+    /// What the cache holds.
+    pub fn typ(&self) -> Option<CacheType> {
+        self.field(CACHE_TYPE_VALID, match (self.attributes >> 2) & 3 {
+            0 => CacheType::Data,
+            1 => CacheType::Instruction,
+            _ => CacheType::Unified,
+        })
+    }
+
+    /// `true` for write-back, `false` for write-through.
+    pub fn write_back(&self) -> Option<bool> {
+        self.field(WRITE_POLICY_VALID, self.attributes & WRITE_THROUGH == 0)
+    }
+
+    /// The size of a cache line in bytes.
+    pub fn line_size(&self) -> Option<u16> {
+        self.field(LINE_SIZE_VALID, self.line_size)
+    }
+
+    /// A unique ID for the cache (revision 3).
+    pub fn id(&self) -> Option<u32> {
+        self.field(CACHE_ID_VALID, self.id)
+    }
+
+    /// The index of the next level of cache, `None` if this is the last
+    /// level.
+    pub fn next_level(&self) -> Option<usize> {
+        (self.next_level != NONE).then_some(self.next_level as usize)
+    }
+}
+
+/// In-memory representation of a processor hierarchy node structure, after
+/// the structure header.
+#[repr(C, packed)]
+struct RawNode {
+    /// Reserved
+    reserved: u16,
+
+    /// Flags, see `PHYSICAL_PACKAGE` and friends
+    flags: u32,
+
+    /// Offset of the parent node, 0 for none
+    parent: u32,
+
+    /// ACPI processor UID
+    acpi_processor_id: u32,
+
+    /// Number of private resource offsets following
+    private_resources: u32,
+}
+
+/// In-memory representation of a cache type structure, after the structure
+/// header.
+#[repr(C, packed)]
+struct RawCache {
+    /// Reserved
+    reserved: u16,
+
+    /// Flags, which of the fields are valid
+    flags: u32,
+
+    /// Offset of the next level of cache, 0 for none
+    next_level: u32,
+
+    /// Size in bytes
+    size: u32,
+
+    /// Number of sets
+    sets: u32,
+
+    /// Number of ways
+    associativity: u8,
+
+    /// Allocation type, cache type and write policy
+    attributes: u8,
+
+    /// Line size in bytes
+    line_size: u16,
+}
+
+/// The processor topology described by the PPTT.
+#[derive(Clone, Copy, Debug)]
+pub struct Pptt {
+    /// Every processor hierarchy node, in table order.
+    nodes: FixedVec<Node, MAX_NODES>,
+
+    /// Every cache, in table order.
+    caches: FixedVec<Cache, MAX_CACHES>,
+}
+
+/// Call `f` with every structure in the PPTT payload at `addr` for `size`
+/// bytes, read through `mem`. `f` gets the offset of the structure from the
+/// start of the table, which is how structures refer to each other, its
+/// type and its body after the structure header.
+unsafe fn walk<'a, M: MemoryReader>(mem: &'a M, addr: PhysAddr, size: usize,
+        mut f: impl FnMut(u32, u8, PhysSlice<'a, M>) -> Result<()>)
+        -> Result<()> {
+    /// The error type when the PPTT is truncated
+    const E: Error = Error::LengthMismatch(TableType::Pptt);
+
+    // Create a slice to the physical memory
+    let mut slice = PhysSlice::new(mem, addr, size);
+
+    while slice.len() > 0 {
+        // References between structures are offsets from the start of the
+        // table, including the header we were not given
+        let offset = (slice.addr().0 - addr.0) as usize + size_of::<Table>();
+
+        // Read the structure header
+        let typ = slice.consume::<u8>().map_err(|_| E)?;
+        let len = (slice.consume::<u8>().map_err(|_| E)? as usize)
+            .checked_sub(2 * size_of::<u8>()).ok_or(E)?;
+
+        // Split off this structure
+        if slice.len() < len {
+            return Err(E);
+        }
+        let body = PhysSlice::new(mem, slice.addr(), len);
+        slice.discard(len).map_err(|_| E)?;
+
+        f(offset as u32, typ, body)?;
+    }
+
+    Ok(())
+}
+
+impl Pptt {
+    /// Process the payload of the PPTT (everything after the table header) at
+    /// `addr` for `size` bytes, read through `mem`.
+    ///
+    /// Structures refer to each other by offset, and can refer forward, so
+    /// the first pass reads every structure and the second turns the offsets
+    /// into indices.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the PPTT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Pptt);
+
+        /// The error when there are more structures than we can track
+        const FULL: Error = Error::TooManyTopologyNodes;
+
+        let mut pptt = Pptt {
+            nodes: FixedVec::new(),
+            caches: FixedVec::new(),
+        };
+
+        // Read every structure
+        walk(mem, addr, size, |offset, typ, mut body| {
+            match typ {
+                // Processor hierarchy node structure
+                0 => {
+                    let node = body.consume::<RawNode>().map_err(|_| E)?;
+                    let count = node.private_resources as usize;
+                    if body.len() != count.checked_mul(size_of::<u32>())
+                            .ok_or(E)? {
+                        return Err(E);
+                    }
+
+                    let flags = node.flags;
+                    pptt.nodes.push(Node {
+                        offset,
+                        kind: if flags & PHYSICAL_PACKAGE != 0 {
+                            NodeKind::Package
+                        } else if flags & PROCESSOR_IS_THREAD != 0 {
+                            NodeKind::Thread
+                        } else if flags & NODE_IS_LEAF != 0 {
+                            NodeKind::Core
+                        } else {
+                            NodeKind::Cluster
+                        },
+                        flags,
+                        acpi_uid: node.acpi_processor_id,
+                        parent: NONE,
+                        cache_count: 0,
+                        caches: [NONE; MAX_PRIVATE_CACHES],
+                    }).map_err(|_| FULL)
+                }
+
+                // Cache type structure
+                1 => {
+                    let cache = body.consume::<RawCache>().map_err(|_| E)?;
+                    let mut flags = cache.flags as u8;
+
+                    // Revision 3 appended the cache ID
+                    let id = if body.len() >= size_of::<u32>() {
+                        body.consume::<u32>().map_err(|_| E)?
+                    } else {
+                        flags &= !CACHE_ID_VALID;
+                        0
+                    };
+
+                    pptt.caches.push(Cache {
+                        offset,
+                        size: cache.size,
+                        sets: cache.sets,
+                        id,
+                        line_size: cache.line_size,
+                        next_level: NONE,
+                        associativity: cache.associativity,
+                        attributes: cache.attributes,
+                        flags,
+                    }).map_err(|_| FULL)
+                }
+
+                // The ID structure (type 2) was removed in ACPI 6.3, and
+                // there's nothing else we care about
+                _ => Ok(()),
+            }
+        })?;
+
+        // Resolve the references, visiting the structures in the same order
+        let mut node_idx = 0;
+        let mut cache_idx = 0;
+        walk(mem, addr, size, |_, typ, mut body| {
+            match typ {
+                0 => {
+                    let raw = body.consume::<RawNode>().map_err(|_| E)?;
+                    let parent = pptt.resolve_node(raw.parent)?;
+
+                    // Private resources may be things other than caches,
+                    // which we don't track
+                    let mut caches = [NONE; MAX_PRIVATE_CACHES];
+                    let mut cache_count = 0;
+                    for _ in 0..raw.private_resources {
+                        let offset = body.consume::<u32>().map_err(|_| E)?;
+                        if let Some(cache) = pptt.cache_by_offset(offset) {
+                            *caches.get_mut(cache_count).ok_or(FULL)? =
+                                cache as u16;
+                            cache_count += 1;
+                        }
+                    }
+
+                    let node = &mut pptt.nodes.entries_mut()[node_idx];
+                    node.parent = parent;
+                    node.caches = caches;
+                    node.cache_count = cache_count as u8;
+                    node_idx += 1;
+                }
+
+                1 => {
+                    let raw = body.consume::<RawCache>().map_err(|_| E)?;
+                    let next_level = pptt.resolve_cache(raw.next_level)?;
+                    pptt.caches.entries_mut()[cache_idx].next_level =
+                        next_level;
+                    cache_idx += 1;
+                }
+
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        // A non-leaf whose children are threads is a core
+        for idx in 0..pptt.nodes.len() {
+            let node = pptt.nodes.entries()[idx];
+            if let (NodeKind::Thread, Some(parent)) =
+                    (node.kind, node.parent()) {
+                let parent = &mut pptt.nodes.entries_mut()[parent];
+                if parent.kind == NodeKind::Cluster {
+                    parent.kind = NodeKind::Core;
+                }
+            }
+        }
+
+        Ok(pptt)
+    }
+
+    /// Turn the node `offset` from a structure into an index, `NONE` for 0.
+    fn resolve_node(&self, offset: u32) -> Result<u16> {
+        match offset {
+            0 => Ok(NONE),
+            _ => self.node_by_offset(offset).map(|x| x as u16)
+                .ok_or(Error::InvalidReference(TableType::Pptt)),
+        }
+    }
+
+    /// Turn the cache `offset` from a structure into an index, `NONE` for 0.
+    fn resolve_cache(&self, offset: u32) -> Result<u16> {
+        match offset {
+            0 => Ok(NONE),
+            _ => self.cache_by_offset(offset).map(|x| x as u16)
+                .ok_or(Error::InvalidReference(TableType::Pptt)),
+        }
+    }
+
+    /// Get the index of the node at `offset` in the table.
+    fn node_by_offset(&self, offset: u32) -> Option<usize> {
+        self.nodes.entries().iter().position(|x| x.offset == offset)
+    }
+
+    /// Get the index of the cache at `offset` in the table.
+    fn cache_by_offset(&self, offset: u32) -> Option<usize> {
+        self.caches.entries().iter().position(|x| x.offset == offset)
+    }
+
+    /// Get every processor hierarchy node.
+    pub fn nodes(&self) -> &[Node] {
+        self.nodes.entries()
+    }
+
+    /// Get every cache.
+    pub fn caches(&self) -> &[Cache] {
+        self.caches.entries()
+    }
+
+    /// Get the nodes of a kind.
+    pub fn nodes_of(&self, kind: NodeKind)
+            -> impl Iterator<Item = &Node> {
+        self.nodes().iter().filter(move |x| x.kind == kind)
+    }
+
+    /// Get the index of the leaf node for the processor with `acpi_uid`, as
+    /// found in the MADT.
+    pub fn processor(&self, acpi_uid: u32) -> Option<usize> {
+        self.nodes().iter()
+            .position(|x| x.leaf() && x.acpi_uid() == Some(acpi_uid))
+    }
+
+    /// Get the indices of the node `idx` and each of its ancestors, up to
+    /// the root.
+    pub fn ancestors(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        // Bound the walk in case the firmware built a loop
+        core::iter::successors(Some(idx), move |&x| self.nodes()[x].parent())
+            .take(self.nodes().len())
+    }
+
+    /// Get the index of the package node of the processor with `acpi_uid`.
+    pub fn package(&self, acpi_uid: u32) -> Option<usize> {
+        self.ancestors(self.processor(acpi_uid)?)
+            .find(|&x| self.nodes()[x].kind == NodeKind::Package)
+    }
+
+    /// Get the caches of the processor with `acpi_uid` as `(level, index)`,
+    /// starting from its own caches and walking up the hierarchy.
+    ///
+    /// The PPTT doesn't record levels. A node's private caches are one level
+    /// above the highest level found on its descendants, and each next
+    /// level of cache is one more.
+    pub fn caches_of(&self, acpi_uid: u32)
+            -> FixedVec<(u8, usize), MAX_CACHE_LEVELS> {
+        let mut levels = FixedVec::<(u8, usize), MAX_CACHE_LEVELS>::new();
+        let leaf = match self.processor(acpi_uid) {
+            Some(leaf) => leaf,
+            None => return levels,
+        };
+
+        let mut base = 1;
+        for node in self.ancestors(leaf) {
+            let mut top = base - 1;
+            for cache in self.nodes()[node].caches() {
+                // Follow the chain, bounded in case the firmware built a loop
+                let chain = core::iter::successors(Some(cache),
+                    |&x| self.caches()[x].next_level())
+                    .take(self.caches().len());
+
+                for (level, cache) in (base..).zip(chain) {
+                    top = top.max(level);
+                    if levels.entries().iter().all(|x| x.1 != cache) {
+                        // Too many levels to be real, stop looking
+                        if levels.push((level, cache)).is_err() {
+                            return levels;
+                        }
+                    }
+                }
+            }
+            base = top + 1;
+        }
+
+        levels
+    }
+
+    /// Get the index of the data or unified cache at `level` (1 for L1) for
+    /// the processor with `acpi_uid`.
+    pub fn cache_at(&self, acpi_uid: u32, level: u8) -> Option<usize> {
+        self.caches_of(acpi_uid).entries().iter()
+            .find(|&&(lvl, x)| lvl == level
+                && self.caches()[x].typ() != Some(CacheType::Instruction))
+            .map(|x| x.1)
+    }
+
+    /// Returns `true` if the processors with `a` and `b` as ACPI UIDs share
+    /// their data or unified cache at `level`.
+    pub fn shares_cache(&self, a: u32, b: u32, level: u8) -> bool {
+        match (self.cache_at(a, level), self.cache_at(b, level)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Get the ACPI UIDs of every processor which shares its data or unified
+    /// cache at `level` with the processor with `acpi_uid`, including
+    /// itself. Empty if the processor has no cache at that level.
+    pub fn sharing(&self, acpi_uid: u32, level: u8)
+            -> FixedVec<u32, MAX_CPUS> {
+        let mut uids = FixedVec::<u32, MAX_CPUS>::new();
+        let cache = match self.cache_at(acpi_uid, level) {
+            Some(cache) => cache,
+            None => return uids,
+        };
+
+        for uid in self.nodes().iter()
+                .filter(|x| x.leaf()).filter_map(|x| x.acpi_uid()) {
+            if self.cache_at(uid, level) == Some(cache) {
+                // We can't track more processors than this anyway
+                if uids.push(uid).is_err() {
+                    break;
+                }
+            }
+        }
+
+        uids
+    }
+}
@@ -0,0 +1,88 @@
+//! System Locality Information Table (SLIT) parsing, the relative distances
+//! between NUMA nodes.
+
+use core::mem::size_of;
+
+use super::{Error, Result, TableType};
+use super::srat::MAX_NUMA_NODES;
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// The distance from a locality to itself. Distances are relative to this,
+/// so 20 means twice the latency of local memory.
+pub const LOCAL_DISTANCE: u8 = 10;
+
+/// The distance we assume between different localities when there is no
+/// SLIT.
+pub const REMOTE_DISTANCE: u8 = 20;
+
+/// A distance meaning the locality is unreachable from the other.
+pub const UNREACHABLE: u8 = 0xff;
+
+/// The matrix of distances between localities (proximity domains).
+#[derive(Clone, Copy, Debug)]
+pub struct NodeDistances {
+    /// The number of localities in `distances`, at most `MAX_NUMA_NODES`.
+    localities: usize,
+
+    /// `distances[from][to]` is the distance from locality `from` to
+    /// locality `to`.
+    distances: [[u8; MAX_NUMA_NODES]; MAX_NUMA_NODES],
+}
+
+impl NodeDistances {
+    /// Process the payload of the SLIT (everything after the table header) at
+    /// `addr` for `size` bytes, read through `mem`. Only the distances
+    /// between the first `MAX_NUMA_NODES` localities are kept, like the SRAT
+    /// we merge the rest into the first node.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the SLIT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Slit);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        // Read the number of localities
+        let localities = slice.consume::<u64>().map_err(|_| E)?;
+
+        // The rest of the table is the matrix, exactly
+        let matrix = localities.checked_mul(localities)
+            .and_then(|x| x.checked_mul(size_of::<u8>() as u64))
+            .ok_or(E)?;
+        if slice.len() as u64 != matrix {
+            return Err(E);
+        }
+        let localities = localities as usize;
+
+        let mut distances = [[UNREACHABLE; MAX_NUMA_NODES]; MAX_NUMA_NODES];
+        for from in 0..localities {
+            for to in 0..localities {
+                let distance = slice.consume::<u8>().map_err(|_| E)?;
+                if let Some(x) = distances.get_mut(from)
+                        .and_then(|row| row.get_mut(to)) {
+                    *x = distance;
+                }
+            }
+        }
+
+        Ok(NodeDistances {
+            localities: localities.min(MAX_NUMA_NODES),
+            distances,
+        })
+    }
+
+    /// The number of localities described.
+    pub fn localities(&self) -> usize {
+        self.localities
+    }
+
+    /// Get the distance from proximity domain `from` to `to`. `None` if
+    /// either is not described by the SLIT.
+    pub fn distance(&self, from: u32, to: u32) -> Option<u8> {
+        if (from as usize) < self.localities && (to as usize) < self.localities {
+            Some(self.distances[from as usize][to as usize])
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Serial Port Console Redirection (SPCR) table parsing, describing the
+//! serial port the firmware used as its console.
+
+use super::{Error, GenericAddress, RawGenericAddress, Result, TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// Interrupt type flag: dual 8259 (PC-AT) IRQ.
+const INTERRUPT_PIC: u8 = 1 << 0;
+
+/// Interrupt type flag: I/O APIC global system interrupt.
+const INTERRUPT_IO_APIC: u8 = 1 << 1;
+
+/// Flow control flag: DCD required for transmit.
+const FLOW_DCD: u8 = 1 << 0;
+
+/// Flow control flag: RTS/CTS hardware flow control.
+const FLOW_RTS_CTS: u8 = 1 << 1;
+
+/// Flow control flag: XON/XOFF software flow control.
+const FLOW_XON_XOFF: u8 = 1 << 2;
+
+/// The kind of UART the console is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialInterface {
+    /// Full 16550 interface.
+    Uart16550,
+
+    /// Full 16450 interface, a 16550 without FIFOs.
+    Uart16450,
+
+    /// ARM PL011 UART.
+    Pl011,
+
+    /// 16550 compatible with parameters defined in the Generic Address
+    /// Structure.
+    Uart16550Gas,
+
+    /// Any other interface type, see the Microsoft Debug Port Table 2
+    /// specification.
+    Other(u8),
+}
+
+impl From<u8> for SerialInterface {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::Uart16550,
+            1 => Self::Uart16450,
+            3 => Self::Pl011,
+            0x12 => Self::Uart16550Gas,
+            _ => Self::Other(val),
+        }
+    }
+}
+
+impl SerialInterface {
+    /// Returns `true` if the UART is programmed like a 16550.
+    pub fn is_16550_compatible(&self) -> bool {
+        matches!(self, Self::Uart16550 | Self::Uart16450 | Self::Uart16550Gas)
+    }
+}
+
+/// Parity of the serial console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity, the only value the SPCR defines.
+    None,
+
+    /// A reserved parity value.
+    Reserved(u8),
+}
+
+/// Flow control used by the serial console.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlowControl {
+    /// DCD is required for transmit.
+    pub dcd: bool,
+
+    /// RTS/CTS hardware flow control.
+    pub rts_cts: bool,
+
+    /// XON/XOFF software flow control.
+    pub xon_xoff: bool,
+}
+
+/// How the UART signals interrupts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SerialInterrupt {
+    /// The dual 8259 IRQ, if the UART is wired to the PIC.
+    pub irq: Option<u8>,
+
+    /// The global system interrupt, if the UART is wired to an I/O APIC.
+    pub gsi: Option<u32>,
+}
+
+/// The PCI location of the UART.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciLocation {
+    /// PCI segment.
+    pub segment: u8,
+
+    /// PCI bus.
+    pub bus: u8,
+
+    /// PCI device.
+    pub device: u8,
+
+    /// PCI function.
+    pub function: u8,
+
+    /// PCI vendor ID.
+    pub vendor_id: u16,
+
+    /// PCI device ID.
+    pub device_id: u16,
+}
+
+/// The serial console described by the SPCR.
+#[derive(Clone, Copy, Debug)]
+pub struct SerialConsoleConfig {
+    /// The kind of UART.
+    pub interface: SerialInterface,
+
+    /// The base address of the UART registers.
+    pub base: GenericAddress,
+
+    /// The baud rate, `None` to keep what the firmware configured.
+    pub baud_rate: Option<u32>,
+
+    /// Parity.
+    pub parity: Parity,
+
+    /// The number of stop bits.
+    pub stop_bits: u8,
+
+    /// Flow control.
+    pub flow_control: FlowControl,
+
+    /// How the UART signals interrupts.
+    pub interrupt: SerialInterrupt,
+
+    /// The terminal protocol, 0 VT100, 1 extended VT100, 2 VT-UTF8 and
+    /// 3 ANSI.
+    pub terminal_type: u8,
+
+    /// Where the UART is on PCI, `None` if it's not a PCI device.
+    pub pci: Option<PciLocation>,
+
+    /// The UART input clock in Hz, `None` if not specified.
+    pub clock_frequency: Option<u32>,
+}
+
+/// In-memory representation of the SPCR payload, up to the fields of
+/// revision 3.
+#[repr(C, packed)]
+struct Spcr {
+    /// The type of the register interface
+    interface_type: u8,
+
+    /// Reserved
+    reserved1: [u8; 3],
+
+    /// The base address of the serial port register set
+    base_address: RawGenericAddress,
+
+    /// Interrupt type(s) used by the UART
+    interrupt_type: u8,
+
+    /// The PC-AT compatible IRQ
+    irq: u8,
+
+    /// The I/O APIC or I/O SAPIC global system interrupt
+    global_system_interrupt: u32,
+
+    /// The baud rate the BIOS used for redirection
+    baud_rate: u8,
+
+    /// Parity, 0 is no parity
+    parity: u8,
+
+    /// Stop bits, 1 is 1 stop bit
+    stop_bits: u8,
+
+    /// Flow control flags
+    flow_control: u8,
+
+    /// The terminal protocol the BIOS was using for console redirection
+    terminal_type: u8,
+
+    /// Language the BIOS was using for console redirection
+    language: u8,
+
+    /// PCI device ID, 0xffff if not a PCI device
+    pci_device_id: u16,
+
+    /// PCI vendor ID, 0xffff if not a PCI device
+    pci_vendor_id: u16,
+
+    /// PCI bus number
+    pci_bus: u8,
+
+    /// PCI device number
+    pci_device: u8,
+
+    /// PCI function number
+    pci_function: u8,
+
+    /// PCI flags
+    pci_flags: u32,
+
+    /// PCI segment number
+    pci_segment: u8,
+
+    /// UART clock frequency in Hz, reserved before revision 3
+    uart_clock_frequency: u32,
+}
+
+impl SerialConsoleConfig {
+    /// Process the payload of the SPCR (everything after the table header)
+    /// at `addr` for `size` bytes, read through `mem`. `revision` is the table
+    /// revision from the header.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize, revision: u8) -> Result<Self> {
+        /// The error type when the SPCR is truncated
+        const E: Error = Error::LengthMismatch(TableType::Spcr);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let spcr = slice.consume::<Spcr>().map_err(|_| E)?;
+
+        // Revision 4 adds a precise baud rate for rates the baud rate code
+        // can't express
+        let precise_baud_rate = if revision >= 4 {
+            slice.consume::<u32>().map_err(|_| E)?
+        } else {
+            0
+        };
+
+        let baud_rate = match spcr.baud_rate {
+            _ if precise_baud_rate != 0 => Some(precise_baud_rate),
+            3 => Some(9600),
+            4 => Some(19200),
+            6 => Some(57600),
+            7 => Some(115200),
+            _ => None,
+        };
+
+        let interrupt = SerialInterrupt {
+            irq: (spcr.interrupt_type & INTERRUPT_PIC != 0)
+                .then_some(spcr.irq),
+            gsi: (spcr.interrupt_type & INTERRUPT_IO_APIC != 0)
+                .then_some(spcr.global_system_interrupt),
+        };
+
+        let pci = if spcr.pci_vendor_id != 0xffff
+                && spcr.pci_device_id != 0xffff {
+            Some(PciLocation {
+                segment: spcr.pci_segment,
+                bus: spcr.pci_bus,
+                device: spcr.pci_device,
+                function: spcr.pci_function,
+                vendor_id: spcr.pci_vendor_id,
+                device_id: spcr.pci_device_id,
+            })
+        } else {
+            None
+        };
+
+        Ok(SerialConsoleConfig {
+            interface: spcr.interface_type.into(),
+            base: spcr.base_address.into(),
+            baud_rate,
+            parity: match spcr.parity {
+                0 => Parity::None,
+                x => Parity::Reserved(x),
+            },
+            stop_bits: spcr.stop_bits,
+            flow_control: FlowControl {
+                dcd: spcr.flow_control & FLOW_DCD != 0,
+                rts_cts: spcr.flow_control & FLOW_RTS_CTS != 0,
+                xon_xoff: spcr.flow_control & FLOW_XON_XOFF != 0,
+            },
+            interrupt,
+            terminal_type: spcr.terminal_type,
+            pci,
+            clock_frequency: (revision >= 3 && spcr.uart_clock_frequency != 0)
+                .then_some(spcr.uart_clock_frequency),
+        })
+    }
+}
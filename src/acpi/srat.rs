@@ -0,0 +1,404 @@
+//! System Resource Affinity Table (SRAT) parsing, giving the NUMA topology:
+//! which memory and which processors belong to each proximity domain.
+
+use core::mem::size_of;
+
+use super::{Error, Result, TableType};
+use super::madt::MAX_CPUS;
+use super::slit::{NodeDistances, LOCAL_DISTANCE, REMOTE_DISTANCE, UNREACHABLE};
+use crate::fixed_vec::FixedVec;
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+use crate::mm::rangeset::{self, Range, RangeSet};
+
+/// The maximum number of NUMA nodes we can track. The processors and memory
+/// of any further proximity domains are merged into the first node.
+pub const MAX_NUMA_NODES: usize = 8;
+
+/// The maximum number of disjoint memory ranges we can track in a node.
+/// Firmware describes a node's memory with a handful of memory affinity
+/// structures.
+pub const MAX_NODE_RANGES: usize = 32;
+
+/// The maximum number of disjoint hot-pluggable memory ranges we can track
+/// in a node, usually one per memory slot.
+pub const MAX_HOTPLUG_RANGES: usize = 8;
+
+/// The memory of a NUMA node.
+pub type NodeMemory = RangeSet<MAX_NODE_RANGES>;
+
+/// The hot-pluggable memory of a NUMA node.
+pub type HotplugMemory = RangeSet<MAX_HOTPLUG_RANGES>;
+
+/// Affinity flag: the structure is enabled. Disabled structures must be
+/// ignored.
+const AFFINITY_ENABLED: u32 = 1 << 0;
+
+/// Memory affinity flag: the memory region is hot-pluggable.
+const MEMORY_HOT_PLUGGABLE: u32 = 1 << 1;
+
+/// A NUMA node, the resources of one proximity domain.
+#[derive(Clone, Copy, Default)]
+pub struct NumaNode {
+    /// The proximity domain of this node.
+    pub domain: u32,
+
+    /// The physical memory in this proximity domain, including hot-pluggable
+    /// memory.
+    pub memory: NodeMemory,
+
+    /// The hot-pluggable memory in this proximity domain. It is also in
+    /// `memory`, but is best avoided for allocations which can never be
+    /// freed.
+    pub hotplug: HotplugMemory,
+}
+
+/// The processor to proximity domain mapping of a processor.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuAffinity {
+    /// The processor's local APIC ID or x2APIC ID.
+    pub apic_id: u32,
+
+    /// The proximity domain the processor belongs to.
+    pub domain: u32,
+}
+
+/// The NUMA topology described by the SRAT.
+#[derive(Clone)]
+pub struct NumaTopology {
+    /// The nodes, in the order their proximity domains were first seen.
+    nodes: FixedVec<NumaNode, MAX_NUMA_NODES>,
+
+    /// The proximity domain of each enabled processor.
+    cpus: FixedVec<CpuAffinity, MAX_CPUS>,
+
+    /// Whether there were more than `MAX_NUMA_NODES` proximity domains, and
+    /// the processors and memory of the rest were merged into the first
+    /// node.
+    pub merged: bool,
+
+    /// The distances between nodes from the SLIT, `None` if there is no SLIT.
+    pub distances: Option<NodeDistances>,
+}
+
+impl NumaTopology {
+    /// Create a new topology with no nodes.
+    fn new() -> Self {
+        NumaTopology {
+            nodes: FixedVec::new(),
+            cpus: FixedVec::new(),
+            merged: false,
+            distances: None,
+        }
+    }
+
+    /// Get the node for `domain`, creating it if it doesn't exist yet. Once
+    /// there are `MAX_NUMA_NODES` nodes, new domains get the first node.
+    fn node_mut(&mut self, domain: u32) -> &mut NumaNode {
+        let idx = match self.nodes.entries().iter()
+                .position(|node| node.domain == domain) {
+            Some(idx) => idx,
+            None => {
+                let node = NumaNode {
+                    domain,
+                    ..NumaNode::default()
+                };
+                if self.nodes.push(node).is_ok() {
+                    self.nodes.len() - 1
+                } else {
+                    self.merged = true;
+                    0
+                }
+            }
+        };
+
+        &mut self.nodes.entries_mut()[idx]
+    }
+
+    /// Record that processor `apic_id` is in `domain`. Firmware may describe
+    /// the same processor with both an APIC and an x2APIC structure, so
+    /// duplicate APIC IDs are ignored.
+    fn add_cpu(&mut self, apic_id: u32, domain: u32) -> Result<()> {
+        let domain = self.node_mut(domain).domain;
+
+        if self.domain_of_apic(apic_id).is_some() {
+            return Ok(());
+        }
+
+        self.cpus.push(CpuAffinity {
+            apic_id,
+            domain,
+        }).map_err(|_| Error::TooManyProcessors)
+    }
+
+    /// Record that the memory at `base` for `length` bytes is in `domain`.
+    fn add_memory(&mut self, domain: u32, base: u64, length: u64,
+            hot_pluggable: bool) -> Result<()> {
+        // Zero length entries are used by some firmware as placeholders
+        if length == 0 {
+            return Ok(());
+        }
+
+        let range = Range {
+            start: base,
+            end: base.checked_add(length - 1).ok_or(Error::IntegerOverflow)?,
+        };
+
+        let node = self.node_mut(domain);
+        node.memory.insert(range).map_err(Error::NumaRangeSet)?;
+        if hot_pluggable {
+            node.hotplug.insert(range).map_err(Error::NumaRangeSet)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the NUMA nodes.
+    pub fn nodes(&self) -> &[NumaNode] {
+        self.nodes.entries()
+    }
+
+    /// Get the proximity domain of every enabled processor.
+    pub fn cpus(&self) -> &[CpuAffinity] {
+        self.cpus.entries()
+    }
+
+    /// Find the node for proximity domain `domain`.
+    pub fn node(&self, domain: u32) -> Option<&NumaNode> {
+        self.nodes().iter().find(|node| node.domain == domain)
+    }
+
+    /// Get the proximity domain of the processor with `apic_id`.
+    pub fn domain_of_apic(&self, apic_id: u32) -> Option<u32> {
+        self.cpus().iter()
+            .find(|cpu| cpu.apic_id == apic_id)
+            .map(|cpu| cpu.domain)
+    }
+
+    /// Get the memory local to the processor with `apic_id`, suitable for
+    /// passing to `RangeSet::allocate_prefer`.
+    pub fn memory_for_apic(&self, apic_id: u32)
+            -> Option<&NodeMemory> {
+        self.domain_of_apic(apic_id)
+            .and_then(|domain| self.node(domain))
+            .map(|node| &node.memory)
+    }
+
+    /// Get the distance from node `from` to node `to`. Without a SLIT every
+    /// node is local to itself and equally far from the others.
+    pub fn distance(&self, from: u32, to: u32) -> u8 {
+        match self.distances.and_then(|x| x.distance(from, to)) {
+            Some(distance) => distance,
+            None if from == to => LOCAL_DISTANCE,
+            None => REMOTE_DISTANCE,
+        }
+    }
+
+    /// Get the other nodes ordered by distance from `from`, nearest first.
+    /// Nodes which are unreachable from `from` are not included.
+    pub fn nearest(&self, from: u32) -> FixedVec<u32, MAX_NUMA_NODES> {
+        let mut nearest = FixedVec::new();
+        for node in self.nodes() {
+            if node.domain != from
+                    && self.distance(from, node.domain) != UNREACHABLE {
+                // Can't fail, we have as many entries as there are nodes
+                let _ = nearest.push(node.domain);
+            }
+        }
+
+        // Order by distance, then by domain to keep ties deterministic
+        nearest.entries_mut()
+            .sort_unstable_by_key(|&to| (self.distance(from, to), to));
+        nearest
+    }
+
+    /// Allocate `size` bytes with `align` alignment from `free`, preferring
+    /// memory local to the processor with `apic_id`. When the local node is
+    /// exhausted the nearest node with space is used, and if no node has
+    /// space the allocation comes from anywhere in `free`.
+    pub fn allocate<const N: usize>(&self, free: &mut RangeSet<N>,
+            size: u64, align: u64, apic_id: u32)
+            -> core::result::Result<usize, rangeset::Error> {
+        if let Some(local) = self.domain_of_apic(apic_id) {
+            let nearest = self.nearest(local);
+            let order = core::iter::once(local)
+                .chain(nearest.entries().iter().copied());
+
+            for domain in order {
+                let memory = match self.node(domain) {
+                    Some(node) => &node.memory,
+                    None => continue,
+                };
+
+                match free.allocate_within(size, align, memory) {
+                    Err(rangeset::Error::OutOfMemory) => continue,
+                    ret => return ret,
+                }
+            }
+        }
+
+        free.allocate(size, align)
+    }
+
+    /// Build the NUMA topology from the payload of the SRAT (everything after
+    /// the table header) at `addr` for `size` bytes, read through `mem`.
+    /// `revision` is the table revision from the header, revision 1 tables
+    /// only have 8-bit processor proximity domains.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize, revision: u8) -> Result<NumaTopology> {
+        /// The error type when the SRAT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Srat);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        // Skip the reserved fields, a u32 which must be 1 for backwards
+        // compatibility and a u64
+        slice.discard(size_of::<u32>() + size_of::<u64>()).map_err(|_| E)?;
+
+        let mut numa = NumaTopology::new();
+
+        // Handle static resource allocation structures
+        while slice.len() > 0 {
+            // Read the structure header
+            let typ = slice.consume::<u8>().map_err(|_| E)?;
+            let len = slice.consume::<u8>().map_err(|_| E)?
+                .checked_sub(2).ok_or(E)?;
+
+            match typ {
+                0 => {
+                    // Processor Local APIC/SAPIC Affinity structure
+                    #[repr(C, packed)]
+                    struct ProcessorApicAffinity {
+                        /// Bits [7:0] of the proximity domain
+                        proximity_domain_lo: u8,
+
+                        /// The processor's local APIC ID
+                        apic_id: u8,
+
+                        /// Flags, bit 0 is enabled
+                        flags: u32,
+
+                        /// The processor's local SAPIC EID
+                        sapic_eid: u8,
+
+                        /// Bits [31:8] of the proximity domain
+                        proximity_domain_hi: [u8; 3],
+
+                        /// The clock domain the processor belongs to
+                        clock_domain: u32,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<ProcessorApicAffinity>() {
+                        return Err(E);
+                    }
+
+                    let cpu = slice.consume::<ProcessorApicAffinity>()
+                        .map_err(|_| E)?;
+                    if cpu.flags & AFFINITY_ENABLED == 0 {
+                        continue;
+                    }
+
+                    // The high bits of the proximity domain were reserved
+                    // before revision 2
+                    let hi = if revision >= 2 {
+                        cpu.proximity_domain_hi
+                    } else {
+                        [0; 3]
+                    };
+                    let domain = u32::from_le_bytes([
+                        cpu.proximity_domain_lo, hi[0], hi[1], hi[2],
+                    ]);
+
+                    numa.add_cpu(cpu.apic_id as u32, domain)?;
+                }
+
+                1 => {
+                    // Memory Affinity structure
+                    #[repr(C, packed)]
+                    struct MemoryAffinity {
+                        /// The proximity domain of the memory range
+                        proximity_domain: u32,
+
+                        /// Reserved
+                        reserved1: u16,
+
+                        /// Base address of the memory range
+                        base: u64,
+
+                        /// Length of the memory range
+                        length: u64,
+
+                        /// Reserved
+                        reserved2: u32,
+
+                        /// Flags, bit 0 is enabled, bit 1 hot-pluggable and
+                        /// bit 2 non-volatile
+                        flags: u32,
+
+                        /// Reserved
+                        reserved3: u64,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<MemoryAffinity>() {
+                        return Err(E);
+                    }
+
+                    let range = slice.consume::<MemoryAffinity>()
+                        .map_err(|_| E)?;
+                    if range.flags & AFFINITY_ENABLED == 0 {
+                        continue;
+                    }
+
+                    numa.add_memory(range.proximity_domain, range.base,
+                        range.length, range.flags & MEMORY_HOT_PLUGGABLE != 0)?;
+                }
+
+                2 => {
+                    // Processor Local x2APIC Affinity structure
+                    #[repr(C, packed)]
+                    struct ProcessorX2apicAffinity {
+                        /// Reserved
+                        reserved1: u16,
+
+                        /// The proximity domain of the processor
+                        proximity_domain: u32,
+
+                        /// The processor's x2APIC ID
+                        x2apic_id: u32,
+
+                        /// Flags, bit 0 is enabled
+                        flags: u32,
+
+                        /// The clock domain the processor belongs to
+                        clock_domain: u32,
+
+                        /// Reserved
+                        reserved2: u32,
+                    }
+
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<ProcessorX2apicAffinity>() {
+                        return Err(E);
+                    }
+
+                    let cpu = slice.consume::<ProcessorX2apicAffinity>()
+                        .map_err(|_| E)?;
+                    if cpu.flags & AFFINITY_ENABLED == 0 {
+                        continue;
+                    }
+
+                    numa.add_cpu(cpu.x2apic_id, cpu.proximity_domain)?;
+                }
+
+                _ => {
+                    // Unknown type, discard the data
+                    slice.discard(len as usize).map_err(|_| E)?;
+                }
+            }
+        }
+
+        Ok(numa)
+    }
+}
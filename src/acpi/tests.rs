@@ -0,0 +1,929 @@
+use std::vec::Vec;
+
+use super::bert::Bert;
+use super::cper::{Guid, SectionType, Severity};
+use super::fixtures::{self, Firmware, HestBuilder, MadtBuilder, PpttBuilder,
+    SratBuilder};
+use super::hest::{ErrorSourceType, Notification};
+use super::madt::{Polarity, TriggerMode};
+use super::pptt::{CacheType, NodeKind};
+use super::slit::REMOTE_DISTANCE;
+use super::catalog::TableStatus;
+use super::*;
+use crate::efi::mock::MockFirmware;
+use crate::mm::physmem::BufferMemory;
+use crate::mm::rangeset::{Range, RangeSet};
+
+/// Signatures of every table in `tables`, in order.
+fn signatures(tables: &AcpiTables) -> Vec<&[u8; 4]> {
+    tables.tables().iter().map(|x| &x.signature).collect()
+}
+
+#[test]
+fn checksum_on_buffer() {
+    const TYP: TableType = TableType::Waet;
+
+    let table = fixtures::table(b"WAET", 1, &[2, 0, 0, 0]);
+    let mem = BufferMemory::new(0x1000, &table);
+    assert!(checksum(&mem, PhysAddr(0x1000), table.len(), TYP).is_ok());
+
+    let mut table = table.clone();
+    table[36] ^= 1;
+    let mem = BufferMemory::new(0x1000, &table);
+    assert!(matches!(checksum(&mem, PhysAddr(0x1000), table.len(), TYP),
+        Err(Error::ChecksumMismatch(TYP))));
+
+    // Past the end of the buffer
+    assert!(matches!(checksum(&mem, PhysAddr(0x1001), table.len(), TYP),
+        Err(Error::Unreadable(0x1001))));
+}
+
+#[test]
+fn null_table() {
+    // A zero table pointer is an error, not a read of address 0
+    let mem = unsafe { PhysicalMemory::new() };
+    assert!(mem.bytes(PhysAddr(0), 36).is_err());
+    assert!(matches!(checksum(&mem, PhysAddr(0), 36, TableType::Dsdt),
+        Err(Error::Unreadable(0))));
+}
+
+#[test]
+fn rsdp_and_table_on_buffer() {
+    let image = fixtures::q35().build();
+    let mem = image.memory();
+
+    let rsdp = unsafe { RsdpExtended::from_addr(&mem, image.rsdp()) }.unwrap();
+    assert_eq!({ rsdp.base.revision }, 2);
+    assert_eq!({ rsdp.base.oem_id }, *b"BOCHS ");
+
+    let xsdt = rsdp.xsdt_addr;
+    let (header, typ, payload, size) =
+        unsafe { Table::from_addr(&mem, PhysAddr(xsdt)) }.unwrap();
+    assert_eq!(typ, TableType::Xsdt);
+    assert_eq!(payload, PhysAddr(xsdt + 36));
+    assert_eq!(size, 5 * 8);
+    assert_eq!({ header.length }, 36 + 5 * 8);
+}
+
+#[test]
+fn madt_on_buffer() {
+    let madt = MadtBuilder::new(0xfee0_0000, true)
+        .local_apic(0, 1, 1)
+        .local_apic(1, 0, 1)
+        .local_apic(2, 2, 0)
+        .build();
+    let mem = BufferMemory::new(0x2000, &madt);
+
+    let madt = unsafe {
+        Madt::from_addr(&mem, PhysAddr(0x2000 + 36), madt.len() - 36, 1)
+    }.unwrap();
+    let cpus = madt.cpus.processors();
+    assert_eq!(cpus.len(), 3);
+    assert_eq!((cpus[0].apic_id, cpus[0].bsp, cpus[0].logical_id),
+        (1, true, Some(0)));
+    assert_eq!((cpus[1].apic_id, cpus[1].logical_id), (0, Some(1)));
+    assert_eq!((cpus[2].apic_id, cpus[2].enabled, cpus[2].logical_id),
+        (2, false, None));
+    assert_eq!(madt.interrupts.local_apic_addr, 0xfee0_0000);
+    assert!(madt.interrupts.pcat_compat);
+}
+
+#[test]
+fn q35() {
+    let image = fixtures::q35().build();
+
+    let tables = image.discover().unwrap();
+    assert_eq!(signatures(&tables), [b"RSDP", b"XSDT", b"FACP", b"APIC",
+        b"HPET", b"MCFG", b"WAET", b"DSDT", b"FACS"]);
+    assert_eq!(tables.find(*b"APIC").unwrap().oem_id, *b"BOCHS ");
+
+    let acpi = image.decode(0).unwrap();
+
+    // Processors
+    assert_eq!(acpi.cpus.enabled().count(), 4);
+    assert_eq!(acpi.cpus.bsp().unwrap().apic_id, 0);
+    assert_eq!(acpi.cpus.by_logical_id(3).unwrap().apic_id, 3);
+
+    // Interrupts: the PIT is on GSI 2 and the SCI is level triggered
+    let interrupts = &acpi.interrupts;
+    assert_eq!(interrupts.io_apics().len(), 1);
+    assert_eq!(interrupts.io_apics()[0].address, 0xfec0_0000);
+    assert_eq!(interrupts.isa_irq(0).0, 2);
+    let (gsi, flags) = interrupts.isa_irq(9);
+    assert_eq!(gsi, 9);
+    assert_eq!(flags.trigger, TriggerMode::Level);
+    assert_eq!(flags.polarity, Polarity::ActiveHigh);
+    assert_eq!(interrupts.isa_irq(4).0, 4);
+    assert_eq!(interrupts.local_apic_nmis_for(2).next().unwrap().lint, 1);
+
+    // Fixed hardware
+    let fadt = acpi.fadt.unwrap();
+    assert_eq!(fadt.dsdt, tables.find(*b"DSDT").unwrap().addr);
+    assert_eq!(fadt.pm_timer.unwrap().port(), Some(0x608));
+    assert_eq!(fadt.reset.unwrap().value, 0x0f);
+    assert_eq!(acpi.s5, Some(SleepType { slp_typ_a: 0, slp_typ_b: 0 }));
+
+    // Timers and PCI
+    let hpet = acpi.hpet.unwrap();
+    assert_eq!(hpet.base.address, 0xfed0_0000);
+    assert_eq!(hpet.comparators, 3);
+    assert_eq!(hpet.vendor_id, 0x8086);
+    assert_eq!(acpi.mcfg.unwrap().find(0, 0).unwrap().base, 0xb000_0000);
+
+    // The FACS comes from the FADT, and QEMU's emulated PM timer is good
+    let facs = acpi.facs.unwrap();
+    assert_eq!(tables.find(*b"FACS").unwrap().addr, fadt.facs);
+    assert_eq!(facs.hardware_signature, 0x1234);
+    assert_eq!(facs.version, 2);
+    let waet = acpi.waet.unwrap();
+    assert!(waet.acpi_pm_timer_good && !waet.rtc_good);
+
+    assert!(acpi.numa.is_none());
+    assert!(acpi.dmar.is_none());
+}
+
+#[test]
+fn typed_headers() {
+    // The BGRT QEMU doesn't have and the error tables a server has
+    let mut bgrt = vec![1, 0, 0x3, 0];
+    bgrt.extend_from_slice(&0x7e00_0000u64.to_le_bytes());
+    bgrt.extend_from_slice(&320u32.to_le_bytes());
+    bgrt.extend_from_slice(&200u32.to_le_bytes());
+    let mut bert = 0x1000u32.to_le_bytes().to_vec();
+    bert.extend_from_slice(&0x7f00_0000u64.to_le_bytes());
+    let mut erst = vec![48, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0];
+    erst.extend_from_slice(&[0; 64]);
+    let image = fixtures::q35()
+        .table(fixtures::table(b"BGRT", 1, &bgrt))
+        .table(fixtures::table(b"BERT", 1, &bert))
+        .table(fixtures::table(b"HEST", 1, &0u32.to_le_bytes()))
+        .table(fixtures::table(b"ERST", 1, &erst))
+        .build();
+    let acpi = image.decode(0).unwrap();
+
+    let bgrt = acpi.bgrt.unwrap();
+    assert!(bgrt.displayed);
+    assert_eq!(bgrt.orientation, 90);
+    assert_eq!((bgrt.offset_x, bgrt.offset_y), (320, 200));
+    assert_eq!(acpi.bert.unwrap().region, 0x7f00_0000);
+    assert!(acpi.hest.unwrap().sources().is_empty());
+    assert_eq!(acpi.erst.unwrap().instructions, 2);
+
+    // The catalog knows the FACS has no OEM information
+    let facs = acpi.tables.find(*b"FACS").unwrap().to_string();
+    assert!(facs.starts_with("  FACS 0x") && facs.ends_with(" 000040"));
+
+    // One instruction short
+    let mut image = fixtures::q35()
+        .table(fixtures::table(b"ERST", 1, &erst[..erst.len() - 32]))
+        .build();
+    assert!(matches!(image.decode(0),
+        Err(Error::LengthMismatch(TableType::Erst))));
+
+    // A FACS which isn't one
+    image = fixtures::q35().build();
+    image.table_mut(b"FACS")[0] = b'X';
+    assert!(matches!(image.discover(),
+        Err(Error::SignatureMismatch(TableType::Unknown(_)))));
+}
+
+#[test]
+fn display() {
+    assert_eq!(TableType::Fadt.to_string(), "FACP");
+    assert_eq!(format!("{:#}", TableType::Pptt),
+        "PPTT (Processor Properties Topology Table)");
+    assert_eq!(format!("{:#}", TableType::Unknown(*b"OEM\0")), "OEM\\x00");
+    assert_eq!(Error::ChecksumMismatch(TableType::Ssdt).to_string(),
+        "SSDT checksum mismatch");
+    assert_eq!(Error::BadRevision(TableType::Madt, 0).to_string(),
+        "APIC has bad revision 0");
+    assert_eq!(Error::Unreadable(0x1000).to_string(),
+        "unreadable memory at 0x1000");
+}
+
+#[test]
+fn acpi1_rsdt() {
+    let image = fixtures::q35().acpi1().build();
+
+    let tables = image.discover().unwrap();
+    assert_eq!(signatures(&tables)[..2], [b"RSDP", b"RSDT"]);
+    assert_eq!(tables.find(*b"RSDP").unwrap().length, 20);
+    assert_eq!(image.decode(0).unwrap().cpus.enabled().count(), 4);
+}
+
+#[test]
+fn multi_socket() {
+    let image = fixtures::multi_socket().build();
+    let acpi = image.decode(0x11).unwrap();
+
+    // The BSP is logical processor 0 wherever it is
+    assert_eq!(acpi.cpus.enabled().count(), 8);
+    assert_eq!(acpi.cpus.by_logical_id(0).unwrap().apic_id, 0x11);
+    assert_eq!(acpi.cpus.by_logical_id(1).unwrap().apic_id, 0);
+
+    // One I/O APIC per socket
+    let (io_apic, input) = acpi.interrupts.io_apic_for_gsi(30).unwrap();
+    assert_eq!((io_apic.id, input), (9, 6));
+    assert_eq!(acpi.interrupts.local_apic_nmis()[0].flags.polarity,
+        Polarity::ActiveHigh);
+
+    // One NUMA node per socket
+    let numa = acpi.numa.unwrap();
+    assert_eq!(numa.nodes().len(), 2);
+    assert_eq!(numa.domain_of_apic(0x12), Some(1));
+    assert_eq!(numa.domain_of_apic(0x03), Some(0));
+    assert_eq!(numa.node(0).unwrap().memory.entries().len(), 2);
+    assert_eq!(numa.memory_for_apic(0x10).unwrap().sum(), Some(0x8000_0000));
+    assert_eq!(numa.distance(0, 1), 21);
+    assert_eq!(numa.nearest(1).entries(), [0]);
+
+    // Two PCI segments
+    let mcfg = acpi.mcfg.unwrap();
+    assert_eq!(mcfg.find(1, 200).unwrap().base, 0x9000_0000);
+    assert!(mcfg.find(0, 200).is_none());
+
+    // The RMRR is kept out of free memory
+    let dmar = acpi.dmar.unwrap();
+    assert_eq!(dmar.drhds().len(), 1);
+    assert!(dmar.drhds()[0].include_pci_all);
+    assert_eq!(dmar.rmrrs()[0].scopes.entries()[0].path.entries()[0].device,
+        0x1d);
+    let mut memory = RangeSet::<4>::new();
+    memory.insert(Range { start: 0x10_0000, end: 0x7fff_ffff }).unwrap();
+    dmar.reserve(&mut memory).unwrap();
+    assert_eq!(memory.sum(), Some(0x7ff0_0000 - 0x20_0000));
+}
+
+#[test]
+fn pptt() {
+    let image = fixtures::multi_socket().build();
+    let acpi = image.decode(0x11).unwrap();
+    let pptt = acpi.pptt.unwrap();
+    assert_eq!(pptt.nodes_of(NodeKind::Package).count(), 2);
+    assert_eq!(pptt.nodes_of(NodeKind::Cluster).count(), 4);
+    assert_eq!(pptt.nodes_of(NodeKind::Core).count(), 8);
+    assert_eq!(pptt.caches().len(), 2 + 4 + 16);
+
+    // The BSP's L2 is shared with its neighbour, its L3 with its socket
+    let bsp = acpi.cpus.bsp().unwrap().acpi_uid;
+    assert_eq!(bsp, 5);
+    assert_eq!(pptt.sharing(bsp, 2).entries(), [4, 5]);
+    assert_eq!(pptt.sharing(bsp, 3).entries(), [4, 5, 6, 7]);
+    assert!(pptt.shares_cache(0, 3, 3));
+    assert!(!pptt.shares_cache(3, 4, 3));
+
+    // Levels come from the hierarchy and the next level links
+    let l1 = pptt.cache_at(bsp, 1).unwrap();
+    assert_eq!(pptt.caches()[l1].typ(), Some(CacheType::Data));
+    assert_eq!(pptt.caches()[l1].size(), Some(48 << 10));
+    let l3 = pptt.cache_at(bsp, 3).unwrap();
+    assert_eq!(pptt.caches()[l3].size(), Some(32 << 20));
+    assert_eq!(pptt.caches()[l3].id(), Some(pptt.caches()[l3].offset));
+    assert_eq!(pptt.caches_of(bsp).len(), 4);
+    assert_eq!(pptt.cache_at(bsp, 4), None);
+
+    // The package node identifies the socket
+    let package = pptt.package(bsp).unwrap();
+    assert_eq!(pptt.nodes()[package].acpi_uid(), Some(1));
+    assert!(pptt.sharing(42, 2).is_empty());
+}
+
+#[test]
+fn boot_errors() {
+    const MEMORY: Guid = Guid(0xa5bc1114, 0x6f64, 0x4ede,
+        [0xb8, 0x63, 0x3e, 0x83, 0xed, 0x7c, 0x83, 0xb1]);
+    const IA32_X64: Guid = Guid(0xdc3ea0b0, 0xa144, 0x4797,
+        [0xb9, 0x5b, 0x53, 0xfa, 0x24, 0x2b, 0x6e, 0x1d]);
+    const VENDOR: Guid = Guid(0x12345678, 0x9abc, 0xdef0, [1; 8]);
+
+    // A fatal machine check on APIC ID 3 while reading a DIMM, with the
+    // physical address valid
+    let mut memory = 0x2u64.to_le_bytes().to_vec();
+    memory.extend_from_slice(&0u64.to_le_bytes());
+    memory.extend_from_slice(&0x1234_5000u64.to_le_bytes());
+    memory.extend_from_slice(&[0; 56]);
+    let mut mce = 0x1u64.to_le_bytes().to_vec();
+    mce.extend_from_slice(&3u64.to_le_bytes());
+    mce.extend_from_slice(&[0; 48]);
+    let mut region = fixtures::error_status(1, &[
+        (IA32_X64, 1, "", &mce),
+        (MEMORY, 1, "DIMM A1", &memory),
+    ]);
+    let first = region.len();
+    region.extend_from_slice(
+        &fixtures::error_status(2, &[(VENDOR, 2, "", &[0; 4])]));
+
+    // The region is bigger than the blocks in it
+    region.extend_from_slice(&[0; 64]);
+    let mem = BufferMemory::new(0x7f00_0000, &region);
+    let bert = Bert { region: 0x7f00_0000, region_length: region.len() as u32 };
+    let errors: Vec<_> = bert.errors(&mem).unwrap().collect();
+    assert_eq!(errors.len(), 2);
+
+    let status = errors[0];
+    assert!(status.uncorrectable && !status.correctable);
+    assert_eq!(status.severity, Severity::Fatal);
+    assert_eq!(status.length(), first);
+    let sections: Vec<_> = status.sections().collect();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].typ, SectionType::Ia32X64);
+    assert_eq!(sections[0].apic_id(), Some(3));
+    assert_eq!(sections[0].physical_address(), None);
+    assert_eq!(sections[1].fru_text, Some(&b"DIMM A1"[..]));
+    assert_eq!(sections[1].physical_address(), Some(0x1234_5000));
+    assert_eq!(sections[1].timestamp, Some(0x2026_1018_0012_3456));
+    assert_eq!(status.to_string(), "Fatal error, 2 sections\n  \
+        [0] IA32/X64 processor error (Fatal, 64 bytes) on APIC ID 3\n  \
+        [1] memory error (Fatal, 80 bytes) FRU \"DIMM A1\" at 0x12345000");
+
+    // Sections we don't know are still shown
+    assert_eq!(errors[1].sections().next().unwrap().to_string(),
+        "section 12345678-9abc-def0-0101-010101010101 (Corrected, 4 bytes)");
+
+    // A section running past the end of its block ends the sections
+    let mut region = fixtures::error_status(1, &[(MEMORY, 1, "", &memory)]);
+    region[12] -= 1;
+    let mem = BufferMemory::new(0x7f00_0000, &region);
+    let bert = Bert { region: 0x7f00_0000, region_length: region.len() as u32 };
+    let status = bert.errors(&mem).unwrap().next().unwrap();
+    assert_eq!(status.sections().count(), 0);
+
+    // A region outside of memory
+    let bert = Bert { region: 0x8000_0000, region_length: 0x1000 };
+    assert!(matches!(bert.errors(&mem),
+        Err(Error::Unreadable(0x8000_0000))));
+}
+
+#[test]
+fn hest() {
+    let hest = HestBuilder::new()
+        .machine_check(0, 20)
+        .corrected_machine_check(1, 20)
+        .nmi(2)
+        .pcie_root_port(3)
+        .generic_v2(4, 0x7f00_1000);
+    let acpi = fixtures::q35().table(hest.build()).build().decode(0).unwrap();
+    let hest = acpi.hest.unwrap();
+
+    let sources = hest.sources();
+    assert_eq!(sources.iter().map(|x| x.typ).collect::<Vec<_>>(), [
+        ErrorSourceType::MachineCheck,
+        ErrorSourceType::CorrectedMachineCheck,
+        ErrorSourceType::Nmi,
+        ErrorSourceType::PcieRootPort,
+        ErrorSourceType::GenericV2,
+    ]);
+    assert_eq!(sources.iter().map(|x| x.source_id).collect::<Vec<_>>(),
+        [0, 1, 2, 3, 4]);
+    assert!(sources.iter().all(|x| x.enabled));
+    assert_eq!((sources[0].banks, sources[1].banks), (20, 20));
+    assert_eq!(sources[1].notification, Some(Notification::Polled));
+    assert!(sources[3].global);
+    assert!(sources[4].firmware_first);
+    assert_eq!(sources[4].notification, Some(Notification::Sci));
+    assert_eq!(sources[4].error_status_address.unwrap().address,
+        0x7f00_1000);
+    assert_eq!(hest.machine_check_sources().count(), 2);
+
+    // One machine check bank short, and a type without a known length
+    let mut table = HestBuilder::new().machine_check(0, 2).build();
+    table[72] = 3;
+    fixtures::fix_checksum(&mut table, 9);
+    let mut image = fixtures::q35().table(table).build();
+    assert!(matches!(image.decode(0),
+        Err(Error::LengthMismatch(TableType::Hest))));
+    let mut table = HestBuilder::new().nmi(0).build();
+    table[40] = 3;
+    fixtures::fix_checksum(&mut table, 9);
+    image = fixtures::q35().table(table).build();
+    assert!(matches!(image.decode(0),
+        Err(Error::LengthMismatch(TableType::Hest))));
+}
+
+#[test]
+fn x2apic_only() {
+    let image = fixtures::x2apic_only().build();
+    let acpi = image.decode(0x100).unwrap();
+
+    let cpus = &acpi.cpus;
+    assert_eq!(cpus.processors().len(), 4);
+    assert_eq!(cpus.enabled().count(), 3);
+    assert_eq!(cpus.bsp().unwrap().acpi_uid, 0);
+    assert_eq!(cpus.by_acpi_uid(2).unwrap().apic_id, 0x102);
+    assert_eq!(cpus.by_apic_id(0x103).unwrap().logical_id, None);
+    assert!(!acpi.interrupts.pcat_compat);
+
+    // The all processors NMI applies to everyone, the other only to UID 2
+    assert_eq!(acpi.interrupts.local_apic_nmis_for(0).count(), 1);
+    assert_eq!(acpi.interrupts.local_apic_nmis_for(2).count(), 2);
+
+    let numa = acpi.numa.unwrap();
+    assert_eq!(numa.domain_of_apic(0x102), Some(1));
+    assert_eq!(numa.domain_of_apic(0x103), None);
+}
+
+#[test]
+fn efi_error() {
+    // Firmware without an ACPI configuration table
+    let (_fw, ret) = MockFirmware::new().install();
+    ret.unwrap();
+    assert!(matches!(unsafe { tables(Policy::Strict) },
+        Err(Error::Efi(efi::Error::AcpiTableNotFound))));
+}
+
+#[test]
+fn checksum_mismatch() {
+    let mut image = fixtures::q35().build();
+    image.bytes[8] ^= 1;
+    assert!(matches!(image.discover(),
+        Err(Error::ChecksumMismatch(TableType::Rsdp))));
+
+    // Only the extended checksum is wrong
+    let mut image = fixtures::q35().build();
+    image.bytes[32] ^= 1;
+    assert!(matches!(image.discover(),
+        Err(Error::ChecksumMismatch(TableType::RsdpExtended))));
+
+    let mut image = fixtures::q35().build();
+    image.table_mut(b"APIC")[44] ^= 1;
+    assert!(matches!(image.discover(),
+        Err(Error::ChecksumMismatch(TableType::Madt))));
+}
+
+#[test]
+fn signature_mismatch() {
+    let mut image = fixtures::q35().build();
+    image.bytes[0] = b'X';
+    image.fix_rsdp();
+    assert!(matches!(image.discover(),
+        Err(Error::SignatureMismatch(TableType::Rsdp))));
+
+    // An XSDT pointer to something else
+    let mut image = fixtures::q35().build();
+    image.table_mut(b"XSDT")[..4].copy_from_slice(b"RSDT");
+    image.fix_checksum(b"RSDT");
+    assert!(matches!(image.discover(),
+        Err(Error::SignatureMismatch(TableType::Rsdt))));
+
+    // A FADT pointing to an SSDT rather than the DSDT
+    let mut image = fixtures::q35().build();
+    image.table_mut(b"DSDT")[..4].copy_from_slice(b"SSDT");
+    image.fix_checksum(b"SSDT");
+    assert!(matches!(image.discover(),
+        Err(Error::SignatureMismatch(TableType::Ssdt))));
+}
+
+#[test]
+fn length_mismatch() {
+    // The extended RSDP length is wrong
+    let mut image = fixtures::q35().build();
+    image.bytes[20] = 20;
+    image.fix_rsdp();
+    assert!(matches!(image.discover(),
+        Err(Error::LengthMismatch(TableType::RsdpExtended))));
+
+    // A table shorter than its header
+    let mut image = fixtures::q35().build();
+    image.set_length(b"HPET", 20);
+    assert!(matches!(image.discover(),
+        Err(Error::LengthMismatch(TableType::Hpet))));
+
+    // Lengths are checked before the checksum, which is never computed over
+    // memory past the end of the table's region
+    for &length in [20, image.bytes.len() as u32, u32::MAX].iter() {
+        let mut image = fixtures::q35().build();
+        image.table_mut(b"HPET")[4..8].copy_from_slice(&length.to_le_bytes());
+        assert!(matches!(image.discover(),
+            Err(Error::LengthMismatch(TableType::Hpet))));
+    }
+
+    // A local APIC structure with the wrong length
+    let image = Firmware::new()
+        .table(MadtBuilder::new(0xfee0_0000, true)
+            .raw(0, &[0, 0, 1, 0, 0, 0, 0])
+            .build())
+        .build();
+    assert!(matches!(image.decode(0),
+        Err(Error::LengthMismatch(TableType::Madt))));
+
+    // A FADT too short for ACPI 1.0
+    let image = Firmware::new()
+        .table(fixtures::table(b"FACP", 1, &[0; 40]))
+        .build();
+    assert!(matches!(image.discover(),
+        Err(Error::LengthMismatch(TableType::Fadt))));
+}
+
+#[test]
+fn revision_too_old() {
+    let image = fixtures::q35().acpi1().build();
+    let mem = image.memory();
+    assert!(matches!(unsafe { RsdpExtended::from_addr(&mem, image.rsdp()) },
+        Err(Error::RevisionTooOld)));
+}
+
+#[test]
+fn bad_revision() {
+    let mut image = fixtures::q35().build();
+    image.table_mut(b"APIC")[8] = 0;
+    image.fix_checksum(b"APIC");
+    assert!(matches!(image.discover(),
+        Err(Error::BadRevision(TableType::Madt, 0))));
+
+    // Too short for the FADT revision
+    let mut image = fixtures::q35().build();
+    image.set_length(b"FACP", 116);
+    assert!(matches!(image.discover(),
+        Err(Error::LengthMismatch(TableType::Fadt))));
+    image.table_mut(b"FACP")[8] = 2;
+    image.fix_checksum(b"FACP");
+    assert!(image.discover().is_ok());
+}
+
+#[test]
+fn bad_entries() {
+    let mut image = fixtures::q35().build();
+    image.set_length(b"XSDT", 36 + 5 * 8 + 4);
+    assert!(matches!(image.discover(), Err(Error::XsdtBadEntries)));
+
+    let mut image = fixtures::q35().acpi1().build();
+    image.set_length(b"RSDT", 36 + 5 * 4 + 2);
+    assert!(matches!(image.discover(), Err(Error::RsdtBadEntries)));
+}
+
+#[test]
+fn integer_overflow() {
+    // A memory range wrapping around the address space
+    let image = fixtures::q35()
+        .table(SratBuilder::new().memory(0, !0xfff, 0x2000).build())
+        .build();
+    assert!(matches!(image.decode(0), Err(Error::IntegerOverflow)));
+
+    // The RSDP at the very end of the address space
+    let bytes = [0u8; 8];
+    let mem = BufferMemory::new(!7, &bytes);
+    assert!(matches!(unsafe { Rsdp::from_addr(&mem, PhysAddr(!7)) },
+        Err(Error::IntegerOverflow)));
+}
+
+#[test]
+fn unreadable() {
+    // An XSDT entry pointing outside of memory
+    let mut image = fixtures::q35().build();
+    image.table_mut(b"XSDT")[36..44].copy_from_slice(&0x1000u64.to_le_bytes());
+    image.fix_checksum(b"XSDT");
+    assert!(matches!(image.discover(), Err(Error::Unreadable(0x1000))));
+}
+
+#[test]
+fn table_not_found() {
+    let image = Firmware::new().table(fixtures::hpet()).build();
+    assert!(matches!(image.decode(0),
+        Err(Error::TableNotFound(TableType::Madt))));
+}
+
+#[test]
+fn too_many_processors() {
+    let mut madt = MadtBuilder::new(0xfee0_0000, false);
+    for id in 0..=madt::MAX_CPUS as u32 {
+        madt = madt.x2apic(id, id, 1);
+    }
+    let image = Firmware::new().table(madt.build()).build();
+    assert!(matches!(image.decode(0), Err(Error::TooManyProcessors)));
+}
+
+#[test]
+fn too_many_interrupt_structures() {
+    let mut madt = MadtBuilder::new(0xfee0_0000, false).local_apic(0, 0, 1);
+    for id in 0..17 {
+        madt = madt.io_apic(id, 0xfec0_0000 + id as u32 * 0x1000,
+            id as u32 * 24);
+    }
+    let image = Firmware::new().table(madt.build()).build();
+    assert!(matches!(image.decode(0), Err(Error::TooManyInterruptStructures)));
+}
+
+#[test]
+fn too_many_ecam_segments() {
+    let regions: Vec<_> = (0..=mcfg::MAX_ECAM_SEGMENTS as u16)
+        .map(|x| (0x8000_0000 + x as u64 * 0x1000_0000, x, 0, 255))
+        .collect();
+    let image = fixtures::q35().table(fixtures::mcfg(&regions)).build();
+    assert!(matches!(image.decode(0), Err(Error::TooManyEcamSegments)));
+}
+
+#[test]
+fn too_many_tables() {
+    // The RSDP and XSDT take two entries
+    let mut firmware = Firmware::new();
+    for _ in 0..catalog::MAX_TABLES - 1 {
+        firmware = firmware.table(fixtures::table(b"SSDT", 2, &[]));
+    }
+    assert!(matches!(firmware.build().discover(), Err(Error::TooManyTables)));
+}
+
+#[test]
+fn too_many_dmar_structures() {
+    let mut payload = vec![38, 1];
+    payload.extend_from_slice(&[0; 10]);
+    for unit in 0..9u64 {
+        payload.extend_from_slice(&[0, 0, 16, 0, 0, 0, 0, 0]);
+        payload.extend_from_slice(&(0xfed9_0000 + unit * 0x1000).to_le_bytes());
+    }
+    let image = fixtures::q35()
+        .table(fixtures::table(b"DMAR", 1, &payload))
+        .build();
+    assert!(matches!(image.decode(0), Err(Error::TooManyDmarStructures)));
+}
+
+#[test]
+fn rmrr_range_set() {
+    let image = fixtures::multi_socket().build();
+    let dmar = image.decode(0).unwrap().dmar.unwrap();
+
+    // Splitting a range in a full set needs an entry we don't have
+    let mut memory = RangeSet::<4>::new();
+    memory.insert(Range { start: 0x7000_0000, end: 0x7fff_ffff }).unwrap();
+    for ii in 1..memory.capacity() as u64 {
+        memory.insert(Range {
+            start: ii << 32,
+            end: (ii << 32) + 0xfff,
+        }).unwrap();
+    }
+    assert!(matches!(dmar.reserve(&mut memory),
+        Err(Error::RmrrRangeSet(rangeset::Error::OutOfEntries))));
+}
+
+#[test]
+fn too_many_numa_nodes() {
+    // The domains past the ones we track are merged into the first node
+    let max = srat::MAX_NUMA_NODES;
+    let mut srat = SratBuilder::new();
+    for domain in 0..=max as u32 {
+        srat = srat.cpu(domain as u8, domain)
+            .memory(domain, domain as u64 * 0x1000_0000, 0x1000_0000);
+    }
+    let row: &[u8] = &[REMOTE_DISTANCE; srat::MAX_NUMA_NODES + 1];
+    let image = fixtures::q35()
+        .table(srat.build())
+        .table(fixtures::slit(&[row; srat::MAX_NUMA_NODES + 1]))
+        .build();
+    let numa = image.decode(0).unwrap().numa.unwrap();
+    assert!(numa.merged);
+    assert_eq!(numa.nodes().len(), max);
+    assert!(numa.node(max as u32).is_none());
+    assert_eq!(numa.domain_of_apic(max as u32), Some(0));
+    assert_eq!(numa.node(0).unwrap().memory.sum(), Some(0x2000_0000));
+
+    // The SLIT keeps the distances between the nodes we track
+    let distances = numa.distances.unwrap();
+    assert_eq!(distances.localities(), max);
+    assert_eq!(distances.distance(0, max as u32 - 1), Some(REMOTE_DISTANCE));
+    assert_eq!(distances.distance(0, max as u32), None);
+}
+
+#[test]
+fn numa_hotplug() {
+    // Hot-pluggable memory is tracked per node, and is part of its memory
+    let srat = SratBuilder::new()
+        .memory(0, 0, 0x8000_0000)
+        .hotplug_memory(0, 0x1_0000_0000, 0x4000_0000)
+        .hotplug_memory(1, 0x2_0000_0000, 0x1000_0000)
+        .build();
+    let image = fixtures::q35().table(srat).build();
+    let numa = image.decode(0).unwrap().numa.unwrap();
+    assert!(!numa.merged);
+    let node = numa.node(0).unwrap();
+    assert_eq!(node.memory.sum(), Some(0xc000_0000));
+    assert_eq!(node.hotplug.sum(), Some(0x4000_0000));
+    let node = numa.node(1).unwrap();
+    assert_eq!(node.memory.sum(), node.hotplug.sum());
+    assert_eq!(node.hotplug.sum(), Some(0x1000_0000));
+}
+
+#[test]
+fn numa_range_set() {
+    // More disjoint ranges in one node than we can track
+    let mut srat = SratBuilder::new();
+    for ii in 0..=srat::MAX_NODE_RANGES as u64 {
+        srat = srat.memory(0, ii * 0x2000, 0x1000);
+    }
+    let image = fixtures::q35().table(srat.build()).build();
+    assert!(matches!(image.decode(0),
+        Err(Error::NumaRangeSet(rangeset::Error::OutOfEntries))));
+}
+
+#[test]
+fn too_many_topology_nodes() {
+    // Two packages of 64 cores with 2 threads each, and their caches
+    let mut pptt = PpttBuilder::new();
+    for _ in 0..2 {
+        let l3 = pptt.cache(32 << 20, 2, 0);
+        let package = pptt.node(0x1, 0, 0, &[l3]);
+        for _ in 0..64 {
+            let l2 = pptt.cache(1 << 20, 2, 0);
+            let l1d = pptt.cache(48 << 10, 0, l2);
+            let l1i = pptt.cache(32 << 10, 1, l2);
+            let core = pptt.node(0x0, package, 0, &[l1d, l1i]);
+            for thread in 0..2 {
+                pptt.node(0xe, core, thread, &[]);
+            }
+        }
+    }
+    let image = fixtures::q35().table(pptt.build()).build();
+    let pptt = image.decode(0).unwrap().pptt.unwrap();
+    assert_eq!(pptt.nodes().len(), 386);
+    assert_eq!(pptt.caches().len(), 386);
+    assert_eq!(pptt.nodes_of(NodeKind::Core).count(), 128);
+
+    let mut pptt = PpttBuilder::new();
+    for uid in 0..=pptt::MAX_NODES as u32 {
+        pptt.node(0xa, 0, uid, &[]);
+    }
+    let image = fixtures::q35().table(pptt.build()).build();
+    assert!(matches!(image.decode(0), Err(Error::TooManyTopologyNodes)));
+}
+
+#[test]
+fn too_many_error_sources() {
+    let mut hest = HestBuilder::new();
+    for id in 0..65 {
+        hest = hest.nmi(id);
+    }
+    let image = fixtures::q35().table(hest.build()).build();
+    assert!(matches!(image.decode(0), Err(Error::TooManyErrorSources)));
+}
+
+#[test]
+fn invalid_reference() {
+    // A core whose parent is the middle of its own structure
+    let mut pptt = PpttBuilder::new();
+    pptt.node(0xa, 40, 0, &[]);
+    let image = fixtures::q35().table(pptt.build()).build();
+    assert!(matches!(image.decode(0),
+        Err(Error::InvalidReference(TableType::Pptt))));
+}
+
+#[test]
+fn lenient_skips_bad_tables() {
+    // A bad HPET checksum and a truncated MCFG allocation
+    let mut image = fixtures::q35()
+        .table(fixtures::table(b"MCFG", 1, &[0; 12]))
+        .build();
+    image.table_mut(b"HPET")[40] ^= 1;
+    assert!(matches!(image.discover(),
+        Err(Error::ChecksumMismatch(TableType::Hpet))));
+
+    let image = image.lenient();
+    let acpi = image.decode(0).unwrap();
+    assert!(acpi.hpet.is_none());
+    assert_eq!(acpi.cpus.enabled().count(), 4);
+
+    // Both tables are in the catalog with why they were skipped
+    let skipped: Vec<_> = acpi.tables.skipped().collect();
+    assert_eq!(skipped.len(), 2);
+    assert_eq!(&skipped[0].signature, b"HPET");
+    assert!(matches!(skipped[0].status,
+        TableStatus::Skipped(Error::ChecksumMismatch(TableType::Hpet))));
+    assert_eq!(&skipped[1].signature, b"MCFG");
+    assert!(matches!(skipped[1].status,
+        TableStatus::Skipped(Error::LengthMismatch(TableType::Mcfg))));
+
+    // Skipped tables can't be found, so the good MCFG is used
+    assert_eq!(acpi.tables.find_all(*b"MCFG").count(), 1);
+    assert_eq!(acpi.mcfg.unwrap().find(0, 0).unwrap().base, 0xb000_0000);
+
+    let report = std::format!("{}", acpi.tables.diagnostics());
+    assert!(report.starts_with("ACPI: 10 tables, 2 skipped\n"));
+    assert!(report.contains("  APIC 0x"));
+    assert!(report.contains(" ok\n"));
+    assert!(report.contains(" skipped: ChecksumMismatch(Hpet)\n"));
+}
+
+#[test]
+fn lenient_keeps_essential_tables() {
+    let mut image = fixtures::q35().build().lenient();
+    image.table_mut(b"APIC")[44] ^= 1;
+    assert!(matches!(image.discover(),
+        Err(Error::ChecksumMismatch(TableType::Madt))));
+
+    let mut image = fixtures::q35().build().lenient();
+    image.table_mut(b"XSDT")[36] ^= 1;
+    assert!(matches!(image.discover(),
+        Err(Error::ChecksumMismatch(TableType::Xsdt))));
+
+    // An MADT which validates but doesn't decode
+    let image = Firmware::new()
+        .table(MadtBuilder::new(0xfee0_0000, true)
+            .raw(0, &[0, 0, 1, 0, 0, 0, 0])
+            .build())
+        .build()
+        .lenient();
+    assert!(matches!(image.decode(0),
+        Err(Error::LengthMismatch(TableType::Madt))));
+}
+
+#[test]
+fn lenient_bad_fadt() {
+    // Without a usable FADT there is no DSDT
+    let image = Firmware::new()
+        .table(fixtures::table(b"FACP", 1, &[0; 40]))
+        .dsdt(fixtures::S5_AML)
+        .build()
+        .lenient();
+    let tables = image.discover().unwrap();
+    assert!(tables.find(*b"FACP").is_none());
+    assert!(tables.find(*b"DSDT").is_none());
+    assert!(matches!(tables.skipped().next().unwrap().status,
+        TableStatus::Skipped(Error::LengthMismatch(TableType::Fadt))));
+
+    let mut image = fixtures::q35().build().lenient();
+    image.table_mut(b"DSDT")[..4].copy_from_slice(b"SSDT");
+    image.fix_checksum(b"SSDT");
+    let acpi = image.decode(0).unwrap();
+    assert!(acpi.s5.is_none());
+    assert!(acpi.tables.find(*b"DSDT").is_none());
+    assert!(matches!(acpi.tables.skipped().next().unwrap().status,
+        TableStatus::Skipped(Error::SignatureMismatch(_))));
+}
+
+#[test]
+fn lenient_unreadable_table() {
+    let mut image = fixtures::q35().build().lenient();
+    image.table_mut(b"XSDT")[36..44].copy_from_slice(&0x1000u64.to_le_bytes());
+    image.fix_checksum(b"XSDT");
+
+    let tables = image.discover().unwrap();
+    let skipped = tables.skipped().next().unwrap();
+    assert_eq!((&skipped.signature, skipped.addr), (b"????", 0x1000));
+    assert!(matches!(skipped.status,
+        TableStatus::Skipped(Error::Unreadable(0x1000))));
+
+    // The FADT was the unreadable entry
+    assert!(image.decode(0).unwrap().fadt.is_none());
+}
+
+#[test]
+fn lenient_dump_skipped() {
+    // A table claiming to be 4 GiB long
+    let mut image = fixtures::q35().build().lenient();
+    image.table_mut(b"HPET")[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    let tables = image.discover().unwrap();
+    let skipped = tables.skipped().next().unwrap();
+    assert_eq!(&skipped.signature, b"HPET");
+
+    // Only what can be read is dumped, not gigabytes
+    let memory = image.memory();
+    let dump = format!("{}", skipped.hex_dump(&memory));
+    assert!(dump.starts_with("HPET @ 0x"));
+    assert!(dump.contains("    0000: 48 50 45 54 FF FF FF FF"));
+    assert!(dump.lines().count() <= image.bytes.len() / 16 + 2);
+}
+
+#[test]
+fn firecracker() {
+    // Tables captured from a Firecracker microVM guest with one vCPU
+    let image = Firmware::captured("firecracker").build();
+
+    let tables = image.discover().unwrap();
+    assert_eq!(signatures(&tables),
+        [b"RSDP", b"XSDT", b"APIC", b"FACP", b"MCFG", b"DSDT"]);
+    assert_eq!(tables.find(*b"FACP").unwrap().oem_id, *b"FIRECK");
+    assert_eq!(tables.find(*b"DSDT").unwrap().length, 3923);
+
+    let acpi = image.decode(0).unwrap();
+    assert_eq!(acpi.tables.skipped().count(), 0);
+
+    // One processor and one I/O APIC, with no legacy PIC overrides
+    assert_eq!(acpi.cpus.enabled().count(), 1);
+    assert_eq!(acpi.cpus.bsp().unwrap().apic_id, 0);
+    assert_eq!(acpi.interrupts.io_apics().len(), 1);
+    assert_eq!(acpi.interrupts.io_apics()[0].address, 0xfec0_0000);
+    assert!(acpi.interrupts.overrides().is_empty());
+
+    // A hardware-reduced platform: no PM1 registers, reset register or FACS
+    let fadt = acpi.fadt.unwrap();
+    assert!(fadt.hardware_reduced());
+    assert_eq!(fadt.revision, 6);
+    assert!(fadt.pm1a_control.is_none() && fadt.reset.is_none());
+    assert!(fadt.boot_arch.vga_not_present && !fadt.boot_arch.i8042);
+    assert_eq!(fadt.dsdt, tables.find(*b"DSDT").unwrap().addr);
+    assert!(acpi.facs.is_none());
+    assert!(acpi.s5.is_none());
+
+    // ECAM for a single bus
+    let mcfg = acpi.mcfg.unwrap();
+    let ecam = mcfg.find(0, 0).unwrap();
+    assert_eq!((ecam.base, ecam.end_bus), (0xeec0_0000, 0));
+    assert!(mcfg.find(0, 1).is_none());
+
+    assert!(acpi.hpet.is_none());
+    assert!(acpi.numa.is_none());
+}
+
+#[test]
+fn fits_on_the_stack() {
+    // `Acpi` lives on the boot stack, which UEFI only guarantees to be
+    // 128 KiB
+    assert!(core::mem::size_of::<Acpi>() <= 64 * 1024);
+}
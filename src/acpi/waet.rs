@@ -0,0 +1,46 @@
+//! Windows ACPI Emulated Devices Table (WAET) parsing, which virtual machines
+//! use to say which emulated devices are cheap to access.
+
+use super::{Error, Result, TableType};
+use crate::mm::physmem::{MemoryReader, PhysAddr, PhysSlice};
+
+/// WAET flag: the RTC doesn't need the status register polled for an
+/// update in progress.
+const RTC_GOOD: u32 = 1 << 0;
+
+/// WAET flag: the ACPI PM timer can be read once, without reading it again
+/// to check for a torn value.
+const ACPI_PM_TIMER_GOOD: u32 = 1 << 1;
+
+/// The emulated device hints from the WAET.
+#[derive(Clone, Copy, Debug)]
+pub struct Waet {
+    /// The RTC doesn't need the status register polled.
+    pub rtc_good: bool,
+
+    /// The ACPI PM timer only needs to be read once.
+    pub acpi_pm_timer_good: bool,
+}
+
+impl Waet {
+    /// Process the payload of the WAET (everything after the table header) at
+    /// `addr` for `size` bytes, read through `mem`.
+    pub unsafe fn from_addr<M: MemoryReader>(mem: &M, addr: PhysAddr,
+            size: usize) -> Result<Self> {
+        /// The error type when the WAET is truncated
+        const E: Error = Error::LengthMismatch(TableType::Waet);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(mem, addr, size);
+
+        let flags = slice.consume::<u32>().map_err(|_| E)?;
+        if slice.len() != 0 {
+            return Err(E);
+        }
+
+        Ok(Waet {
+            rtc_good: flags & RTC_GOOD != 0,
+            acpi_pm_timer_good: flags & ACPI_PM_TIMER_GOOD != 0,
+        })
+    }
+}
@@ -1,3 +1,5 @@
+use core::arch::asm;
+
 // was temporarily removed
 #[inline(always)]
 #[cfg(target_arch = "x86_64")]
@@ -0,0 +1,150 @@
+//! Routines for querying the CPU we are running on
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+
+/// The registers returned from a `cpuid` invocation.
+#[derive(Clone, Copy, Debug)]
+pub struct Cpuid {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Execute `cpuid` with `leaf` in `eax` and `subleaf` in `ecx`.
+pub fn cpuid(leaf: u32, subleaf: u32) -> Cpuid {
+    let res = __cpuid_count(leaf, subleaf);
+    Cpuid {
+        eax: res.eax,
+        ebx: res.ebx,
+        ecx: res.ecx,
+        edx: res.edx,
+    }
+}
+
+/// Hypervisors we can identify from their CPUID vendor signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hypervisor {
+    /// The hypervisor bit is clear, we're (probably) on bare metal.
+    None,
+
+    /// Linux KVM.
+    Kvm,
+
+    /// Microsoft Hyper-V.
+    HyperV,
+
+    /// VMware.
+    Vmware,
+
+    /// Xen HVM.
+    Xen,
+
+    /// QEMU without acceleration (Tiny Code Generator).
+    Tcg,
+
+    /// Oracle VirtualBox.
+    VirtualBox,
+
+    /// FreeBSD bhyve.
+    Bhyve,
+
+    /// The hypervisor bit is set, but the vendor signature is not one we know.
+    Unknown([u8; 12]),
+}
+
+impl Hypervisor {
+    /// Detect the hypervisor we're running under via the CPUID hypervisor
+    /// present bit and the vendor signature in leaf `0x40000000`.
+    pub fn detect() -> Self {
+        // CPUID.1:ECX[31] is reserved for use by hypervisors to indicate
+        // their presence.
+        if cpuid(1, 0).ecx & (1 << 31) == 0 {
+            return Hypervisor::None;
+        }
+
+        // Get the 12-byte vendor signature from ebx, ecx, edx.
+        let leaf = cpuid(0x4000_0000, 0);
+        let mut sig = [0u8; 12];
+        sig[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+        sig[4..8].copy_from_slice(&leaf.ecx.to_le_bytes());
+        sig[8..12].copy_from_slice(&leaf.edx.to_le_bytes());
+
+        match &sig {
+            b"KVMKVMKVM\0\0\0" => Hypervisor::Kvm,
+            b"Microsoft Hv" => Hypervisor::HyperV,
+            b"VMwareVMware" => Hypervisor::Vmware,
+            b"XenVMMXenVMM" => Hypervisor::Xen,
+            b"TCGTCGTCGTCG" => Hypervisor::Tcg,
+            b"VBoxVBoxVBox" => Hypervisor::VirtualBox,
+            b"bhyve bhyve " => Hypervisor::Bhyve,
+            _ => Hypervisor::Unknown(sig),
+        }
+    }
+
+    /// Returns `true` if we're running under any hypervisor.
+    pub fn is_virtualized(&self) -> bool {
+        *self != Hypervisor::None
+    }
+}
+
+/// Get the APIC ID of the processor we're running on. This is the full
+/// 32-bit x2APIC ID if the CPU reports it, otherwise the 8-bit initial APIC
+/// ID.
+pub fn apic_id() -> u32 {
+    // Leaf 0xb (extended topology) reports the x2APIC ID in edx. It's only
+    // valid if the leaf exists and reports a non-zero number of logical
+    // processors.
+    if cpuid(0, 0).eax >= 0xb {
+        let topo = cpuid(0xb, 0);
+        if topo.ebx != 0 {
+            return topo.edx;
+        }
+    }
+
+    // Initial APIC ID from CPUID.1:EBX[31:24].
+    cpuid(1, 0).ebx >> 24
+}
+
+/// Read a byte from I/O `port`.
+#[inline]
+pub unsafe fn in8(port: u16) -> u8 {
+    let val: u8;
+    asm!("in al, dx", in("dx") port, out("al") val);
+    val
+}
+
+/// Write `val` to I/O `port`.
+#[inline]
+pub unsafe fn out8(port: u16, val: u8) {
+    asm!("out dx, al", in("dx") port, in("al") val);
+}
+
+/// Read a 16-bit word from I/O `port`.
+#[inline]
+pub unsafe fn in16(port: u16) -> u16 {
+    let val: u16;
+    asm!("in ax, dx", in("dx") port, out("ax") val);
+    val
+}
+
+/// Write the 16-bit `val` to I/O `port`.
+#[inline]
+pub unsafe fn out16(port: u16, val: u16) {
+    asm!("out dx, ax", in("dx") port, in("ax") val);
+}
+
+/// Read a 32-bit dword from I/O `port`.
+#[inline]
+pub unsafe fn in32(port: u16) -> u32 {
+    let val: u32;
+    asm!("in eax, dx", in("dx") port, out("eax") val);
+    val
+}
+
+/// Write the 32-bit `val` to I/O `port`.
+#[inline]
+pub unsafe fn out32(port: u16, val: u32) {
+    asm!("out dx, eax", in("dx") port, in("eax") val);
+}
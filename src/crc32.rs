@@ -0,0 +1,62 @@
+//! A software implementation of the CRC32 used by UEFI (and zlib, Ethernet,
+//! etc.), the reflected form of polynomial 0x04c11db7.
+
+/// Lookup table for a byte at a time CRC32 computation.
+const TABLE: [u32; 256] = make_table();
+
+/// Generate the CRC32 lookup table at compile time.
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+
+    let mut ii = 0;
+    while ii < 256 {
+        let mut crc = ii as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[ii] = crc;
+        ii += 1;
+    }
+
+    table
+}
+
+/// A running CRC32 computation, for when the data is not in one contiguous
+/// slice.
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    /// Start a new CRC32 computation.
+    pub const fn new() -> Self {
+        Crc32(!0)
+    }
+
+    /// Add `byte` to the CRC.
+    #[inline]
+    pub fn update(&mut self, byte: u8) {
+        self.0 = TABLE[((self.0 ^ byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+    }
+
+    /// Get the final CRC32 value.
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// Compute the CRC32 of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    for &byte in bytes {
+        crc.update(byte);
+    }
+    crc.finish()
+}
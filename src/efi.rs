@@ -1,20 +1,30 @@
 use core::{
+    fmt,
     mem::size_of,
     sync::atomic::{AtomicPtr, Ordering},
-    usize,
 };
 
-use crate::mm::rangeset::{self, Range,RangeSet};
+use crate::crc32::Crc32;
+use crate::mm::rangeset::{self, DefaultRangeSet, Range};
+
+#[cfg(test)]
+pub mod mock;
+#[cfg(test)]
+mod tests;
 
 
 /// The maximum number of memory regions that we can save from EFI
 const NUM_MEMORY_REGIONS: usize = 64;
 
+/// The largest `header_size` we accept for an EFI table. The tables we read
+/// are a few hundred bytes, anything past a page is a corrupt header.
+const MAX_TABLE_SIZE: usize = 4096;
+
 /// A 'Result' type wrapping an EFI error.
-type Result<T> = core::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// Errors from EFI calls.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum Error {
     /// The EFI System Table has not been registered.
     NotRegistered,
@@ -26,14 +36,18 @@ pub enum Error {
     EfiSystemTableNotFound,
 
     /// We failed to get the memory map from EFI.
-    MemoryMap(EfiStatus),
+    MemoryMap(EfiError),
 
     /// The memory map returned from EFI was did not fit within the bounds
     /// that it was reported.
     MemoryMapOutOfBounds,
 
     /// We failed exiting EFI boot services.
-    ExitBootServices(EfiStatus),
+    ExitBootServices(EfiError),
+
+    /// The EFI memory map descriptor size was smaller than an
+    /// `EfiMemoryDescriptor`.
+    MemoryMapBadDescriptorSize,
 
     /// An integer overflow occured when processing EFI memory map data.
     MemoryMapIntegerOverflow,
@@ -43,8 +57,88 @@ pub enum Error {
 
     /// An error occured when trying to construct the memory map `RangeSet`.
     MemoryRangeSet(rangeset::Error),
+
+    /// The console failed to output a string.
+    OutputString(EfiError),
+
+    /// We failed to read an EFI variable.
+    GetVariable(EfiError),
+
+    /// The EFI variable name did not fit in our fixed size UCS-2 buffer.
+    VariableNameTooLong,
+
+    /// A pointer to an EFI table was null.
+    TableNull(EfiTable),
+
+    /// An EFI table did not have the expected signature.
+    TableSignature(EfiTable),
+
+    /// An EFI table reported a `header_size` too small to hold the header or
+    /// the fields we need, or too large to be real.
+    TableHeaderSize(EfiTable),
+
+    /// The CRC32 of an EFI table did not match the `crc32` in its header.
+    TableCrc32(EfiTable),
+
+    /// An EFI table had a revision older than the revision which defines a
+    /// field we wanted to use. Holds the table revision and the required
+    /// revision.
+    TableRevisionTooOld(EfiTable, u32, u32),
+}
+
+/// The EFI tables which start with an `EfiTableHeader`, mainly used for error
+/// information.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EfiTable {
+    /// The EFI system table.
+    SystemTable,
+
+    /// The EFI boot services table.
+    BootServices,
+
+    /// The EFI runtime services table.
+    RuntimeServices,
 }
 
+impl EfiTable {
+    /// The signature expected in the header of this table.
+    fn signature(&self) -> u64 {
+        match self {
+            EfiTable::SystemTable => u64::from_le_bytes(*b"IBI SYST"),
+            EfiTable::BootServices => u64::from_le_bytes(*b"BOOTSERV"),
+            EfiTable::RuntimeServices => u64::from_le_bytes(*b"RUNTSERV"),
+        }
+    }
+}
+
+/// EFI 1.02, the first revision of the spec we support. Every table field we
+/// use up to and including `exit_boot_services` is defined by this revision.
+const EFI_1_02_REVISION: u32 = (1 << 16) | 2;
+
+/// ACPI 2.0 or newer tables should use EFI_ACPI_TABLE_GUID
+const EFI_ACPI_TABLE_GUID: EfiGuid = EfiGuid(
+    0x8868e871,
+    0xe4f1,
+    0x11d3,
+    [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+);
+
+/// ACPI 1.0 or newer tables should use EFI_ACPI_TABLE_GUID
+const ACPI_TABLE_GUID: EfiGuid = EfiGuid(
+    0xeb9d2d30,
+    0x2d88,
+    0x11d3,
+    [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+);
+
+/// The vendor GUID for all architecturally defined variables.
+const EFI_GLOBAL_VARIABLE: EfiGuid = EfiGuid(
+    0x8be4df61,
+    0x93ca,
+    0x11d2,
+    [0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+);
+
 static EFI_SYSTEM_TABLE: AtomicPtr<EfiSystemTable> = AtomicPtr::new(core::ptr::null_mut());
 
 /// A strongly typed EFI system table which will disallow the copying
@@ -56,7 +150,18 @@ impl EfiSystemTablePtr {
     /// Register this system table into a global so it can be used for prints
     /// which do not take a self, or a pointer as an argument and thus this
     /// must be able to be found on a pointer.
-    pub unsafe fn register(self) {
+    ///
+    /// The system table header is validated before registering, if this fails
+    /// nothing from the table is trusted and we cannot print. The boot
+    /// services and runtime services tables are validated after registering.
+    pub unsafe fn register(self) -> Result<()> {
+        // Validate the system table and make sure it holds all the fields
+        // we use.
+        let header = EfiTableHeader::validate(
+            self.0 as *const EfiTableHeader, EfiTable::SystemTable)?;
+        header.require(EfiTable::SystemTable, EFI_1_02_REVISION,
+            size_of::<EfiSystemTable>())?;
+
         EFI_SYSTEM_TABLE
         .compare_exchange(
             core::ptr::null_mut(),
@@ -65,9 +170,25 @@ impl EfiSystemTablePtr {
             Ordering::SeqCst,
         )
         .unwrap();
+
+        // Validate the tables of services we call into.
+        EfiTableHeader::validate(
+            (*self.0).boot_services as *const EfiTableHeader,
+            EfiTable::BootServices)?;
+        EfiTableHeader::validate(
+            (*self.0).runtime_services as *const EfiTableHeader,
+            EfiTable::RuntimeServices)?;
+
+        Ok(())
     }
 }
 
+/// Get the offset of the end of `field` in the table at `table`. Used to
+/// check whether a table's `header_size` covers a function pointer.
+fn field_end<T, F>(table: *const T, field: *const F) -> usize {
+    (field as usize - table as usize) + size_of::<F>()
+}
+
 pub fn output_string(string: &str) -> Result<()> {
     let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
 
@@ -101,7 +222,8 @@ pub fn output_string(string: &str) -> Result<()> {
             tmp[in_use] = 0;
 
             unsafe {
-                ((*(console_out)).output_string)(console_out, tmp.as_ptr());
+                ((*(console_out)).output_string)(console_out, tmp.as_ptr())
+                    .into_result().map_err(Error::OutputString)?;
             }
 
             in_use = 0;
@@ -111,33 +233,113 @@ pub fn output_string(string: &str) -> Result<()> {
     if in_use > 0 {
         tmp[in_use] = 0;
         unsafe {
-            ((*(console_out)).output_string)(console_out, tmp.as_ptr());
+            ((*(console_out)).output_string)(console_out, tmp.as_ptr())
+                .into_result().map_err(Error::OutputString)?;
         }
     }
 
     Ok(())
 }
 
+/// Read the EFI global variable `name` (eg. `SecureBoot`) into `data`.
+/// Returns the number of bytes of `data` which were filled in.
+pub fn get_global_variable(name: &str, data: &mut [u8]) -> Result<usize> {
+    let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    if system_table.is_null() {
+        return Err(Error::NotRegistered);
+    }
+
+    // Convert the name into a null terminated UCS-2 string.
+    let mut tmp = [0u16; 64];
+    for (in_use, chr) in name.encode_utf16().enumerate() {
+        // Make sure there is always room left for the null terminator.
+        if in_use >= tmp.len() - 1 {
+            return Err(Error::VariableNameTooLong);
+        }
+
+        tmp[in_use] = chr;
+    }
+
+    unsafe {
+        // Make sure the runtime services provide `get_variable`.
+        let runtime_services = (*system_table).runtime_services;
+        (*runtime_services).header.require(
+            EfiTable::RuntimeServices,
+            EFI_1_02_REVISION,
+            field_end(runtime_services,
+                core::ptr::addr_of!((*runtime_services).get_variable)),
+        )?;
+
+        let mut size = data.len();
+        ((*runtime_services).get_variable)(
+            tmp.as_ptr(),
+            &EFI_GLOBAL_VARIABLE,
+            core::ptr::null_mut(),
+            &mut size,
+            data.as_mut_ptr(),
+        ).into_result().map_err(Error::GetVariable)?;
+
+        Ok(size)
+    }
+}
+
+/// Copy the firmware vendor string from the system table into `buf`. UEFI
+/// gives us UCS-2, anything outside of ASCII is replaced with a `?`. Returns
+/// the number of bytes of `buf` which were filled in, the vendor string is
+/// truncated if it does not fit.
+pub fn firmware_vendor(buf: &mut [u8]) -> Result<usize> {
+    let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    if system_table.is_null() {
+        return Err(Error::NotRegistered);
+    }
+
+    let vendor = unsafe { (*system_table).firmware_vendor };
+    if vendor.is_null() {
+        return Ok(0);
+    }
+
+    let mut in_use = 0;
+    while in_use < buf.len() {
+        let chr = unsafe { core::ptr::read(vendor.add(in_use)) };
+        if chr == 0 {
+            break;
+        }
+
+        buf[in_use] = if chr < 0x80 { chr as u8 } else { b'?' };
+        in_use += 1;
+    }
+
+    Ok(in_use)
+}
+
+/// Get the vendor specific firmware revision from the system table.
+pub fn firmware_revision() -> Result<u32> {
+    let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    if system_table.is_null() {
+        return Err(Error::NotRegistered);
+    }
+
+    Ok(unsafe { (*system_table).firmware_revision })
+}
+
+/// Get the UEFI specification revision the firmware conforms to. The major
+/// revision is in the upper 16 bits and the minor revision in the lower 16.
+pub fn uefi_revision() -> Result<u32> {
+    let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    if system_table.is_null() {
+        return Err(Error::NotRegistered);
+    }
+
+    Ok(unsafe { (*system_table).header.revision })
+}
+
 /// Get the base of the ACPI table RSD PTR (RSDP). If EFI did not report an ACPI
 /// table, then we return `None`.
 pub fn get_acpi_table() -> Result<usize> {
-
-    /// ACPI 2.0 or newer tables should use EFI_ACPI_TABLE_GUID
-    const EFI_ACPI_TABLE_GUID: EfiGuid = EfiGuid(
-        0x8868e871,
-        0xe4f1,
-        0x11d3,
-        [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
-    );
-    
-    /// ACPI 1.0 or newer tables should use EFI_ACPI_TABLE_GUID
-    const ACPI_TABLE_GUID: EfiGuid = EfiGuid(
-        0xeb9d2d30,
-        0x2d88,
-        0x11d3,
-        [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
-    );
-
     let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
 
     if system_table.is_null() {
@@ -176,7 +378,15 @@ pub struct UsableMemory {
     pub end: u64,
 }
 
-pub fn get_memory_map(image_handle: EfiHandle) -> Result<RangeSet> {
+/// The key identifying the memory map snapshot returned by
+/// `get_memory_map`, required to exit boot services. Any allocation by the
+/// firmware invalidates it, so it is consumed by `exit_boot_services`.
+#[derive(Debug)]
+pub struct MapKey(usize);
+
+/// Get the EFI memory map, returning the memory which will be usable once
+/// boot services are exited and the key to exit boot services with.
+pub fn get_memory_map() -> Result<(DefaultRangeSet, MapKey)> {
     let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
 
     if system_table.is_null() {
@@ -185,10 +395,16 @@ pub fn get_memory_map(image_handle: EfiHandle) -> Result<RangeSet> {
 
     let mut memory_map = [0u8; 4 * 1024];
 
-    // The Rust memory map
-    let mut usable_memory = RangeSet::new();
-
     unsafe {
+        // Make sure the boot services provide `get_memory_map`.
+        let boot_services = (*system_table).boot_services;
+        (*boot_services).header.require(
+            EfiTable::BootServices,
+            EFI_1_02_REVISION,
+            field_end(boot_services,
+                core::ptr::addr_of!((*boot_services).get_memory_map)),
+        )?;
+
         // Set up the initial arguments to get the `get_memory_map` EFI call.
         let mut size = core::mem::size_of_val(&memory_map);
         let mut key = 0;
@@ -196,80 +412,154 @@ pub fn get_memory_map(image_handle: EfiHandle) -> Result<RangeSet> {
         let mut mdesc_version = 0;
 
         // Get the memory map.
-        let ret= ((*(*system_table).boot_services).get_memory_map)(
+        ((*boot_services).get_memory_map)(
             &mut size,
             memory_map.as_mut_ptr(),
             &mut key,
             &mut mdesc_size,
             &mut mdesc_version,
-        ).into();
-        
-        // Check that the memory map is obtained.
-        if ret != EfiStatus::Success {
-            return Err(Error::MemoryMap(ret));
-        }
+        ).into_result().map_err(Error::MemoryMap)?;
+
+        // Parse the part of the buffer the firmware filled in.
+        let memory_map = memory_map.get(..size)
+            .ok_or(Error::MemoryMapOutOfBounds)?;
+        Ok((parse_memory_map(memory_map, mdesc_size)?, MapKey(key)))
+    }
+}
+
+/// Parse the raw EFI memory map in `memory_map`, made up of descriptors
+/// which are each `mdesc_size` bytes, into the set of memory which is usable
+/// after boot services are exited.
+fn parse_memory_map(memory_map: &[u8], mdesc_size: usize)
+        -> Result<DefaultRangeSet> {
+    // Descriptors may be larger than the structure we know about, but never
+    // smaller.
+    if mdesc_size < size_of::<EfiMemoryDescriptor>() {
+        return Err(Error::MemoryMapBadDescriptorSize);
+    }
+
+    // The Rust memory map
+    let mut usable_memory = DefaultRangeSet::new();
 
-        // Go through each memory map entry.
-        for offset in (0..size).step_by(mdesc_size) {
-            let entry = core::ptr::read_unaligned(
+    // Go through each memory map entry.
+    for offset in (0..memory_map.len()).step_by(mdesc_size) {
+        let entry = unsafe {
+            core::ptr::read_unaligned(
                 memory_map.get(offset..)
-                    .ok_or(Error::MemoryMapOutOfBounds)?
-                    .get(..offset + size_of::<EfiMemoryDescriptor>())
+                    .and_then(|x| x.get(..size_of::<EfiMemoryDescriptor>()))
                     .ok_or(Error::MemoryMapOutOfBounds)?
                     .as_ptr() as *const EfiMemoryDescriptor
-            );
-
-            let typ: EfiMemoryType = entry.typ.into();
-
-            // Check if this memory is usable after boot services are exited
-            if typ.avail_post_exit_boot_service() && entry.number_of_pages > 0 {
-                // Get the number of bytes for this memory region
-                let bytes = entry.number_of_pages.checked_mul(4096)
-                    .ok_or(Error::MemoryMapIntegerOverflow)?;
-                
-                // Compute the end physical address of this region
-                let end = entry.physical_start.checked_add(bytes - 1)
-                    .ok_or(Error::MemoryMapIntegerOverflow)?;
-                                 
-                // Set the usable memory information
-                usable_memory.insert(Range {
-                    start: entry.physical_start,
-                    end: end,
-                }).map_err(|e| Error::MemoryRangeSet(e))?;
-            }
-           
+            )
+        };
+
+        let typ: EfiMemoryType = entry.typ.into();
+
+        // Check if this memory is usable after boot services are exited
+        if typ.avail_post_exit_boot_service() && entry.number_of_pages > 0 {
+            // Get the number of bytes for this memory region
+            let bytes = entry.number_of_pages.checked_mul(4096)
+                .ok_or(Error::MemoryMapIntegerOverflow)?;
+            
+            // Compute the end physical address of this region
+            let end = entry.physical_start.checked_add(bytes - 1)
+                .ok_or(Error::MemoryMapIntegerOverflow)?;
+                             
+            // Set the usable memory information
+            usable_memory.insert(Range {
+                start: entry.physical_start,
+                end,
+            }).map_err(Error::MemoryRangeSet)?;
         }
+    }
+
+    Ok(usable_memory)
+}
+
+/// Exit boot services using the `key` from the latest `get_memory_map`. After
+/// this only runtime services remain and we own the machine.
+pub fn exit_boot_services(image_handle: EfiHandle, key: MapKey) -> Result<()> {
+    let system_table = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    if system_table.is_null() {
+        return Err(Error::NotRegistered);
+    }
+
+    unsafe {
+        // Make sure the boot services provide `exit_boot_services`.
+        let boot_services = (*system_table).boot_services;
+        (*boot_services).header.require(
+            EfiTable::BootServices,
+            EFI_1_02_REVISION,
+            field_end(boot_services,
+                core::ptr::addr_of!((*boot_services).exit_boot_services)),
+        )?;
 
         // Exit Boot serices
-        let ret = ((*(*system_table).boot_services).exit_boot_services)(
+        ((*boot_services).exit_boot_services)(
             image_handle,
-            key
-        ).into();
+            key.0
+        ).into_result().map_err(Error::ExitBootServices)?;
 
-        if ret == EfiStatus::Success {
-            return Err(Error::ExitBootServices(ret));
-        }
+        // Kill the EFI system table, boot services are gone
+        EFI_SYSTEM_TABLE.store(core::ptr::null_mut(), Ordering::SeqCst);
+    }
 
-        // Kill the EFI system table
-        // EFI_SYSTEM_TABLE.store(core::ptr::null_mut(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// The number of memory maps `exit_boot_services_with_map` tries before
+/// giving up on a firmware which keeps changing the map.
+const EXIT_BOOT_SERVICES_ATTEMPTS: usize = 4;
+
+/// Get the memory map, let `prepare` adjust it, and exit boot services with
+/// its key, returning the adjusted map. Nothing between getting the map and
+/// exiting may call boot services, even a console write can allocate and
+/// change the map, so `prepare` must not print. If the map changes anyway
+/// the firmware rejects the stale key, and we get the map again and retry.
+pub fn exit_boot_services_with_map<E: From<Error>>(image_handle: EfiHandle,
+        mut prepare: impl FnMut(&mut DefaultRangeSet)
+            -> core::result::Result<(), E>)
+        -> core::result::Result<DefaultRangeSet, E> {
+    let mut attempts = 0;
+    loop {
+        let (mut memory, key) = get_memory_map()?;
+        prepare(&mut memory)?;
+
+        attempts += 1;
+        match exit_boot_services(EfiHandle(image_handle.0), key) {
+            Ok(()) => return Ok(memory),
+            Err(Error::ExitBootServices(EfiError::InvalidParameter))
+                if attempts < EXIT_BOOT_SERVICES_ATTEMPTS => continue,
+            Err(err) => return Err(err.into()),
+        }
     }
-    
-    Ok(usable_memory)
 }
 
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct EfiHandle(usize);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct EfiStatusCode(usize);
 
+/// The top bit of a status code, set for errors and clear for warnings.
+const STATUS_ERROR_BIT: usize = 1 << (usize::BITS - 1);
 
+/// The bit below `STATUS_ERROR_BIT`, set for error and warning codes reserved
+/// for use by OEMs.
+const STATUS_OEM_BIT: usize = STATUS_ERROR_BIT >> 1;
 
+impl EfiStatusCode {
+    /// Decode this status code and turn it into a `Result`. See
+    /// `EfiStatus::into_result`.
+    pub fn into_result(self) -> core::result::Result<Option<EfiWarning>, EfiError> {
+        EfiStatus::from(self).into_result()
+    }
+}
 
 /// EFI status codes
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum EfiStatus {
     /// EFI Success
     Success,
@@ -281,74 +571,69 @@ pub enum EfiStatus {
     Error(EfiError),
 }
 
+impl EfiStatus {
+    /// Turn this status into a `Result`. A warning means the operation still
+    /// completed, so it is not a failure, but it is returned to the caller in
+    /// case it wants to act on it.
+    pub fn into_result(self) -> core::result::Result<Option<EfiWarning>, EfiError> {
+        match self {
+            EfiStatus::Success => Ok(None),
+            EfiStatus::Warning(warning) => Ok(Some(warning)),
+            EfiStatus::Error(error) => Err(error),
+        }
+    }
+}
+
 impl From<EfiStatusCode> for EfiStatus {
-    fn from(val: EfiStatusCode) -> Self{
-        
-        // Sign extend the error code to make this not tied with a specific
-        // bitness 
-        let val = val.0 as i32 as i64 as u64;
+    fn from(val: EfiStatusCode) -> Self {
+        // The status code is a `usize`, so the error and OEM bits are the top
+        // two bits of whatever the native width is. We never sign extend, the
+        // remaining bits are the code itself.
+        let code = (val.0 & !(STATUS_ERROR_BIT | STATUS_OEM_BIT)) as u64;
+
+        if val.0 == 0 {
+            EfiStatus::Success
+        } else if val.0 & STATUS_ERROR_BIT != 0 {
+            EfiStatus::Error(if val.0 & STATUS_OEM_BIT != 0 {
+                EfiError::Oem(code)
+            } else {
+                EfiError::from_code(code)
+            })
+        } else {
+            EfiStatus::Warning(if val.0 & STATUS_OEM_BIT != 0 {
+                EfiWarning::Oem(code)
+            } else {
+                EfiWarning::from_code(code)
+            })
+        }
+    }
+}
 
-        
-        match val {
-            0 => {
-                Self::Success
-            }
-            0x0000000000000001..=0x7fffffffffffffff => {
-                EfiStatus::Warning(match val & !(1<< 63) {
-                    1 => EfiWarning::UnknownGlyph,
-                    2 => EfiWarning::DeleteFailure,
-                    3 => EfiWarning::WriteFailure,
-                    4 => EfiWarning::BufferTooSmall,
-                    5 => EfiWarning::StaleData,
-                    6 => EfiWarning::FileSystem,
-                    7 => EfiWarning::ResetRequired,
-                    _ => EfiWarning::Unknown(val),
-                })
-            }
-            0x8000000000000000..=0xffffffffffffffff => {
-                EfiStatus::Error(match val & !(1<< 63) {
-                    1 => EfiError::LoadError,
-                    2 => EfiError::InvalidParameter,
-                    3 => EfiError::Unsupported,
-                    4 => EfiError::BadBufferSize,
-                    5 => EfiError::BufferTooSmall,
-                    6 => EfiError::NotReady,
-                    7 => EfiError::DeviceError,
-                    8 => EfiError::WriteProtected,
-                    9 => EfiError::OutOfResources,
-                    10 => EfiError::VolumeCorrupted,
-                    11 => EfiError::VolumeFull,
-                    12 => EfiError::NoMedia,
-                    13 => EfiError::MediaChanged,
-                    14 => EfiError::NotFound,
-                    15 => EfiError::AccessDenied,
-                    16 => EfiError::NoResponse,
-                    17 => EfiError::NoMapping,
-                    18 => EfiError::Timeout,
-                    19 => EfiError::NotStarted,
-                    20 => EfiError::AlreadyStarted,
-                    21 => EfiError::Aborted,
-                    22 => EfiError::IcmpError,
-                    23 => EfiError::TftpError,
-                    24 => EfiError::ProtocolError,
-                    25 => EfiError::IncompatibleVersion,
-                    26 => EfiError::SecurityViolation,
-                    27 => EfiError::CrcError,
-                    28 => EfiError::EndOfMedia,
-                    31 => EfiError::EndOfFile,
-                    32 => EfiError::InvalidLanguage,
-                    33 => EfiError::CompromisedData,
-                    34 => EfiError::IpAddressConflict,
-                    35 => EfiError::HttpError,
-                    _ => EfiError::Unknown(val),
-                })
-            }
+impl From<EfiStatus> for EfiStatusCode {
+    fn from(val: EfiStatus) -> Self {
+        EfiStatusCode(match val {
+            EfiStatus::Success => 0,
+            EfiStatus::Warning(EfiWarning::Oem(code)) => STATUS_OEM_BIT | code as usize,
+            EfiStatus::Warning(warning) => warning.code() as usize,
+            EfiStatus::Error(EfiError::Oem(code)) =>
+                STATUS_ERROR_BIT | STATUS_OEM_BIT | code as usize,
+            EfiStatus::Error(error) => STATUS_ERROR_BIT | error.code() as usize,
+        })
+    }
+}
+
+impl fmt::Display for EfiStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EfiStatus::Success => write!(f, "EFI_SUCCESS"),
+            EfiStatus::Warning(warning) => write!(f, "{}", warning),
+            EfiStatus::Error(error) => write!(f, "{}", error),
         }
     }
 }
 
 /// EFI warning codes
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum EfiWarning {
     /// The string contained one or more characters that the deice could not 
     /// render and were skipped
@@ -374,13 +659,66 @@ pub enum EfiWarning {
     /// The operation will be processed accross a system reset
     ResetRequired,
 
-    /// An Unknown warning
-    Unknown(u64),
+    /// A warning code in the range reserved for the UEFI spec, but not one
+    /// we know about
+    Reserved(u64),
+
+    /// A warning code in the range reserved for OEMs
+    Oem(u64),
+}
+
+impl EfiWarning {
+    /// Get the warning from a spec defined warning `code`, with the error and
+    /// OEM bits clear.
+    fn from_code(code: u64) -> Self {
+        match code {
+            1 => EfiWarning::UnknownGlyph,
+            2 => EfiWarning::DeleteFailure,
+            3 => EfiWarning::WriteFailure,
+            4 => EfiWarning::BufferTooSmall,
+            5 => EfiWarning::StaleData,
+            6 => EfiWarning::FileSystem,
+            7 => EfiWarning::ResetRequired,
+            _ => EfiWarning::Reserved(code),
+        }
+    }
+
+    /// Get the warning code, with the error and OEM bits clear.
+    fn code(&self) -> u64 {
+        match self {
+            EfiWarning::UnknownGlyph => 1,
+            EfiWarning::DeleteFailure => 2,
+            EfiWarning::WriteFailure => 3,
+            EfiWarning::BufferTooSmall => 4,
+            EfiWarning::StaleData => 5,
+            EfiWarning::FileSystem => 6,
+            EfiWarning::ResetRequired => 7,
+            EfiWarning::Reserved(code) | EfiWarning::Oem(code) => *code,
+        }
+    }
+}
+
+impl fmt::Display for EfiWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EfiWarning::UnknownGlyph => "EFI_WARN_UNKNOWN_GLYPH",
+            EfiWarning::DeleteFailure => "EFI_WARN_DELETE_FAILURE",
+            EfiWarning::WriteFailure => "EFI_WARN_WRITE_FAILURE",
+            EfiWarning::BufferTooSmall => "EFI_WARN_BUFFER_TOO_SMALL",
+            EfiWarning::StaleData => "EFI_WARN_STALE_DATA",
+            EfiWarning::FileSystem => "EFI_WARN_FILE_SYSTEM",
+            EfiWarning::ResetRequired => "EFI_WARN_RESET_REQUIRED",
+            EfiWarning::Reserved(code) =>
+                return write!(f, "reserved EFI warning {:#x}", code),
+            EfiWarning::Oem(code) =>
+                return write!(f, "OEM EFI warning {:#x}", code),
+        };
+        write!(f, "{}", name)
+    }
 }
 
 /// EFI error codes
-#[derive(Debug, PartialEq, Eq)]
-#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum EfiError {
     
     /// The image failed to load
@@ -467,10 +805,10 @@ pub enum EfiError {
     CrcError,
     
     /// Beginning or end of media reached
-    EndOfMedia = 28,
+    EndOfMedia,
     
     /// The end of the file was reached
-    EndOfFile = 31,
+    EndOfFile,
     
     /// The language specified was invalid
     InvalidLanguage,
@@ -485,8 +823,140 @@ pub enum EfiError {
     /// An HTTP error occurred durin the network operation
     HttpError,
 
-    /// An Unknown error
-    Unknown(u64),
+    /// An error code in the range reserved for the UEFI spec, but not one we
+    /// know about
+    Reserved(u64),
+
+    /// An error code in the range reserved for OEMs
+    Oem(u64),
+}
+
+impl EfiError {
+    /// Get the error from a spec defined error `code`, with the error and OEM
+    /// bits clear.
+    fn from_code(code: u64) -> Self {
+        match code {
+            1 => EfiError::LoadError,
+            2 => EfiError::InvalidParameter,
+            3 => EfiError::Unsupported,
+            4 => EfiError::BadBufferSize,
+            5 => EfiError::BufferTooSmall,
+            6 => EfiError::NotReady,
+            7 => EfiError::DeviceError,
+            8 => EfiError::WriteProtected,
+            9 => EfiError::OutOfResources,
+            10 => EfiError::VolumeCorrupted,
+            11 => EfiError::VolumeFull,
+            12 => EfiError::NoMedia,
+            13 => EfiError::MediaChanged,
+            14 => EfiError::NotFound,
+            15 => EfiError::AccessDenied,
+            16 => EfiError::NoResponse,
+            17 => EfiError::NoMapping,
+            18 => EfiError::Timeout,
+            19 => EfiError::NotStarted,
+            20 => EfiError::AlreadyStarted,
+            21 => EfiError::Aborted,
+            22 => EfiError::IcmpError,
+            23 => EfiError::TftpError,
+            24 => EfiError::ProtocolError,
+            25 => EfiError::IncompatibleVersion,
+            26 => EfiError::SecurityViolation,
+            27 => EfiError::CrcError,
+            28 => EfiError::EndOfMedia,
+            31 => EfiError::EndOfFile,
+            32 => EfiError::InvalidLanguage,
+            33 => EfiError::CompromisedData,
+            34 => EfiError::IpAddressConflict,
+            35 => EfiError::HttpError,
+            _ => EfiError::Reserved(code),
+        }
+    }
+
+    /// Get the error code, with the error and OEM bits clear.
+    fn code(&self) -> u64 {
+        match self {
+            EfiError::LoadError => 1,
+            EfiError::InvalidParameter => 2,
+            EfiError::Unsupported => 3,
+            EfiError::BadBufferSize => 4,
+            EfiError::BufferTooSmall => 5,
+            EfiError::NotReady => 6,
+            EfiError::DeviceError => 7,
+            EfiError::WriteProtected => 8,
+            EfiError::OutOfResources => 9,
+            EfiError::VolumeCorrupted => 10,
+            EfiError::VolumeFull => 11,
+            EfiError::NoMedia => 12,
+            EfiError::MediaChanged => 13,
+            EfiError::NotFound => 14,
+            EfiError::AccessDenied => 15,
+            EfiError::NoResponse => 16,
+            EfiError::NoMapping => 17,
+            EfiError::Timeout => 18,
+            EfiError::NotStarted => 19,
+            EfiError::AlreadyStarted => 20,
+            EfiError::Aborted => 21,
+            EfiError::IcmpError => 22,
+            EfiError::TftpError => 23,
+            EfiError::ProtocolError => 24,
+            EfiError::IncompatibleVersion => 25,
+            EfiError::SecurityViolation => 26,
+            EfiError::CrcError => 27,
+            EfiError::EndOfMedia => 28,
+            EfiError::EndOfFile => 31,
+            EfiError::InvalidLanguage => 32,
+            EfiError::CompromisedData => 33,
+            EfiError::IpAddressConflict => 34,
+            EfiError::HttpError => 35,
+            EfiError::Reserved(code) | EfiError::Oem(code) => *code,
+        }
+    }
+}
+
+impl fmt::Display for EfiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EfiError::LoadError => "EFI_LOAD_ERROR",
+            EfiError::InvalidParameter => "EFI_INVALID_PARAMETER",
+            EfiError::Unsupported => "EFI_UNSUPPORTED",
+            EfiError::BadBufferSize => "EFI_BAD_BUFFER_SIZE",
+            EfiError::BufferTooSmall => "EFI_BUFFER_TOO_SMALL",
+            EfiError::NotReady => "EFI_NOT_READY",
+            EfiError::DeviceError => "EFI_DEVICE_ERROR",
+            EfiError::WriteProtected => "EFI_WRITE_PROTECTED",
+            EfiError::OutOfResources => "EFI_OUT_OF_RESOURCES",
+            EfiError::VolumeCorrupted => "EFI_VOLUME_CORRUPTED",
+            EfiError::VolumeFull => "EFI_VOLUME_FULL",
+            EfiError::NoMedia => "EFI_NO_MEDIA",
+            EfiError::MediaChanged => "EFI_MEDIA_CHANGED",
+            EfiError::NotFound => "EFI_NOT_FOUND",
+            EfiError::AccessDenied => "EFI_ACCESS_DENIED",
+            EfiError::NoResponse => "EFI_NO_RESPONSE",
+            EfiError::NoMapping => "EFI_NO_MAPPING",
+            EfiError::Timeout => "EFI_TIMEOUT",
+            EfiError::NotStarted => "EFI_NOT_STARTED",
+            EfiError::AlreadyStarted => "EFI_ALREADY_STARTED",
+            EfiError::Aborted => "EFI_ABORTED",
+            EfiError::IcmpError => "EFI_ICMP_ERROR",
+            EfiError::TftpError => "EFI_TFTP_ERROR",
+            EfiError::ProtocolError => "EFI_PROTOCOL_ERROR",
+            EfiError::IncompatibleVersion => "EFI_INCOMPATIBLE_VERSION",
+            EfiError::SecurityViolation => "EFI_SECURITY_VIOLATION",
+            EfiError::CrcError => "EFI_CRC_ERROR",
+            EfiError::EndOfMedia => "EFI_END_OF_MEDIA",
+            EfiError::EndOfFile => "EFI_END_OF_FILE",
+            EfiError::InvalidLanguage => "EFI_INVALID_LANGUAGE",
+            EfiError::CompromisedData => "EFI_COMPROMISED_DATA",
+            EfiError::IpAddressConflict => "EFI_IP_ADDRESS_CONFLICT",
+            EfiError::HttpError => "EFI_HTTP_ERROR",
+            EfiError::Reserved(code) =>
+                return write!(f, "reserved EFI error {:#x}", code),
+            EfiError::Oem(code) =>
+                return write!(f, "OEM EFI error {:#x}", code),
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[repr(C)]
@@ -560,6 +1030,65 @@ struct EfiTableHeader {
     reserved: u32,
 }
 
+impl EfiTableHeader {
+    /// Validate the signature, `header_size` and CRC32 of the EFI table at
+    /// `table` which is expected to be a `typ` table.
+    unsafe fn validate<'a>(table: *const EfiTableHeader, typ: EfiTable)
+            -> Result<&'a EfiTableHeader> {
+        if table.is_null() {
+            return Err(Error::TableNull(typ));
+        }
+
+        let header = &*table;
+
+        // Check the signature.
+        if header.signature != typ.signature() {
+            return Err(Error::TableSignature(typ));
+        }
+
+        // The header size covers the entire table, including the header.
+        // Bound it before reading that much memory for the CRC32.
+        let size = header.header_size as usize;
+        if size < size_of::<EfiTableHeader>() || size > MAX_TABLE_SIZE {
+            return Err(Error::TableHeaderSize(typ));
+        }
+
+        // The CRC32 is computed over `header_size` bytes with the `crc32`
+        // field itself set to zero.
+        let crc_offset = field_end(table, core::ptr::addr_of!(header.crc32))
+            - size_of::<u32>();
+        let mut crc = Crc32::new();
+        for offset in 0..size {
+            if (crc_offset..crc_offset + size_of::<u32>()).contains(&offset) {
+                crc.update(0);
+            } else {
+                crc.update(core::ptr::read((table as *const u8).add(offset)));
+            }
+        }
+
+        if crc.finish() != header.crc32 {
+            return Err(Error::TableCrc32(typ));
+        }
+
+        Ok(header)
+    }
+
+    /// Make sure this `typ` table is at least `revision` and is large enough
+    /// to contain the first `size` bytes of the table. This must be checked
+    /// before touching any field that older revisions did not define.
+    fn require(&self, typ: EfiTable, revision: u32, size: usize) -> Result<()> {
+        if self.revision < revision {
+            return Err(Error::TableRevisionTooOld(typ, self.revision, revision));
+        }
+
+        if (self.header_size as usize) < size {
+            return Err(Error::TableHeaderSize(typ));
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy, Default, Debug)]
 #[repr(C)]
 struct EfiMemoryDescriptor {
@@ -613,6 +1142,29 @@ struct EfiBootServices {
     exit_boot_services: unsafe fn(image_handle: EfiHandle, map_key: usize) -> EfiStatusCode,
 }
 
+#[repr(C)]
+struct EfiRuntimeServices {
+    header: EfiTableHeader,
+    _get_time: usize,
+    _set_time: usize,
+    _get_wakeup_time: usize,
+    _set_wakeup_time: usize,
+    _set_virtual_address_map: usize,
+    _convert_pointer: usize,
+    // Returns the value of a variable
+    get_variable: unsafe fn(
+        variable_name: *const u16,
+        vendor_guid: *const EfiGuid,
+        attributes: *mut u32,
+        data_size: &mut usize,
+        data: *mut u8,
+    ) -> EfiStatusCode,
+    _get_next_variable_name: usize,
+    _set_variable: usize,
+    _get_next_high_monotonic_count: usize,
+    _reset_system: usize,
+}
+
 #[repr(C)]
 struct EfiSimpleTextInputProtocol {
     reset: unsafe fn(
@@ -660,7 +1212,7 @@ struct EfiSystemTable {
     console_out: *const EfiSimpleTextOutputProtocol,
     console_error_handle: u32,
     console_error: *const EfiSimpleTextOutputProtocol,
-    _runtime_services: usize,
+    runtime_services: *const EfiRuntimeServices,
     boot_services: *const EfiBootServices,
 
     number_of_tables: usize,
@@ -692,6 +1244,6 @@ struct EfiConfigurationTable {
 }
 /// 128-bit buffer containing a unique identifier value. Unless otherwise
 /// specified, aligned on a 64-bit boundary.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 struct EfiGuid(u32, u16, u16, [u8; 8]);
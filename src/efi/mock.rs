@@ -0,0 +1,475 @@
+//! A mock EFI system table with scripted boot services, so the `efi` module
+//! can be exercised on the host under `cargo test` instead of under OVMF.
+
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::string::String;
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+use super::*;
+
+/// Only one mock firmware can be registered at a time as `EFI_SYSTEM_TABLE`
+/// is a global, this serializes the tests using it.
+static FIRMWARE_LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// The script and recorded calls of the installed mock firmware. The
+    /// firmware functions are plain function pointers, so this is where
+    /// they find their state.
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// A scripted response to a `get_memory_map` call.
+#[derive(Clone, Debug)]
+pub(super) struct MemoryMap {
+    /// The descriptors to report.
+    pub(super) descriptors: Vec<EfiMemoryDescriptor>,
+
+    /// The size of each descriptor in the buffer, which may be larger than
+    /// `EfiMemoryDescriptor`. The padding is filled with garbage.
+    pub(super) descriptor_size: usize,
+
+    /// The map key to report.
+    pub(super) key: usize,
+
+    /// If set, return this status without filling in the buffer.
+    pub(super) status: Option<EfiStatus>,
+}
+
+impl MemoryMap {
+    /// A memory map of `descriptors` laid out `descriptor_size` bytes apart.
+    pub(super) fn new(descriptors: &[EfiMemoryDescriptor],
+            descriptor_size: usize) -> Self {
+        MemoryMap {
+            descriptors: descriptors.to_vec(),
+            descriptor_size,
+            key: 0x1337,
+            status: None,
+        }
+    }
+
+    /// The size in bytes this map takes up in the caller's buffer.
+    fn size(&self) -> usize {
+        self.descriptors.len() * self.descriptor_size
+    }
+}
+
+/// Create a memory descriptor of `typ` for `pages` 4 KiB pages at `start`.
+pub(super) fn descriptor(typ: u32, start: u64, pages: u64)
+        -> EfiMemoryDescriptor {
+    EfiMemoryDescriptor {
+        typ,
+        physical_start: start,
+        virtual_start: 0,
+        number_of_pages: pages,
+        attribute: 0xf,
+    }
+}
+
+/// The state behind the mock firmware functions.
+#[derive(Default)]
+struct State {
+    /// Responses to successive `get_memory_map` calls. The last one is
+    /// repeated once the queue is down to one entry.
+    memory_maps: VecDeque<MemoryMap>,
+
+    /// Status to return from the next `exit_boot_services`, on top of
+    /// checking the key.
+    exit_boot_services_status: Option<EfiStatus>,
+
+    /// The key of the last memory map successfully returned.
+    last_key: Option<usize>,
+
+    /// Keys `exit_boot_services` was called with.
+    exit_boot_services_calls: Vec<usize>,
+
+    /// EFI global variables by name.
+    variables: Vec<(String, Vec<u8>)>,
+
+    /// Everything written to the console, with `\r` stripped.
+    console: String,
+
+    /// The length in UCS-2 characters of each `output_string` call.
+    console_writes: Vec<usize>,
+}
+
+/// Get the header of `table` in `firmware`.
+fn header_mut(firmware: &mut MockFirmware, table: EfiTable)
+        -> &mut EfiTableHeader {
+    match table {
+        EfiTable::SystemTable => &mut firmware.system_table.header,
+        EfiTable::BootServices => &mut firmware.boot_services.header,
+        EfiTable::RuntimeServices => &mut firmware.runtime_services.header,
+    }
+}
+
+/// A function corrupting a table header after its CRC32 is computed.
+type TamperFn = fn(&mut EfiTableHeader);
+
+/// A mock firmware, built up with scripted responses and then installed as
+/// the registered EFI system table.
+pub struct MockFirmware {
+    system_table: Box<EfiSystemTable>,
+    boot_services: Box<EfiBootServices>,
+    runtime_services: Box<EfiRuntimeServices>,
+    console_out: Box<EfiSimpleTextOutputProtocol>,
+    vendor: Vec<u16>,
+    tables: Vec<EfiConfigurationTable>,
+    state: State,
+    tamper: Vec<(EfiTable, TamperFn)>,
+}
+
+impl MockFirmware {
+    /// A UEFI 2.70 firmware with an empty memory map and no configuration
+    /// tables.
+    pub fn new() -> Self {
+        let header = |signature: EfiTable| EfiTableHeader {
+            signature: signature.signature(),
+            revision: (2 << 16) | 70,
+            header_size: 0,
+            crc32: 0,
+            reserved: 0,
+        };
+
+        let console_out = Box::new(EfiSimpleTextOutputProtocol {
+            reset: mock_reset,
+            output_string: mock_output_string,
+            test_string: mock_output_string,
+            _query_mode: 0,
+            _set_mode: 0,
+            _set_attribute: 0,
+            _clean_screen: 0,
+            _set_cursor_position: 0,
+            _enable_cursor: 0,
+            _mode: 0,
+        });
+
+        let boot_services = Box::new(EfiBootServices {
+            header: header(EfiTable::BootServices),
+            _raise_tpl: 0,
+            _restore_tpl: 0,
+            _allocate_pages: 0,
+            _free_pages: 0,
+            get_memory_map: mock_get_memory_map,
+            _allocale_pool: 0,
+            _free_pool: 0,
+            _create_event: 0,
+            _set_timer: 0,
+            _wait_for_event: 0,
+            _signal_event: 0,
+            _close_event: 0,
+            _check_event: 0,
+            _install_protocol_interface: 0,
+            _reinstall_protocol_interface: 0,
+            _uninstall_protocol_interface: 0,
+            _handle_protocol: 0,
+            _reserved: 0,
+            _register_protocol_notify: 0,
+            _locate_handle: 0,
+            _locate_device_path: 0,
+            _install_configuration_table: 0,
+            _load_image: 0,
+            _start_image: 0,
+            _exit: 0,
+            _unload_image: 0,
+            exit_boot_services: mock_exit_boot_services,
+        });
+
+        let runtime_services = Box::new(EfiRuntimeServices {
+            header: header(EfiTable::RuntimeServices),
+            _get_time: 0,
+            _set_time: 0,
+            _get_wakeup_time: 0,
+            _set_wakeup_time: 0,
+            _set_virtual_address_map: 0,
+            _convert_pointer: 0,
+            get_variable: mock_get_variable,
+            _get_next_variable_name: 0,
+            _set_variable: 0,
+            _get_next_high_monotonic_count: 0,
+            _reset_system: 0,
+        });
+
+        let system_table = Box::new(EfiSystemTable {
+            header: header(EfiTable::SystemTable),
+            firmware_vendor: core::ptr::null(),
+            firmware_revision: 0x10000,
+            console_in_handle: EfiHandle(0),
+            console_in: core::ptr::null(),
+            console_out_handle: 0,
+            console_out: core::ptr::null(),
+            console_error_handle: 0,
+            console_error: core::ptr::null(),
+            runtime_services: core::ptr::null(),
+            boot_services: core::ptr::null(),
+            number_of_tables: 0,
+            tables: core::ptr::null(),
+        });
+
+        let mut state = State::default();
+        state.memory_maps.push_back(MemoryMap::new(&[], 48));
+
+        MockFirmware {
+            system_table,
+            boot_services,
+            runtime_services,
+            console_out,
+            vendor: "EDK II".encode_utf16().chain(Some(0)).collect(),
+            tables: Vec::new(),
+            state,
+            tamper: Vec::new(),
+        }
+    }
+
+    /// Set the firmware vendor string.
+    pub fn vendor(mut self, vendor: &str) -> Self {
+        self.vendor = vendor.encode_utf16().chain(Some(0)).collect();
+        self
+    }
+
+    /// Set the revision of `table`, the CRC32 is computed afterwards.
+    pub fn revision(mut self, table: EfiTable, revision: u32) -> Self {
+        header_mut(&mut self, table).revision = revision;
+        self
+    }
+
+    /// Modify the header of `table` after its CRC32 has been computed, to
+    /// script a corrupt table.
+    pub(super) fn tamper(mut self, table: EfiTable, f: TamperFn) -> Self {
+        self.tamper.push((table, f));
+        self
+    }
+
+    /// Queue a response for `get_memory_map`. The first call replaces the
+    /// default empty map.
+    pub(super) fn memory_map(mut self, map: MemoryMap) -> Self {
+        if self.state.memory_maps.len() == 1
+                && self.state.memory_maps[0].descriptors.is_empty() {
+            self.state.memory_maps.clear();
+        }
+        self.state.memory_maps.push_back(map);
+        self
+    }
+
+    /// Make the next `exit_boot_services` fail with `status`, as firmware
+    /// does when the memory map changed after the key was handed out.
+    pub fn exit_boot_services_status(mut self, status: EfiStatus) -> Self {
+        self.state.exit_boot_services_status = Some(status);
+        self
+    }
+
+    /// Add an EFI global variable.
+    pub fn variable(mut self, name: &str, data: &[u8]) -> Self {
+        self.state.variables.push((name.into(), data.to_vec()));
+        self
+    }
+
+    /// Add a configuration table.
+    pub(super) fn configuration_table(mut self, guid: EfiGuid, table: usize)
+            -> Self {
+        self.tables.push(EfiConfigurationTable { guid, table });
+        self
+    }
+
+    /// Link up the tables, compute their CRC32s and register the system
+    /// table. Registration is not unwrapped so tests can check it fails for
+    /// scripted corrupt tables. The firmware is unregistered when the
+    /// returned `Installed` is dropped.
+    pub fn install(mut self) -> (Installed, Result<()>) {
+        let lock = FIRMWARE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        self.system_table.firmware_vendor = self.vendor.as_ptr();
+        self.system_table.console_out = &*self.console_out;
+        self.system_table.boot_services = &*self.boot_services;
+        self.system_table.runtime_services = &*self.runtime_services;
+        self.system_table.number_of_tables = self.tables.len();
+        self.system_table.tables = self.tables.as_ptr();
+
+        unsafe {
+            seal(&mut *self.system_table);
+            seal(&mut *self.boot_services);
+            seal(&mut *self.runtime_services);
+        }
+
+        for (table, f) in core::mem::take(&mut self.tamper) {
+            f(header_mut(&mut self, table));
+        }
+
+        STATE.with(|state| *state.borrow_mut() = core::mem::take(&mut self.state));
+
+        let ptr = EfiSystemTablePtr(&mut *self.system_table);
+        let ret = unsafe { ptr.register() };
+
+        (Installed { _firmware: self, _lock: lock }, ret)
+    }
+}
+
+/// Fill in the `header_size` and `crc32` of the table at `table`, which must
+/// start with an `EfiTableHeader`.
+unsafe fn seal<T>(table: &mut T) {
+    let header = &mut *(table as *mut T as *mut EfiTableHeader);
+    header.header_size = size_of::<T>() as u32;
+    header.crc32 = 0;
+
+    let bytes = core::slice::from_raw_parts(
+        table as *const T as *const u8, size_of::<T>());
+    let crc = crate::crc32::crc32(bytes);
+    (*(table as *mut T as *mut EfiTableHeader)).crc32 = crc;
+}
+
+/// A registered mock firmware.
+pub struct Installed {
+    _firmware: MockFirmware,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Installed {
+    /// Everything written to the console so far, with `\r` stripped.
+    pub fn console(&self) -> String {
+        STATE.with(|state| state.borrow().console.clone())
+    }
+
+    /// The length of each `output_string` call in UCS-2 characters.
+    pub fn console_writes(&self) -> Vec<usize> {
+        STATE.with(|state| state.borrow().console_writes.clone())
+    }
+
+    /// The keys `exit_boot_services` was called with.
+    pub fn exit_boot_services_calls(&self) -> Vec<usize> {
+        STATE.with(|state| state.borrow().exit_boot_services_calls.clone())
+    }
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        EFI_SYSTEM_TABLE.store(core::ptr::null_mut(), Ordering::SeqCst);
+        STATE.with(|state| *state.borrow_mut() = State::default());
+    }
+}
+
+unsafe fn mock_reset(_this: *const EfiSimpleTextOutputProtocol,
+        _extended_verification: bool) -> EfiStatusCode {
+    EfiStatus::Success.into()
+}
+
+unsafe fn mock_output_string(_this: *const EfiSimpleTextOutputProtocol,
+        string: *const u16) -> EfiStatusCode {
+    let mut len = 0;
+    while *string.add(len) != 0 {
+        len += 1;
+    }
+
+    let chars = core::slice::from_raw_parts(string, len);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.console_writes.push(len);
+        state.console.extend(
+            char::decode_utf16(chars.iter().copied())
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .filter(|&c| c != '\r'));
+    });
+
+    EfiStatus::Success.into()
+}
+
+unsafe fn mock_get_memory_map(
+    memory_map_size: &mut usize,
+    memory_map: *mut u8,
+    map_key: &mut usize,
+    descriptor_size: &mut usize,
+    descriptor_version: &mut u32,
+) -> EfiStatusCode {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let map = if state.memory_maps.len() > 1 {
+            state.memory_maps.pop_front().unwrap()
+        } else {
+            state.memory_maps[0].clone()
+        };
+
+        *descriptor_size = map.descriptor_size;
+        *descriptor_version = 1;
+
+        if let Some(status) = map.status {
+            return status.into();
+        }
+
+        // Like real firmware, report the size we need if the buffer is too
+        // small.
+        if *memory_map_size < map.size() {
+            *memory_map_size = map.size();
+            return EfiStatus::Error(EfiError::BufferTooSmall).into();
+        }
+
+        // Lay out the descriptors, filling any padding with garbage.
+        core::ptr::write_bytes(memory_map, 0xcc, map.size());
+        for (ii, desc) in map.descriptors.iter().enumerate() {
+            core::ptr::write_unaligned(
+                memory_map.add(ii * map.descriptor_size) as *mut EfiMemoryDescriptor,
+                *desc);
+        }
+
+        *memory_map_size = map.size();
+        *map_key = map.key;
+        state.last_key = Some(map.key);
+        EfiStatus::Success.into()
+    })
+}
+
+unsafe fn mock_exit_boot_services(_image_handle: EfiHandle, map_key: usize)
+        -> EfiStatusCode {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.exit_boot_services_calls.push(map_key);
+
+        if let Some(status) = state.exit_boot_services_status.take() {
+            return status.into();
+        }
+
+        // The key must match the latest memory map.
+        if state.last_key != Some(map_key) {
+            return EfiStatus::Error(EfiError::InvalidParameter).into();
+        }
+
+        EfiStatus::Success.into()
+    })
+}
+
+unsafe fn mock_get_variable(
+    variable_name: *const u16,
+    vendor_guid: *const EfiGuid,
+    _attributes: *mut u32,
+    data_size: &mut usize,
+    data: *mut u8,
+) -> EfiStatusCode {
+    let mut len = 0;
+    while *variable_name.add(len) != 0 {
+        len += 1;
+    }
+    let name = String::from_utf16_lossy(
+        core::slice::from_raw_parts(variable_name, len));
+
+    // We only implement the global variables.
+    if *vendor_guid != EFI_GLOBAL_VARIABLE {
+        return EfiStatus::Error(EfiError::NotFound).into();
+    }
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let value = match state.variables.iter().find(|(x, _)| *x == name) {
+            Some((_, value)) => value,
+            None => return EfiStatus::Error(EfiError::NotFound).into(),
+        };
+
+        if *data_size < value.len() {
+            *data_size = value.len();
+            return EfiStatus::Error(EfiError::BufferTooSmall).into();
+        }
+
+        core::ptr::copy_nonoverlapping(value.as_ptr(), data, value.len());
+        *data_size = value.len();
+        EfiStatus::Success.into()
+    })
+}
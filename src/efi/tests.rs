@@ -0,0 +1,366 @@
+use std::string::String;
+
+use super::mock::{descriptor, MemoryMap, MockFirmware};
+use super::*;
+
+/// `EfiMemoryType::ConventionalMemory`
+const CONVENTIONAL: u32 = 7;
+
+/// `EfiMemoryType::BootServiceData`
+const BOOT_SERVICE_DATA: u32 = 4;
+
+/// `EfiMemoryType::RuntimeServiceData`
+const RUNTIME_SERVICE_DATA: u32 = 6;
+
+#[test]
+fn status_decoding() {
+    assert_eq!(EfiStatus::from(EfiStatusCode(0)), EfiStatus::Success);
+    assert_eq!(EfiStatus::from(EfiStatusCode(4)),
+        EfiStatus::Warning(EfiWarning::BufferTooSmall));
+    assert_eq!(EfiStatus::from(EfiStatusCode(0x8000_0000_0000_0005)),
+        EfiStatus::Error(EfiError::BufferTooSmall));
+    assert_eq!(EfiStatus::from(EfiStatusCode(0x8000_0000_0000_001d)),
+        EfiStatus::Error(EfiError::Reserved(29)));
+    assert_eq!(EfiStatus::from(EfiStatusCode(0xc000_0000_0000_0001)),
+        EfiStatus::Error(EfiError::Oem(1)));
+    assert_eq!(EfiStatus::from(EfiStatusCode(0x4000_0000_0000_0002)),
+        EfiStatus::Warning(EfiWarning::Oem(2)));
+
+    // A 32-bit looking error is not sign extended into an error.
+    assert_eq!(EfiStatus::from(EfiStatusCode(0x8000_0005)),
+        EfiStatus::Warning(EfiWarning::Reserved(0x8000_0005)));
+}
+
+#[test]
+fn status_round_trip() {
+    for &code in &[0, 1, 7, 8, 0x8000_0000_0000_0001, 0x8000_0000_0000_0023,
+            0x8000_0000_0000_1234, 0xc000_0000_0000_0042, 0x4000_0000_0000_0042] {
+        let status = EfiStatus::from(EfiStatusCode(code));
+        assert_eq!(EfiStatusCode::from(status), EfiStatusCode(code));
+    }
+}
+
+#[test]
+fn status_display() {
+    assert_eq!(std::format!("{}", EfiStatus::Success), "EFI_SUCCESS");
+    assert_eq!(std::format!("{}", EfiStatus::Error(EfiError::BufferTooSmall)),
+        "EFI_BUFFER_TOO_SMALL");
+    assert_eq!(std::format!("{}", EfiStatus::Warning(EfiWarning::StaleData)),
+        "EFI_WARN_STALE_DATA");
+    assert_eq!(std::format!("{}", EfiStatus::Error(EfiError::Oem(5))),
+        "OEM EFI error 0x5");
+}
+
+#[test]
+fn status_into_result() {
+    assert_eq!(EfiStatusCode(0).into_result(), Ok(None));
+    assert_eq!(EfiStatusCode(1).into_result(), Ok(Some(EfiWarning::UnknownGlyph)));
+    assert_eq!(EfiStatusCode(0x8000_0000_0000_000e).into_result(),
+        Err(EfiError::NotFound));
+}
+
+#[test]
+fn register_validates_tables() {
+    {
+        let (_fw, ret) = MockFirmware::new().install();
+        assert!(ret.is_ok());
+    }
+
+    {
+        let (_fw, ret) = MockFirmware::new()
+            .tamper(EfiTable::SystemTable, |h| h.signature = 0)
+            .install();
+        assert!(matches!(ret, Err(Error::TableSignature(EfiTable::SystemTable))));
+        assert!(EFI_SYSTEM_TABLE.load(Ordering::SeqCst).is_null());
+    }
+
+    {
+        let (_fw, ret) = MockFirmware::new()
+            .tamper(EfiTable::BootServices, |h| h.crc32 ^= 1)
+            .install();
+        assert!(matches!(ret, Err(Error::TableCrc32(EfiTable::BootServices))));
+    }
+
+    {
+        let (_fw, ret) = MockFirmware::new()
+            .tamper(EfiTable::RuntimeServices, |h| h.header_size = 8)
+            .install();
+        assert!(matches!(ret, Err(Error::TableHeaderSize(EfiTable::RuntimeServices))));
+    }
+
+    {
+        // Rejected before the CRC32 reads past the end of the table
+        let (_fw, ret) = MockFirmware::new()
+            .tamper(EfiTable::BootServices, |h| h.header_size = u32::MAX)
+            .install();
+        assert!(matches!(ret, Err(Error::TableHeaderSize(EfiTable::BootServices))));
+    }
+
+    {
+        let (_fw, ret) = MockFirmware::new()
+            .revision(EfiTable::SystemTable, 1 << 16)
+            .install();
+        assert!(matches!(ret, Err(Error::TableRevisionTooOld(
+            EfiTable::SystemTable, 0x10000, EFI_1_02_REVISION))));
+    }
+}
+
+#[test]
+fn output_string_translates_newlines() {
+    let (fw, ret) = MockFirmware::new().install();
+    ret.unwrap();
+
+    let text: String = "0123456789abcdef\n".repeat(8);
+    output_string(&text).unwrap();
+
+    assert_eq!(fw.console(), text);
+
+    // Every flush must fit in our 32 character buffer with the terminator.
+    assert!(fw.console_writes().iter().all(|&len| len < 32));
+}
+
+#[test]
+fn print_goes_to_console() {
+    let (fw, ret) = MockFirmware::new().install();
+    ret.unwrap();
+
+    print!("Hello {}\n", 42);
+    assert_eq!(fw.console(), "Hello 42\n");
+}
+
+#[test]
+fn acpi_table_prefers_acpi_20() {
+    let (_fw, ret) = MockFirmware::new().install();
+    ret.unwrap();
+    assert!(matches!(get_acpi_table(), Err(Error::AcpiTableNotFound)));
+    drop(_fw);
+
+    let (_fw, ret) = MockFirmware::new()
+        .configuration_table(ACPI_TABLE_GUID, 0x1000)
+        .install();
+    ret.unwrap();
+    assert_eq!(get_acpi_table().unwrap(), 0x1000);
+    drop(_fw);
+
+    let (_fw, ret) = MockFirmware::new()
+        .configuration_table(ACPI_TABLE_GUID, 0x1000)
+        .configuration_table(EFI_ACPI_TABLE_GUID, 0x2000)
+        .install();
+    ret.unwrap();
+    assert_eq!(get_acpi_table().unwrap(), 0x2000);
+}
+
+#[test]
+fn memory_map_descriptor_sizes() {
+    let descriptors = [
+        descriptor(CONVENTIONAL, 0x1000, 0x9f),
+        descriptor(RUNTIME_SERVICE_DATA, 0xa0000, 0x10),
+        descriptor(BOOT_SERVICE_DATA, 0x100000, 0x100),
+        descriptor(CONVENTIONAL, 0x200000, 0x200),
+    ];
+
+    for &size in &[size_of::<EfiMemoryDescriptor>(), 48, 56, 64, 101] {
+        let (_fw, ret) = MockFirmware::new()
+            .memory_map(MemoryMap::new(&descriptors, size))
+            .install();
+        ret.unwrap();
+
+        let (mm, _key) = get_memory_map().unwrap();
+        let mut entries = mm.entries().to_vec();
+        entries.sort_by_key(|x| x.start);
+
+        assert_eq!(entries.len(), 2, "descriptor size {}", size);
+        assert_eq!((entries[0].start, entries[0].end), (0x1000, 0x9ffff));
+        assert_eq!((entries[1].start, entries[1].end), (0x100000, 0x3fffff));
+        assert_eq!(mm.sum(), Some(0x9f000 + 0x300000));
+    }
+}
+
+#[test]
+fn memory_map_fills_buffer() {
+    // Enough descriptors to fill most of our 4 KiB buffer, so the entries at
+    // the end of the map are parsed too.
+    let descriptors: std::vec::Vec<_> = (0..60)
+        .map(|ii| descriptor(CONVENTIONAL, ii * 0x10000, 1))
+        .collect();
+
+    let (_fw, ret) = MockFirmware::new()
+        .memory_map(MemoryMap::new(&descriptors, 64))
+        .install();
+    ret.unwrap();
+
+    let (mm, _key) = get_memory_map().unwrap();
+    assert_eq!(mm.entries().len(), 60);
+}
+
+#[test]
+fn memory_map_errors() {
+    // More descriptors than fit in our buffer.
+    let descriptors: std::vec::Vec<_> = (0..200)
+        .map(|ii| descriptor(CONVENTIONAL, ii * 0x10000, 1))
+        .collect();
+    let (_fw, ret) = MockFirmware::new()
+        .memory_map(MemoryMap::new(&descriptors, 48))
+        .install();
+    ret.unwrap();
+    assert!(matches!(get_memory_map(),
+        Err(Error::MemoryMap(EfiError::BufferTooSmall))));
+    drop(_fw);
+
+    // A descriptor size smaller than the structure.
+    let (_fw, ret) = MockFirmware::new()
+        .memory_map(MemoryMap::new(&[descriptor(CONVENTIONAL, 0, 1)], 16))
+        .install();
+    ret.unwrap();
+    assert!(matches!(get_memory_map(), Err(Error::MemoryMapBadDescriptorSize)));
+    drop(_fw);
+
+    // A scripted firmware error.
+    let mut map = MemoryMap::new(&[], 48);
+    map.status = Some(EfiStatus::Error(EfiError::DeviceError));
+    let (_fw, ret) = MockFirmware::new().memory_map(map).install();
+    ret.unwrap();
+    assert!(matches!(get_memory_map(), Err(Error::MemoryMap(EfiError::DeviceError))));
+    drop(_fw);
+
+    // A region which wraps the address space.
+    let (_fw, ret) = MockFirmware::new()
+        .memory_map(MemoryMap::new(
+            &[descriptor(CONVENTIONAL, 0xffff_ffff_ffff_f000, 2)], 48))
+        .install();
+    ret.unwrap();
+    assert!(matches!(get_memory_map(), Err(Error::MemoryMapIntegerOverflow)));
+}
+
+#[test]
+fn parse_memory_map_truncated() {
+    // A trailing partial descriptor is out of bounds.
+    let buf = [0u8; 48 + 20];
+    assert!(matches!(parse_memory_map(&buf, 48), Err(Error::MemoryMapOutOfBounds)));
+    assert!(matches!(parse_memory_map(&buf, 0), Err(Error::MemoryMapBadDescriptorSize)));
+}
+
+#[test]
+fn exit_boot_services_uses_key() {
+    let mut map = MemoryMap::new(&[descriptor(CONVENTIONAL, 0, 1)], 48);
+    map.key = 0xabcd;
+    let (fw, ret) = MockFirmware::new().memory_map(map).install();
+    ret.unwrap();
+
+    let (_mm, key) = get_memory_map().unwrap();
+    exit_boot_services(EfiHandle(0), key).unwrap();
+    assert_eq!(fw.exit_boot_services_calls(), [0xabcd]);
+}
+
+#[test]
+fn exit_boot_services_error() {
+    let (_fw, ret) = MockFirmware::new()
+        .exit_boot_services_status(EfiStatus::Error(EfiError::InvalidParameter))
+        .install();
+    ret.unwrap();
+
+    let (_mm, key) = get_memory_map().unwrap();
+    assert!(matches!(exit_boot_services(EfiHandle(0), key),
+        Err(Error::ExitBootServices(EfiError::InvalidParameter))));
+}
+
+#[test]
+fn exit_boot_services_retries_stale_key() {
+    let mut first = MemoryMap::new(&[descriptor(CONVENTIONAL, 0, 2)], 48);
+    first.key = 1;
+    let mut second = MemoryMap::new(&[descriptor(CONVENTIONAL, 0, 1)], 48);
+    second.key = 2;
+    let (fw, ret) = MockFirmware::new()
+        .memory_map(first)
+        .memory_map(second)
+        .exit_boot_services_status(EfiStatus::Error(EfiError::InvalidParameter))
+        .install();
+    ret.unwrap();
+
+    // The map is prepared again for the second attempt
+    let mut prepared = 0;
+    let mm = exit_boot_services_with_map(EfiHandle(0), |mm| {
+        prepared += 1;
+        mm.remove(Range { start: 0, end: 0xfff })
+            .map_err(Error::MemoryRangeSet)
+    }).unwrap();
+    assert_eq!(prepared, 2);
+    assert_eq!(fw.exit_boot_services_calls(), [1, 2]);
+    assert_eq!(mm.sum(), Some(0));
+}
+
+#[test]
+fn exit_boot_services_gives_up() {
+    let (fw, ret) = MockFirmware::new()
+        .exit_boot_services_status(EfiStatus::Error(EfiError::Unsupported))
+        .install();
+    ret.unwrap();
+
+    // Only a stale key is retried
+    assert!(matches!(exit_boot_services_with_map(EfiHandle(0), |_| Ok(())),
+        Err(Error::ExitBootServices(EfiError::Unsupported))));
+    assert_eq!(fw.exit_boot_services_calls().len(), 1);
+
+    // A map we can't prepare never gets to exit boot services
+    assert!(matches!(exit_boot_services_with_map(EfiHandle(0),
+            |_| Err(Error::NotRegistered)),
+        Err(Error::NotRegistered)));
+    assert_eq!(fw.exit_boot_services_calls().len(), 1);
+}
+
+#[test]
+fn global_variables() {
+    let (_fw, ret) = MockFirmware::new()
+        .variable("SecureBoot", &[1])
+        .variable("PlatformLang", b"en-US\0")
+        .install();
+    ret.unwrap();
+
+    let mut data = [0u8; 16];
+    assert_eq!(get_global_variable("SecureBoot", &mut data).unwrap(), 1);
+    assert_eq!(data[0], 1);
+
+    assert!(matches!(get_global_variable("SetupMode", &mut data),
+        Err(Error::GetVariable(EfiError::NotFound))));
+
+    let mut small = [0u8; 2];
+    assert!(matches!(get_global_variable("PlatformLang", &mut small),
+        Err(Error::GetVariable(EfiError::BufferTooSmall))));
+
+    let long: String = "x".repeat(100);
+    assert!(matches!(get_global_variable(&long, &mut data),
+        Err(Error::VariableNameTooLong)));
+}
+
+#[test]
+fn environment_ignores_variable_errors() {
+    // A `SecureBoot` which isn't a single byte, and no `SetupMode`
+    let (_fw, ret) = MockFirmware::new()
+        .variable("SecureBoot", &[1, 0])
+        .install();
+    ret.unwrap();
+
+    let env = crate::environment::Environment::detect().unwrap();
+    assert_eq!(env.secure_boot, None);
+    assert_eq!(env.setup_mode, None);
+    assert!(!env.secure_boot_enforced());
+}
+
+#[test]
+fn firmware_information() {
+    let (_fw, ret) = MockFirmware::new()
+        .vendor("Acme\u{e9} Firmware")
+        .install();
+    ret.unwrap();
+
+    let mut buf = [0u8; 64];
+    let len = firmware_vendor(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"Acme? Firmware");
+
+    let mut small = [0u8; 4];
+    assert_eq!(firmware_vendor(&mut small).unwrap(), 4);
+
+    assert_eq!(firmware_revision().unwrap(), 0x10000);
+    assert_eq!(uefi_revision().unwrap(), (2 << 16) | 70);
+}
+
@@ -0,0 +1,125 @@
+//! A report of the environment we were booted in: the firmware, its Secure
+//! Boot state, and whether we are running under a hypervisor. Firmware quirks
+//! tend to depend on the vendor, so other modules can branch on this.
+
+use core::fmt;
+
+use crate::cpu::Hypervisor;
+use crate::efi;
+
+/// The maximum number of bytes of the firmware vendor string we keep.
+const VENDOR_LEN: usize = 64;
+
+/// A UEFI specification revision as reported in the EFI table headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UefiRevision(pub u32);
+
+impl UefiRevision {
+    /// Major revision of the specification, eg. 2 for UEFI 2.70.
+    pub fn major(&self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    /// Minor revision of the specification, eg. 70 for UEFI 2.70.
+    pub fn minor(&self) -> u16 {
+        self.0 as u16
+    }
+}
+
+impl fmt::Display for UefiRevision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The minor revision encodes two digits, the last one is only shown
+        // if it's non-zero (2.31 is "2.3.1", 2.70 is "2.7").
+        let minor = self.minor();
+        if !minor.is_multiple_of(10) {
+            write!(f, "{}.{}.{}", self.major(), minor / 10, minor % 10)
+        } else {
+            write!(f, "{}.{}", self.major(), minor / 10)
+        }
+    }
+}
+
+/// The environment reported by the firmware and the CPU at boot.
+#[derive(Clone, Copy)]
+pub struct Environment {
+    /// The firmware vendor string from the EFI system table (ASCII only).
+    vendor: [u8; VENDOR_LEN],
+
+    /// Number of in use bytes in `vendor`.
+    vendor_len: usize,
+
+    /// The vendor specific firmware revision.
+    pub firmware_revision: u32,
+
+    /// The UEFI specification revision the firmware conforms to.
+    pub uefi_revision: UefiRevision,
+
+    /// The `SecureBoot` variable, `None` if the firmware does not implement it.
+    pub secure_boot: Option<bool>,
+
+    /// The `SetupMode` variable, `None` if the firmware does not implement it.
+    pub setup_mode: Option<bool>,
+
+    /// The hypervisor we're running under, if any.
+    pub hypervisor: Hypervisor,
+}
+
+impl Environment {
+    /// Gather the environment from EFI and CPUID. The EFI system table must
+    /// be registered and the EFI variables are only readable while we
+    /// still have runtime services.
+    pub fn detect() -> efi::Result<Self> {
+        let mut vendor = [0u8; VENDOR_LEN];
+        let vendor_len = efi::firmware_vendor(&mut vendor)?;
+
+        Ok(Environment {
+            vendor,
+            vendor_len,
+            firmware_revision: efi::firmware_revision()?,
+            uefi_revision: UefiRevision(efi::uefi_revision()?),
+            secure_boot: read_bool("SecureBoot")?,
+            setup_mode: read_bool("SetupMode")?,
+            hypervisor: Hypervisor::detect(),
+        })
+    }
+
+    /// The firmware vendor string, eg. "EDK II" for OVMF.
+    pub fn vendor(&self) -> &str {
+        // We only ever store ASCII bytes in here.
+        core::str::from_utf8(&self.vendor[..self.vendor_len]).unwrap_or("")
+    }
+
+    /// Returns `true` if Secure Boot is enforced by the firmware. Secure Boot
+    /// is only enforced when it is enabled and the platform is not in setup
+    /// mode.
+    pub fn secure_boot_enforced(&self) -> bool {
+        self.secure_boot == Some(true) && self.setup_mode != Some(true)
+    }
+
+    /// Returns `true` if we are running under a hypervisor.
+    pub fn is_virtualized(&self) -> bool {
+        self.hypervisor.is_virtualized()
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Firmware:    {} (rev {:#x})", self.vendor(), self.firmware_revision)?;
+        writeln!(f, "UEFI:        {}", self.uefi_revision)?;
+        writeln!(f, "Secure Boot: {:?} (setup mode {:?})", self.secure_boot, self.setup_mode)?;
+        writeln!(f, "Hypervisor:  {:?}", self.hypervisor)
+    }
+}
+
+/// Read a single byte boolean EFI global variable. Returns `None` if the
+/// firmware can't give us the variable for any reason, it doesn't exist, the
+/// runtime variable services are unsupported or broken, or it isn't a single
+/// byte. The banner is informational, so none of that should stop a boot.
+fn read_bool(name: &str) -> efi::Result<Option<bool>> {
+    let mut data = [0u8; 1];
+    match efi::get_global_variable(name, &mut data) {
+        Ok(1) => Ok(Some(data[0] != 0)),
+        Ok(_) | Err(efi::Error::GetVariable(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
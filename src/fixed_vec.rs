@@ -0,0 +1,66 @@
+//! Module which provides `FixedVec`, a vector with a fixed capacity. We have
+//! no heap while parsing firmware tables, so this is what we collect into.
+
+use core::fmt;
+
+/// A vector of up to `N` `T`s stored inline.
+#[derive(Clone, Copy)]
+pub struct FixedVec<T: Copy, const N: usize> {
+    /// Fixed array of entries.
+    entries: [T; N],
+
+    /// Number of in use entries in `entries`.
+    in_use: usize,
+}
+
+impl<T: Copy + Default, const N: usize> FixedVec<T, N> {
+    /// Create a new empty FixedVec.
+    pub fn new() -> Self {
+        FixedVec {
+            entries: [T::default(); N],
+            in_use: 0,
+        }
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Default for FixedVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, const N: usize> FixedVec<T, N> {
+    /// Append `val`, fails if the FixedVec is full.
+    pub fn push(&mut self, val: T) -> Result<(), ()> {
+        let ent = self.entries.get_mut(self.in_use).ok_or(())?;
+        *ent = val;
+        self.in_use += 1;
+        Ok(())
+    }
+
+    /// Get all the entries in the FixedVec as a slice.
+    pub fn entries(&self) -> &[T] {
+        &self.entries[..self.in_use]
+    }
+
+    /// Get all the entries in the FixedVec as a mutable slice.
+    pub fn entries_mut(&mut self) -> &mut [T] {
+        &mut self.entries[..self.in_use]
+    }
+
+    /// Number of entries in the FixedVec.
+    pub fn len(&self) -> usize {
+        self.in_use
+    }
+
+    /// Returns `true` if the FixedVec has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.in_use == 0
+    }
+}
+
+impl<T: Copy + fmt::Debug, const N: usize> fmt::Debug for FixedVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.entries()).finish()
+    }
+}
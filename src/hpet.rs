@@ -0,0 +1,313 @@
+//! Driver for the High Precision Event Timer. We use it as our reference
+//! clock, to calibrate the TSC and as a watchdog.
+
+use core::arch::x86_64::_rdtsc;
+
+use crate::acpi::AddressSpace;
+use crate::acpi::hpet::HpetTable;
+use crate::mm::physmem::PhysAddr;
+
+/// A `Result` type which wraps an HPET error.
+type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from the HPET driver.
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    /// The HPET registers are not memory mapped.
+    NotMemoryMapped,
+
+    /// The counter period is zero or larger than the 100 ns the
+    /// specification allows.
+    InvalidPeriod(u64),
+
+    /// The comparator does not exist.
+    InvalidComparator(u8),
+
+    /// The comparator can't operate in periodic mode.
+    PeriodicNotSupported(u8),
+
+    /// The comparator can't be routed to the requested I/O APIC input.
+    InvalidRoute(u8, u8),
+}
+
+/// General capabilities and ID register.
+const GENERAL_CAPABILITIES: u64 = 0x000;
+
+/// General configuration register.
+const GENERAL_CONFIG: u64 = 0x010;
+
+/// General interrupt status register.
+const GENERAL_INTERRUPT_STATUS: u64 = 0x020;
+
+/// Main counter value register.
+const MAIN_COUNTER: u64 = 0x0f0;
+
+/// Comparator configuration and capability register of comparator `n`.
+const fn timer_config(n: u8) -> u64 {
+    0x100 + 0x20 * n as u64
+}
+
+/// Comparator value register of comparator `n`.
+const fn timer_comparator(n: u8) -> u64 {
+    0x108 + 0x20 * n as u64
+}
+
+/// General configuration: the main counter runs and interrupts are enabled.
+const ENABLE_CNF: u64 = 1 << 0;
+
+/// Comparator configuration: level triggered interrupts.
+const INT_TYPE_CNF: u64 = 1 << 1;
+
+/// Comparator configuration: interrupts enabled.
+const INT_ENB_CNF: u64 = 1 << 2;
+
+/// Comparator configuration: periodic mode.
+const TYPE_CNF: u64 = 1 << 3;
+
+/// Comparator capability: periodic mode is supported.
+const PER_INT_CAP: u64 = 1 << 4;
+
+/// Comparator configuration: the next comparator write sets the periodic
+/// accumulator.
+const VAL_SET_CNF: u64 = 1 << 6;
+
+/// Comparator configuration: the I/O APIC input, bits 13:9.
+const INT_ROUTE_SHIFT: u64 = 9;
+const INT_ROUTE_MASK: u64 = 0x1f << INT_ROUTE_SHIFT;
+
+/// The longest counter period the specification allows, in femtoseconds.
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Femtoseconds per nanosecond.
+const FS_PER_NS: u64 = 1_000_000;
+
+/// Femtoseconds per second.
+const FS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// An HPET timer block.
+#[derive(Clone, Copy, Debug)]
+pub struct Hpet {
+    /// Base of the register block.
+    base: PhysAddr,
+
+    /// Period of the main counter in femtoseconds.
+    period_fs: u64,
+
+    /// The number of comparators.
+    comparators: u8,
+
+    /// The main counter is 64 bits wide.
+    counter_64bit: bool,
+
+    /// The general configuration the firmware left, for `restore`.
+    firmware_config: u64,
+}
+
+impl Hpet {
+    /// Initialize the HPET described by `table` and start its main counter.
+    pub unsafe fn new(table: &HpetTable) -> Result<Self> {
+        if table.base.space != AddressSpace::SystemMemory {
+            return Err(Error::NotMemoryMapped);
+        }
+
+        let mut hpet = Hpet {
+            base: PhysAddr(table.base.address),
+            period_fs: 0,
+            comparators: 0,
+            counter_64bit: false,
+            firmware_config: 0,
+        };
+
+        // The capabilities are the source of truth, not the table
+        let caps = hpet.read(GENERAL_CAPABILITIES);
+        hpet.period_fs = caps >> 32;
+        hpet.comparators = (((caps >> 8) & 0x1f) + 1) as u8;
+        hpet.counter_64bit = caps & (1 << 13) != 0;
+
+        if hpet.period_fs == 0 || hpet.period_fs > MAX_PERIOD_FS {
+            return Err(Error::InvalidPeriod(hpet.period_fs));
+        }
+
+        // Start the main counter, leaving legacy replacement off
+        hpet.firmware_config = hpet.read(GENERAL_CONFIG);
+        hpet.write(GENERAL_CONFIG, hpet.firmware_config | ENABLE_CNF);
+
+        Ok(hpet)
+    }
+
+    /// Put the general configuration back the way the firmware left it, for
+    /// when we hand the machine back to the firmware. This stops the main
+    /// counter if it wasn't running before.
+    pub unsafe fn restore(self) {
+        self.write(GENERAL_CONFIG, self.firmware_config);
+    }
+
+    /// Read the register at `offset`.
+    unsafe fn read(&self, offset: u64) -> u64 {
+        PhysAddr(self.base.0 + offset).read_volatile::<u64>()
+    }
+
+    /// Write `val` to the register at `offset`.
+    unsafe fn write(&self, offset: u64, val: u64) {
+        PhysAddr(self.base.0 + offset).write_volatile(val)
+    }
+
+    /// Period of the main counter in femtoseconds.
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    /// Frequency of the main counter in Hz.
+    pub fn frequency(&self) -> u64 {
+        FS_PER_SEC / self.period_fs
+    }
+
+    /// The number of comparators.
+    pub fn comparators(&self) -> u8 {
+        self.comparators
+    }
+
+    /// Returns `true` if the main counter is 64 bits wide. A 32-bit counter
+    /// wraps, every ~5 minutes at 14.318 MHz.
+    pub fn counter_64bit(&self) -> bool {
+        self.counter_64bit
+    }
+
+    /// Read the main counter.
+    pub fn counter(&self) -> u64 {
+        unsafe { self.read(MAIN_COUNTER) }
+    }
+
+    /// Convert `ticks` of the main counter to nanoseconds.
+    pub fn ticks_to_ns(&self, ticks: u64) -> u64 {
+        ((ticks as u128 * self.period_fs as u128) / FS_PER_NS as u128) as u64
+    }
+
+    /// Convert `ns` nanoseconds to ticks of the main counter.
+    pub fn ns_to_ticks(&self, ns: u64) -> u64 {
+        ((ns as u128 * FS_PER_NS as u128) / self.period_fs as u128) as u64
+    }
+
+    /// Nanoseconds since the main counter started.
+    pub fn nanos(&self) -> u64 {
+        self.ticks_to_ns(self.counter())
+    }
+
+    /// The counter value `ns` nanoseconds from now, for `expired`.
+    pub fn deadline(&self, ns: u64) -> u64 {
+        self.counter().wrapping_add(self.ns_to_ticks(ns))
+    }
+
+    /// Returns `true` once the main counter has passed `deadline`. This is
+    /// a polled watchdog which needs no interrupts.
+    pub fn expired(&self, deadline: u64) -> bool {
+        // Signed difference so wrapping is handled, for 32-bit counters the
+        // difference is taken in 32 bits
+        let now = self.counter();
+        if self.counter_64bit {
+            (now.wrapping_sub(deadline) as i64) >= 0
+        } else {
+            ((now as u32).wrapping_sub(deadline as u32) as i32) >= 0
+        }
+    }
+
+    /// Busy wait for `ns` nanoseconds.
+    pub fn sleep_ns(&self, ns: u64) {
+        let deadline = self.deadline(ns);
+        while !self.expired(deadline) {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Measure the TSC frequency in Hz against the HPET, over `ns`
+    /// nanoseconds. `None` if no time passed on the HPET, the counter isn't
+    /// running.
+    pub fn calibrate_tsc(&self, ns: u64) -> Option<u64> {
+        let start_ticks = self.counter();
+        let start_tsc = unsafe { _rdtsc() };
+
+        self.sleep_ns(ns);
+
+        let end_tsc = unsafe { _rdtsc() };
+        let end_ticks = self.counter();
+
+        // A 32-bit counter can wrap during the measurement
+        let ticks = if self.counter_64bit {
+            end_ticks.wrapping_sub(start_ticks)
+        } else {
+            (end_ticks as u32).wrapping_sub(start_ticks as u32) as u64
+        };
+
+        let elapsed = self.ticks_to_ns(ticks) as u128;
+        let cycles = end_tsc.wrapping_sub(start_tsc) as u128;
+        (cycles * 1_000_000_000).checked_div(elapsed).map(|x| x as u64)
+    }
+
+    /// Get the configuration of comparator `n`, making sure it exists.
+    unsafe fn timer(&self, n: u8) -> Result<u64> {
+        if n >= self.comparators {
+            return Err(Error::InvalidComparator(n));
+        }
+        Ok(self.read(timer_config(n)))
+    }
+
+    /// The I/O APIC inputs comparator `n` can be routed to, as a bitmap.
+    pub fn routes(&self, n: u8) -> Result<u32> {
+        unsafe { Ok((self.timer(n)? >> 32) as u32) }
+    }
+
+    /// Build the configuration of comparator `n` to raise an edge triggered
+    /// interrupt on I/O APIC input `route`, from its current `config`.
+    fn route(&self, n: u8, config: u64, route: u8) -> Result<u64> {
+        if route >= 32 || (config >> 32) & (1 << route) == 0 {
+            return Err(Error::InvalidRoute(n, route));
+        }
+
+        Ok((config & !(INT_ROUTE_MASK | INT_TYPE_CNF | TYPE_CNF)) |
+            ((route as u64) << INT_ROUTE_SHIFT) | INT_ENB_CNF)
+    }
+
+    /// Arm comparator `n` to interrupt once, on I/O APIC input `route`, `ns`
+    /// nanoseconds from now.
+    pub fn one_shot(&self, n: u8, ns: u64, route: u8) -> Result<()> {
+        unsafe {
+            let config = self.route(n, self.timer(n)?, route)?;
+            self.write(timer_config(n), config);
+            self.write(timer_comparator(n), self.deadline(ns));
+        }
+        Ok(())
+    }
+
+    /// Arm comparator `n` to interrupt every `ns` nanoseconds on I/O APIC
+    /// input `route`.
+    pub fn periodic(&self, n: u8, ns: u64, route: u8) -> Result<()> {
+        unsafe {
+            let config = self.timer(n)?;
+            if config & PER_INT_CAP == 0 {
+                return Err(Error::PeriodicNotSupported(n));
+            }
+
+            let config = self.route(n, config, route)? | TYPE_CNF;
+            let period = self.ns_to_ticks(ns);
+
+            // With VAL_SET_CNF the first write sets the first deadline and
+            // the second the period
+            self.write(timer_config(n), config | VAL_SET_CNF);
+            self.write(timer_comparator(n), self.counter().wrapping_add(period));
+            self.write(timer_comparator(n), period);
+        }
+        Ok(())
+    }
+
+    /// Stop comparator `n` from raising interrupts.
+    pub fn disable(&self, n: u8) -> Result<()> {
+        unsafe {
+            let config = self.timer(n)?;
+            self.write(timer_config(n), config & !(INT_ENB_CNF | TYPE_CNF));
+
+            // Clear any pending level triggered status
+            self.write(GENERAL_INTERRUPT_STATUS, 1 << n);
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,28 @@
-#![feature(asm, panic_info_message, core_intrinsics, bool_to_option)]
 #![allow(clippy::print_with_newline, non_snake_case, dead_code)]
-#![feature(arbitrary_enum_discriminant)]
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
 #[macro_use]
 mod print;
 mod acpi;
+#[cfg(not(test))]
 mod core_requirements;
+mod cpu;
+mod crc32;
 mod efi;
+mod environment;
+mod fixed_vec;
+mod hpet;
 mod mm;
-use core::panic::PanicInfo;
-use efi::{EfiHandle, EfiSystemTablePtr, EfiStatusCode};
+mod pci;
+mod power;
+mod serial;
+use efi::{EfiError, EfiHandle, EfiStatus, EfiSystemTablePtr, EfiStatusCode};
+use mm::rangeset::DefaultRangeSet;
 
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
+fn panic(info: &core::panic::PanicInfo) -> ! {
     print!("!!! PANIC !!!\n");
     if let Some(location) = info.location() {
         print!(
@@ -25,36 +33,229 @@ fn panic(info: &PanicInfo) -> ! {
         );
     }
 
-    if let Some(message) = info.message() {
-        print!("{}\n", message);
-    }
+    print!("{}\n", info.message());
     loop {
-        unsafe { asm!("hlt") }
+        unsafe { core::arch::asm!("hlt") }
+    }
+}
+
+/// How `efi_main` behaves, selected at build time with cargo features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StartupMode {
+    /// Take over the machine. Failures panic and halt, leaving the message on
+    /// the screen.
+    Halt,
+
+    /// Take over the machine. Failures before exiting boot services are
+    /// returned to the firmware as an `EfiStatusCode`, so the next boot
+    /// option or the EFI shell takes over.
+    ReturnToFirmware,
+
+    /// Gather and report ACPI and memory information, then return to the
+    /// firmware without exiting boot services.
+    DryRun,
+
+    /// Hex dump every ACPI table in the `acpidump` format, then return to the
+    /// firmware without exiting boot services.
+    AcpiDump,
+}
+
+// The startup mode features are exclusive, rather than one silently winning.
+#[cfg(any(
+    all(feature = "dry-run", feature = "acpi-dump"),
+    all(feature = "dry-run", feature = "return-to-firmware"),
+    all(feature = "acpi-dump", feature = "return-to-firmware"),
+))]
+compile_error!("enable at most one of the dry-run, acpi-dump and \
+    return-to-firmware features");
+
+/// The startup mode this image was built with.
+const STARTUP_MODE: StartupMode = if cfg!(feature = "dry-run") {
+    StartupMode::DryRun
+} else if cfg!(feature = "acpi-dump") {
+    StartupMode::AcpiDump
+} else if cfg!(feature = "return-to-firmware") {
+    StartupMode::ReturnToFirmware
+} else {
+    StartupMode::Halt
+};
+
+/// Errors which can occur while we still have boot services, and thus can be
+/// reported back to the firmware.
+#[derive(Debug)]
+enum BootError {
+    /// An error from EFI.
+    Efi(efi::Error),
+
+    /// An error from ACPI table parsing.
+    Acpi(acpi::Error),
+}
+
+impl BootError {
+    /// The status we return to the firmware for this error.
+    fn status(&self) -> EfiStatus {
+        let err = match self {
+            BootError::Efi(err) => match err {
+                efi::Error::MemoryMap(err)
+                | efi::Error::ExitBootServices(err)
+                | efi::Error::OutputString(err)
+                | efi::Error::GetVariable(err) => *err,
+                efi::Error::AcpiTableNotFound => EfiError::NotFound,
+                efi::Error::TableCrc32(_) => EfiError::CrcError,
+                efi::Error::TableRevisionTooOld(..) => EfiError::IncompatibleVersion,
+                efi::Error::MemoryMapOutOfEntries
+                | efi::Error::MemoryRangeSet(_) => EfiError::OutOfResources,
+                _ => EfiError::LoadError,
+            },
+            BootError::Acpi(err) => match err {
+                acpi::Error::Efi(err) => return BootError::Efi(*err).status(),
+                acpi::Error::ChecksumMismatch(_) => EfiError::CrcError,
+                acpi::Error::RevisionTooOld => EfiError::IncompatibleVersion,
+                acpi::Error::NumaRangeSet(_)
+                | acpi::Error::RmrrRangeSet(_) => EfiError::OutOfResources,
+                _ => EfiError::LoadError,
+            },
+        };
+        EfiStatus::Error(err)
+    }
+}
+
+impl From<efi::Error> for BootError {
+    fn from(err: efi::Error) -> Self {
+        BootError::Efi(err)
+    }
+}
+
+impl From<acpi::Error> for BootError {
+    fn from(err: acpi::Error) -> Self {
+        BootError::Acpi(err)
     }
 }
 
 #[no_mangle]
 extern "C" fn efi_main(image_handle: EfiHandle, system_table: EfiSystemTablePtr) -> EfiStatusCode {
-    
-    unsafe {
-        
-        // First,  register the EFI system table in a global so we can use it
-        // in other places such as a `print!` macro.
-        system_table.register();
-
-        // Initalize ACPI.
-        acpi::init().expect("Failed to initialize ACPI");
-        
-        // Get the memory map and exit boot services
-        let mm = efi::get_memory_map(image_handle)
-            .expect("Failed to get EFI Memory Map");
+    let ret = unsafe { boot(image_handle, system_table) };
+
+    match ret {
+        // We only get here in a dry run or ACPI dump, we're done and return
+        // to firmware.
+        Ok(()) => EfiStatus::Success.into(),
 
+        Err(err) if STARTUP_MODE == StartupMode::Halt => {
+            panic!("Failed to boot: {:?}", err);
+        }
+
+        Err(err) => {
+            let status = err.status();
+            print!("Failed to boot: {:?}\n", err);
+            print!("Returning {} to firmware\n", status);
+            status.into()
+        }
+    }
+}
+
+/// Bring up the OS. This only returns while we still have boot services, for
+/// a dry run, an ACPI dump or on failure. Once boot services are exited we
+/// never return.
+unsafe fn boot(image_handle: EfiHandle, system_table: EfiSystemTablePtr)
+        -> Result<(), BootError> {
+    // First,  register the EFI system table in a global so we can use it
+    // in other places such as a `print!` macro.
+    system_table.register()?;
+
+    // Report the firmware environment we were booted in.
+    let env = environment::Environment::detect()?;
+    print!("{}", env);
+
+    // Dump the ACPI tables before we try to make sense of them, so even
+    // tables we can't parse end up in the dump. Bad tables are always
+    // skipped rather than failing, whatever the policy.
+    if STARTUP_MODE == StartupMode::AcpiDump {
+        acpi::tables(acpi::Policy::Lenient)?
+            .dump(&mm::physmem::PhysicalMemory::new());
+        return Ok(());
+    }
+
+    // Initalize ACPI.
+    let acpi = acpi::init()?;
+
+    // Calibrate the TSC against the HPET.
+    if let Some(table) = &acpi.hpet {
+        match hpet::Hpet::new(table) {
+            Ok(hpet) => {
+                print!("HPET: {} Hz, {} comparators\n",
+                    hpet.frequency(), hpet.comparators());
+                match hpet.calibrate_tsc(10_000_000) {
+                    Some(hz) => {
+                        print!("TSC: {} Hz\n", hz);
+                    }
+                    None => {
+                        print!("TSC: HPET counter not running\n");
+                    }
+                }
+
+                // The firmware gets the machine back after a dry run
+                if STARTUP_MODE == StartupMode::DryRun {
+                    hpet.restore();
+                }
+            }
+            Err(err) => {
+                print!("HPET unusable: {:?}\n", err);
+            }
+        }
+    }
+
+    // List the PCI devices.
+    let pci = pci::PciConfig::new(acpi.mcfg);
+    pci.for_each_function(|addr, vendor, device| {
+        print!("PCI {} {:04x}:{:04x}\n", addr, vendor, device);
+    });
+
+    // Never hand out memory devices may DMA to behind our back.
+    let reserve = |mm: &mut DefaultRangeSet| -> Result<(), BootError> {
+        if let Some(dmar) = &acpi.dmar {
+            dmar.reserve(mm)?;
+        }
+        Ok(())
+    };
+
+    if STARTUP_MODE == StartupMode::DryRun {
+        // We keep boot services, so the key doesn't matter
+        let (mut mm, _key) = efi::get_memory_map()?;
+        reserve(&mut mm)?;
         print!("{:#x?}\n", mm.entries());
         print!("Physical free: {:?}\n", mm.sum().unwrap());
+        print!("Dry run complete, returning to firmware\n");
+        return Ok(());
+    }
+
+    // Pick the serial console while we can still say if there is none.
+    let uart = serial::select(acpi.serial_console.as_ref());
+    if uart.is_none() {
+        print!("WARNING: Unsupported serial console, no output after \
+            exiting boot services\n");
+    }
+
+    // Get the memory map and exit boot services, from here on failures can
+    // no longer be returned to the firmware. Printing between the two would
+    // change the map, so it waits for the serial console.
+    let mm = efi::exit_boot_services_with_map(image_handle, reserve)?;
+
+    // The EFI console is gone, switch to the serial console.
+    if let Some(uart) = uart {
+        serial::init(uart);
+    }
+    print!("Exited boot services\n");
+    print!("{:#x?}\n", mm.entries());
+    print!("Physical free: {:?}\n", mm.sum().unwrap());
+
+    if cfg!(feature = "shutdown-when-done") {
+        power::shutdown(&acpi);
     }
 
-    loop {}
+    power::halt()
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 fn __chkstk() {}
\ No newline at end of file
@@ -12,6 +12,20 @@ impl PhysAddr {
         core::ptr::read(self.0 as *const T)
     }
 
+    /// Read a `T` from physical memory address `paddr` with a volatile
+    /// access, for MMIO registers.
+    #[inline]
+    pub unsafe fn read_volatile<T>(&self) -> T {
+        core::ptr::read_volatile(self.0 as *const T)
+    }
+
+    /// Write `val` to physical memory address `paddr` with a volatile
+    /// access, for MMIO registers.
+    #[inline]
+    pub unsafe fn write_volatile<T>(&self, val: T) {
+        core::ptr::write_volatile(self.0 as *mut T, val)
+    }
+
     /// Read an unaligned `T` from physical memory address `paddr`.
     #[inline]
     pub unsafe fn read_unaligned<T>(&self) -> T {
@@ -19,21 +33,117 @@ impl PhysAddr {
     }
 }
 
-/// A consumeable slice of physical memory at `addr` for `size` bytes.
-pub struct PhysSlice {
+/// Something ACPI tables and other firmware structures can be read from:
+/// physical memory itself, or a byte buffer standing in for it so the
+/// parsers can run on the host.
+pub trait MemoryReader {
+    /// Get the `len` bytes at `addr`, or `Err` if any of them can't be read.
+    fn bytes(&self, addr: PhysAddr, len: usize) -> Result<&[u8], ()>;
+
+    /// Get the number of bytes from `addr` to the end of the region holding
+    /// it, the most a structure at `addr` can span. Without better
+    /// knowledge that's the rest of the address space.
+    fn extent(&self, addr: PhysAddr) -> u64 {
+        !addr.0
+    }
+
+    /// Read an unaligned `T` from `addr`. `T` must be valid for any bit
+    /// pattern.
+    unsafe fn read_unaligned<T>(&self, addr: PhysAddr) -> Result<T, ()> {
+        let bytes = self.bytes(addr, size_of::<T>())?;
+        Ok(core::ptr::read_unaligned(bytes.as_ptr() as *const T))
+    }
+}
+
+/// Physical memory, which is identity mapped.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalMemory(());
+
+impl PhysicalMemory {
+    /// Get a reader for physical memory. Every address read through it must
+    /// be mapped, except address 0 which is never read.
+    pub unsafe fn new() -> Self {
+        PhysicalMemory(())
+    }
+}
+
+impl MemoryReader for PhysicalMemory {
+    fn bytes(&self, addr: PhysAddr, len: usize) -> Result<&[u8], ()> {
+        // Firmware uses address 0 for absent tables, and a slice can't start
+        // at a null pointer
+        if addr.0 == 0 {
+            return Err(());
+        }
+
+        // The range can't wrap around the address space
+        addr.0.checked_add(len as u64).ok_or(())?;
+        if len == 0 {
+            return Ok(&[]);
+        }
+
+        // Safe because `PhysicalMemory::new` requires memory to be mapped
+        Ok(unsafe {
+            core::slice::from_raw_parts(addr.0 as *const u8, len)
+        })
+    }
+}
+
+/// A byte buffer standing in for physical memory at `base`, everything
+/// outside of it can't be read.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferMemory<'a> {
+    /// Physical address of the first byte of the buffer.
+    base: u64,
+
+    /// The memory contents.
+    buffer: &'a [u8],
+}
+
+impl<'a> BufferMemory<'a> {
+    /// Make `buffer` appear at physical address `base`.
+    pub fn new(base: u64, buffer: &'a [u8]) -> Self {
+        BufferMemory {
+            base,
+            buffer,
+        }
+    }
+}
+
+impl<'a> MemoryReader for BufferMemory<'a> {
+    fn bytes(&self, addr: PhysAddr, len: usize) -> Result<&[u8], ()> {
+        let start = addr.0.checked_sub(self.base).ok_or(())? as usize;
+        let end = start.checked_add(len).ok_or(())?;
+        self.buffer.get(start..end).ok_or(())
+    }
+
+    fn extent(&self, addr: PhysAddr) -> u64 {
+        (self.base + self.buffer.len() as u64).saturating_sub(addr.0)
+    }
+}
+
+/// A consumeable slice of memory read through `mem`, at `addr` for `size`
+/// bytes.
+pub struct PhysSlice<'a, M: MemoryReader> {
+    mem: &'a M,
     addr: PhysAddr, 
     len: usize,
 }
 
-impl PhysSlice {
-    /// Create a new slice to physical memory.
-    pub unsafe fn new(addr: PhysAddr, size: usize) -> Self {
+impl<'a, M: MemoryReader> PhysSlice<'a, M> {
+    /// Create a new slice to the memory of `mem`.
+    pub fn new(mem: &'a M, addr: PhysAddr, size: usize) -> Self {
         PhysSlice {
+            mem,
             addr, 
             len: size
         }
     }
 
+    /// Get the address of the remaining slice.
+    pub fn addr(&self) -> PhysAddr {
+        self.addr
+    }
+
     /// Get the remaining length of the slice.
     pub fn len(&self) -> usize {
         self.len
@@ -51,7 +161,8 @@ impl PhysSlice {
         }
     }
 
-    /// Read a `T` from the slice, updating the pointer.
+    /// Read a `T` from the slice, updating the pointer. `T` must be valid for
+    /// any bit pattern.
     pub unsafe fn consume<T>(&mut self) -> Result<T, ()> {
         // Make sure we have enough data to consume.
         if self.len < size_of::<T>() {
@@ -59,7 +170,7 @@ impl PhysSlice {
         }
 
         // Read the actual data.
-        let data = self.addr.read_unaligned::<T>();
+        let data = self.mem.read_unaligned::<T>(self.addr)?;
 
         // Update the pointer and length.
         (self.addr).0 += size_of::<T>() as u64; 
@@ -2,11 +2,13 @@
 //! The `Rangeset` can be used to insert or remove ranges of `u64`s and thus is 
 //! very useful for physical memory management. 
 
+#[cfg(test)]
+mod tests;
 
 /// A `Result` type which wraps a `RangeSet` error.
 type Result<T> = core::result::Result<T, Error>;
 /// Errors associated with `RangeSet` operations.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum Error {
     
     /// An 'out of bounds' index was specified.
@@ -36,7 +38,7 @@ pub enum Error {
 use core::cmp;
 /// An inclusive range. We do not use `RangeInclusive` as it does not implement
 /// a `Copy`.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Range {
     
     /// Start of the range (inclusive).
@@ -45,30 +47,96 @@ pub struct Range {
     /// End of the range (inclusive).
     pub end: u64,
 }
-/// A set of non-overlapping inclusive `u64` ranges. 
+/// The number of ranges a `DefaultRangeSet` holds.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// A `RangeSet` with the capacity used for the EFI memory map. Sets with a
+/// known bound, like the memory of a NUMA node, pick their own capacity.
+pub type DefaultRangeSet = RangeSet<DEFAULT_CAPACITY>;
+
+/// Where a `RangeSetIn` keeps its ranges: a fixed array, a buffer borrowed
+/// from elsewhere or, once there is an allocator, the heap.
+pub trait Storage: AsRef<[Range]> + AsMut<[Range]> {
+    /// Make room for at least one more range when the storage is full,
+    /// returning `false` if it can't. Fixed storage never can, a heap-backed
+    /// one would reallocate here so the set never runs out of entries.
+    fn grow(&mut self) -> bool {
+        false
+    }
+}
+
+impl<const N: usize> Storage for [Range; N] {}
+
+impl Storage for &mut [Range] {}
+
+/// A set of up to `N` non-overlapping inclusive `u64` ranges, in a fixed
+/// array.
+pub type RangeSet<const N: usize> = RangeSetIn<[Range; N]>;
+
+/// A set of non-overlapping inclusive `u64` ranges, kept in `S`.
+/// An `insert` or `remove` which fails leaves the set unchanged. So on
+/// `OutOfEntries` the caller can `grow` or `grow_with` the set and retry.
 #[derive(Clone, Copy)]
-pub struct RangeSet {
+pub struct RangeSetIn<S: Storage> {
     
-    /// Fixed array of `ranges`.
-    ranges: [Range; 256],
+    /// Storage for the `ranges`, in use or not.
+    ranges: S,
     
     /// Number of in use entries in `ranges`.
     in_use: usize,
 }
 
-impl RangeSet {
+impl<const N: usize> RangeSet<N> {
     
     /// Create a new empty RangeSet.
-    pub const fn new() -> RangeSet {
-        RangeSet {
-            ranges: [ Range{ start: 0, end: 0} ; 256],
+    pub const fn new() -> Self {
+        RangeSetIn {
+            ranges: [ Range{ start: 0, end: 0} ; N],
             in_use: 0,
         }
     }
+}
+
+impl<S: Storage> RangeSetIn<S> {
+
+    /// Create a new empty set keeping its ranges in `storage`.
+    pub fn with_storage(storage: S) -> Self {
+        RangeSetIn {
+            ranges: storage,
+            in_use: 0,
+        }
+    }
+
+    /// The maximum number of ranges the set can hold without growing its
+    /// storage.
+    pub fn capacity(&self) -> usize {
+        self.ranges.as_ref().len()
+    }
+
+    /// Copy the ranges into a RangeSet with room for `M` ranges. Fails with
+    /// `OutOfEntries` if they don't fit, leaving `self` untouched. The new
+    /// set is returned by value, so a big `M` costs as much stack as any
+    /// other set of that size. Use `grow_with` to move somewhere else.
+    pub fn grow<const M: usize>(&self) -> Result<RangeSet<M>> {
+        self.grow_with(RangeSet::<M>::new().ranges)
+    }
+
+    /// Copy the ranges into a set kept in `storage`, such as a buffer in
+    /// memory we allocated from this set, or a heap-backed storage once
+    /// there is an allocator. Fails with `OutOfEntries` if they don't fit,
+    /// leaving `self` untouched.
+    pub fn grow_with<T: Storage>(&self, storage: T) -> Result<RangeSetIn<T>> {
+        let mut grown = RangeSetIn::with_storage(storage);
+        grown.ranges.as_mut().get_mut(..self.in_use)
+            .ok_or(Error::OutOfEntries)?
+            .copy_from_slice(self.entries());
+        grown.in_use = self.in_use;
+        Ok(grown)
+    }
 
     /// Get al the entries in the RangeSet as slice.
     pub fn entries(&self) -> &[Range] {
-        &self.ranges[..self.in_use]
+        &self.ranges.as_ref()[..self.in_use]
     }
 
     /// Delete the `Range` contained in the RangeSet at `idx`.
@@ -78,10 +146,10 @@ impl RangeSet {
             return Err(Error::InvalidIndex);
         }
         
-        assert!(idx < self.in_use as usize, "Index out of bounds.");
+        assert!(idx < self.in_use, "Index out of bounds.");
 
         // Copy the deleted range to the end of the list.
-        self.ranges.swap(idx, self.in_use - 1);
+        self.ranges.as_mut().swap(idx, self.in_use - 1);
 
         // Decrement the number of valid ranges
         self.in_use -= 1;
@@ -104,8 +172,8 @@ impl RangeSet {
         // Outside loop forever until we run out of merges with existing
         // ranges.
         'try_merges: loop {
-            for ii in 0..self.in_use as usize {
-                let ent = self.ranges[ii];
+            for ii in 0..self.in_use {
+                let ent = self.ranges.as_ref()[ii];
 
                 // Check for overlap with an existing range.
                 // Note that we do a saturated add of one to each range.
@@ -140,8 +208,11 @@ impl RangeSet {
         
 
         
-        // Add the new range to the end.
-        if let Some(ent) = self.ranges.get_mut(self.in_use) {
+        // Add the new range to the end, making room if the storage can.
+        if self.in_use == self.capacity() {
+            self.ranges.grow();
+        }
+        if let Some(ent) = self.ranges.as_mut().get_mut(self.in_use) {
             *ent = range;
             self.in_use += 1;
             Ok(())
@@ -165,8 +236,8 @@ impl RangeSet {
         }
 
         'try_subtraction: loop {
-            for ii in 0..self.in_use as usize {
-                let ent = self.ranges[ii];
+            for ii in 0..self.in_use {
+                let ent = self.ranges.as_ref()[ii];
 
                 // If there is no overlap, there is nothing to do with this
                 // range.
@@ -188,29 +259,35 @@ impl RangeSet {
                     // If the overlap is on the low end of the range, adjust 
                     // the start of the range to the end of the range we want
                     // to remove.
-                    self.ranges[ii].start = range.end.saturating_add(1);
+                    self.ranges.as_mut()[ii].start =
+                        range.end.saturating_add(1);
                 } else if range.end >= ent.end {
                     // If the overlap is on the high end of the range, adjust
                     // the end of the range to the start of the range we want
                     // to remove.
-                    self.ranges[ii].end = range.start.saturating_sub(1);
+                    self.ranges.as_mut()[ii].end =
+                        range.start.saturating_sub(1);
                 }
                 else {
                     // If the range to remove fits inside of the range then we
-                    // need to split it into two ranges.
-                    self.ranges[ii].start = range.end.saturating_add(1);
-                    
-                    // Insert a new range for the tail.
-                    if let Some(ent) = self.ranges.get_mut(self.in_use) {
-                        *ent = Range {
-                            start: ent.start,
-                            end: range.start.saturating_sub(1),
-                        };
-                        self.in_use += 1; 
-                    } else {
+                    // need to split it into two ranges. The ranges don't
+                    // overlap, so this is the only entry `range` touches and
+                    // nothing has been changed yet. Make sure there is room
+                    // for the tail before changing anything, so the set is
+                    // unchanged if there isn't.
+                    if self.in_use >= self.capacity() && !self.ranges.grow() {
                         return Err(Error::OutOfEntries);
                     }
-                        
+
+                    // Insert a new range for the tail.
+                    self.ranges.as_mut()[ii].start =
+                        range.end.saturating_add(1);
+                    self.ranges.as_mut()[self.in_use] = Range {
+                        start: ent.start,
+                        end: range.start.saturating_sub(1),
+                    };
+                    self.in_use += 1;
+
                     continue 'try_subtraction;
                 }
 
@@ -222,7 +299,8 @@ impl RangeSet {
     } 
 
     /// Subtracts a `RangeSet` from `self`
-    pub fn subtract(&mut self, rs: &RangeSet) -> Result<()> {
+    pub fn subtract<T: Storage>(&mut self, rs: &RangeSetIn<T>)
+            -> Result<()> {
         for &ent in rs.entries() {
             self.remove(ent)?;
         }
@@ -239,7 +317,7 @@ impl RangeSet {
     /// Allocate `size` bytes of memory with `align` requirement for alignment
     pub fn allocate(&mut  self, size: u64, align: u64) -> Result<usize> {
         // Allocate anywhere from the `RangeSet`
-        self.allocate_prefer(size, align, None)
+        self.allocate_prefer(size, align, None::<&RangeSet<0>>)
     }
 
     
@@ -248,8 +326,23 @@ impl RangeSet {
     /// satisfied from `regions` the allocation will come from whatever is next
     /// best. If `regions` is `None`, the allocation will be satisfied 
     /// from anywhere. This will be the core of our physical memory manager.
-    pub fn allocate_prefer(&mut self, size: u64, align: u64,
-                                regions: Option<&RangeSet>) -> Result<usize> {
+    pub fn allocate_prefer<T: Storage>(&mut self, size: u64, align: u64,
+            regions: Option<&RangeSetIn<T>>) -> Result<usize> {
+        self.allocate_inner(size, align, regions, false)
+    }
+
+    /// Allocate `size` bytes of memory with `align` requirements for alignment
+    /// only from `regions`. Unlike `allocate_prefer` this fails with
+    /// `OutOfMemory` rather than falling back to memory outside of `regions`.
+    pub fn allocate_within<T: Storage>(&mut self, size: u64, align: u64,
+                                regions: &RangeSetIn<T>) -> Result<usize> {
+        self.allocate_inner(size, align, Some(regions), true)
+    }
+
+    /// Allocation shared by `allocate_prefer` and `allocate_within`. If
+    /// `strict` is set, only allocations overlapping `regions` are considered.
+    fn allocate_inner<T: Storage>(&mut self, size: u64, align: u64,
+            regions: Option<&RangeSetIn<T>>, strict: bool) -> Result<usize> {
         // Don't allow allocations of zero size
         if size == 0 {
             return Err(Error::ZeroSizeAllocation);
@@ -337,6 +430,11 @@ impl RangeSet {
                 }
             }
 
+            // A strict allocation can only come from the regions.
+            if strict {
+                continue;
+            }
+
             // Compute the "best" allocation size to date.
             let prev_size = allocation.map(|(base, end, _)| end - base);
 
@@ -359,6 +457,12 @@ impl RangeSet {
     }
 }
 
+impl<const N: usize> Default for RangeSet<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 fn overlaps(mut a: Range, mut b: Range) -> Option<Range> {
     // Make sure range 'a' is always lowest to biggest.
     if a.start > a.end {
@@ -0,0 +1,204 @@
+//! Tests for `RangeSet`, mostly that operations which run out of entries
+//! leave the set as it was.
+
+use std::vec::Vec;
+
+use super::*;
+
+/// The `(start, end)` of every range in `set`, sorted.
+fn ranges<S: Storage>(set: &RangeSetIn<S>) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<_> = set.entries().iter()
+        .map(|x| (x.start, x.end))
+        .collect();
+    ranges.sort_unstable();
+    ranges
+}
+
+/// A set with the `count` disjoint ranges `[0x10 * ii, 0x10 * ii + 7]`.
+fn disjoint<const N: usize>(count: u64) -> RangeSet<N> {
+    let mut set = RangeSet::new();
+    for ii in 0..count {
+        set.insert(Range { start: ii * 0x10, end: ii * 0x10 + 7 }).unwrap();
+    }
+    set
+}
+
+#[test]
+fn insert_and_remove() {
+    let mut set = RangeSet::<4>::new();
+    assert_eq!(set.capacity(), 4);
+
+    // Touching ranges merge
+    set.insert(Range { start: 0x1000, end: 0x1fff }).unwrap();
+    set.insert(Range { start: 0x2000, end: 0x2fff }).unwrap();
+    set.insert(Range { start: 0x5000, end: 0x5fff }).unwrap();
+    assert_eq!(ranges(&set), [(0x1000, 0x2fff), (0x5000, 0x5fff)]);
+
+    // Splitting a range takes an entry
+    set.remove(Range { start: 0x1800, end: 0x18ff }).unwrap();
+    assert_eq!(ranges(&set),
+        [(0x1000, 0x17ff), (0x1900, 0x2fff), (0x5000, 0x5fff)]);
+
+    // Removing across ranges trims and deletes them
+    set.remove(Range { start: 0x1700, end: 0x5000 }).unwrap();
+    assert_eq!(ranges(&set), [(0x1000, 0x16ff), (0x5001, 0x5fff)]);
+    assert_eq!(set.sum(), Some(0x700 + 0xfff));
+
+    assert!(matches!(set.insert(Range { start: 1, end: 0 }),
+        Err(Error::InvalidRange)));
+    assert!(matches!(set.remove(Range { start: 1, end: 0 }),
+        Err(Error::InvalidRange)));
+}
+
+#[test]
+fn out_of_entries_unchanged() {
+    let mut set = disjoint::<4>(4);
+    let before = ranges(&set);
+
+    // No room for another disjoint range
+    assert!(matches!(set.insert(Range { start: 0x100, end: 0x107 }),
+        Err(Error::OutOfEntries)));
+    assert_eq!(ranges(&set), before);
+
+    // No room for the tail of a split
+    assert!(matches!(set.remove(Range { start: 0x32, end: 0x33 }),
+        Err(Error::OutOfEntries)));
+    assert_eq!(ranges(&set), before);
+
+    // An allocation which would split a range
+    let mut regions = RangeSet::<1>::new();
+    regions.insert(Range { start: 0x34, end: 0x37 }).unwrap();
+    assert!(matches!(set.allocate_within(1, 1, &regions),
+        Err(Error::OutOfEntries)));
+    assert_eq!(ranges(&set), before);
+
+    // Merging frees up entries, so this still fits
+    set.insert(Range { start: 0x8, end: 0xf }).unwrap();
+    assert_eq!(ranges(&set)[0], (0x0, 0x17));
+}
+
+#[test]
+fn grow() {
+    let mut set = disjoint::<4>(4);
+    assert!(matches!(set.remove(Range { start: 0x32, end: 0x33 }),
+        Err(Error::OutOfEntries)));
+
+    // Grow and retry
+    let mut grown = set.grow::<8>().unwrap();
+    assert_eq!(ranges(&grown), ranges(&set));
+    grown.remove(Range { start: 0x32, end: 0x33 }).unwrap();
+    assert_eq!(grown.entries().len(), 5);
+
+    // Shrinking works while the ranges fit
+    assert_eq!(ranges(&grown.grow::<5>().unwrap()), ranges(&grown));
+    assert!(matches!(grown.grow::<4>(), Err(Error::OutOfEntries)));
+
+    // The default capacity
+    let set: DefaultRangeSet = disjoint(DEFAULT_CAPACITY as u64);
+    assert_eq!(set.grow::<{ DEFAULT_CAPACITY * 2 }>().unwrap().entries().len(),
+        DEFAULT_CAPACITY);
+}
+
+/// Storage which grows whenever it is full, like a heap-backed one.
+struct Growing(Vec<Range>);
+
+impl AsRef<[Range]> for Growing {
+    fn as_ref(&self) -> &[Range] {
+        &self.0
+    }
+}
+
+impl AsMut<[Range]> for Growing {
+    fn as_mut(&mut self) -> &mut [Range] {
+        &mut self.0
+    }
+}
+
+impl Storage for Growing {
+    fn grow(&mut self) -> bool {
+        self.0.push(Range::default());
+        true
+    }
+}
+
+#[test]
+fn grow_with() {
+    let set = disjoint::<4>(4);
+
+    // Into a buffer from somewhere else
+    let mut buffer = [Range::default(); 16];
+    let mut grown = set.grow_with(&mut buffer[..]).unwrap();
+    assert_eq!(grown.capacity(), 16);
+    grown.remove(Range { start: 0x32, end: 0x33 }).unwrap();
+    assert_eq!(grown.entries().len(), 5);
+    assert!(matches!(set.grow_with(&mut buffer[..3]),
+        Err(Error::OutOfEntries)));
+
+    // Into storage which grows itself never runs out of entries
+    let mut grown = set.grow_with(Growing(vec![Range::default(); 4]))
+        .unwrap();
+    grown.remove(Range { start: 0x32, end: 0x33 }).unwrap();
+    for ii in 4..64 {
+        grown.insert(Range { start: ii * 0x10, end: ii * 0x10 + 7 }).unwrap();
+    }
+    assert_eq!(grown.entries().len(), 65);
+    assert!(grown.capacity() >= 65);
+}
+
+#[test]
+fn mixed_capacities() {
+    let mut free = DefaultRangeSet::new();
+    free.insert(Range { start: 0, end: 0xffff }).unwrap();
+
+    // Sets of different capacities work together
+    let mut local = RangeSet::<2>::new();
+    local.insert(Range { start: 0x8000, end: 0x8fff }).unwrap();
+    assert_eq!(free.allocate_within(0x1000, 0x1000, &local).unwrap(), 0x8000);
+    assert!(matches!(free.allocate_within(0x1000, 0x1000, &local),
+        Err(Error::OutOfMemory)));
+
+    let mut reserved = RangeSet::<1>::new();
+    reserved.insert(Range { start: 0xf000, end: 0xffff }).unwrap();
+    free.subtract(&reserved).unwrap();
+    assert_eq!(ranges(&free), [(0x0, 0x7fff), (0x9000, 0xefff)]);
+}
+
+#[test]
+fn allocate_within() {
+    let mut free = RangeSet::<4>::new();
+    free.insert(Range { start: 0, end: 0x3fff }).unwrap();
+    free.insert(Range { start: 0x10000, end: 0x1ffff }).unwrap();
+
+    // The allocation is aligned within the overlap with the region
+    let mut region = RangeSet::<1>::new();
+    region.insert(Range { start: 0x10800, end: 0x13fff }).unwrap();
+    assert_eq!(free.allocate_within(0x1000, 0x1000, &region).unwrap(),
+        0x11000);
+    assert_eq!(ranges(&free),
+        [(0x0, 0x3fff), (0x10000, 0x10fff), (0x12000, 0x1ffff)]);
+
+    // Too big for the region, even though the set has room elsewhere
+    let before = ranges(&free);
+    assert!(matches!(free.allocate_within(0x4000, 0x1000, &region),
+        Err(Error::OutOfMemory)));
+    assert_eq!(ranges(&free), before);
+
+    // A region outside of the set
+    let mut outside = RangeSet::<1>::new();
+    outside.insert(Range { start: 0x8000, end: 0xffff }).unwrap();
+    assert!(matches!(free.allocate_within(0x1000, 0x1000, &outside),
+        Err(Error::OutOfMemory)));
+    assert_eq!(ranges(&free), before);
+
+    // Without `strict` the same request falls back to the best fit
+    assert_eq!(free.allocate_prefer(0x1000, 0x1000, Some(&outside)).unwrap(),
+        0x0);
+    assert_eq!(free.allocate_prefer(0x3000, 0x1000, Some(&region)).unwrap(),
+        0x1000);
+
+    // Bad requests are refused before looking at the regions
+    assert!(matches!(free.allocate_within(0, 0x1000, &region),
+        Err(Error::ZeroSizeAllocation)));
+    assert!(matches!(free.allocate_within(0x1000, 0x1800, &region),
+        Err(Error::InvalidAlignment)));
+}
@@ -0,0 +1,225 @@
+//! PCI configuration space access, through ECAM when the firmware gives us
+//! an MCFG and the legacy 0xcf8/0xcfc ports otherwise.
+
+use core::fmt;
+
+use crate::acpi::mcfg::Mcfg;
+use crate::cpu;
+use crate::mm::physmem::PhysAddr;
+
+/// A `Result` type which wraps a PCI error.
+type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from PCI configuration space access.
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    /// The device or function number is out of range.
+    InvalidAddress(PciAddress),
+
+    /// No ECAM region decodes the segment and bus. Without an MCFG only
+    /// segment 0 exists.
+    NoSuchBus(PciAddress),
+
+    /// The register offset is outside of the configuration space, 4 KiB with
+    /// ECAM and 256 bytes with the legacy ports.
+    OffsetOutOfRange(u16),
+
+    /// The register offset is not aligned to the access size.
+    Unaligned(u16),
+}
+
+/// The legacy configuration address port.
+const CONFIG_ADDRESS: u16 = 0xcf8;
+
+/// The legacy configuration data port.
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Configuration space register: vendor ID.
+const VENDOR_ID: u16 = 0x00;
+
+/// Configuration space register: header type.
+const HEADER_TYPE: u16 = 0x0e;
+
+/// The location of a PCI function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciAddress {
+    /// PCI segment group.
+    pub segment: u16,
+
+    /// Bus number.
+    pub bus: u8,
+
+    /// Device number, 0 to 31.
+    pub device: u8,
+
+    /// Function number, 0 to 7.
+    pub function: u8,
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:02x}:{:02x}.{}", self.segment, self.bus,
+            self.device, self.function)
+    }
+}
+
+/// Access to PCI configuration space.
+#[derive(Clone, Copy, Debug)]
+pub struct PciConfig {
+    /// The ECAM regions, `None` to use the legacy ports.
+    mcfg: Option<Mcfg>,
+}
+
+impl PciConfig {
+    /// Access configuration space through the ECAM regions in `mcfg`, or the
+    /// legacy ports if there is no MCFG.
+    pub fn new(mcfg: Option<Mcfg>) -> Self {
+        PciConfig {
+            mcfg,
+        }
+    }
+
+    /// Returns `true` if we are using ECAM.
+    pub fn is_ecam(&self) -> bool {
+        self.mcfg.is_some()
+    }
+
+    /// Validate `addr` and `offset` for an access of `size` bytes.
+    fn check(&self, addr: PciAddress, offset: u16, size: u16) -> Result<()> {
+        if addr.device >= 32 || addr.function >= 8 {
+            return Err(Error::InvalidAddress(addr));
+        }
+
+        let limit = if self.is_ecam() { 4096 } else { 256 };
+        if offset >= limit {
+            return Err(Error::OffsetOutOfRange(offset));
+        }
+
+        if !offset.is_multiple_of(size) {
+            return Err(Error::Unaligned(offset));
+        }
+
+        Ok(())
+    }
+
+    /// Get the ECAM physical address of `offset` in the configuration space
+    /// of `addr`.
+    fn ecam(mcfg: &Mcfg, addr: PciAddress, offset: u16) -> Result<PhysAddr> {
+        let segment = mcfg.find(addr.segment, addr.bus)
+            .ok_or(Error::NoSuchBus(addr))?;
+
+        Ok(PhysAddr(segment.base +
+            (((addr.bus as u64) << 20) |
+             ((addr.device as u64) << 15) |
+             ((addr.function as u64) << 12) |
+             offset as u64)))
+    }
+
+    /// Select the dword containing `offset` in the configuration space of
+    /// `addr` through the legacy address port.
+    unsafe fn legacy_select(addr: PciAddress, offset: u16) -> Result<()> {
+        if addr.segment != 0 {
+            return Err(Error::NoSuchBus(addr));
+        }
+
+        cpu::out32(CONFIG_ADDRESS, 0x8000_0000 |
+            ((addr.bus as u32) << 16) |
+            ((addr.device as u32) << 11) |
+            ((addr.function as u32) << 8) |
+            (offset as u32 & 0xfc));
+        Ok(())
+    }
+
+    /// Read the 32-bit register at `offset` of `addr`.
+    pub fn read32(&self, addr: PciAddress, offset: u16) -> Result<u32> {
+        self.check(addr, offset, 4)?;
+        unsafe {
+            match &self.mcfg {
+                Some(mcfg) => Ok(Self::ecam(mcfg, addr, offset)?
+                    .read_volatile::<u32>()),
+                None => {
+                    Self::legacy_select(addr, offset)?;
+                    Ok(cpu::in32(CONFIG_DATA))
+                }
+            }
+        }
+    }
+
+    /// Write `val` to the 32-bit register at `offset` of `addr`.
+    pub fn write32(&self, addr: PciAddress, offset: u16, val: u32)
+            -> Result<()> {
+        self.check(addr, offset, 4)?;
+        unsafe {
+            match &self.mcfg {
+                Some(mcfg) => Self::ecam(mcfg, addr, offset)?
+                    .write_volatile(val),
+                None => {
+                    Self::legacy_select(addr, offset)?;
+                    cpu::out32(CONFIG_DATA, val);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the 16-bit register at `offset` of `addr`.
+    pub fn read16(&self, addr: PciAddress, offset: u16) -> Result<u16> {
+        self.check(addr, offset, 2)?;
+        let dword = self.read32(addr, offset & !3)?;
+        Ok((dword >> ((offset & 3) * 8)) as u16)
+    }
+
+    /// Read the 8-bit register at `offset` of `addr`.
+    pub fn read8(&self, addr: PciAddress, offset: u16) -> Result<u8> {
+        let dword = self.read32(addr, offset & !3)?;
+        Ok((dword >> ((offset & 3) * 8)) as u8)
+    }
+
+    /// Write `val` to the 16-bit register at `offset` of `addr`, preserving
+    /// the other half of the dword.
+    pub fn write16(&self, addr: PciAddress, offset: u16, val: u16)
+            -> Result<()> {
+        self.check(addr, offset, 2)?;
+        let shift = (offset & 3) * 8;
+        let dword = self.read32(addr, offset & !3)?;
+        self.write32(addr, offset & !3,
+            (dword & !(0xffff << shift)) | ((val as u32) << shift))
+    }
+
+    /// Call `func` with the address, vendor ID and device ID of every PCI
+    /// function present, by brute force over every bus we can reach.
+    pub fn for_each_function(&self,
+            mut func: impl FnMut(PciAddress, u16, u16)) {
+        let mut scan = |segment: u16, buses: core::ops::RangeInclusive<u8>| {
+            for bus in buses {
+                for device in 0..32 {
+                    for function in 0..8 {
+                        let addr = PciAddress { segment, bus, device, function };
+                        let id = match self.read32(addr, VENDOR_ID) {
+                            Ok(id) if id as u16 != 0xffff => id,
+                            _ if function == 0 => break,
+                            _ => continue,
+                        };
+                        func(addr, id as u16, (id >> 16) as u16);
+
+                        // Only multi-function devices have functions 1-7
+                        if function == 0 && !matches!(
+                                self.read8(addr, HEADER_TYPE),
+                                Ok(x) if x & 0x80 != 0) {
+                            break;
+                        }
+                    }
+                }
+            }
+        };
+
+        match &self.mcfg {
+            Some(mcfg) => {
+                for segment in mcfg.segments() {
+                    scan(segment.segment, segment.start_bus..=segment.end_bus);
+                }
+            }
+            None => scan(0, 0..=255),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Powering the machine off and rebooting it once we are done with it.
+
+use core::arch::asm;
+
+use crate::acpi::Acpi;
+use crate::acpi::fadt::Fadt;
+use crate::cpu;
+
+/// PM1 control: SCI_EN, the hardware is in ACPI mode.
+const SCI_EN: u64 = 1 << 0;
+
+/// PM1 control: the SLP_TYP field.
+const SLP_TYP_SHIFT: u64 = 10;
+const SLP_TYP_MASK: u64 = 7 << SLP_TYP_SHIFT;
+
+/// PM1 control: SLP_EN, enter the sleep state in SLP_TYP.
+const SLP_EN: u64 = 1 << 13;
+
+/// The 8042 keyboard controller status and command port.
+const I8042_COMMAND: u16 = 0x64;
+
+/// 8042 status: the input buffer is full, the controller can't take a
+/// command yet.
+const I8042_INPUT_FULL: u8 = 1 << 1;
+
+/// 8042 command: pulse the CPU reset line.
+const I8042_RESET: u8 = 0xfe;
+
+/// How many times we poll hardware before giving up on it.
+const POLL_LIMIT: usize = 1_000_000;
+
+/// Power off the machine by entering S5 (soft-off) through the FADT PM1
+/// control registers. If that's not possible, or doesn't work, we halt.
+pub unsafe fn shutdown(acpi: &Acpi) -> ! {
+    if let (Some(fadt), Some(s5)) = (&acpi.fadt, acpi.s5) {
+        if enable_acpi(fadt) {
+            // Set the sleep type in both PM1 control registers first,
+            // leaving the other bits alone, as PM1a and PM1b together form
+            // one register (ACPI 6.4 section 4.8.3.2.1)
+            let mut pm1 = [
+                (fadt.pm1a_control, s5.slp_typ_a, None),
+                (fadt.pm1b_control, s5.slp_typ_b, None),
+            ];
+            for (reg, slp_typ, written) in pm1.iter_mut() {
+                if let Some(reg) = reg {
+                    if let Ok(val) = reg.read() {
+                        let val = (val & !SLP_TYP_MASK) |
+                            ((*slp_typ as u64) << SLP_TYP_SHIFT);
+                        if reg.write(val).is_ok() {
+                            *written = Some(val);
+                        }
+                    }
+                }
+            }
+
+            // Only then enter the sleep state
+            for &(reg, _, written) in &pm1 {
+                if let (Some(reg), Some(val)) = (reg, written) {
+                    let _ = reg.write(val | SLP_EN);
+                }
+            }
+        }
+    }
+
+    print!("Failed to power off, halting\n");
+    halt()
+}
+
+/// Reboot the machine through the FADT reset register, falling back to the
+/// 8042 keyboard controller and then a triple fault.
+pub unsafe fn reboot(acpi: &Acpi) -> ! {
+    // The reset register
+    if let Some(reset) = acpi.fadt.and_then(|x| x.reset) {
+        let _ = reset.register.write(reset.value as u64);
+        spin();
+    }
+
+    // The 8042 CPU reset line. Without a FADT we have to assume it's there.
+    let i8042 = match acpi.fadt {
+        Some(fadt) => fadt.boot_arch.i8042,
+        None => true,
+    };
+    if i8042 {
+        for _ in 0..POLL_LIMIT {
+            if cpu::in8(I8042_COMMAND) & I8042_INPUT_FULL == 0 {
+                cpu::out8(I8042_COMMAND, I8042_RESET);
+                break;
+            }
+        }
+        spin();
+    }
+
+    triple_fault()
+}
+
+/// Make sure the hardware is in ACPI mode, handing it over from SMM if
+/// needed. Returns `true` if it is.
+unsafe fn enable_acpi(fadt: &Fadt) -> bool {
+    let pm1a = match fadt.pm1a_control {
+        Some(pm1a) => pm1a,
+        None => return false,
+    };
+
+    let enabled = || matches!(pm1a.read(), Ok(x) if x & SCI_EN != 0);
+    if enabled() {
+        return true;
+    }
+
+    // There is no way to switch, so we're in ACPI mode already as far as the
+    // firmware is concerned
+    if fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return true;
+    }
+
+    cpu::out8(fadt.smi_command as u16, fadt.acpi_enable);
+    (0..POLL_LIMIT).any(|_| enabled())
+}
+
+/// Give a reset some time to take effect.
+fn spin() {
+    for _ in 0..POLL_LIMIT {
+        core::hint::spin_loop();
+    }
+}
+
+/// Halt forever.
+pub fn halt() -> ! {
+    loop {
+        unsafe { asm!("cli", "hlt") }
+    }
+}
+
+/// Reset by loading an empty IDT and taking an exception, which can't be
+/// delivered and escalates to a triple fault.
+unsafe fn triple_fault() -> ! {
+    /// The operand of `lidt`, a 16-bit limit and 64-bit base.
+    #[repr(C, packed)]
+    struct Idtr {
+        limit: u16,
+        base: u64,
+    }
+
+    let idtr = Idtr { limit: 0, base: 0 };
+    asm!("lidt [{}]", "int3", in(reg) &idtr);
+
+    halt()
+}
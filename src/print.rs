@@ -5,7 +5,12 @@ pub struct ScreenWriter;
 
 impl Write for ScreenWriter {
     fn write_str(&mut self, s: &str) -> Result {
-        crate::efi::output_string(s).map_err(|_| Error)
+        // Once we have a serial console the EFI console is gone
+        if crate::serial::is_active() {
+            crate::serial::write_str(s).map_err(|_| Error)
+        } else {
+            crate::efi::output_string(s).map_err(|_| Error)
+        }
     }
 }
 
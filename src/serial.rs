@@ -0,0 +1,195 @@
+//! A minimal polled 16550 UART driver. Once boot services are exited the EFI
+//! console is gone, so this is where `print!` goes.
+
+use core::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+
+use crate::acpi::AddressSpace;
+use crate::acpi::spcr::{Parity, SerialConsoleConfig};
+use crate::cpu;
+
+#[cfg(test)]
+mod tests;
+
+/// The I/O port of the UART we are using as the console, 0 if there is none.
+static SERIAL_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// The modem status bits which must be set before we transmit, for flow
+/// control.
+static SERIAL_READY: AtomicU8 = AtomicU8::new(0);
+
+/// The legacy COM1 I/O port, used if the firmware doesn't describe a console
+/// we can drive.
+const COM1: u16 = 0x3f8;
+
+/// The baud rate we use if the firmware doesn't give one.
+const DEFAULT_BAUD_RATE: u32 = 115200;
+
+/// The standard UART input clock in Hz.
+const DEFAULT_CLOCK: u32 = 1_843_200;
+
+/// Register offsets from the base of a 16550.
+const THR: u16 = 0;
+const IER: u16 = 1;
+const FCR: u16 = 2;
+const LCR: u16 = 3;
+const MCR: u16 = 4;
+const LSR: u16 = 5;
+const MSR: u16 = 6;
+
+/// Divisor latch registers, when `LCR_DLAB` is set.
+const DLL: u16 = 0;
+const DLM: u16 = 1;
+
+/// Line control: divisor latch access.
+const LCR_DLAB: u8 = 1 << 7;
+
+/// Line control: 8 data bits, no parity, 1 stop bit.
+const LCR_8N1: u8 = 0x03;
+
+/// Line control: 2 stop bits instead of 1.
+const LCR_2_STOP_BITS: u8 = 1 << 2;
+
+/// Modem control: assert DTR and RTS.
+const MCR_DTR_RTS: u8 = 0x03;
+
+/// Modem status: clear to send.
+const MSR_CTS: u8 = 1 << 4;
+
+/// Modem status: data carrier detect.
+const MSR_DCD: u8 = 1 << 7;
+
+/// Line status: the transmit holding register is empty.
+const LSR_THRE: u8 = 1 << 5;
+
+/// A 16550 UART in I/O space we can use as the console.
+#[derive(Clone, Copy, Debug)]
+pub struct Uart {
+    /// The base I/O port.
+    port: u16,
+
+    /// The baud rate divisor to program, `None` to keep what the firmware
+    /// set up.
+    divisor: Option<u16>,
+
+    /// The line control register value: data bits, parity and stop bits.
+    line_control: u8,
+
+    /// The modem status bits which must be set before we transmit.
+    ready: u8,
+}
+
+/// Get the divisor for `baud_rate` from a `clock` Hz input clock. Rates the
+/// clock can't produce get the divisor for `DEFAULT_BAUD_RATE`.
+fn divisor(clock: u32, baud_rate: u32) -> u16 {
+    let divisor = baud_rate.checked_mul(16)
+        .and_then(|x| clock.checked_div(x))
+        .filter(|&x| x != 0 && x <= u16::MAX as u32)
+        .unwrap_or(clock / (16 * DEFAULT_BAUD_RATE));
+    divisor.clamp(1, u16::MAX as u32) as u16
+}
+
+/// Pick the UART to use as the console from the firmware's SPCR `config`,
+/// falling back to COM1 if there is none. Returns `None` if the firmware
+/// describes a console we can't drive: an MMIO or non-16550 UART, a parity
+/// or stop bits a 16550 can't do, or XON/XOFF flow control, which needs a
+/// receive path. Writing to COM1 then would at best go nowhere.
+pub fn select(config: Option<&SerialConsoleConfig>) -> Option<Uart> {
+    let spcr = match config {
+        Some(x) => x,
+        None => return Some(Uart {
+            port: COM1,
+            divisor: Some(divisor(DEFAULT_CLOCK, DEFAULT_BAUD_RATE)),
+            line_control: LCR_8N1,
+            ready: 0,
+        }),
+    };
+
+    // We can only drive 16550s in I/O space
+    let supported = spcr.interface.is_16550_compatible() &&
+        spcr.base.space == AddressSpace::SystemIo &&
+        spcr.base.address != 0 && spcr.base.address <= u16::MAX as u64;
+    if !supported || spcr.parity != Parity::None ||
+            spcr.flow_control.xon_xoff {
+        return None;
+    }
+
+    let line_control = match spcr.stop_bits {
+        1 => LCR_8N1,
+        2 => LCR_8N1 | LCR_2_STOP_BITS,
+        _ => return None,
+    };
+
+    // With hardware flow control we wait for the other end before sending
+    let mut ready = 0;
+    if spcr.flow_control.rts_cts {
+        ready |= MSR_CTS;
+    }
+    if spcr.flow_control.dcd {
+        ready |= MSR_DCD;
+    }
+
+    let clock = spcr.clock_frequency.unwrap_or(DEFAULT_CLOCK);
+    Some(Uart {
+        port: spcr.base.address as u16,
+        divisor: spcr.baud_rate.map(|x| divisor(clock, x)),
+        line_control,
+        ready,
+    })
+}
+
+/// Initialize `uart` and use it as the console.
+pub unsafe fn init(uart: Uart) {
+    let Uart { port, divisor, line_control, ready } = uart;
+
+    // Disable interrupts, we poll
+    cpu::out8(port + IER, 0);
+
+    // Program the baud rate, unless we keep what the firmware set up
+    if let Some(divisor) = divisor {
+        cpu::out8(port + LCR, LCR_DLAB);
+        cpu::out8(port + DLL, divisor as u8);
+        cpu::out8(port + DLM, (divisor >> 8) as u8);
+    }
+
+    // Set the line format, enable and clear the FIFOs, and assert DTR and
+    // RTS
+    cpu::out8(port + LCR, line_control);
+    cpu::out8(port + FCR, 0x07);
+    cpu::out8(port + MCR, MCR_DTR_RTS);
+
+    SERIAL_READY.store(ready, Ordering::SeqCst);
+    SERIAL_PORT.store(port, Ordering::SeqCst);
+}
+
+/// Returns `true` if a serial console has been initialized.
+pub fn is_active() -> bool {
+    SERIAL_PORT.load(Ordering::SeqCst) != 0
+}
+
+/// Write `string` to the serial console, translating newlines.
+pub fn write_str(string: &str) -> Result<(), ()> {
+    let port = SERIAL_PORT.load(Ordering::SeqCst);
+    if port == 0 {
+        return Err(());
+    }
+
+    for &byte in string.as_bytes() {
+        if byte == b'\n' {
+            write_byte(port, b'\r');
+        }
+        write_byte(port, byte);
+    }
+
+    Ok(())
+}
+
+/// Write `byte` to the UART at `port` once it, and with flow control the
+/// other end, can take it.
+fn write_byte(port: u16, byte: u8) {
+    let ready = SERIAL_READY.load(Ordering::Relaxed);
+    unsafe {
+        while cpu::in8(port + MSR) & ready != ready {}
+        while cpu::in8(port + LSR) & LSR_THRE == 0 {}
+        cpu::out8(port + THR, byte);
+    }
+}
@@ -0,0 +1,104 @@
+//! Tests for picking and configuring the serial console.
+
+use super::*;
+use crate::acpi::GenericAddress;
+use crate::acpi::spcr::{FlowControl, SerialInterface};
+
+/// An SPCR describing a 16550 at COM2, 9600 baud 8N1.
+fn com2() -> SerialConsoleConfig {
+    SerialConsoleConfig {
+        interface: SerialInterface::Uart16550,
+        base: GenericAddress {
+            space: AddressSpace::SystemIo,
+            bit_width: 8,
+            bit_offset: 0,
+            access_size: 1,
+            address: 0x2f8,
+        },
+        baud_rate: Some(9600),
+        parity: Parity::None,
+        stop_bits: 1,
+        flow_control: FlowControl::default(),
+        interrupt: Default::default(),
+        terminal_type: 0,
+        pci: None,
+        clock_frequency: None,
+    }
+}
+
+#[test]
+fn select_default() {
+    let uart = select(None).unwrap();
+    assert_eq!(uart.port, COM1);
+    assert_eq!(uart.divisor, Some(1));
+    assert_eq!(uart.line_control, LCR_8N1);
+    assert_eq!(uart.ready, 0);
+}
+
+#[test]
+fn select_spcr() {
+    let uart = select(Some(&com2())).unwrap();
+    assert_eq!(uart.port, 0x2f8);
+    assert_eq!(uart.divisor, Some(12));
+    assert_eq!(uart.line_control, LCR_8N1);
+
+    // Keep the firmware's baud rate
+    let uart = select(Some(&SerialConsoleConfig {
+        baud_rate: None,
+        ..com2()
+    })).unwrap();
+    assert_eq!(uart.divisor, None);
+
+    // Two stop bits and hardware flow control
+    let uart = select(Some(&SerialConsoleConfig {
+        stop_bits: 2,
+        flow_control: FlowControl {
+            dcd: true,
+            rts_cts: true,
+            xon_xoff: false,
+        },
+        ..com2()
+    })).unwrap();
+    assert_eq!(uart.line_control, LCR_8N1 | LCR_2_STOP_BITS);
+    assert_eq!(uart.ready, MSR_CTS | MSR_DCD);
+}
+
+#[test]
+fn select_unsupported() {
+    let unsupported = [
+        SerialConsoleConfig {
+            interface: SerialInterface::Pl011,
+            ..com2()
+        },
+        SerialConsoleConfig {
+            parity: Parity::Reserved(1),
+            ..com2()
+        },
+        SerialConsoleConfig {
+            stop_bits: 0,
+            ..com2()
+        },
+        SerialConsoleConfig {
+            flow_control: FlowControl {
+                xon_xoff: true,
+                ..FlowControl::default()
+            },
+            ..com2()
+        },
+    ];
+    for config in unsupported.iter() {
+        assert!(select(Some(config)).is_none(), "{:?}", config);
+    }
+}
+
+#[test]
+fn baud_rate_divisor() {
+    assert_eq!(divisor(DEFAULT_CLOCK, 115200), 1);
+    assert_eq!(divisor(DEFAULT_CLOCK, 1200), 96);
+
+    // Rates which overflow, or the clock can't produce, get the default
+    assert_eq!(divisor(DEFAULT_CLOCK, u32::MAX), 1);
+    assert_eq!(divisor(48_000_000, 9600), 312);
+    assert_eq!(divisor(48_000_000, 1), 26);
+    assert_eq!(divisor(DEFAULT_CLOCK, 0), 1);
+}
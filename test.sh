@@ -0,0 +1,6 @@
+#!/bin/bash
+set -e
+
+# The host tests need the standard library, so they are built for the host
+# rather than the UEFI target set in .cargo/config.toml.
+cargo test -Z build-std=std,panic_unwind --target x86_64-unknown-linux-gnu "$@"
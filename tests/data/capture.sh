@@ -0,0 +1,56 @@
+#!/bin/bash
+# Capture the ACPI tables of a QEMU machine into tests/data/<machine>, for
+# `fixtures::Firmware::captured`. Boots FuzzOS with the `acpi-dump` feature
+# and extracts the tables from the console log with `acpixtract`.
+#
+#   tests/data/capture.sh q35|multi-socket|x2apic
+set -e
+
+machine=$1
+case "$machine" in
+    q35)
+        args="-machine q35 -smp 4 -m 512"
+        ;;
+    multi-socket)
+        # Two sockets, each its own NUMA node, 21 apart
+        args="-machine q35 -m 1G
+            -smp 8,sockets=2,cores=2,threads=2
+            -object memory-backend-ram,size=512M,id=m0
+            -object memory-backend-ram,size=512M,id=m1
+            -numa node,nodeid=0,cpus=0-3,memdev=m0
+            -numa node,nodeid=1,cpus=4-7,memdev=m1
+            -numa dist,src=0,dst=1,val=21"
+        ;;
+    x2apic)
+        # APIC IDs past 254 need x2APIC structures and interrupt remapping
+        args="-machine q35,kernel-irqchip=split -m 1G -smp 288
+            -device intel-iommu,intremap=on,eim=on"
+        ;;
+    *)
+        echo "usage: $0 q35|multi-socket|x2apic" >&2
+        exit 1
+        ;;
+esac
+
+cd "$(dirname "$0")/../.."
+cargo build --features acpi-dump
+image=$(pwd)/target/x86_64-unknown-uefi/debug
+
+log=$(mktemp)
+trap 'rm -f "$log"' EXIT
+
+# FuzzOS returns to the firmware after the dump, which then sits in its boot
+# menu, so give it a fixed time
+timeout 120 qemu-system-x86_64 $args \
+    -enable-kvm \
+    -nographic \
+    -bios /usr/share/OVMF/OVMF_CODE.fd \
+    -device driver=e1000,netdev=n0 \
+    -netdev user,id=n0,tftp=$image,bootfile=FuzzOS.efi \
+    > "$log" || true
+
+mkdir -p "tests/data/$machine"
+cd "tests/data/$machine"
+rm -f ./*.dat
+acpixtract -a "$log"
+ls